@@ -1,6 +1,6 @@
 use crate::{
-    ConnectionSchema, GetGroupRespSchema, GetInterceptDataResp, HttpInterceptSchema, TrafficResp,
-    TunStatusSchema,
+    ConnectionSchema, GetGroupRespSchema, GetInterceptDataResp, HttpInterceptSchema,
+    ProviderSchema, TrafficResp, TunStatusSchema,
 };
 
 pub const MAX_CODEC_FRAME_LENGTH: usize = 512 * 1024 * 1024;
@@ -16,6 +16,11 @@ pub trait ControlService {
 
     async fn update_group_latency(group: String) -> bool;
 
+    // Providers
+    async fn get_providers() -> Vec<ProviderSchema>;
+
+    async fn update_provider(kind: Option<String>, name: String) -> bool;
+
     // Interceptions
     async fn get_all_interceptions() -> Vec<HttpInterceptSchema>;
 
@@ -9,6 +9,9 @@ pub struct ProcessSchema {
     pub name: String,
     pub cmdline: String,
     pub parent_name: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub user: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -52,6 +55,7 @@ pub struct ProxyData {
     pub name: String,
     pub proto: String,
     pub latency: Option<String>,
+    pub udp: bool,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -118,3 +122,30 @@ pub struct TrafficResp {
 pub struct TunStatusSchema {
     pub enabled: bool,
 }
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SubscriptionInfoSchema {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+    pub total: Option<u64>,
+    pub expire: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct ProviderSchema {
+    pub name: String,
+    pub kind: String,
+    pub url: Option<String>,
+    pub path: String,
+    pub count: usize,
+    pub updated_at: Option<u64>,
+    pub subscription: Option<SubscriptionInfoSchema>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UpdateProviderReq {
+    pub kind: Option<String>,
+}
@@ -70,7 +70,7 @@ impl ChainOutbound {
                     }
                 } else {
                     let inbound = inbound_udp_container.take().unwrap();
-                    if tunnel.outbound_type().udp_transfer_type() == UdpTransferType::UdpOverTcp {
+                    if tunnel.udp_transfer_type() == UdpTransferType::UdpOverTcp {
                         // UoT, then next jump will use TCP
                         use_tcp = true;
                         let (inner, outer) = Connector::new_pair(10);
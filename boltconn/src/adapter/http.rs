@@ -2,24 +2,38 @@ use crate::adapter::{
     empty_handle, established_tcp, lookup, AddrConnector, Connector, Outbound, OutboundType,
 };
 
-use crate::common::{io_err, StreamOutboundTrait};
+use crate::common::conn_pool::{IsActive, SharedConnPool};
+use crate::common::{as_io_err, io_err, StreamOutboundTrait};
 use crate::config::AuthData;
 use crate::network::dns::Dns;
-use crate::network::egress::Egress;
+use crate::network::egress::{DialOptions, Egress};
 use crate::proxy::{ConnAbortHandle, NetworkAddr};
-use crate::transport::UdpSocketAdapter;
+use crate::transport::{connect_tls, UdpSocketAdapter};
 use async_trait::async_trait;
 use base64::Engine;
+use bytes::Bytes;
+use http_body_util::Empty;
 use httparse::Response;
+use hyper::client::conn::http2;
+use hyper::{Method, Request, StatusCode};
+use hyper_util::rt::{TokioExecutor, TokioIo};
 use std::io;
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::task::JoinHandle;
 
-#[derive(Debug, Clone)]
+type H2Sender = http2::SendRequest<Empty<Bytes>>;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct HttpConfig {
     pub(crate) server_addr: NetworkAddr,
     pub(crate) auth: Option<AuthData>,
+    pub(crate) tls: bool,
+    pub(crate) sni: String,
+    pub(crate) skip_cert_verify: bool,
+    pub(crate) http2: bool,
+    pub(crate) dial: DialOptions,
 }
 
 #[derive(Clone)]
@@ -28,69 +42,66 @@ pub struct HttpOutbound {
     dst: NetworkAddr,
     dns: Arc<Dns>,
     config: HttpConfig,
+    manager: Arc<HttpManager>,
 }
 
 impl HttpOutbound {
-    pub fn new(iface_name: &str, dst: NetworkAddr, dns: Arc<Dns>, config: HttpConfig) -> Self {
+    pub fn new(
+        iface_name: &str,
+        dst: NetworkAddr,
+        dns: Arc<Dns>,
+        config: HttpConfig,
+        manager: Arc<HttpManager>,
+    ) -> Self {
         Self {
             iface_name: iface_name.to_string(),
             dst,
             dns,
             config,
+            manager,
         }
     }
 
     async fn run_tcp<S>(
         self,
         inbound: Connector,
-        mut outbound: S,
+        outbound: S,
         abort_handle: ConnAbortHandle,
     ) -> io::Result<()>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
-        // construct request
-        let mut req = format!(
-            "CONNECT {0} HTTP/1.1\r\n\
-            Host: {0}\r\n\
-            Proxy-Connection: Keep-Alive\r\n",
-            self.dst
-        );
-        if let Some(auth) = self.config.auth {
-            let b64encoder = base64::engine::general_purpose::STANDARD;
-            let encoded = b64encoder.encode(format!("{}:{}", auth.username, auth.password));
-            req += format!("Proxy-Authorization: basic {encoded}\r\n").as_str();
-        }
-        req += "\r\n";
-
-        outbound.write_all(req.as_bytes()).await?;
-        outbound.flush().await?;
-
-        // get response
-        let mut buf_reader = BufReader::new(outbound);
-        let mut resp = String::new();
-        while !resp.ends_with("\r\n\r\n") {
-            if buf_reader.read_line(&mut resp).await? == 0 {
-                return Err(io_err("EOF"));
-            }
-            if resp.len() > 4096 {
-                return Err(io_err("Too long resp"));
-            }
+        if self.config.http2 {
+            // chained connections are not shared, so a dedicated HTTP/2 connection is used
+            let sender = h2_handshake(&self.config, outbound).await?;
+            return self.run_h2(inbound, sender, abort_handle).await;
         }
-        let mut buf = [httparse::EMPTY_HEADER; 16];
-        let mut resp_struct = Response::new(buf.as_mut());
-        resp_struct
-            .parse(resp.as_bytes())
-            .map_err(|_| io_err("Parse response failed"))?;
-        if let Some(200) = resp_struct.code {
-            let tcp_stream = buf_reader.into_inner();
-            established_tcp(inbound, tcp_stream, abort_handle).await;
-            Ok(())
+        if self.config.tls {
+            let stream = connect_tls(
+                outbound,
+                &self.config.sni,
+                self.config.skip_cert_verify,
+                &[b"http/1.1"],
+            )
+            .await?;
+            let stream = http1_connect(stream, &self.dst, self.config.auth.as_ref()).await?;
+            established_tcp(inbound, stream, abort_handle).await;
         } else {
-            Err(io_err(
-                format!("Http Connect Failed: {:?}", resp_struct.code).as_str(),
-            ))
+            let stream = http1_connect(outbound, &self.dst, self.config.auth.as_ref()).await?;
+            established_tcp(inbound, stream, abort_handle).await;
         }
+        Ok(())
+    }
+
+    async fn run_h2(
+        self,
+        inbound: Connector,
+        sender: H2Sender,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()> {
+        let stream = h2_connect(sender, &self.dst, self.config.auth.as_ref()).await?;
+        established_tcp(inbound, stream, abort_handle).await;
+        Ok(())
     }
 }
 
@@ -107,9 +118,17 @@ impl Outbound for HttpOutbound {
     ) -> JoinHandle<io::Result<()>> {
         let self_clone = self.clone();
         tokio::spawn(async move {
+            if self_clone.config.http2 {
+                let sender = self_clone.manager.get_h2_sender(&self_clone.config).await?;
+                return self_clone
+                    .run_h2(inbound, sender, abort_handle)
+                    .await
+                    .map_err(|e| io_err(e.to_string().as_str()));
+            }
             let server_addr =
                 lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
             let tcp_stream = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
                 .tcp_stream(server_addr)
                 .await?;
             self_clone
@@ -162,3 +181,279 @@ impl Outbound for HttpOutbound {
         return Err(io::ErrorKind::InvalidData.into());
     }
 }
+
+/// Share HTTP/2 connections among streams to the same proxy server.
+pub struct HttpManager {
+    iface: String,
+    active_conn: SharedConnPool<HttpConfig, H2Sender>,
+    server_resolver: Arc<Dns>,
+    timeout: Duration,
+}
+
+impl HttpManager {
+    pub fn new(iface: &str, dns: Arc<Dns>, timeout: Duration) -> Self {
+        Self {
+            iface: iface.to_string(),
+            active_conn: Default::default(),
+            server_resolver: dns,
+            timeout,
+        }
+    }
+
+    async fn get_h2_sender(&self, config: &HttpConfig) -> io::Result<H2Sender> {
+        self.active_conn
+            .get_or_connect(config, || async {
+                let server_addr =
+                    lookup(self.server_resolver.as_ref(), &config.server_addr).await?;
+                tokio::time::timeout(self.timeout, async {
+                    let tcp_stream = Egress::new(&self.iface)
+                        .with_dial(&config.dial)
+                        .tcp_stream(server_addr)
+                        .await?;
+                    h2_handshake(config, tcp_stream).await
+                })
+                .await
+                .map_err(|_| io_err("HTTP/2 connection timeout"))?
+            })
+            .await
+    }
+}
+
+impl IsActive for H2Sender {
+    fn is_active(&self) -> bool {
+        !self.is_closed()
+    }
+}
+
+fn basic_auth(auth: &AuthData) -> String {
+    let b64encoder = base64::engine::general_purpose::STANDARD;
+    let encoded = b64encoder.encode(format!("{}:{}", auth.username, auth.password));
+    format!("basic {encoded}")
+}
+
+async fn http1_connect<S>(
+    mut outbound: S,
+    dst: &NetworkAddr,
+    auth: Option<&AuthData>,
+) -> io::Result<BufReader<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    // construct request
+    let mut req = format!(
+        "CONNECT {0} HTTP/1.1\r\n\
+        Host: {0}\r\n\
+        Proxy-Connection: Keep-Alive\r\n",
+        dst
+    );
+    if let Some(auth) = auth {
+        req += format!("Proxy-Authorization: {}\r\n", basic_auth(auth)).as_str();
+    }
+    req += "\r\n";
+
+    outbound.write_all(req.as_bytes()).await?;
+    outbound.flush().await?;
+
+    // get response
+    let mut buf_reader = BufReader::new(outbound);
+    let mut resp = String::new();
+    while !resp.ends_with("\r\n\r\n") {
+        if buf_reader.read_line(&mut resp).await? == 0 {
+            return Err(io_err("EOF"));
+        }
+        if resp.len() > 4096 {
+            return Err(io_err("Too long resp"));
+        }
+    }
+    let mut buf = [httparse::EMPTY_HEADER; 16];
+    let mut resp_struct = Response::new(buf.as_mut());
+    resp_struct
+        .parse(resp.as_bytes())
+        .map_err(|_| io_err("Parse response failed"))?;
+    if let Some(200) = resp_struct.code {
+        // data after the header (if any) is kept in the buffer
+        Ok(buf_reader)
+    } else {
+        Err(io_err(
+            format!("Http Connect Failed: {:?}", resp_struct.code).as_str(),
+        ))
+    }
+}
+
+async fn h2_handshake<S>(config: &HttpConfig, outbound: S) -> io::Result<H2Sender>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    async fn handshake<S>(stream: S) -> io::Result<H2Sender>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (sender, connection) = http2::handshake(TokioExecutor::new(), TokioIo::new(stream))
+            .await
+            .map_err(as_io_err)?;
+        tokio::spawn(async move {
+            if let Err(e) = connection.await {
+                tracing::debug!("HTTP/2 proxy connection closed: {}", e);
+            }
+        });
+        Ok(sender)
+    }
+
+    if config.tls {
+        let stream = connect_tls(outbound, &config.sni, config.skip_cert_verify, &[b"h2"]).await?;
+        handshake(stream).await
+    } else {
+        handshake(outbound).await
+    }
+}
+
+async fn h2_connect(
+    mut sender: H2Sender,
+    dst: &NetworkAddr,
+    auth: Option<&AuthData>,
+) -> io::Result<TokioIo<hyper::upgrade::Upgraded>> {
+    let mut req = Request::builder()
+        .method(Method::CONNECT)
+        .uri(dst.to_string());
+    if let Some(auth) = auth {
+        req = req.header(hyper::header::PROXY_AUTHORIZATION, basic_auth(auth));
+    }
+    let req = req.body(Empty::new()).map_err(as_io_err)?;
+    sender.ready().await.map_err(as_io_err)?;
+    let resp = sender.send_request(req).await.map_err(as_io_err)?;
+    if resp.status() != StatusCode::OK {
+        return Err(io_err(
+            format!("Http Connect Failed: {}", resp.status()).as_str(),
+        ));
+    }
+    let upgraded = hyper::upgrade::on(resp).await.map_err(as_io_err)?;
+    Ok(TokioIo::new(upgraded))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::self_signed_cert;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::TlsAcceptor;
+
+    // Echo the tunneled bytes back after checking the CONNECT request
+    async fn proxy_service(
+        req: Request<hyper::body::Incoming>,
+    ) -> Result<Response<Empty<Bytes>>, hyper::Error> {
+        assert_eq!(req.method(), Method::CONNECT);
+        assert_eq!(req.uri().to_string(), "example.com:443");
+        if req.headers().get(hyper::header::PROXY_AUTHORIZATION)
+            != Some(&hyper::header::HeaderValue::from_static(
+                "basic dXNlcjpwYXNz",
+            ))
+        {
+            return Ok(Response::builder()
+                .status(StatusCode::PROXY_AUTHENTICATION_REQUIRED)
+                .body(Empty::new())
+                .unwrap());
+        }
+        tokio::spawn(async move {
+            let upgraded = hyper::upgrade::on(req).await.unwrap();
+            let (mut rd, mut wr) = tokio::io::split(TokioIo::new(upgraded));
+            let _ = tokio::io::copy(&mut rd, &mut wr).await;
+        });
+        Ok(Response::new(Empty::new()))
+    }
+
+    fn test_config(server_addr: std::net::SocketAddr, tls: bool, http2: bool) -> HttpConfig {
+        HttpConfig {
+            server_addr: NetworkAddr::Raw(server_addr),
+            auth: Some(AuthData {
+                username: "user".to_string(),
+                password: "pass".to_string(),
+            }),
+            tls,
+            sni: "localhost".to_string(),
+            skip_cert_verify: true,
+            http2,
+            dial: DialOptions::default(),
+        }
+    }
+
+    fn dst() -> NetworkAddr {
+        NetworkAddr::DomainName {
+            domain_name: "example.com".to_string(),
+            port: 443,
+        }
+    }
+
+    async fn echo<S: AsyncRead + AsyncWrite + Unpin>(mut stream: S, msg: &[u8]) {
+        stream.write_all(msg).await.unwrap();
+        let mut buf = vec![0u8; msg.len()];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf.as_slice(), msg);
+    }
+
+    #[tokio::test]
+    async fn test_http1_connect() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(
+                    hyper::server::conn::http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service_fn(proxy_service))
+                        .with_upgrades(),
+                );
+            }
+        });
+
+        let config = test_config(server_addr, false, false);
+        let stream = TcpStream::connect(server_addr).await.unwrap();
+        let stream = http1_connect(stream, &dst(), config.auth.as_ref())
+            .await
+            .unwrap();
+        echo(stream, b"hello").await;
+
+        let stream = TcpStream::connect(server_addr).await.unwrap();
+        assert!(http1_connect(stream, &dst(), None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_h2_tls_connect() {
+        let (cert, key) = self_signed_cert();
+        let mut tls_config = tokio_rustls::rustls::ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert)],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)),
+            )
+            .unwrap();
+        tls_config.alpn_protocols = vec![b"h2".to_vec()];
+        let acceptor = TlsAcceptor::from(Arc::new(tls_config));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let server_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // only one connection is expected as streams are multiplexed
+            let (stream, _) = listener.accept().await.unwrap();
+            let stream = acceptor.accept(stream).await.unwrap();
+            let _ = hyper::server::conn::http2::Builder::new(TokioExecutor::new())
+                .serve_connection(TokioIo::new(stream), service_fn(proxy_service))
+                .await;
+        });
+
+        let config = test_config(server_addr, true, true);
+        let stream = TcpStream::connect(server_addr).await.unwrap();
+        let sender = h2_handshake(&config, stream).await.unwrap();
+        let first = h2_connect(sender.clone(), &dst(), config.auth.as_ref())
+            .await
+            .unwrap();
+        let second = h2_connect(sender.clone(), &dst(), config.auth.as_ref())
+            .await
+            .unwrap();
+        echo(first, b"hello").await;
+        echo(second, b"world").await;
+        assert!(h2_connect(sender, &dst(), None).await.is_err());
+    }
+}
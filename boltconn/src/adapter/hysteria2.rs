@@ -0,0 +1,194 @@
+use crate::adapter::{
+    established_tcp, established_udp, get_dst, AddrConnector, Connector, Outbound, OutboundType,
+};
+use crate::common::conn_pool::SharedConnPool;
+use crate::common::{as_io_err, io_err, StreamOutboundTrait};
+use crate::network::dns::Dns;
+use crate::network::egress::Egress;
+use crate::proxy::error::TransportError;
+use crate::proxy::{ConnAbortHandle, NetworkAddr};
+use crate::transport::hysteria2::{Hysteria2Config, Hysteria2Connection, Hysteria2UdpSession};
+use crate::transport::UdpSocketAdapter;
+use async_trait::async_trait;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+#[derive(Clone)]
+pub struct Hysteria2Outbound {
+    dst: NetworkAddr,
+    config: Hysteria2Config,
+    manager: Arc<Hysteria2Manager>,
+}
+
+impl Hysteria2Outbound {
+    pub fn new(dst: NetworkAddr, config: Hysteria2Config, manager: Arc<Hysteria2Manager>) -> Self {
+        Self {
+            dst,
+            config,
+            manager,
+        }
+    }
+
+    async fn run_tcp(self, inbound: Connector, abort_handle: ConnAbortHandle) -> io::Result<()> {
+        let conn = self
+            .manager
+            .get_conn(&self.config)
+            .await
+            .map_err(as_io_err)?;
+        let stream = conn.open_tcp(&self.dst).await.map_err(as_io_err)?;
+        established_tcp(inbound, stream, abort_handle).await;
+        Ok(())
+    }
+
+    async fn run_udp(
+        self,
+        inbound: AddrConnector,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
+    ) -> io::Result<()> {
+        let conn = self
+            .manager
+            .get_conn(&self.config)
+            .await
+            .map_err(as_io_err)?;
+        let session = conn.open_udp().map_err(as_io_err)?;
+        let tunnel_addr = if tunnel_only {
+            Some(self.dst.clone())
+        } else {
+            None
+        };
+        established_udp(
+            inbound,
+            Hysteria2UdpAdapter { session },
+            tunnel_addr,
+            abort_handle,
+        )
+        .await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Outbound for Hysteria2Outbound {
+    fn outbound_type(&self) -> OutboundType {
+        OutboundType::Hysteria2
+    }
+
+    fn spawn_tcp(
+        &self,
+        inbound: Connector,
+        abort_handle: ConnAbortHandle,
+    ) -> JoinHandle<io::Result<()>> {
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone.run_tcp(inbound, abort_handle).await;
+            if r.is_err() {
+                abort_handle2.cancel();
+            }
+            r
+        })
+    }
+
+    async fn spawn_tcp_with_outbound(
+        &self,
+        _inbound: Connector,
+        _tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        _udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        _abort_handle: ConnAbortHandle,
+    ) -> io::Result<bool> {
+        tracing::error!("Hysteria2 cannot be used as a non-first hop of a chain");
+        Err(io::ErrorKind::InvalidData.into())
+    }
+
+    fn spawn_udp(
+        &self,
+        inbound: AddrConnector,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
+    ) -> JoinHandle<io::Result<()>> {
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            if !self_clone.config.udp {
+                return Err(io_err("Hysteria2 UDP is disabled"));
+            }
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone.run_udp(inbound, abort_handle, tunnel_only).await;
+            if r.is_err() {
+                abort_handle2.cancel();
+            }
+            r
+        })
+    }
+
+    async fn spawn_udp_with_outbound(
+        &self,
+        _inbound: AddrConnector,
+        _tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        _udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        _abort_handle: ConnAbortHandle,
+        _tunnel_only: bool,
+    ) -> io::Result<bool> {
+        tracing::error!("Hysteria2 cannot be used as a non-first hop of a chain");
+        Err(io::ErrorKind::InvalidData.into())
+    }
+}
+
+struct Hysteria2UdpAdapter {
+    session: Hysteria2UdpSession,
+}
+
+#[async_trait]
+impl UdpSocketAdapter for Hysteria2UdpAdapter {
+    async fn send_to(&self, data: &[u8], addr: NetworkAddr) -> Result<(), TransportError> {
+        self.session.send_to(data, &addr)
+    }
+
+    async fn recv_from(&self, data: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        self.session.recv_from(data).await
+    }
+}
+
+pub struct Hysteria2Manager {
+    iface: String,
+    active_conn: SharedConnPool<Hysteria2Config, Arc<Hysteria2Connection>>,
+    server_resolver: Arc<Dns>,
+    timeout: Duration,
+}
+
+impl Hysteria2Manager {
+    pub fn new(iface: &str, dns: Arc<Dns>, timeout: Duration) -> Self {
+        Self {
+            iface: iface.to_string(),
+            active_conn: Default::default(),
+            server_resolver: dns,
+            timeout,
+        }
+    }
+
+    pub async fn get_conn(
+        &self,
+        config: &Hysteria2Config,
+    ) -> Result<Arc<Hysteria2Connection>, TransportError> {
+        self.active_conn
+            .get_or_connect(config, || async {
+                let server_addr = get_dst(&self.server_resolver, &config.server_addr).await?;
+                let egress = Egress::new(&self.iface).with_dial(&config.dial);
+                let socket = match server_addr {
+                    SocketAddr::V4(_) => egress.udpv4_socket().await?,
+                    SocketAddr::V6(_) => egress.udpv6_socket().await?,
+                };
+                let conn = tokio::time::timeout(
+                    self.timeout,
+                    Hysteria2Connection::connect(config, server_addr, socket.into_std()?),
+                )
+                .await
+                .map_err(|_| TransportError::Hysteria2("Connection timeout"))??;
+                Ok::<_, TransportError>(Arc::new(conn))
+            })
+            .await
+    }
+}
@@ -14,13 +14,17 @@ use tokio::task::JoinHandle;
 mod chain;
 mod direct;
 mod http;
+mod hysteria2;
 mod shadowsocks;
 mod socks5;
 mod ssh;
 mod tcp_adapter;
 mod trojan;
+mod tuic;
 mod udp_adapter;
 mod udp_over_tcp;
+mod vless;
+mod vmess;
 mod wireguard;
 
 pub use self::http::*;
@@ -33,13 +37,17 @@ use crate::proxy::{ConnAbortHandle, ConnContext, NetworkAddr};
 use crate::transport::UdpSocketAdapter;
 pub use chain::*;
 pub use direct::*;
+pub use hysteria2::*;
 pub use socks5::*;
 pub use ssh::*;
 use std::future::Future;
 use std::io::ErrorKind;
 pub use tcp_adapter::*;
 pub use trojan::*;
+pub use tuic::*;
 pub use udp_adapter::*;
+pub use vless::*;
+pub use vmess::*;
 pub use wireguard::*;
 
 pub struct TcpStatus {
@@ -103,6 +111,10 @@ pub enum OutboundType {
     Http,
     Shadowsocks,
     Trojan,
+    Vmess,
+    Vless,
+    Hysteria2,
+    Tuic,
     Wireguard,
     Chain,
     Ssh,
@@ -116,6 +128,10 @@ impl Display for OutboundType {
             OutboundType::Http => "http",
             OutboundType::Shadowsocks => "shadowsocks",
             OutboundType::Trojan => "trojan",
+            OutboundType::Vmess => "vmess",
+            OutboundType::Vless => "vless",
+            OutboundType::Hysteria2 => "hysteria2",
+            OutboundType::Tuic => "tuic",
             OutboundType::Wireguard => "wireguard",
             OutboundType::Chain => "chain",
             OutboundType::Ssh => "ssh",
@@ -144,8 +160,12 @@ impl OutboundType {
             | OutboundType::Socks5
             | OutboundType::Http
             | OutboundType::Shadowsocks
-            | OutboundType::Trojan => TcpTransferType::Tcp,
-            OutboundType::Wireguard => TcpTransferType::TcpOverUdp,
+            | OutboundType::Trojan
+            | OutboundType::Vmess
+            | OutboundType::Vless => TcpTransferType::Tcp,
+            OutboundType::Hysteria2 | OutboundType::Tuic | OutboundType::Wireguard => {
+                TcpTransferType::TcpOverUdp
+            }
             OutboundType::Chain => TcpTransferType::NotApplicable,
             OutboundType::Ssh => TcpTransferType::Tcp,
         }
@@ -158,9 +178,13 @@ impl OutboundType {
             OutboundType::Http => UdpTransferType::NotApplicable,
             OutboundType::Shadowsocks => UdpTransferType::Udp,
             OutboundType::Trojan => UdpTransferType::UdpOverTcp,
+            OutboundType::Vmess => UdpTransferType::UdpOverTcp,
+            OutboundType::Vless => UdpTransferType::UdpOverTcp,
+            OutboundType::Hysteria2 => UdpTransferType::Udp,
+            OutboundType::Tuic => UdpTransferType::Udp,
             OutboundType::Wireguard => UdpTransferType::Udp,
             OutboundType::Chain => UdpTransferType::NotApplicable,
-            OutboundType::Ssh => UdpTransferType::NotApplicable,
+            OutboundType::Ssh => UdpTransferType::UdpOverTcp,
         }
     }
 }
@@ -169,6 +193,11 @@ impl OutboundType {
 pub trait Outbound: Send + Sync {
     fn outbound_type(&self) -> OutboundType;
 
+    /// Transport of relayed UDP sessions, which may differ from the protocol's default.
+    fn udp_transfer_type(&self) -> UdpTransferType {
+        self.outbound_type().udp_transfer_type()
+    }
+
     /// Run with tokio::spawn.
     fn spawn_tcp(
         &self,
@@ -441,12 +470,14 @@ pub(super) async fn get_dst(dns: &Dns, dst: &NetworkAddr) -> io::Result<SocketAd
 
 pub(super) async fn connect_timeout<F: Future<Output = io::Result<()>>>(
     future: F,
+    timeout: Option<Duration>,
     component_str: &str,
 ) -> io::Result<()> {
-    tokio::time::timeout(Duration::from_secs(10), future)
+    let timeout = timeout.unwrap_or(Duration::from_secs(10));
+    tokio::time::timeout(timeout, future)
         .await
         .unwrap_or_else(|_| {
-            tracing::debug!("{} timeout after 10s", component_str);
+            tracing::debug!("{} timeout after {:?}", component_str, timeout);
             Err(ErrorKind::TimedOut.into())
         })
 }
@@ -1,12 +1,16 @@
+use crate::adapter::udp_over_tcp::{spawn_uot, spawn_uot_with_outbound, uot_magic_addr};
 use crate::adapter::{
     established_tcp, established_udp, lookup, AddrConnector, Connector, Outbound, OutboundType,
+    UdpTransferType,
 };
 
 use crate::common::{io_err, StreamOutboundTrait};
 use crate::network::dns::Dns;
-use crate::network::egress::Egress;
+use crate::network::egress::{DialOptions, Egress};
 use crate::proxy::error::TransportError;
 use crate::proxy::{ConnAbortHandle, NetworkAddr};
+use crate::transport::shadow_tls::ShadowTlsStream;
+use crate::transport::simple_obfs::{ObfsMode, ObfsStream};
 use crate::transport::{AdapterOrSocket, UdpSocketAdapter};
 use async_trait::async_trait;
 use bytes::{Bytes, BytesMut};
@@ -28,6 +32,15 @@ pub struct ShadowSocksConfig {
     pub(crate) password: String,
     pub(crate) cipher_kind: shadowsocks::crypto::CipherKind,
     pub(crate) udp: bool,
+    pub(crate) plugin: Option<ShadowsocksPlugin>,
+    pub(crate) udp_over_tcp: bool,
+    pub(crate) dial: DialOptions,
+}
+
+#[derive(Clone, Debug)]
+pub enum ShadowsocksPlugin {
+    Obfs { mode: ObfsMode, host: String },
+    ShadowTls { host: String, password: String },
 }
 
 impl From<ShadowSocksConfig> for ServerConfig {
@@ -42,6 +55,9 @@ pub struct SSOutbound {
     dst: NetworkAddr,
     dns: Arc<Dns>,
     config: ServerConfig,
+    plugin: Option<ShadowsocksPlugin>,
+    udp_over_tcp: bool,
+    dial: DialOptions,
 }
 
 impl SSOutbound {
@@ -55,6 +71,9 @@ impl SSOutbound {
             iface_name: iface_name.to_string(),
             dst,
             dns,
+            plugin: config.plugin.clone(),
+            udp_over_tcp: config.udp_over_tcp,
+            dial: config.dial.clone(),
             config: config.into(),
         }
     }
@@ -99,6 +118,35 @@ impl SSOutbound {
         server_addr: SocketAddr,
         abort_handle: ConnAbortHandle,
     ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        match self.plugin.clone() {
+            None => {
+                self.run_ss_tcp(inbound, outbound, server_addr, abort_handle)
+                    .await
+            }
+            Some(ShadowsocksPlugin::Obfs { mode, host }) => {
+                let stream = ObfsStream::new(outbound, mode, host.as_str(), server_addr.port());
+                self.run_ss_tcp(inbound, stream, server_addr, abort_handle)
+                    .await
+            }
+            Some(ShadowsocksPlugin::ShadowTls { host, password }) => {
+                let stream =
+                    ShadowTlsStream::connect(outbound, host.as_str(), password.as_str()).await?;
+                self.run_ss_tcp(inbound, stream, server_addr, abort_handle)
+                    .await
+            }
+        }
+    }
+
+    async fn run_ss_tcp<S>(
+        self,
+        inbound: Connector,
+        outbound: S,
+        server_addr: SocketAddr,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
@@ -136,6 +184,14 @@ impl Outbound for SSOutbound {
         OutboundType::Shadowsocks
     }
 
+    fn udp_transfer_type(&self) -> UdpTransferType {
+        if self.udp_over_tcp {
+            UdpTransferType::UdpOverTcp
+        } else {
+            UdpTransferType::Udp
+        }
+    }
+
     fn spawn_tcp(
         &self,
         inbound: Connector,
@@ -145,6 +201,7 @@ impl Outbound for SSOutbound {
         tokio::spawn(async move {
             let server_addr = self_clone.get_server_addr().await?;
             let tcp_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.dial)
                 .tcp_stream(server_addr)
                 .await?;
             self_clone
@@ -180,12 +237,22 @@ impl Outbound for SSOutbound {
         abort_handle: ConnAbortHandle,
         tunnel_only: bool,
     ) -> JoinHandle<io::Result<()>> {
+        if self.udp_over_tcp {
+            let mut uot = self.clone();
+            uot.dst = uot_magic_addr();
+            return spawn_uot(&uot, inbound, self.dst.clone(), abort_handle, tunnel_only);
+        }
         let self_clone = self.clone();
         tokio::spawn(async move {
             let server_addr = self_clone.get_server_addr().await?;
             let out_sock = {
                 let socket = match server_addr {
-                    SocketAddr::V4(_) => Egress::new(&self_clone.iface_name).udpv4_socket().await?,
+                    SocketAddr::V4(_) => {
+                        Egress::new(&self_clone.iface_name)
+                            .with_dial(&self_clone.dial)
+                            .udpv4_socket()
+                            .await?
+                    }
                     SocketAddr::V6(_) => return Err(io_err("ss ipv6 udp not supported now")),
                 };
                 socket.connect(server_addr).await?;
@@ -211,6 +278,19 @@ impl Outbound for SSOutbound {
         abort_handle: ConnAbortHandle,
         tunnel_only: bool,
     ) -> io::Result<bool> {
+        if self.udp_over_tcp && tcp_outbound.is_some() {
+            let mut uot = self.clone();
+            uot.dst = uot_magic_addr();
+            return spawn_uot_with_outbound(
+                &uot,
+                tcp_outbound.unwrap(),
+                inbound,
+                self.dst.clone(),
+                abort_handle,
+                tunnel_only,
+            )
+            .await;
+        }
         if tcp_outbound.is_some() || udp_outbound.is_none() {
             tracing::error!("Invalid Shadowsocks UDP outbound ancestor");
             return Err(io::ErrorKind::InvalidData.into());
@@ -1,14 +1,17 @@
+use crate::adapter::udp_over_tcp::{spawn_uot, spawn_uot_with_outbound, uot_magic_addr};
 use crate::adapter::{
     established_tcp, established_udp, lookup, AddrConnector, Connector, Outbound, OutboundType,
+    UdpTransferType,
 };
 
 use crate::common::{as_io_err, io_err, StreamOutboundTrait};
 use crate::config::AuthData;
 use crate::network::dns::Dns;
-use crate::network::egress::Egress;
+use crate::network::egress::{DialOptions, Egress};
 use crate::proxy::error::TransportError;
 use crate::proxy::{ConnAbortHandle, NetworkAddr};
-use crate::transport::UdpSocketAdapter;
+use crate::transport::socks4::socks4_connect;
+use crate::transport::{connect_tls, UdpSocketAdapter};
 use async_trait::async_trait;
 use fast_socks5::client::Socks5Stream;
 use fast_socks5::util::target_addr::TargetAddr;
@@ -20,11 +23,35 @@ use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::UdpSocket;
 use tokio::task::JoinHandle;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocksVersion {
+    V4,
+    V4a,
+    V5,
+}
+
+impl SocksVersion {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "4" => Some(Self::V4),
+            "4a" => Some(Self::V4a),
+            "5" => Some(Self::V5),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Socks5Config {
     pub(crate) server_addr: NetworkAddr,
     pub(crate) auth: Option<AuthData>,
     pub(crate) udp: bool,
+    pub(crate) version: SocksVersion,
+    pub(crate) tls: bool,
+    pub(crate) sni: String,
+    pub(crate) skip_cert_verify: bool,
+    pub(crate) udp_over_tcp: bool,
+    pub(crate) dial: DialOptions,
 }
 
 impl Socks5Config {
@@ -77,6 +104,54 @@ impl Socks5Outbound {
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
+        if self.config.tls {
+            let stream = connect_tls(
+                outbound,
+                &self.config.sni,
+                self.config.skip_cert_verify,
+                &[],
+            )
+            .await?;
+            self.run_tcp_with_transport(inbound, stream, abort_handle)
+                .await
+        } else {
+            self.run_tcp_with_transport(inbound, outbound, abort_handle)
+                .await
+        }
+    }
+
+    async fn run_tcp_with_transport<S>(
+        self,
+        inbound: Connector,
+        mut outbound: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if self.config.version != SocksVersion::V5 {
+            let dst = match (&self.dst, self.config.version) {
+                // plain SOCKS4 requires the destination to be resolved locally
+                (NetworkAddr::DomainName { .. }, SocksVersion::V4) => {
+                    NetworkAddr::Raw(lookup(self.dns.as_ref(), &self.dst).await?)
+                }
+                _ => self.dst.clone(),
+            };
+            let user_id = self
+                .config
+                .auth
+                .as_ref()
+                .map_or("", |auth| auth.username.as_str());
+            socks4_connect(
+                &mut outbound,
+                &dst,
+                user_id,
+                self.config.version == SocksVersion::V4a,
+            )
+            .await?;
+            established_tcp(inbound, outbound, abort_handle).await;
+            return Ok(());
+        }
         let mut socks_stream = self.connect_proxy(outbound).await?;
         let target = self.dst.into();
         let _bound_addr = socks_stream
@@ -94,6 +169,35 @@ impl Socks5Outbound {
         abort_handle: ConnAbortHandle,
         tunnel_only: bool,
     ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        if self.config.version != SocksVersion::V5 {
+            return Err(io_err("UDP is not supported by SOCKS4"));
+        }
+        if self.config.tls {
+            let stream = connect_tls(
+                outbound,
+                &self.config.sni,
+                self.config.skip_cert_verify,
+                &[],
+            )
+            .await?;
+            self.run_udp_with_transport(inbound, stream, abort_handle, tunnel_only)
+                .await
+        } else {
+            self.run_udp_with_transport(inbound, outbound, abort_handle, tunnel_only)
+                .await
+        }
+    }
+
+    async fn run_udp_with_transport<S>(
+        self,
+        inbound: AddrConnector,
+        outbound: S,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
+    ) -> io::Result<()>
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
     {
@@ -106,7 +210,12 @@ impl Socks5Outbound {
         let server_addr = lookup(self.dns.as_ref(), &self.config.server_addr).await?;
         let mut socks_stream = self.connect_proxy(outbound).await?;
         let out_sock = Arc::new(match server_addr {
-            SocketAddr::V4(_) => Egress::new(&self.iface_name).udpv4_socket().await?,
+            SocketAddr::V4(_) => {
+                Egress::new(&self.iface_name)
+                    .with_dial(&self.config.dial)
+                    .udpv4_socket()
+                    .await?
+            }
             SocketAddr::V6(_) => return Err(io_err("udp v6 not supported")),
         });
         let bound_addr = socks_stream
@@ -134,6 +243,14 @@ impl Outbound for Socks5Outbound {
         OutboundType::Socks5
     }
 
+    fn udp_transfer_type(&self) -> UdpTransferType {
+        if self.config.udp_over_tcp {
+            UdpTransferType::UdpOverTcp
+        } else {
+            UdpTransferType::Udp
+        }
+    }
+
     fn spawn_tcp(
         &self,
         inbound: Connector,
@@ -144,6 +261,7 @@ impl Outbound for Socks5Outbound {
             let server_addr =
                 lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
             let socks_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
                 .tcp_stream(server_addr)
                 .await?;
             self_clone.run_tcp(inbound, socks_conn, abort_handle).await
@@ -176,11 +294,17 @@ impl Outbound for Socks5Outbound {
         abort_handle: ConnAbortHandle,
         tunnel_only: bool,
     ) -> JoinHandle<io::Result<()>> {
+        if self.config.udp_over_tcp {
+            let mut uot = self.clone();
+            uot.dst = uot_magic_addr();
+            return spawn_uot(&uot, inbound, self.dst.clone(), abort_handle, tunnel_only);
+        }
         let self_clone = self.clone();
         tokio::spawn(async move {
             let server_addr =
                 lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
             let socks_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
                 .tcp_stream(server_addr)
                 .await?;
             self_clone
@@ -191,12 +315,25 @@ impl Outbound for Socks5Outbound {
 
     async fn spawn_udp_with_outbound(
         &self,
-        _inbound: AddrConnector,
-        _tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        inbound: AddrConnector,
+        tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
         _udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
-        _abort_handle: ConnAbortHandle,
-        _tunnel_only: bool,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
     ) -> io::Result<bool> {
+        if self.config.udp_over_tcp && tcp_outbound.is_some() {
+            let mut uot = self.clone();
+            uot.dst = uot_magic_addr();
+            return spawn_uot_with_outbound(
+                &uot,
+                tcp_outbound.unwrap(),
+                inbound,
+                self.dst.clone(),
+                abort_handle,
+                tunnel_only,
+            )
+            .await;
+        }
         tracing::error!("Socks5 does not support UDP chain");
         return Err(io::ErrorKind::InvalidData.into());
     }
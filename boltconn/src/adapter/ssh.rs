@@ -1,13 +1,13 @@
 use crate::adapter;
 use crate::adapter::{
-    empty_handle, established_tcp, AddrConnector, Connector, Outbound, OutboundType,
+    established_tcp, established_udp, AddrConnector, Connector, Outbound, OutboundType,
 };
 use crate::common::{io_err, StreamOutboundTrait};
 use crate::network::dns::Dns;
 use crate::network::egress::Egress;
 use crate::proxy::error::TransportError;
 use crate::proxy::{ConnAbortHandle, NetworkAddr};
-use crate::transport::ssh::{SshConfig, SshTunnel};
+use crate::transport::ssh::{SshConfig, SshTunnel, UdpgwAdapter};
 use crate::transport::UdpSocketAdapter;
 use async_trait::async_trait;
 use std::collections::HashMap;
@@ -43,41 +43,79 @@ impl SshOutboundHandle {
         }
     }
 
-    async fn attach_tcp(
-        self,
-        inbound: Connector,
+    async fn get_master_conn(
+        &self,
         outbound: Option<Box<dyn StreamOutboundTrait>>,
-        abort_handle: ConnAbortHandle,
         completion_tx: tokio::sync::oneshot::Sender<bool>,
-    ) -> Result<(), TransportError> {
-        let master_conn = match tokio::time::timeout(
+    ) -> Result<Arc<SshTunnel>, TransportError> {
+        match tokio::time::timeout(
             Duration::from_secs(10),
             self.manager
                 .get_ssh_conn(&self.config, outbound, completion_tx),
         )
         .await
         {
-            Ok(Ok(conn)) => conn,
+            Ok(Ok(conn)) => Ok(conn),
             Ok(Err(e)) => {
                 tracing::trace!(
                     "Failed to establish SSH proxy connection to {}: {:?}",
                     self.config.server,
                     e
                 );
-                return Err(e);
+                Err(e)
             }
             Err(_) => {
                 tracing::trace!(
                     "Failed to establish SSH proxy connection to {}: timeout",
                     self.config.server
                 );
-                return Err(TransportError::Ssh(russh::Error::ConnectionTimeout));
+                Err(TransportError::Ssh(russh::Error::ConnectionTimeout))
             }
-        };
+        }
+    }
+
+    async fn attach_tcp(
+        self,
+        inbound: Connector,
+        outbound: Option<Box<dyn StreamOutboundTrait>>,
+        abort_handle: ConnAbortHandle,
+        completion_tx: tokio::sync::oneshot::Sender<bool>,
+    ) -> Result<(), TransportError> {
+        let master_conn = self.get_master_conn(outbound, completion_tx).await?;
         let channel = master_conn.new_mapped_connection(self.dst.clone()).await?;
         established_tcp(inbound, channel, abort_handle).await;
         Ok(())
     }
+
+    async fn attach_udp(
+        self,
+        inbound: AddrConnector,
+        outbound: Option<Box<dyn StreamOutboundTrait>>,
+        abort_handle: ConnAbortHandle,
+        completion_tx: tokio::sync::oneshot::Sender<bool>,
+        tunnel_only: bool,
+    ) -> Result<(), TransportError> {
+        let udpgw = self
+            .config
+            .udpgw
+            .clone()
+            .ok_or_else(|| io_err("SSH UDP requires udpgw"))?;
+        let master_conn = self.get_master_conn(outbound, completion_tx).await?;
+        let channel = master_conn.new_mapped_connection(udpgw).await?;
+        let tunnel_addr = if tunnel_only {
+            Some(self.dst.clone())
+        } else {
+            None
+        };
+        established_udp(
+            inbound,
+            UdpgwAdapter::new(channel, self.dns.clone()),
+            tunnel_addr,
+            abort_handle,
+        )
+        .await;
+        Ok(())
+    }
 }
 
 #[async_trait]
@@ -135,24 +173,56 @@ impl Outbound for SshOutboundHandle {
 
     fn spawn_udp(
         &self,
-        _inbound: AddrConnector,
-        _abort_handle: ConnAbortHandle,
-        _tunnel_only: bool,
+        inbound: AddrConnector,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
     ) -> JoinHandle<std::io::Result<()>> {
-        tracing::error!("spawn_udp() should not be called with SshOutbound");
-        empty_handle()
+        let (tx, _) = tokio::sync::oneshot::channel();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone
+                .attach_udp(inbound, None, abort_handle, tx, tunnel_only)
+                .await;
+            if let Err(e) = r {
+                abort_handle2.cancel();
+                return Err(io_err(format!("SSH UDP spawn error: {:?}", e).as_str()));
+            }
+            Ok(())
+        })
     }
 
     async fn spawn_udp_with_outbound(
         &self,
-        _inbound: AddrConnector,
-        _tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
-        _udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
-        _abort_handle: ConnAbortHandle,
-        _tunnel_only: bool,
+        inbound: AddrConnector,
+        tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
     ) -> std::io::Result<bool> {
-        tracing::error!("spawn_udp() should not be called with SshOutbound");
-        Err(io::ErrorKind::InvalidData.into())
+        if tcp_outbound.is_none() || udp_outbound.is_some() {
+            tracing::error!("Invalid SSH proxy udp spawn");
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        if self.config.udpgw.is_none() {
+            return Err(io_err("SSH UDP requires udpgw"));
+        }
+        let (comp_tx, comp_rx) = tokio::sync::oneshot::channel();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone
+                .attach_udp(inbound, tcp_outbound, abort_handle, comp_tx, tunnel_only)
+                .await;
+            if let Err(e) = r {
+                abort_handle2.cancel();
+                return Err(io_err(format!("SSH UDP spawn error: {:?}", e).as_str()));
+            }
+            Ok(())
+        });
+        comp_rx
+            .await
+            .map_err(|_| ErrorKind::ConnectionAborted.into())
     }
 }
 
@@ -198,7 +268,10 @@ impl SshManager {
                     None => {
                         let server_addr =
                             adapter::get_dst(&self.server_resolver, &config.server).await?;
-                        let stream = Egress::new(&self.iface).tcp_stream(server_addr).await?;
+                        let stream = Egress::new(&self.iface)
+                            .with_dial(&config.dial)
+                            .tcp_stream(server_addr)
+                            .await?;
                         SshTunnel::new(config, stream).await?
                     }
                 });
@@ -1,29 +1,23 @@
+use crate::adapter::udp_over_tcp::{spawn_uot, spawn_uot_with_outbound, uot_magic_addr};
 use crate::adapter::{
     established_tcp, established_udp, lookup, AddrConnector, Connector, Outbound, OutboundType,
 };
-use crate::common::async_ws_stream::AsyncWsStream;
-
-use crate::common::{as_io_err, io_err, StreamOutboundTrait};
+use crate::common::{io_err, StreamOutboundTrait};
 use crate::network::dns::Dns;
 use crate::network::egress::Egress;
 use crate::proxy::error::TransportError;
 use crate::proxy::{ConnAbortHandle, NetworkAddr};
 use crate::transport::trojan::{
-    encapsule_udp_packet, make_tls_config, TrojanAddr, TrojanCmd, TrojanConfig, TrojanReqInner,
-    TrojanRequest, TrojanUdpSocket,
+    encapsule_udp_packet, TrojanAddr, TrojanCmd, TrojanConfig, TrojanReqInner, TrojanRequest,
+    TrojanUdpSocket,
 };
-use crate::transport::UdpSocketAdapter;
+use crate::transport::{connect_tls, connect_websocket, UdpSocketAdapter};
 use async_trait::async_trait;
 use bytes::Bytes;
-use http::{StatusCode, Uri};
 use std::io;
 use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::task::JoinHandle;
-use tokio_rustls::client::TlsStream;
-use tokio_rustls::rustls::pki_types::ServerName;
-use tokio_rustls::TlsConnector;
-use tokio_tungstenite::client_async;
 
 #[derive(Clone)]
 pub struct TrojanOutbound {
@@ -52,13 +46,16 @@ impl TrojanOutbound {
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
     {
-        let mut stream = self.connect_proxy(outbound).await?;
+        let mut stream = connect_tls(
+            outbound,
+            &self.config.sni,
+            self.config.skip_cert_verify,
+            &[],
+        )
+        .await?;
         let first_packet = inbound.rx.recv().await.ok_or_else(|| io_err("No resp"))?;
         if let Some(ref uri) = self.config.websocket_path {
-            let mut stream = self
-                .with_websocket(stream, uri.as_str())
-                .await
-                .map_err(|e| io_err(e.to_string().as_str()))?;
+            let mut stream = connect_websocket(stream, &self.config.sni, uri, true).await?;
             self.first_packet(first_packet, TrojanCmd::Connect, &mut stream)
                 .await?;
             established_tcp(inbound, stream, abort_handle).await;
@@ -80,14 +77,17 @@ impl TrojanOutbound {
     where
         S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
     {
-        let mut stream = self.connect_proxy(outbound).await?;
+        let mut stream = connect_tls(
+            outbound,
+            &self.config.sni,
+            self.config.skip_cert_verify,
+            &[],
+        )
+        .await?;
         let (data, dst) = inbound.rx.recv().await.ok_or_else(|| io_err("No resp"))?;
         let first_packet = Bytes::from(encapsule_udp_packet(data.as_ref(), dst));
         if let Some(ref uri) = self.config.websocket_path {
-            let mut stream = self
-                .with_websocket(stream, uri.as_str())
-                .await
-                .map_err(|e| io_err(e.to_string().as_str()))?;
+            let mut stream = connect_websocket(stream, &self.config.sni, uri, true).await?;
             self.first_packet(first_packet, TrojanCmd::Associate, &mut stream)
                 .await?;
             let udp_socket = TrojanUdpSocket::bind(stream);
@@ -137,38 +137,6 @@ impl TrojanOutbound {
         stream.flush().await?;
         Ok(())
     }
-
-    async fn connect_proxy<S>(&self, outbound: S) -> io::Result<TlsStream<S>>
-    where
-        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
-    {
-        let server_name = ServerName::try_from(self.config.sni.as_str())
-            .map_err(as_io_err)?
-            .to_owned();
-        let tls_conn = TlsConnector::from(make_tls_config(self.config.skip_cert_verify));
-        let stream = tls_conn.connect(server_name, outbound).await?;
-        Ok(stream)
-    }
-
-    async fn with_websocket<S: AsyncRead + AsyncWrite + Unpin + Send + Sync>(
-        &self,
-        stream: S,
-        path: &str,
-    ) -> Result<AsyncWsStream<S>, TransportError> {
-        let uri = Uri::builder()
-            .scheme("wss")
-            .authority(self.config.sni.as_str())
-            .path_and_query(path)
-            .build()
-            .map_err(|_| TransportError::Trojan("Invalid wss uri"))?;
-        let (stream, resp) = client_async(uri, stream)
-            .await
-            .map_err(|_| TransportError::Trojan("Websocket client_sync failed"))?;
-        if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
-            return Err(TransportError::Trojan("Websocket upgrade failed"));
-        }
-        Ok(AsyncWsStream::new(stream))
-    }
 }
 
 #[async_trait]
@@ -187,6 +155,7 @@ impl Outbound for TrojanOutbound {
             let server_addr =
                 lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
             let tcp_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
                 .tcp_stream(server_addr)
                 .await?;
             self_clone.run_tcp(inbound, tcp_conn, abort_handle).await
@@ -219,11 +188,17 @@ impl Outbound for TrojanOutbound {
         abort_handle: ConnAbortHandle,
         tunnel_only: bool,
     ) -> JoinHandle<io::Result<()>> {
+        if self.config.udp_over_tcp {
+            let mut uot = self.clone();
+            uot.dst = uot_magic_addr();
+            return spawn_uot(&uot, inbound, self.dst.clone(), abort_handle, tunnel_only);
+        }
         let self_clone = self.clone();
         tokio::spawn(async move {
             let server_addr =
                 lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
             let tcp_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
                 .tcp_stream(server_addr)
                 .await?;
             self_clone
@@ -245,6 +220,19 @@ impl Outbound for TrojanOutbound {
             return Err(io::ErrorKind::InvalidData.into());
         }
         let tcp_outbound = tcp_outbound.unwrap();
+        if self.config.udp_over_tcp {
+            let mut uot = self.clone();
+            uot.dst = uot_magic_addr();
+            return spawn_uot_with_outbound(
+                &uot,
+                tcp_outbound,
+                inbound,
+                self.dst.clone(),
+                abort_handle,
+                tunnel_only,
+            )
+            .await;
+        }
         let self_clone = self.clone();
         tokio::spawn(async move {
             self_clone
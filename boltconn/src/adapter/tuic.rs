@@ -0,0 +1,268 @@
+use crate::adapter::{
+    established_tcp, established_udp, get_dst, AddrConnector, Connector, Outbound, OutboundType,
+};
+use crate::common::conn_pool::SharedConnPool;
+use crate::common::{io_err, StreamOutboundTrait};
+use crate::network::dns::Dns;
+use crate::network::egress::Egress;
+use crate::proxy::error::TransportError;
+use crate::proxy::{ConnAbortHandle, NetworkAddr};
+use crate::transport::quic_socket::QuicAdapterSocket;
+use crate::transport::tuic::{TuicConfig, TuicConnection, TuicUdpSession};
+use crate::transport::UdpSocketAdapter;
+use async_trait::async_trait;
+use quinn::{Endpoint, EndpointConfig, TokioRuntime};
+use std::io;
+use std::io::ErrorKind;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinHandle;
+
+#[derive(Clone)]
+pub struct TuicOutbound {
+    dst: NetworkAddr,
+    config: TuicConfig,
+    manager: Arc<TuicManager>,
+}
+
+impl TuicOutbound {
+    pub fn new(dst: NetworkAddr, config: TuicConfig, manager: Arc<TuicManager>) -> Self {
+        Self {
+            dst,
+            config,
+            manager,
+        }
+    }
+
+    async fn attach_tcp(
+        self,
+        inbound: Connector,
+        outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+        completion_tx: tokio::sync::oneshot::Sender<bool>,
+    ) -> Result<(), TransportError> {
+        let conn = self
+            .manager
+            .get_tuic_conn(&self.config, outbound, completion_tx)
+            .await?;
+        let stream = conn.open_tcp(&self.dst).await?;
+        established_tcp(inbound, stream, abort_handle).await;
+        Ok(())
+    }
+
+    async fn attach_udp(
+        self,
+        inbound: AddrConnector,
+        outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+        completion_tx: tokio::sync::oneshot::Sender<bool>,
+        tunnel_only: bool,
+    ) -> Result<(), TransportError> {
+        let conn = self
+            .manager
+            .get_tuic_conn(&self.config, outbound, completion_tx)
+            .await?;
+        let tunnel_addr = if tunnel_only {
+            Some(self.dst.clone())
+        } else {
+            None
+        };
+        established_udp(
+            inbound,
+            TuicUdpAdapter {
+                session: conn.open_udp()?,
+            },
+            tunnel_addr,
+            abort_handle,
+        )
+        .await;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl Outbound for TuicOutbound {
+    fn outbound_type(&self) -> OutboundType {
+        OutboundType::Tuic
+    }
+
+    fn spawn_tcp(
+        &self,
+        inbound: Connector,
+        abort_handle: ConnAbortHandle,
+    ) -> JoinHandle<io::Result<()>> {
+        let (tx, _) = tokio::sync::oneshot::channel();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone.attach_tcp(inbound, None, abort_handle, tx).await;
+            if let Err(e) = r {
+                abort_handle2.cancel();
+                return Err(io_err(format!("TUIC TCP spawn error: {:?}", e).as_str()));
+            }
+            Ok(())
+        })
+    }
+
+    async fn spawn_tcp_with_outbound(
+        &self,
+        inbound: Connector,
+        tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<bool> {
+        if tcp_outbound.is_some() || udp_outbound.is_none() {
+            tracing::error!("Invalid TUIC TCP outbound ancestor");
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let (comp_tx, comp_rx) = tokio::sync::oneshot::channel();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone
+                .attach_tcp(inbound, udp_outbound, abort_handle, comp_tx)
+                .await;
+            if let Err(e) = r {
+                abort_handle2.cancel();
+                return Err(io_err(format!("TUIC TCP spawn error: {:?}", e).as_str()));
+            }
+            Ok(())
+        });
+        comp_rx
+            .await
+            .map_err(|_| ErrorKind::ConnectionAborted.into())
+    }
+
+    fn spawn_udp(
+        &self,
+        inbound: AddrConnector,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
+    ) -> JoinHandle<io::Result<()>> {
+        let (tx, _) = tokio::sync::oneshot::channel();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            if !self_clone.config.udp {
+                return Err(io_err("TUIC UDP is disabled"));
+            }
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone
+                .attach_udp(inbound, None, abort_handle, tx, tunnel_only)
+                .await;
+            if let Err(e) = r {
+                abort_handle2.cancel();
+                return Err(io_err(format!("TUIC UDP spawn error: {:?}", e).as_str()));
+            }
+            Ok(())
+        })
+    }
+
+    async fn spawn_udp_with_outbound(
+        &self,
+        inbound: AddrConnector,
+        tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+        tunnel_only: bool,
+    ) -> io::Result<bool> {
+        if tcp_outbound.is_some() || udp_outbound.is_none() {
+            tracing::error!("Invalid TUIC UDP outbound ancestor");
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        if !self.config.udp {
+            return Err(io_err("TUIC UDP is disabled"));
+        }
+        let (comp_tx, comp_rx) = tokio::sync::oneshot::channel();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let abort_handle2 = abort_handle.clone();
+            let r = self_clone
+                .attach_udp(inbound, udp_outbound, abort_handle, comp_tx, tunnel_only)
+                .await;
+            if let Err(e) = r {
+                abort_handle2.cancel();
+                return Err(io_err(format!("TUIC UDP spawn error: {:?}", e).as_str()));
+            }
+            Ok(())
+        });
+        comp_rx
+            .await
+            .map_err(|_| ErrorKind::ConnectionAborted.into())
+    }
+}
+
+struct TuicUdpAdapter {
+    session: TuicUdpSession,
+}
+
+#[async_trait]
+impl UdpSocketAdapter for TuicUdpAdapter {
+    async fn send_to(&self, data: &[u8], addr: NetworkAddr) -> Result<(), TransportError> {
+        self.session.send_to(data, &addr).await
+    }
+
+    async fn recv_from(&self, data: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        self.session.recv_from(data).await
+    }
+}
+
+pub struct TuicManager {
+    iface: String,
+    active_conn: SharedConnPool<TuicConfig, Arc<TuicConnection>>,
+    server_resolver: Arc<Dns>,
+    timeout: Duration,
+}
+
+impl TuicManager {
+    pub fn new(iface: &str, dns: Arc<Dns>, timeout: Duration) -> Self {
+        Self {
+            iface: iface.to_string(),
+            active_conn: Default::default(),
+            server_resolver: dns,
+            timeout,
+        }
+    }
+
+    pub async fn get_tuic_conn(
+        &self,
+        config: &TuicConfig,
+        next_step: Option<Box<dyn UdpSocketAdapter>>,
+        ret_tx: tokio::sync::oneshot::Sender<bool>,
+    ) -> Result<Arc<TuicConnection>, TransportError> {
+        if let Some(conn) = self.active_conn.get(config).await {
+            let _ = ret_tx.send(false);
+            return Ok(conn);
+        }
+        let _ = ret_tx.send(true);
+        let server_addr = get_dst(&self.server_resolver, &config.server_addr).await?;
+        let endpoint = match next_step {
+            Some(next_step) => Endpoint::new_with_abstract_socket(
+                EndpointConfig::default(),
+                None,
+                QuicAdapterSocket::new(next_step, server_addr),
+                Arc::new(TokioRuntime),
+            )?,
+            None => {
+                let egress = Egress::new(&self.iface).with_dial(&config.dial);
+                let socket = match server_addr {
+                    SocketAddr::V4(_) => egress.udpv4_socket().await?,
+                    SocketAddr::V6(_) => egress.udpv6_socket().await?,
+                };
+                Endpoint::new(
+                    EndpointConfig::default(),
+                    None,
+                    socket.into_std()?,
+                    Arc::new(TokioRuntime),
+                )?
+            }
+        };
+        let conn = tokio::time::timeout(
+            self.timeout,
+            TuicConnection::connect(config, endpoint, server_addr),
+        )
+        .await
+        .map_err(|_| TransportError::Tuic("Connection timeout"))??;
+        Ok(self.active_conn.insert(config, Arc::new(conn)).await)
+    }
+}
@@ -1,12 +1,16 @@
+use crate::adapter::{established_udp, AddrConnector, Connector, Outbound};
+use crate::common::duplex_chan::DuplexChan;
+use crate::common::{io_err, StreamOutboundTrait};
 use crate::proxy::error::TransportError;
-use crate::proxy::NetworkAddr;
+use crate::proxy::{ConnAbortHandle, NetworkAddr};
 use crate::transport::UdpSocketAdapter;
 use async_trait::async_trait;
 use std::io;
-use std::net::SocketAddr;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 use tokio::net::{tcp, TcpStream};
 use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
 
 pub(super) struct UdpOverTcpAdapter {
     reader: Mutex<tcp::OwnedReadHalf>,
@@ -50,3 +54,234 @@ impl UdpSocketAdapter for UdpOverTcpAdapter {
         Ok((len, NetworkAddr::Raw(self.address)))
     }
 }
+
+const UOT_MAGIC_ADDRESS: &str = "sp.v2.udp-over-tcp.arpa";
+
+/// Destination to dial through the proxy for a sing-box UoT v2 session.
+pub(super) fn uot_magic_addr() -> NetworkAddr {
+    NetworkAddr::DomainName {
+        domain_name: UOT_MAGIC_ADDRESS.to_string(),
+        port: 0,
+    }
+}
+
+// (ipv4, ipv6, domain) address types
+const SOCKS_ADDR_TYPE: (u8, u8, u8) = (0x01, 0x04, 0x03);
+const UOT_ADDR_TYPE: (u8, u8, u8) = (0x00, 0x01, 0x02);
+
+fn encode_addr(buf: &mut Vec<u8>, addr: &NetworkAddr, addr_type: (u8, u8, u8)) -> io::Result<()> {
+    match addr {
+        NetworkAddr::Raw(SocketAddr::V4(v4)) => {
+            buf.push(addr_type.0);
+            buf.extend_from_slice(&v4.ip().octets());
+        }
+        NetworkAddr::Raw(SocketAddr::V6(v6)) => {
+            buf.push(addr_type.1);
+            buf.extend_from_slice(&v6.ip().octets());
+        }
+        NetworkAddr::DomainName { domain_name, .. } => {
+            let len = u8::try_from(domain_name.len()).map_err(|_| io_err("Domain too long"))?;
+            buf.push(addr_type.2);
+            buf.push(len);
+            buf.extend_from_slice(domain_name.as_bytes());
+        }
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+    Ok(())
+}
+
+async fn decode_addr<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<NetworkAddr> {
+    let addr_type = reader.read_u8().await?;
+    let addr = if addr_type == UOT_ADDR_TYPE.0 {
+        let mut ip = [0u8; 4];
+        reader.read_exact(&mut ip).await?;
+        let port = reader.read_u16().await?;
+        NetworkAddr::Raw(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+    } else if addr_type == UOT_ADDR_TYPE.1 {
+        let mut ip = [0u8; 16];
+        reader.read_exact(&mut ip).await?;
+        let port = reader.read_u16().await?;
+        NetworkAddr::Raw(SocketAddr::new(Ipv6Addr::from(ip).into(), port))
+    } else if addr_type == UOT_ADDR_TYPE.2 {
+        let mut domain = vec![0u8; reader.read_u8().await? as usize];
+        reader.read_exact(&mut domain).await?;
+        let port = reader.read_u16().await?;
+        NetworkAddr::DomainName {
+            domain_name: String::from_utf8(domain).map_err(|_| io_err("Invalid domain"))?,
+            port,
+        }
+    } else {
+        return Err(io_err("Unknown UoT address type"));
+    };
+    Ok(addr)
+}
+
+/// UDP packets over a proxied TCP stream, in the sing-box UoT v2 non-connect format.
+pub(super) struct UotV2Adapter<S> {
+    reader: Mutex<ReadHalf<S>>,
+    writer: Mutex<WriteHalf<S>>,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> UotV2Adapter<S> {
+    pub async fn new(mut stream: S, dst: &NetworkAddr) -> io::Result<Self> {
+        // isConnect = false, so that each packet carries its own destination
+        let mut req = vec![0u8];
+        encode_addr(&mut req, dst, SOCKS_ADDR_TYPE)?;
+        stream.write_all(req.as_slice()).await?;
+        let (read_half, write_half) = tokio::io::split(stream);
+        Ok(Self {
+            reader: Mutex::new(read_half),
+            writer: Mutex::new(write_half),
+        })
+    }
+}
+
+#[async_trait]
+impl<S: AsyncRead + AsyncWrite + Unpin + Send> UdpSocketAdapter for UotV2Adapter<S> {
+    async fn send_to(&self, data: &[u8], addr: NetworkAddr) -> Result<(), TransportError> {
+        let len = u16::try_from(data.len())
+            .map_err(|_| TransportError::Internal("UDP-over-TCP exceeded u16::size"))?;
+        let mut buf = Vec::with_capacity(data.len() + 32);
+        encode_addr(&mut buf, &addr, UOT_ADDR_TYPE)?;
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(data);
+        let mut writer = self.writer.lock().await;
+        writer.write_all(buf.as_slice()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn recv_from(&self, data: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        let mut reader = self.reader.lock().await;
+        let addr = decode_addr(&mut *reader).await?;
+        let len = reader.read_u16().await? as usize;
+        if data.len() < len {
+            // drop the packet, so that the next one is read from its address
+            tokio::io::copy(&mut (&mut *reader).take(len as u64), &mut tokio::io::sink()).await?;
+            return Err(TransportError::Internal("UDP-over-TCP buffer too small"));
+        }
+        reader.read_exact(&mut data[0..len]).await?;
+        Ok((len, addr))
+    }
+}
+
+/// Relay UDP of `inbound` through a TCP connection opened by `uot_outbound`,
+/// whose destination must be the UoT magic address.
+pub(super) fn spawn_uot(
+    uot_outbound: &dyn Outbound,
+    inbound: AddrConnector,
+    dst: NetworkAddr,
+    abort_handle: ConnAbortHandle,
+    tunnel_only: bool,
+) -> JoinHandle<io::Result<()>> {
+    let (inner, outer) = Connector::new_pair(10);
+    let _handle = uot_outbound.spawn_tcp(inner, abort_handle.clone());
+    tokio::spawn(run_uot(
+        inbound,
+        DuplexChan::new(outer),
+        dst,
+        abort_handle,
+        tunnel_only,
+    ))
+}
+
+/// Same as `spawn_uot`, with the TCP connection built upon a chained outbound.
+pub(super) async fn spawn_uot_with_outbound(
+    uot_outbound: &dyn Outbound,
+    tcp_outbound: Box<dyn StreamOutboundTrait>,
+    inbound: AddrConnector,
+    dst: NetworkAddr,
+    abort_handle: ConnAbortHandle,
+    tunnel_only: bool,
+) -> io::Result<bool> {
+    let (inner, outer) = Connector::new_pair(10);
+    let ret = uot_outbound
+        .spawn_tcp_with_outbound(inner, Some(tcp_outbound), None, abort_handle.clone())
+        .await?;
+    tokio::spawn(run_uot(
+        inbound,
+        DuplexChan::new(outer),
+        dst,
+        abort_handle,
+        tunnel_only,
+    ));
+    Ok(ret)
+}
+
+async fn run_uot<S>(
+    inbound: AddrConnector,
+    stream: S,
+    dst: NetworkAddr,
+    abort_handle: ConnAbortHandle,
+    tunnel_only: bool,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let adapter = UotV2Adapter::new(stream, &dst).await?;
+    established_udp(
+        inbound,
+        adapter,
+        if tunnel_only { Some(dst) } else { None },
+        abort_handle,
+    )
+    .await;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_uot_v2() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let dst = NetworkAddr::Raw("1.1.1.1:53".parse().unwrap());
+        let adapter = UotV2Adapter::new(client, &dst).await.unwrap();
+        let mut header = [0u8; 8];
+        server.read_exact(&mut header).await.unwrap();
+        assert_eq!(header, [0x00, 0x01, 1, 1, 1, 1, 0, 53]);
+
+        let domain = NetworkAddr::DomainName {
+            domain_name: "example.com".to_string(),
+            port: 443,
+        };
+        adapter.send_to(b"ping", domain.clone()).await.unwrap();
+        let mut packet = [0u8; 21];
+        server.read_exact(&mut packet).await.unwrap();
+        assert_eq!(&packet[..2], &[0x02, 11]);
+        assert_eq!(&packet[2..13], b"example.com");
+        assert_eq!(&packet[13..], &[1, 187, 0, 4, b'p', b'i', b'n', b'g']);
+
+        server
+            .write_all(&[0x00, 8, 8, 8, 8, 0, 53, 0, 4, b'p', b'o', b'n', b'g'])
+            .await
+            .unwrap();
+        let mut buf = [0u8; 64];
+        let (len, addr) = adapter.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"pong");
+        assert_eq!(addr, NetworkAddr::Raw("8.8.8.8:53".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_uot_v2_oversized() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let dst = NetworkAddr::Raw("1.1.1.1:53".parse().unwrap());
+        let adapter = UotV2Adapter::new(client, &dst).await.unwrap();
+        server
+            .write_all(&[
+                0x00, 8, 8, 8, 8, 0, 53, 0, 8, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42, 0x42,
+            ])
+            .await
+            .unwrap();
+        server
+            .write_all(&[0x00, 8, 8, 4, 4, 0, 53, 0, 2, b'o', b'k'])
+            .await
+            .unwrap();
+        let mut buf = [0u8; 4];
+        assert!(adapter.recv_from(&mut buf).await.is_err());
+        let (len, addr) = adapter.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ok");
+        assert_eq!(addr, NetworkAddr::Raw("8.8.4.4:53".parse().unwrap()));
+    }
+}
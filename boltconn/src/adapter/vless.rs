@@ -0,0 +1,268 @@
+use crate::adapter::{
+    established_tcp, established_udp, lookup, AddrConnector, Connector, Outbound, OutboundType,
+};
+use crate::common::{as_io_err, io_err, StreamOutboundTrait};
+use crate::network::dns::Dns;
+use crate::network::egress::Egress;
+use crate::proxy::error::TransportError;
+use crate::proxy::{ConnAbortHandle, NetworkAddr};
+use crate::transport::vless::{VlessCmd, VlessConfig, VlessStream, VlessUdpSocket};
+use crate::transport::{connect_tls, connect_websocket, UdpSocketAdapter};
+use async_trait::async_trait;
+use std::io;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use tokio::task::JoinHandle;
+
+#[derive(Clone)]
+pub struct VlessOutbound {
+    iface_name: String,
+    dst: NetworkAddr,
+    dns: Arc<Dns>,
+    config: VlessConfig,
+}
+
+impl VlessOutbound {
+    pub fn new(iface_name: &str, dst: NetworkAddr, dns: Arc<Dns>, config: VlessConfig) -> Self {
+        Self {
+            iface_name: iface_name.to_string(),
+            dst,
+            dns,
+            config,
+        }
+    }
+
+    async fn run_tcp<S>(
+        self,
+        inbound: Connector,
+        outbound: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        if self.config.tls {
+            let stream = connect_tls(
+                outbound,
+                &self.config.sni,
+                self.config.skip_cert_verify,
+                &[],
+            )
+            .await?;
+            self.run_tcp_with_transport(inbound, stream, abort_handle)
+                .await
+        } else {
+            self.run_tcp_with_transport(inbound, outbound, abort_handle)
+                .await
+        }
+    }
+
+    async fn run_tcp_with_transport<S>(
+        &self,
+        inbound: Connector,
+        stream: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        if let Some(ref path) = self.config.websocket_path {
+            let stream = connect_websocket(stream, &self.config.sni, path, self.config.tls).await?;
+            self.proxy_tcp(inbound, stream, abort_handle).await
+        } else {
+            self.proxy_tcp(inbound, stream, abort_handle).await
+        }
+    }
+
+    async fn proxy_tcp<S>(
+        &self,
+        mut inbound: Connector,
+        stream: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        let first_packet = inbound.rx.recv().await.ok_or_else(|| io_err("No resp"))?;
+        let mut stream = self.vless_stream(stream, VlessCmd::Tcp);
+        stream.write_all(first_packet.as_ref()).await?;
+        stream.flush().await?;
+        established_tcp(inbound, stream, abort_handle).await;
+        Ok(())
+    }
+
+    async fn run_udp<S>(
+        self,
+        inbound: AddrConnector,
+        outbound: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        if self.config.tls {
+            let stream = connect_tls(
+                outbound,
+                &self.config.sni,
+                self.config.skip_cert_verify,
+                &[],
+            )
+            .await?;
+            self.run_udp_with_transport(inbound, stream, abort_handle)
+                .await
+        } else {
+            self.run_udp_with_transport(inbound, outbound, abort_handle)
+                .await
+        }
+    }
+
+    async fn run_udp_with_transport<S>(
+        &self,
+        inbound: AddrConnector,
+        stream: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        if let Some(ref path) = self.config.websocket_path {
+            let stream = connect_websocket(stream, &self.config.sni, path, self.config.tls).await?;
+            self.proxy_udp(inbound, stream, abort_handle).await
+        } else {
+            self.proxy_udp(inbound, stream, abort_handle).await
+        }
+    }
+
+    async fn proxy_udp<S>(
+        &self,
+        mut inbound: AddrConnector,
+        stream: S,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static,
+    {
+        let (first_packet, _) = inbound.rx.recv().await.ok_or_else(|| io_err("No resp"))?;
+        let stream = self.vless_stream(stream, VlessCmd::Udp);
+        let socket = VlessUdpSocket::bind(stream, self.dst.clone());
+        socket
+            .send(first_packet.as_ref())
+            .await
+            .map_err(as_io_err)?;
+        let adapter = VlessUdpAdapter {
+            socket: Arc::new(socket),
+        };
+        // VLESS UDP session is bound to the destination, so it is always a tunnel
+        established_udp(inbound, adapter, Some(self.dst.clone()), abort_handle).await;
+        Ok(())
+    }
+
+    fn vless_stream<S>(&self, stream: S, cmd: VlessCmd) -> VlessStream<S>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        VlessStream::new(stream, &self.config.uuid, cmd, &self.dst)
+    }
+}
+
+#[async_trait]
+impl Outbound for VlessOutbound {
+    fn outbound_type(&self) -> OutboundType {
+        OutboundType::Vless
+    }
+
+    fn spawn_tcp(
+        &self,
+        inbound: Connector,
+        abort_handle: ConnAbortHandle,
+    ) -> JoinHandle<io::Result<()>> {
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let server_addr =
+                lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
+            let tcp_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
+                .tcp_stream(server_addr)
+                .await?;
+            self_clone.run_tcp(inbound, tcp_conn, abort_handle).await
+        })
+    }
+
+    async fn spawn_tcp_with_outbound(
+        &self,
+        inbound: Connector,
+        tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+    ) -> io::Result<bool> {
+        if tcp_outbound.is_none() || udp_outbound.is_some() {
+            tracing::error!("Invalid VLESS UDP outbound ancestor");
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            self_clone
+                .run_tcp(inbound, tcp_outbound.unwrap(), abort_handle)
+                .await
+        });
+        Ok(true)
+    }
+
+    fn spawn_udp(
+        &self,
+        inbound: AddrConnector,
+        abort_handle: ConnAbortHandle,
+        _tunnel_only: bool,
+    ) -> JoinHandle<io::Result<()>> {
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            let server_addr =
+                lookup(self_clone.dns.as_ref(), &self_clone.config.server_addr).await?;
+            let tcp_conn = Egress::new(&self_clone.iface_name)
+                .with_dial(&self_clone.config.dial)
+                .tcp_stream(server_addr)
+                .await?;
+            self_clone.run_udp(inbound, tcp_conn, abort_handle).await
+        })
+    }
+
+    async fn spawn_udp_with_outbound(
+        &self,
+        inbound: AddrConnector,
+        tcp_outbound: Option<Box<dyn StreamOutboundTrait>>,
+        udp_outbound: Option<Box<dyn UdpSocketAdapter>>,
+        abort_handle: ConnAbortHandle,
+        _tunnel_only: bool,
+    ) -> io::Result<bool> {
+        if tcp_outbound.is_none() || udp_outbound.is_some() {
+            tracing::error!("Invalid VLESS UDP outbound ancestor");
+            return Err(io::ErrorKind::InvalidData.into());
+        }
+        let tcp_outbound = tcp_outbound.unwrap();
+        let self_clone = self.clone();
+        tokio::spawn(async move {
+            self_clone
+                .run_udp(inbound, tcp_outbound, abort_handle)
+                .await
+        });
+        Ok(true)
+    }
+}
+
+struct VlessUdpAdapter<S: AsyncRead + AsyncWrite + Unpin> {
+    socket: Arc<VlessUdpSocket<S>>,
+}
+
+#[async_trait]
+impl<S> UdpSocketAdapter for VlessUdpAdapter<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send,
+{
+    async fn send_to(&self, data: &[u8], _addr: NetworkAddr) -> Result<(), TransportError> {
+        self.socket.send(data).await
+    }
+
+    async fn recv_from(&self, data: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        self.socket.recv_from(data).await
+    }
+}
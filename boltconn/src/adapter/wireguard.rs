@@ -9,7 +9,7 @@ use crate::network::egress::Egress;
 use crate::proxy::error::TransportError;
 use crate::proxy::{ConnAbortHandle, NetworkAddr};
 use crate::transport::smol::{SmolDnsProvider, SmolStack, VirtualIpDevice};
-use crate::transport::wireguard::{WireguardConfig, WireguardTunnel};
+use crate::transport::wireguard::{WireguardConfig, WireguardPeerTable, WireguardTunnel};
 use crate::transport::{AdapterOrSocket, InterfaceAddress, UdpSocketAdapter};
 use async_trait::async_trait;
 use bytes::Bytes;
@@ -31,7 +31,7 @@ use tokio::task::JoinHandle;
 
 // Shared Wireguard Tunnel between multiple client connections
 pub struct Endpoint {
-    wg: Arc<WireguardTunnel>,
+    peers: Arc<WireguardPeerTable>,
     stack: Arc<Mutex<SmolStack>>,
     stop_sender: broadcast::Sender<()>,
     notify: Arc<Notify>,
@@ -40,7 +40,7 @@ pub struct Endpoint {
 
 impl Endpoint {
     pub async fn new(
-        outbound: AdapterOrSocket,
+        outbounds: Vec<AdapterOrSocket>,
         config: &WireguardConfig,
         endpoint_resolver: Arc<Dns>,
         timeout: Duration,
@@ -50,11 +50,23 @@ impl Endpoint {
         // control conn
         let (stop_send, mut stop_recv) = broadcast::channel(1);
 
-        let (mut wg_smol_tx, wg_smol_rx) = flume::bounded(4096);
+        let (wg_smol_tx, wg_smol_rx) = flume::bounded(4096);
         let (smol_wg_tx, mut smol_wg_rx) = flume::unbounded();
-        let tunnel = Arc::new(
-            WireguardTunnel::new(outbound, config, endpoint_resolver, notify.clone()).await?,
-        );
+        let mut tunnels = Vec::with_capacity(config.peers.len());
+        for (idx, (outbound, peer)) in outbounds.into_iter().zip(config.peers.iter()).enumerate() {
+            tunnels.push(Arc::new(
+                WireguardTunnel::new(
+                    outbound,
+                    &config.private_key,
+                    peer,
+                    13 + idx as u32,
+                    endpoint_resolver.clone(),
+                    notify.clone(),
+                )
+                .await?,
+            ));
+        }
+        let peers = Arc::new(WireguardPeerTable::new(tunnels));
         let device = VirtualIpDevice::new(config.mtu, wg_smol_rx, smol_wg_tx);
         let smol_stack = {
             let iface =
@@ -90,13 +102,13 @@ impl Endpoint {
 
         // drive wg tunnel
         let wg_out = {
-            let tunnel = tunnel.clone();
+            let peers = peers.clone();
             let stop_send = stop_send.clone();
             let timer = last_active.clone();
             tokio::spawn(async move {
                 let mut buf = [0u8; MAX_PKT_SIZE];
                 loop {
-                    if tunnel
+                    if peers
                         .send_outgoing_packet(&mut smol_wg_rx, &mut buf)
                         .await
                         .is_err()
@@ -109,60 +121,71 @@ impl Endpoint {
             })
         };
 
-        let wg_in = {
-            let tunnel = tunnel.clone();
-            let stop_send = stop_send.clone();
-            let timer = last_active.clone();
-            tokio::spawn(async move {
-                let mut buf = [0u8; MAX_PKT_SIZE];
-                let mut wg_buf = [0u8; MAX_PKT_SIZE];
-                loop {
-                    match tunnel
-                        .receive_incoming_packet(&mut wg_smol_tx, &mut buf, &mut wg_buf)
-                        .await
-                    {
-                        Ok(true) => *timer.lock().await = Instant::now(),
-                        Ok(false) => {}
-                        Err(_) => {
-                            let _ = stop_send.send(());
-                            return;
-                        }
-                    }
-                }
-            })
-        };
-
-        let wg_tick = {
-            let tunnel = tunnel.clone();
-            let stop_send = stop_send.clone();
-            let name = config.name.clone();
-            tokio::spawn(async move {
-                let mut buf = [0u8; MAX_PKT_SIZE];
-                let mut continuous_err_cnt = 0;
-                loop {
-                    match tunnel.tick(&mut buf).await {
-                        Err(e) => {
-                            continuous_err_cnt += 1;
-                            if continuous_err_cnt >= 2 {
-                                // Stop the current WireGuard connection
+        let mut peer_tasks = Vec::with_capacity(peers.peers().len() * 2);
+        for tunnel in peers.peers() {
+            let wg_in = {
+                let tunnel = tunnel.clone();
+                let stop_send = stop_send.clone();
+                let timer = last_active.clone();
+                let mut wg_smol_tx = wg_smol_tx.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; MAX_PKT_SIZE];
+                    let mut wg_buf = [0u8; MAX_PKT_SIZE];
+                    loop {
+                        match tunnel
+                            .receive_incoming_packet(&mut wg_smol_tx, &mut buf, &mut wg_buf)
+                            .await
+                        {
+                            Ok(true) => *timer.lock().await = Instant::now(),
+                            Ok(false) => {}
+                            Err(_) => {
                                 let _ = stop_send.send(());
-                                tracing::warn!("[WireGuard] Close connection #{} for {}", name, e);
                                 return;
                             }
-                            tokio::time::sleep(Duration::from_millis(300)).await;
                         }
-                        Ok(has_sent) => {
-                            if has_sent {
-                                continuous_err_cnt = 0;
+                    }
+                })
+            };
+
+            let wg_tick = {
+                let tunnel = tunnel.clone();
+                let stop_send = stop_send.clone();
+                let name = config.name.clone();
+                tokio::spawn(async move {
+                    let mut buf = [0u8; MAX_PKT_SIZE];
+                    let mut continuous_err_cnt = 0;
+                    loop {
+                        match tunnel.tick(&mut buf).await {
+                            Err(e) => {
+                                continuous_err_cnt += 1;
+                                if continuous_err_cnt >= 2 {
+                                    // Stop the current WireGuard connection
+                                    let _ = stop_send.send(());
+                                    tracing::warn!(
+                                        "[WireGuard] Close connection #{} for {}",
+                                        name,
+                                        e
+                                    );
+                                    return;
+                                }
+                                tokio::time::sleep(Duration::from_millis(300)).await;
+                            }
+                            Ok(has_sent) => {
+                                if has_sent {
+                                    continuous_err_cnt = 0;
+                                }
+                                // <del>From boringtun, the recommended interval is 100ms.</del>
+                                // Comments from Tunn::update_timers says one second interval is enough.
+                                tokio::time::sleep(Duration::from_millis(1000)).await;
                             }
-                            // <del>From boringtun, the recommended interval is 100ms.</del>
-                            // Comments from Tunn::update_timers says one second interval is enough.
-                            tokio::time::sleep(Duration::from_millis(1000)).await;
                         }
                     }
-                }
-            })
-        };
+                })
+            };
+            peer_tasks.push(wg_in);
+            peer_tasks.push(wg_tick);
+        }
+        drop(wg_smol_tx);
 
         // drive smol
         let smol_drive = {
@@ -227,13 +250,14 @@ impl Endpoint {
             let _ = stop_recv.recv().await;
             indi_write.store(false, Ordering::Relaxed);
             wg_out.abort();
-            wg_in.abort();
-            wg_tick.abort();
+            for task in peer_tasks {
+                task.abort();
+            }
             smol_drive.abort();
         });
 
         Ok(Arc::new(Self {
-            wg: tunnel,
+            peers,
             stack: smol_stack,
             stop_sender: stop_send,
             notify,
@@ -283,35 +307,27 @@ impl WireguardManager {
                 }
             } else {
                 let _ = ret_tx.send(true);
-                let server_addr =
-                    adapter::get_dst(&self.endpoint_resolver, &config.endpoint).await?;
-                let outbound = match adapter {
-                    Some(a) => a,
+                let outbounds = match adapter {
+                    Some(a) => {
+                        if config.peers.len() > 1 {
+                            return Err(TransportError::WireGuard(
+                                "WireGuard with multiple peers cannot be chained",
+                            ));
+                        }
+                        vec![a]
+                    }
                     None => {
-                        if config.over_tcp {
-                            let stream = Egress::new(&self.iface).tcp_stream(server_addr).await?;
-                            AdapterOrSocket::Adapter(Arc::new(UdpOverTcpAdapter::new(
-                                stream,
-                                server_addr,
-                            )?))
-                        } else {
-                            AdapterOrSocket::Socket(match server_addr {
-                                SocketAddr::V4(_) => {
-                                    let socket = Egress::new(&self.iface).udpv4_socket().await?;
-                                    socket.connect(server_addr).await?;
-                                    socket
-                                }
-                                SocketAddr::V6(_) => {
-                                    let socket = Egress::new(&self.iface).udpv6_socket().await?;
-                                    socket.connect(server_addr).await?;
-                                    socket
-                                }
-                            })
+                        let mut outbounds = Vec::with_capacity(config.peers.len());
+                        for peer in &config.peers {
+                            let server_addr =
+                                adapter::get_dst(&self.endpoint_resolver, &peer.endpoint).await?;
+                            outbounds.push(self.create_outbound(config, server_addr).await?);
                         }
+                        outbounds
                     }
                 };
                 let ep = Endpoint::new(
-                    outbound,
+                    outbounds,
                     config,
                     self.endpoint_resolver.clone(),
                     self.timeout,
@@ -325,6 +341,39 @@ impl WireguardManager {
             "get_wg_conn: unexpected loop time",
         ))
     }
+
+    async fn create_outbound(
+        &self,
+        config: &WireguardConfig,
+        server_addr: SocketAddr,
+    ) -> Result<AdapterOrSocket, TransportError> {
+        Ok(if config.over_tcp {
+            let stream = Egress::new(&self.iface)
+                .with_dial(&config.dial)
+                .tcp_stream(server_addr)
+                .await?;
+            AdapterOrSocket::Adapter(Arc::new(UdpOverTcpAdapter::new(stream, server_addr)?))
+        } else {
+            AdapterOrSocket::Socket(match server_addr {
+                SocketAddr::V4(_) => {
+                    let socket = Egress::new(&self.iface)
+                        .with_dial(&config.dial)
+                        .udpv4_socket()
+                        .await?;
+                    socket.connect(server_addr).await?;
+                    socket
+                }
+                SocketAddr::V6(_) => {
+                    let socket = Egress::new(&self.iface)
+                        .with_dial(&config.dial)
+                        .udpv6_socket()
+                        .await?;
+                    socket.connect(server_addr).await?;
+                    socket
+                }
+            })
+        })
+    }
 }
 
 #[derive(Clone)]
@@ -373,6 +422,9 @@ impl WireguardHandle {
                 port,
             ),
         };
+        if endpoint.peers.route(dst.ip()).is_none() {
+            return Err(io_err("No WireGuard peer allows the destination"));
+        }
         let mut x = endpoint.stack.lock().await;
         x.open_tcp(self.src, dst, inbound, abort_handle, notify)
     }
@@ -416,6 +468,7 @@ impl Outbound for WireguardHandle {
         let (tx, _) = tokio::sync::oneshot::channel();
         tokio::spawn(adapter::connect_timeout(
             self.clone().attach_tcp(inbound, abort_handle, None, tx),
+            self.config.dial.connect_timeout,
             "WireGuard TCP",
         ))
     }
@@ -440,6 +493,7 @@ impl Outbound for WireguardHandle {
                 Some(AdapterOrSocket::Adapter(Arc::from(udp_outbound))),
                 ret_tx,
             ),
+            self.config.dial.connect_timeout,
             "WireGuard TCP multi-hop",
         ));
         ret_rx
@@ -456,6 +510,7 @@ impl Outbound for WireguardHandle {
         let (ret_tx, _) = tokio::sync::oneshot::channel();
         tokio::spawn(adapter::connect_timeout(
             self.clone().attach_udp(inbound, abort_handle, None, ret_tx),
+            self.config.dial.connect_timeout,
             "WireGuard UDP",
         ))
     }
@@ -481,6 +536,7 @@ impl Outbound for WireguardHandle {
                 Some(AdapterOrSocket::Adapter(Arc::from(udp_outbound))),
                 ret_tx,
             ),
+            self.config.dial.connect_timeout,
             "WireGuard UDP multi-hop",
         ));
         ret_rx
@@ -1,12 +1,13 @@
 use crate::config::{
-    default_inbound_ip_addr, safe_join_path, LinkedState, LoadedConfig, RawDnsConfig,
+    default_inbound_ip_addr, read_proxy_schema, read_rule_schema, safe_join_path, LinkedState,
+    LoadedConfig, ProviderKind, ProviderRegistry, ProviderUpdate, ProviderUpdater, RawDnsConfig,
     RawInboundConfig, RawInboundServiceConfig, RawInstrumentConfig, RawRootCfg,
-    RawWebControllerConfig, SingleOrVec,
+    RawWebControllerConfig, RuleSchema, SingleOrVec,
 };
-use crate::dispatch::{DispatchingBuilder, RuleSet, RuleSetBuilder};
+use crate::dispatch::{Dispatching, DispatchingBuilder, RuleSet, RuleSetBuilder, RuleSetTable};
 use crate::external::{
-    Controller, DatabaseHandle, InstrumentServer, MmdbReader, SharedDispatching, StreamLoggerSend,
-    UdsController, UnixListenerGuard, WebController,
+    Controller, DatabaseHandle, GeositeReader, InstrumentServer, MmdbReader, SharedDispatching,
+    StreamLoggerSend, UdsController, UnixListenerGuard, WebController,
 };
 use crate::instrument::bus::MessageBus;
 use crate::intercept::{InterceptModifier, InterceptionManager};
@@ -17,8 +18,8 @@ use crate::network::dns::{
 use crate::network::tun_device::TunDevice;
 use crate::platform::get_default_v4_route;
 use crate::proxy::{
-    ContextManager, Dispatcher, HttpCapturer, HttpInbound, MixedInbound, SessionManager,
-    Socks5Inbound, TunTcpInbound, TunUdpInbound,
+    ContextManager, Dispatcher, GroupHealthChecker, HttpCapturer, HttpInbound, MixedInbound,
+    SessionManager, Socks5Inbound, TunTcpInbound, TunUdpInbound,
 };
 use crate::{external, platform};
 use anyhow::anyhow;
@@ -46,10 +47,22 @@ pub struct App {
     linked_state: Arc<std::sync::Mutex<LinkedState>>,
     speedtest_url: Arc<std::sync::RwLock<String>>,
     receiver: tokio::sync::mpsc::Receiver<()>,
+    provider_receiver: tokio::sync::mpsc::Receiver<ProviderUpdate>,
+    provider_updater: std::sync::Mutex<ProviderUpdater>,
+    provider_registry: Arc<ProviderRegistry>,
+    running: std::sync::Mutex<RunningConfig>,
     uds_socket: Arc<UnixListenerGuard>,
     msg_bus: Arc<MessageBus>,
 }
 
+/// The running configuration, patched in place when a provider refreshes.
+struct RunningConfig {
+    loaded_config: LoadedConfig,
+    mmdb: Option<Arc<MmdbReader>>,
+    geosite: Option<Arc<GeositeReader>>,
+    rulesets: RuleSetTable,
+}
+
 impl App {
     /// Create a running App instance.
     pub async fn create(
@@ -71,6 +84,7 @@ impl App {
             .map_err(|e| anyhow!("Load config from {:?} failed: {}", &config_path, e))?;
         let config = &loaded_config.config;
         let mmdb = load_mmdb(config.geoip_db.as_ref(), &config_path)?;
+        let geosite = load_geosite(config.geosite_db.as_ref(), &config_path)?;
 
         let outbound_iface = detect_interface(config)?;
 
@@ -149,6 +163,7 @@ impl App {
                 config_path.as_path(),
                 dns.clone(),
                 mmdb.clone(),
+                geosite.clone(),
                 &loaded_config,
                 &ruleset,
                 msg_bus.clone(),
@@ -166,6 +181,7 @@ impl App {
                     config.interception.as_slice(),
                     dns.clone(),
                     mmdb.clone(),
+                    geosite.clone(),
                     &ruleset,
                     msg_bus.clone(),
                 )
@@ -189,9 +205,18 @@ impl App {
         let api_dispatching_handler = Arc::new(ArcSwap::new(dispatching));
         let (reload_sender, reload_receiver) = tokio::sync::mpsc::channel::<()>(1);
         let speedtest_url = Arc::new(std::sync::RwLock::new(config.speedtest_url.clone()));
+        let (provider_sender, provider_receiver) = tokio::sync::mpsc::channel(8);
+        let provider_registry = Arc::new(ProviderRegistry::new(config_path.as_path()));
+        provider_registry.refresh(&loaded_config);
+        let mut provider_updater = ProviderUpdater::new(
+            config_path.as_path(),
+            provider_sender,
+            provider_registry.clone(),
+        );
+        provider_updater.sync(config);
         let linked_state = Arc::new(std::sync::Mutex::new(LinkedState {
             state_path: LoadedConfig::state_path(&data_path),
-            state: loaded_config.state,
+            state: loaded_config.state.clone(),
         }));
         let controller = Arc::new(Controller::new(
             manager.clone(),
@@ -202,6 +227,7 @@ impl App {
             api_dispatching_handler.clone(),
             tun_configure.clone(),
             reload_sender,
+            provider_registry.clone(),
             linked_state.clone(),
             stream_logger,
             speedtest_url.clone(),
@@ -228,6 +254,14 @@ impl App {
 
         start_instrument_services(msg_bus.clone(), config.instrument.as_ref());
 
+        start_health_check_services(dispatcher.clone(), speedtest_url.clone());
+
+        let running = RunningConfig {
+            loaded_config,
+            mmdb,
+            geosite,
+            rulesets: ruleset,
+        };
         Ok(Self {
             config_path,
             data_path,
@@ -240,6 +274,10 @@ impl App {
             linked_state,
             speedtest_url,
             receiver: reload_receiver,
+            provider_receiver,
+            provider_updater: std::sync::Mutex::new(provider_updater),
+            provider_registry,
+            running: std::sync::Mutex::new(running),
             uds_socket: uds_listener,
             msg_bus,
         })
@@ -259,6 +297,11 @@ impl App {
                         break 'outer;
                     }
                 }
+                update = self.provider_receiver.recv() => {
+                    if let Some(update) = update {
+                        self.refresh_provider(update).await;
+                    }
+                }
             }
         }
         tun_configure.lock().unwrap().disable(false);
@@ -279,11 +322,61 @@ impl App {
         }
     }
 
+    async fn refresh_provider(&self, mut update: ProviderUpdate) {
+        let start = Instant::now();
+        let previous = match update.commit() {
+            Ok(p) => p,
+            Err(err) => {
+                tracing::error!(
+                    "Saving {} provider {} failed: {}",
+                    update.kind,
+                    update.name,
+                    err
+                );
+                return;
+            }
+        };
+        let result = match update.kind {
+            ProviderKind::Rule => self.refresh_ruleset(&update.name).await,
+            ProviderKind::Proxy(_) => self.refresh_proxies(&update.name).await,
+            // modules may bring their own providers, rules and interceptions
+            ProviderKind::Module => self.reload_inner().await,
+        };
+        match result {
+            Ok(_) => {
+                tracing::info!(
+                    "Refreshed {} provider {} in {}ms",
+                    update.kind,
+                    update.name,
+                    start.elapsed().as_millis()
+                );
+                update.respond(true);
+            }
+            Err(err) => {
+                tracing::error!(
+                    "Refreshing {} provider {} failed: {}",
+                    update.kind,
+                    update.name,
+                    err
+                );
+                if let Err(err) = update.rollback(previous) {
+                    tracing::error!(
+                        "Restoring {} provider {} failed: {}",
+                        update.kind,
+                        update.name,
+                        err
+                    );
+                }
+            }
+        }
+    }
+
     async fn reload_inner(&self) -> anyhow::Result<()> {
         // reload parsing
         let loaded_config = LoadedConfig::load_config(&self.config_path, &self.data_path).await?;
         let config = &loaded_config.config;
         let mmdb = load_mmdb(config.geoip_db.as_ref(), &self.config_path)?;
+        let geosite = load_geosite(config.geosite_db.as_ref(), &self.config_path)?;
         let ruleset = load_rulesets(&loaded_config)?;
 
         let bootstrap =
@@ -300,6 +393,7 @@ impl App {
                 self.config_path.as_path(),
                 self.dns.clone(),
                 mmdb.clone(),
+                geosite.clone(),
                 &loaded_config,
                 &ruleset,
                 self.msg_bus.clone(),
@@ -313,13 +407,15 @@ impl App {
                 config.interception.as_slice(),
                 self.dns.clone(),
                 mmdb.clone(),
+                geosite.clone(),
                 &ruleset,
                 self.msg_bus.clone(),
             )
             .map_err(|e| anyhow!("Load intercept rules failed: {}", e))?,
         );
 
-        self.linked_state.lock().unwrap().state = loaded_config.state;
+        self.provider_registry.refresh(&loaded_config);
+        self.linked_state.lock().unwrap().state = loaded_config.state.clone();
 
         self.dns.replace_resolvers(&self.outbound_iface, group);
         self.dns.replace_ns_policy(ns_policy);
@@ -338,8 +434,110 @@ impl App {
             .write()
             .unwrap()
             .clone_from(&config.speedtest_url);
+        self.provider_updater.lock().unwrap().sync(config);
+        *self.running.lock().unwrap() = RunningConfig {
+            loaded_config,
+            mmdb,
+            geosite,
+            rulesets: ruleset,
+        };
+        Ok(())
+    }
+
+    /// Swap the ruleset of a refreshed rule provider, shared by all rules referring to it.
+    async fn refresh_ruleset(&self, name: &str) -> anyhow::Result<()> {
+        let (provider, ruleset) = {
+            let running = self.running.lock().unwrap();
+            (
+                running
+                    .loaded_config
+                    .config
+                    .rule_provider
+                    .get(name)
+                    .cloned(),
+                running.rulesets.get(name).cloned(),
+            )
+        };
+        let (Some(provider), Some(ruleset)) = (provider, ruleset) else {
+            return Err(anyhow!("rule provider {} is not in use", name));
+        };
+        let mut schema = read_rule_schema(
+            &self.config_path,
+            &HashMap::from([(name.to_string(), provider)]),
+            false,
+        )
+        .await?;
+        let schema = schema
+            .remove(name)
+            .ok_or_else(|| anyhow!("rule provider {} is not loaded", name))?;
+        ruleset.store(Arc::new(build_ruleset(name, &schema)?));
+        let mut running = self.running.lock().unwrap();
+        running
+            .loaded_config
+            .rule_schema
+            .insert(name.to_string(), schema);
+        self.provider_registry.refresh(&running.loaded_config);
         Ok(())
     }
+
+    /// Rebuild the dispatching with the refreshed proxies, reusing everything else loaded.
+    async fn refresh_proxies(&self, name: &str) -> anyhow::Result<()> {
+        let provider = self
+            .running
+            .lock()
+            .unwrap()
+            .loaded_config
+            .config
+            .proxy_provider
+            .get(name)
+            .cloned()
+            .ok_or_else(|| anyhow!("proxy provider {} is not in use", name))?;
+        let mut schema = read_proxy_schema(
+            &self.config_path,
+            &HashMap::from([(name.to_string(), provider)]),
+            false,
+        )
+        .await?;
+        let schema = schema
+            .remove(name)
+            .ok_or_else(|| anyhow!("proxy provider {} is not loaded", name))?;
+        let mut running = self.running.lock().unwrap();
+        // keep selections and temporary rules made since the last reload
+        running.loaded_config.state = self.linked_state.lock().unwrap().state.clone();
+        let previous = running
+            .loaded_config
+            .proxy_schema
+            .insert(name.to_string(), schema);
+        let dispatching = match self.build_dispatching(&running) {
+            Ok(d) => Arc::new(d),
+            Err(e) => {
+                if let Some(previous) = previous {
+                    running
+                        .loaded_config
+                        .proxy_schema
+                        .insert(name.to_string(), previous);
+                }
+                return Err(e);
+            }
+        };
+        self.api_dispatching_handler.store(dispatching.clone());
+        self.dispatcher.replace_dispatching(dispatching);
+        self.provider_registry.refresh(&running.loaded_config);
+        Ok(())
+    }
+
+    fn build_dispatching(&self, running: &RunningConfig) -> anyhow::Result<Dispatching> {
+        let builder = DispatchingBuilder::new(
+            self.config_path.as_path(),
+            self.dns.clone(),
+            running.mmdb.clone(),
+            running.geosite.clone(),
+            &running.loaded_config,
+            &running.rulesets,
+            self.msg_bus.clone(),
+        )?;
+        Ok(builder.build(&running.loaded_config)?)
+    }
 }
 
 pub async fn validate_config(
@@ -353,6 +551,7 @@ pub async fn validate_config(
         .map_err(|e| anyhow!("Load config from {:?} failed: {}", config_path, e))?;
     let config = &loaded_config.config;
     let mmdb = load_mmdb(config.geoip_db.as_ref(), config_path)?;
+    let geosite = load_geosite(config.geosite_db.as_ref(), config_path)?;
     let outbound_iface = detect_interface(config)?;
     // initialize resources
     let _bootstrap =
@@ -372,6 +571,7 @@ pub async fn validate_config(
         config_path,
         dns.clone(),
         mmdb.clone(),
+        geosite.clone(),
         &loaded_config,
         &ruleset,
         msg_bus.clone(),
@@ -383,6 +583,7 @@ pub async fn validate_config(
         config.interception.as_slice(),
         dns,
         mmdb,
+        geosite,
         &ruleset,
         msg_bus,
     )
@@ -400,6 +601,19 @@ fn load_mmdb(db_path: Option<&String>, cfg_path: &Path) -> anyhow::Result<Option
     })
 }
 
+fn load_geosite(
+    db_path: Option<&String>,
+    cfg_path: &Path,
+) -> anyhow::Result<Option<Arc<GeositeReader>>> {
+    Ok(match db_path {
+        None => None,
+        Some(p) => {
+            let path = safe_join_path(cfg_path, p)?;
+            Some(Arc::new(GeositeReader::read_from_file(path)?))
+        }
+    })
+}
+
 fn detect_interface(config: &RawRootCfg) -> anyhow::Result<String> {
     Ok(if config.interface != "auto" {
         tracing::info!("Use pre-configured interface: {}", config.interface);
@@ -451,6 +665,14 @@ fn start_instrument_services(bus: Arc<MessageBus>, config: Option<&RawInstrument
     tokio::spawn(async move { bus.run().await });
 }
 
+fn start_health_check_services(
+    dispatcher: Arc<Dispatcher>,
+    speedtest_url: Arc<std::sync::RwLock<String>>,
+) {
+    let checker = GroupHealthChecker::new(dispatcher, speedtest_url);
+    tokio::spawn(async move { checker.run().await });
+}
+
 fn start_tun_services(
     nat_addr: SocketAddr,
     manager: Arc<SessionManager>,
@@ -534,17 +756,27 @@ fn start_controller_services(
     }
 }
 
-fn load_rulesets(loaded_config: &LoadedConfig) -> anyhow::Result<HashMap<String, Arc<RuleSet>>> {
+fn load_rulesets(loaded_config: &LoadedConfig) -> anyhow::Result<RuleSetTable> {
     let mut ruleset = HashMap::new();
     for (name, schema) in &loaded_config.rule_schema {
-        let Some(builder) = RuleSetBuilder::new(name.as_str(), schema) else {
-            return Err(anyhow!("Filter: failed to parse provider {}", name));
-        };
-        ruleset.insert(name.clone(), Arc::new(builder.build()?));
+        ruleset.insert(
+            name.clone(),
+            Arc::new(ArcSwap::from_pointee(build_ruleset(name, schema)?)),
+        );
     }
     Ok(ruleset)
 }
 
+fn build_ruleset(name: &str, schema: &RuleSchema) -> anyhow::Result<RuleSet> {
+    if let Some(binary) = &schema.binary {
+        return Ok(RuleSet::load_file(name, binary)?);
+    }
+    let Some(builder) = RuleSetBuilder::new(name, schema) else {
+        return Err(anyhow!("Filter: failed to parse provider {}", name));
+    };
+    Ok(builder.build()?)
+}
+
 fn load_cert_and_key(cert_path: &Path) -> anyhow::Result<Certificate> {
     let cert_str = fs::read_to_string(cert_path.join("crt.pem"))?;
     let key_str = fs::read_to_string(cert_path.join("key.pem"))?;
@@ -3,12 +3,13 @@ mod clean;
 mod request;
 mod request_uds;
 mod request_web;
+mod ruleset;
 mod streaming;
 
 use crate::cli::streaming::ConnectionState;
 use crate::ProgramArgs;
 use anyhow::anyhow;
-use clap::{Args, CommandFactory, Subcommand, ValueHint};
+use clap::{Args, CommandFactory, Subcommand, ValueEnum, ValueHint};
 use colored::Colorize;
 use is_root::is_root;
 use std::path::PathBuf;
@@ -35,6 +36,49 @@ pub(crate) enum ProxyOptions {
     },
 }
 
+#[derive(Debug, Subcommand)]
+pub(crate) enum ProviderOptions {
+    /// List all providers
+    List,
+    /// Refetch a remote provider without reloading others
+    Update {
+        #[clap(value_hint = ValueHint::Other)]
+        name: String,
+        /// Required if providers of different kinds share the name
+        #[arg(short, long, value_enum)]
+        kind: Option<ProviderKind>,
+    },
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum ProviderKind {
+    Rule,
+    Proxy,
+    Module,
+}
+
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub(crate) enum RulesetBehavior {
+    Classical,
+    Domain,
+    Ipcidr,
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum RulesetOptions {
+    /// Compile a YAML or sing-box rule-set into the binary format
+    Compile {
+        #[clap(value_hint = ValueHint::FilePath)]
+        input: PathBuf,
+        /// Default to the input path with extension .brs
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Behavior of a YAML rule-set
+        #[arg(short, long, value_enum, default_value_t = RulesetBehavior::Classical)]
+        behavior: RulesetBehavior,
+    },
+}
+
 #[derive(Debug, Subcommand)]
 pub(crate) enum ConnOptions {
     /// List all active connections
@@ -191,12 +235,18 @@ pub(crate) enum SubCommand {
     /// Proxy settings
     #[command(subcommand)]
     Proxy(ProxyOptions),
+    /// Provider information
+    #[command(subcommand)]
+    Provider(ProviderOptions),
     /// DNS information
     #[command(subcommand)]
     Dns(DnsOptions),
     /// Modify temporary rules
     #[command(subcommand)]
     TempRule(TempRuleOptions),
+    /// Rule-set tools
+    #[command(subcommand)]
+    Ruleset(RulesetOptions),
     /// Adjust TUN status
     #[command(subcommand)]
     Tun(TunOptions),
@@ -305,6 +355,17 @@ pub(crate) async fn controller_main(args: ProgramArgs) -> ! {
             state.stream_log().await.unwrap();
             exit(0)
         }
+        SubCommand::Ruleset(RulesetOptions::Compile {
+            input,
+            output,
+            behavior,
+        }) => match ruleset::compile_ruleset(&input, output, behavior) {
+            Ok(_) => exit(0),
+            Err(err) => {
+                eprintln!("{}", err);
+                exit(-1)
+            }
+        },
         SubCommand::Validate(opt) => {
             let (config_path, data_path, cert_path) = match crate::process_path(&opt) {
                 Ok(r) => r,
@@ -337,6 +398,14 @@ pub(crate) async fn controller_main(args: ProgramArgs) -> ! {
             ProxyOptions::Get { group } => requester.get_group_proxy(group).await,
             ProxyOptions::List { full: short } => requester.get_group_list(short).await,
         },
+        SubCommand::Provider(opt) => match opt {
+            ProviderOptions::List => requester.get_providers().await,
+            ProviderOptions::Update { name, kind } => {
+                let kind =
+                    kind.and_then(|k| k.to_possible_value().map(|v| v.get_name().to_string()));
+                requester.update_provider(kind, name).await
+            }
+        },
         SubCommand::Conn(opt) => match opt {
             ConnOptions::List => requester.get_connections().await,
             ConnOptions::Stop { nth } => requester.stop_connections(nth).await,
@@ -376,6 +445,7 @@ pub(crate) async fn controller_main(args: ProgramArgs) -> ! {
         | SubCommand::Generate(_)
         | SubCommand::Clean
         | SubCommand::Log
+        | SubCommand::Ruleset(_)
         | SubCommand::Validate(_) => {
             unreachable!()
         }
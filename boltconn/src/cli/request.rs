@@ -50,7 +50,11 @@ impl Requester {
             );
             if full {
                 for i in entry.list {
-                    println!("  - {}", i.name)
+                    if i.udp {
+                        println!("  - {}", i.name)
+                    } else {
+                        println!("  - {} {}", i.name, "(no UDP)".dimmed())
+                    }
                 }
             }
         }
@@ -301,6 +305,71 @@ impl Requester {
         Ok(())
     }
 
+    pub async fn get_providers(&self) -> Result<()> {
+        let result = match &self.inner {
+            Inner::Web(c) => c.get_providers().await,
+            Inner::Uds(c) => c.get_providers().await,
+        }?;
+        let mut table = Table::new("{:<} {:<} {:<} {:<} {:<} {:<}");
+        table.add_row(
+            Row::new()
+                .with_cell("Name")
+                .with_cell("Kind")
+                .with_cell("Count")
+                .with_cell("Updated")
+                .with_cell("Usage")
+                .with_cell("Expire"),
+        );
+        for p in result {
+            let (usage, expire) = match p.subscription {
+                Some(info) => (
+                    format!(
+                        "{} / {}",
+                        pretty_size(info.upload.unwrap_or(0) + info.download.unwrap_or(0)),
+                        info.total.map_or("N/A".to_string(), pretty_size)
+                    ),
+                    info.expire
+                        .and_then(|t| chrono::DateTime::from_timestamp(t as i64, 0))
+                        .map_or("N/A".to_string(), |t| t.format("%Y-%m-%d").to_string()),
+                ),
+                None => ("N/A".to_string(), "N/A".to_string()),
+            };
+            table.add_row(
+                Row::new()
+                    .with_cell(p.name)
+                    .with_cell(p.kind)
+                    .with_cell(p.count)
+                    .with_cell(
+                        p.updated_at
+                            .and_then(|t| {
+                                SystemTime::now()
+                                    .duration_since(UNIX_EPOCH.add(Duration::from_secs(t)))
+                                    .ok()
+                            })
+                            .map_or("N/A".to_string(), |t| pretty_time(t.as_secs())),
+                    )
+                    .with_cell(usage)
+                    .with_cell(expire),
+            );
+        }
+        println!("{}", table);
+        Ok(())
+    }
+
+    pub async fn update_provider(&self, kind: Option<String>, name: String) -> Result<()> {
+        let result = match &self.inner {
+            Inner::Web(c) => c.update_provider(kind, name).await,
+            Inner::Uds(c) => c.update_provider(kind, name).await,
+        }?;
+        if result {
+            println!("{}", "Success".green());
+            Ok(())
+        } else {
+            println!("{}", "Failed".red());
+            Err(anyhow!("Failed to update provider"))
+        }
+    }
+
     pub async fn reload_config(&self) -> Result<()> {
         match &self.inner {
             Inner::Web(c) => c.reload_config().await,
@@ -314,8 +383,10 @@ fn pretty_size(data: u64) -> String {
         format!("{} Bytes", data)
     } else if data < 1024 * 1024 {
         format!("{} KB", data / 1024)
-    } else {
+    } else if data < 1024 * 1024 * 1024 {
         format!("{} MB", data / 1024 / 1024)
+    } else {
+        format!("{:.2} GB", data as f64 / 1024.0 / 1024.0 / 1024.0)
     }
 }
 
@@ -3,7 +3,7 @@ use boltapi::multiplex::rpc_multiplex_twoway;
 use boltapi::rpc::{ClientStreamServiceRequest, ClientStreamServiceResponse, ControlServiceClient};
 use boltapi::{
     ConnectionSchema, GetGroupRespSchema, GetInterceptDataResp, HttpInterceptSchema,
-    TunStatusSchema,
+    ProviderSchema, TunStatusSchema,
 };
 use std::path::PathBuf;
 use tarpc::context::Context;
@@ -163,6 +163,17 @@ impl UdsConnector {
             .await?)
     }
 
+    pub async fn get_providers(&self) -> Result<Vec<ProviderSchema>> {
+        Ok(self.client.get_providers(Context::current()).await?)
+    }
+
+    pub async fn update_provider(&self, kind: Option<String>, name: String) -> Result<bool> {
+        Ok(self
+            .client
+            .update_provider(Context::current(), kind, name)
+            .await?)
+    }
+
     pub async fn reload_config(&self) -> Result<()> {
         Ok(self.client.reload(Context::current()).await?)
     }
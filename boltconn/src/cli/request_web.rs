@@ -1,8 +1,9 @@
 use anyhow::Result;
 use boltapi::{
     ConnectionSchema, GetGroupRespSchema, GetInterceptDataResp, HttpInterceptSchema,
-    TunStatusSchema,
+    ProviderSchema, TunStatusSchema,
 };
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
 
 pub struct WebConnector {
     pub url: String,
@@ -140,6 +141,31 @@ impl WebConnector {
         Ok(result)
     }
 
+    pub async fn get_providers(&self) -> Result<Vec<ProviderSchema>> {
+        let data = reqwest::get(self.route("/providers")).await?.text().await?;
+        let result: Vec<ProviderSchema> = serde_json::from_str(data.as_str())?;
+        Ok(result)
+    }
+
+    pub async fn update_provider(&self, kind: Option<String>, name: String) -> Result<bool> {
+        let name = utf8_percent_encode(name.as_str(), NON_ALPHANUMERIC);
+        let path = match kind {
+            Some(kind) => format!(
+                "/providers/{}?kind={}",
+                name,
+                utf8_percent_encode(kind.as_str(), NON_ALPHANUMERIC)
+            ),
+            None => format!("/providers/{}", name),
+        };
+        let result = reqwest::Client::new()
+            .post(self.route(path.as_str()))
+            .send()
+            .await?
+            .text()
+            .await?;
+        Ok(result.as_str() == "true")
+    }
+
     pub async fn reload_config(&self) -> Result<()> {
         reqwest::Client::new()
             .post(self.route("/reload"))
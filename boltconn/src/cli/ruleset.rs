@@ -0,0 +1,41 @@
+use crate::cli::RulesetBehavior;
+use crate::config::{write_atomically, ProviderBehavior, RawRuleSchema, RuleSchema};
+use crate::dispatch::{RuleSetBuilder, SRS_MAGIC};
+use anyhow::anyhow;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn compile_ruleset(
+    input: &Path,
+    output: Option<PathBuf>,
+    behavior: RulesetBehavior,
+) -> anyhow::Result<()> {
+    let name = input.to_string_lossy();
+    let data = fs::read(input)?;
+    let builder = if data.starts_with(SRS_MAGIC) {
+        RuleSetBuilder::from_srs(&name, &data)?
+    } else {
+        let content: RawRuleSchema = serde_yaml::from_slice(&data)?;
+        let schema = RuleSchema {
+            behavior: match behavior {
+                RulesetBehavior::Classical => ProviderBehavior::Classical,
+                RulesetBehavior::Domain => ProviderBehavior::Domain,
+                RulesetBehavior::Ipcidr => ProviderBehavior::IpCidr,
+            },
+            payload: content.payload,
+            binary: None,
+        };
+        RuleSetBuilder::new(&name, &schema)
+            .ok_or_else(|| anyhow!("Failed to parse rules in {}", name))?
+    };
+    let compiled = builder.compile()?;
+    let output = output.unwrap_or_else(|| input.with_extension("brs"));
+    write_atomically(&output, &compiled)?;
+    println!(
+        "Compiled {} into {} ({} bytes)",
+        name,
+        output.to_string_lossy(),
+        compiled.len()
+    );
+    Ok(())
+}
@@ -0,0 +1,108 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::hash::Hash;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+/// Connection that can be shared until it is closed.
+pub trait IsActive {
+    fn is_active(&self) -> bool;
+}
+
+impl<T: IsActive> IsActive for Arc<T> {
+    fn is_active(&self) -> bool {
+        self.as_ref().is_active()
+    }
+}
+
+/// Connections shared among streams to the same server.
+///
+/// Servers are dialed without holding the lock, so a slow server does not stall other proxies.
+/// If another stream has connected concurrently, its connection is kept and shared instead.
+pub struct SharedConnPool<K, C> {
+    conns: Mutex<HashMap<K, C>>,
+}
+
+impl<K, C> Default for SharedConnPool<K, C> {
+    fn default() -> Self {
+        Self {
+            conns: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+impl<K: Eq + Hash + Clone, C: IsActive + Clone> SharedConnPool<K, C> {
+    /// The active connection to `key`, if any.
+    pub async fn get(&self, key: &K) -> Option<C> {
+        self.conns
+            .lock()
+            .await
+            .get(key)
+            .filter(|c| c.is_active())
+            .cloned()
+    }
+
+    /// Add a newly dialed connection, returning the one to share.
+    pub async fn insert(&self, key: &K, conn: C) -> C {
+        let mut guard = self.conns.lock().await;
+        if let Some(existing) = guard.get(key) {
+            if existing.is_active() {
+                return existing.clone();
+            }
+        }
+        guard.insert(key.clone(), conn.clone());
+        conn
+    }
+
+    pub async fn get_or_connect<E, F, Fut>(&self, key: &K, connect: F) -> Result<C, E>
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<C, E>>,
+    {
+        if let Some(conn) = self.get(key).await {
+            return Ok(conn);
+        }
+        let conn = connect().await?;
+        Ok(self.insert(key, conn).await)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    struct Conn(u32, AtomicBool);
+
+    impl IsActive for Conn {
+        fn is_active(&self) -> bool {
+            self.1.load(Ordering::Relaxed)
+        }
+    }
+
+    fn conn(id: u32) -> Arc<Conn> {
+        Arc::new(Conn(id, AtomicBool::new(true)))
+    }
+
+    #[tokio::test]
+    async fn test_shared_conn_pool() {
+        let pool = SharedConnPool::<&str, Arc<Conn>>::default();
+        let first = pool
+            .get_or_connect(&"a", || async { Ok::<_, ()>(conn(1)) })
+            .await
+            .unwrap();
+        // reused without dialing
+        let reused = pool
+            .get_or_connect(&"a", || async { Err(()) })
+            .await
+            .unwrap();
+        assert_eq!(reused.0, 1);
+        // a concurrent dial loses to the shared connection
+        assert_eq!(pool.insert(&"a", conn(2)).await.0, 1);
+        // closed connections are replaced
+        first.1.store(false, Ordering::Relaxed);
+        assert!(pool.get(&"a").await.is_none());
+        assert_eq!(pool.insert(&"a", conn(3)).await.0, 3);
+        assert_eq!(pool.get(&"a").await.unwrap().0, 3);
+    }
+}
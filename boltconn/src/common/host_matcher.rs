@@ -1,9 +1,11 @@
 use radix_trie::{Trie, TrieCommon};
 
-#[derive(Debug, Clone, Copy)]
-enum HostType {
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum HostType {
     Exact,
     Suffix,
+    /// Like `Suffix`, but excluding the host itself
+    Subdomain,
 }
 
 pub struct HostMatcher(Trie<String, HostType>);
@@ -11,27 +13,38 @@ pub struct HostMatcher(Trie<String, HostType>);
 impl HostMatcher {
     pub fn matches(&self, host: &str) -> bool {
         let rev_dn: String = host.chars().rev().collect();
-        if let Some(result) = self.0.get_ancestor(rev_dn.as_str()) {
-            if let Some(val) = result.value() {
-                let key = result.key().unwrap();
-                match val {
-                    HostType::Exact => {
-                        if key.len() == rev_dn.len() {
-                            // DOMAIN rule
-                            return true;
-                        }
+        let mut prefix = rev_dn.as_str();
+        // an exact entry may shadow a shorter suffix, so walk up all ancestors
+        while let Some(result) = self.0.get_ancestor(prefix) {
+            let (Some(key), Some(val)) = (result.key(), result.value()) else {
+                break;
+            };
+            match val {
+                HostType::Exact => {
+                    if key.len() == rev_dn.len() {
+                        // DOMAIN rule
+                        return true;
                     }
-                    HostType::Suffix => {
-                        if key.len() == rev_dn.len()
-                            || (key.len() < rev_dn.len()
-                                && rev_dn.chars().nth(key.len()).unwrap() == '.')
-                        {
-                            // DOMAIN-SUFFIX rule
-                            return true;
-                        }
+                }
+                HostType::Suffix => {
+                    if key.len() == rev_dn.len()
+                        || (key.len() < rev_dn.len() && rev_dn.as_bytes()[key.len()] == b'.')
+                    {
+                        // DOMAIN-SUFFIX rule
+                        return true;
+                    }
+                }
+                HostType::Subdomain => {
+                    if key.len() < rev_dn.len() && rev_dn.as_bytes()[key.len()] == b'.' {
+                        return true;
                     }
                 }
             }
+            let mut shorter = rev_dn[..key.len()].chars();
+            if shorter.next_back().is_none() {
+                break;
+            }
+            prefix = shorter.as_str();
         }
         false
     }
@@ -57,6 +70,10 @@ impl HostMatcherBuilder {
             .push((host.chars().rev().collect(), HostType::Suffix))
     }
 
+    pub fn add(&mut self, host: &str, ty: HostType) {
+        self.0.push((host.chars().rev().collect(), ty))
+    }
+
     /// Automatically add a host to the matcher, determining the type based on wildcards.
     pub fn add_auto(&mut self, host: &str) {
         if let Some(stripped_host) = host.strip_prefix("*.") {
@@ -67,7 +84,24 @@ impl HostMatcherBuilder {
     }
 
     pub fn build(self) -> HostMatcher {
-        HostMatcher(Trie::from_iter(self.0))
+        let mut trie = Trie::new();
+        for (host, ty) in self.0 {
+            let ty = match (trie.get(&host), ty) {
+                (None, ty) => ty,
+                (Some(old), ty) if *old == ty => continue,
+                // a suffix covers both the exact host and its subdomains
+                _ => HostType::Suffix,
+            };
+            trie.insert(host, ty);
+        }
+        HostMatcher(trie)
+    }
+
+    /// Hosts added so far.
+    pub fn iter(&self) -> impl Iterator<Item = (String, HostType)> + '_ {
+        self.0
+            .iter()
+            .map(|(rev, ty)| (rev.chars().rev().collect(), *ty))
     }
 
     pub fn merge(&mut self, rhs: Self) {
@@ -91,9 +125,25 @@ fn test_matcher() {
     assert!(!matcher.matches("ogle.com"));
     assert!(!matcher.matches("t-02.test.google.com"));
     let mut builder = HostMatcher::builder();
+    builder.add_suffix("google.com");
+    builder.add_exact("www.google.com");
+    builder.add_exact("google.com");
+    let matcher = builder.build();
+    assert!(matcher.matches("google.com"));
+    assert!(matcher.matches("a.www.google.com"));
+    let mut builder = HostMatcher::builder();
     builder.add_suffix("ogle.com");
     let matcher = builder.build();
     assert!(matcher.matches("hi.ogle.com"));
     assert!(!matcher.matches("google.com"));
     assert!(!matcher.matches("hi.google.com"));
+    let mut builder = HostMatcher::builder();
+    builder.add("google.com", HostType::Subdomain);
+    builder.add("example.com", HostType::Subdomain);
+    builder.add_exact("example.com");
+    let matcher = builder.build();
+    assert!(!matcher.matches("google.com"));
+    assert!(matcher.matches("www.google.com"));
+    assert!(matcher.matches("example.com"));
+    assert!(matcher.matches("www.example.com"));
 }
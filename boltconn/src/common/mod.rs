@@ -11,10 +11,12 @@ pub mod async_raw_fd;
 pub mod async_socket;
 pub mod async_ws_stream;
 pub mod client_hello;
+pub mod conn_pool;
 pub mod duplex_chan;
 pub mod evictable_vec;
 pub mod host_matcher;
 pub mod id_gen;
+pub mod quic_stream;
 mod sync;
 
 pub use sync::{local_async_run, AbortCanary};
@@ -30,6 +32,16 @@ where
     std::io::Error::new(std::io::ErrorKind::Other, err.to_string())
 }
 
+/// Self-signed certificate for `localhost`, as DER certificate and PKCS#8 key.
+#[cfg(test)]
+pub fn self_signed_cert() -> (Vec<u8>, Vec<u8>) {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    (
+        cert.serialize_der().unwrap(),
+        cert.serialize_private_key_der(),
+    )
+}
+
 pub trait StreamOutboundTrait: AsyncRead + AsyncWrite + Unpin + Send + Sync + 'static {}
 
 pub const MAX_PKT_SIZE: usize = 65576;
@@ -28,6 +28,8 @@ pub struct RawRootCfg {
     pub speedtest_url: String,
     #[serde(alias = "geoip-db")]
     pub geoip_db: Option<String>,
+    #[serde(alias = "geosite-db")]
+    pub geosite_db: Option<String>,
     pub dns: RawDnsConfig,
     #[serde(alias = "proxy-local", default = "default_local_proxy")]
     pub proxy_local: HashMap<String, RawProxyLocalCfg>,
@@ -103,6 +105,23 @@ pub struct RawInstrumentConfig {
     pub cors_allowed_list: Vec<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RawDialOptions {
+    pub interface: Option<String>,
+    #[serde(alias = "source-addr")]
+    pub source_addr: Option<IpAddr>,
+    pub mark: Option<u32>,
+    // in seconds
+    #[serde(alias = "tcp-keepalive")]
+    pub tcp_keepalive: Option<u64>,
+    #[serde(alias = "tcp-fast-open", default = "default_false")]
+    pub tcp_fast_open: bool,
+    // in seconds
+    #[serde(alias = "connect-timeout")]
+    pub connect_timeout: Option<u64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(deny_unknown_fields, tag = "type")]
 pub enum RawProxyLocalCfg {
@@ -111,6 +130,14 @@ pub enum RawProxyLocalCfg {
         server: RawServerAddr,
         port: u16,
         auth: Option<AuthData>,
+        #[serde(default = "default_false")]
+        tls: bool,
+        sni: Option<String>,
+        #[serde(alias = "skip-cert-verify", default = "default_false")]
+        skip_cert_verify: bool,
+        #[serde(default = "default_false")]
+        http2: bool,
+        dial: Option<RawDialOptions>,
     },
     #[serde(alias = "socks5")]
     Socks5 {
@@ -119,6 +146,16 @@ pub enum RawProxyLocalCfg {
         auth: Option<AuthData>,
         #[serde(default = "default_true")]
         udp: bool,
+        #[serde(default = "default_socks_version")]
+        version: String,
+        #[serde(default = "default_false")]
+        tls: bool,
+        sni: Option<String>,
+        #[serde(alias = "skip-cert-verify", default = "default_false")]
+        skip_cert_verify: bool,
+        #[serde(alias = "udp-over-tcp", default = "default_false")]
+        udp_over_tcp: bool,
+        dial: Option<RawDialOptions>,
     },
     #[serde(alias = "ss")]
     Shadowsocks {
@@ -128,6 +165,10 @@ pub enum RawProxyLocalCfg {
         cipher: String,
         #[serde(default = "default_true")]
         udp: bool,
+        plugin: Option<RawShadowsocksPlugin>,
+        #[serde(alias = "udp-over-tcp", default = "default_false")]
+        udp_over_tcp: bool,
+        dial: Option<RawDialOptions>,
     },
     #[serde(alias = "trojan")]
     Trojan {
@@ -141,6 +182,71 @@ pub enum RawProxyLocalCfg {
         websocket_path: Option<String>,
         #[serde(default = "default_true")]
         udp: bool,
+        #[serde(alias = "udp-over-tcp", default = "default_false")]
+        udp_over_tcp: bool,
+        dial: Option<RawDialOptions>,
+    },
+    #[serde(alias = "vmess")]
+    Vmess {
+        server: RawServerAddr,
+        port: u16,
+        uuid: String,
+        #[serde(default = "default_vmess_cipher")]
+        cipher: String,
+        #[serde(default = "default_false")]
+        tls: bool,
+        sni: Option<String>,
+        #[serde(alias = "skip-cert-verify", default = "default_false")]
+        skip_cert_verify: bool,
+        #[serde(alias = "websocket-path")]
+        websocket_path: Option<String>,
+        #[serde(default = "default_true")]
+        udp: bool,
+        dial: Option<RawDialOptions>,
+    },
+    #[serde(alias = "vless")]
+    Vless {
+        server: RawServerAddr,
+        port: u16,
+        uuid: String,
+        #[serde(default = "default_true")]
+        tls: bool,
+        sni: Option<String>,
+        #[serde(alias = "skip-cert-verify", default = "default_false")]
+        skip_cert_verify: bool,
+        #[serde(alias = "websocket-path")]
+        websocket_path: Option<String>,
+        #[serde(default = "default_true")]
+        udp: bool,
+        dial: Option<RawDialOptions>,
+    },
+    #[serde(alias = "hysteria2")]
+    Hysteria2 {
+        server: RawServerAddr,
+        port: u16,
+        password: String,
+        sni: Option<String>,
+        #[serde(alias = "skip-cert-verify", default = "default_false")]
+        skip_cert_verify: bool,
+        #[serde(default = "default_true")]
+        udp: bool,
+        dial: Option<RawDialOptions>,
+    },
+    #[serde(alias = "tuic")]
+    Tuic {
+        server: RawServerAddr,
+        port: u16,
+        uuid: String,
+        password: String,
+        sni: Option<String>,
+        alpn: Option<SingleOrVec<String>>,
+        #[serde(alias = "skip-cert-verify", default = "default_false")]
+        skip_cert_verify: bool,
+        #[serde(alias = "udp-relay-mode", default = "default_tuic_udp_relay_mode")]
+        udp_relay_mode: String,
+        #[serde(default = "default_true")]
+        udp: bool,
+        dial: Option<RawDialOptions>,
     },
     #[serde(alias = "wireguard")]
     Wireguard {
@@ -161,8 +267,22 @@ pub enum RawProxyLocalCfg {
         preshared_key: Option<String>,
         keepalive: Option<u16>,
         reserved: Option<[u8; 3]>,
+        #[serde(alias = "allowed-ips")]
+        allowed_ips: Option<Vec<String>>,
+        #[serde(default = "default_wireguard_peers")]
+        peers: Vec<RawWireguardPeer>,
+        #[serde(alias = "over-tcp", default = "default_false")]
+        over_tcp: bool,
+        dial: Option<RawDialOptions>,
+    },
+    #[serde(alias = "wireguard-conf")]
+    WireguardConf {
+        path: PathBuf,
+        #[serde(alias = "dns-preference", default = "default_dns_pref")]
+        dns_preference: DnsPreference,
         #[serde(alias = "over-tcp", default = "default_false")]
         over_tcp: bool,
+        dial: Option<RawDialOptions>,
     },
     #[serde(alias = "ssh")]
     Ssh {
@@ -176,6 +296,43 @@ pub enum RawProxyLocalCfg {
         key_passphrase: Option<String>,
         #[serde(alias = "host-pubkey")]
         host_pubkey: Option<SingleOrVec<String>>,
+        #[serde(default = "default_false")]
+        agent: bool,
+        #[serde(alias = "agent-socket")]
+        agent_socket: Option<PathBuf>,
+        #[serde(alias = "known-hosts")]
+        known_hosts: Option<PathBuf>,
+        udpgw: Option<RawServerSockAddr>,
+        dial: Option<RawDialOptions>,
+    },
+}
+
+/// Additional peers of a WireGuard interface
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RawWireguardPeer {
+    #[serde(alias = "public-key")]
+    pub public_key: String,
+    pub endpoint: RawServerSockAddr,
+    #[serde(alias = "preshared-key")]
+    pub preshared_key: Option<String>,
+    pub keepalive: Option<u16>,
+    pub reserved: Option<[u8; 3]>,
+    #[serde(alias = "allowed-ips")]
+    pub allowed_ips: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields, tag = "type")]
+pub enum RawShadowsocksPlugin {
+    #[serde(alias = "obfs", alias = "simple-obfs")]
+    Obfs { mode: String, host: String },
+    #[serde(alias = "shadow-tls")]
+    ShadowTls {
+        host: String,
+        password: String,
+        #[serde(default = "default_shadow_tls_version")]
+        version: u8,
     },
 }
 
@@ -188,6 +345,26 @@ pub(super) fn default_false() -> bool {
     false
 }
 
+fn default_vmess_cipher() -> String {
+    "auto".to_string()
+}
+
+fn default_wireguard_peers() -> Vec<RawWireguardPeer> {
+    Default::default()
+}
+
+fn default_socks_version() -> String {
+    "5".to_string()
+}
+
+fn default_shadow_tls_version() -> u8 {
+    3
+}
+
+fn default_tuic_udp_relay_mode() -> String {
+    "native".to_string()
+}
+
 fn default_local_proxy() -> HashMap<String, RawProxyLocalCfg> {
     Default::default()
 }
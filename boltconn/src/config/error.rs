@@ -60,6 +60,8 @@ pub enum FileError {
     Serde(String, serde_yaml::Error),
     #[error("{0} serialization error: {1}")]
     Http(String, reqwest::Error),
+    #[error("{0} format error: {1}")]
+    Format(String, &'static str),
     #[error("Env variable error: {0}")]
     Env(#[from] std::env::VarError),
 }
@@ -80,6 +82,8 @@ pub enum RuleError {
     Invalid(String),
     #[error("Ruleset {0} exceeded limit")]
     RulesetExceededLimit(String),
+    #[error("Ruleset {0}: {1}")]
+    RulesetFile(String, String),
 }
 
 #[derive(Error, Debug)]
@@ -108,6 +112,10 @@ pub enum ProxyError {
     ShadowsocksCipher(String, String),
     #[error("Proxy {0} error: {1}")]
     ProxyFieldError(String, &'static str),
+    #[error("Proxy {0} has unsupported WireGuard directive {1} at line {2}")]
+    WireguardDirective(String, String, usize),
+    #[error("Proxy {0} has invalid WireGuard key in {1} at line {2}")]
+    WireguardKey(String, String, usize),
     #[error("Unknown proxy {proxy} in group {group}")]
     UnknownProxyInGroup { proxy: String, group: String },
 }
@@ -5,11 +5,14 @@ mod file_path;
 mod inbound;
 mod interception;
 mod module;
+mod provider_updater;
 mod proxy_group;
 mod proxy_provider;
 mod rule;
 mod rule_provider;
 mod state;
+mod subscription;
+mod wireguard_conf;
 
 use crate::platform::get_user_info;
 pub use config::*;
@@ -18,6 +21,7 @@ pub(crate) use file_path::*;
 pub use inbound::*;
 pub use interception::*;
 pub use module::*;
+pub use provider_updater::*;
 pub use proxy_group::*;
 pub use proxy_provider::*;
 pub use rule::*;
@@ -30,6 +34,8 @@ use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
 use std::{fs, io};
+pub use subscription::*;
+pub use wireguard_conf::*;
 
 pub fn safe_join_path(root: &Path, file_path: &str) -> io::Result<PathBuf> {
     let file_path = if file_path.starts_with('/') {
@@ -107,9 +113,10 @@ impl LoadedConfig {
     pub fn apply_module(&mut self) {
         let mut rule_local = vec![];
         let mut intercept_rule = vec![];
-        for i in self.module_schema.drain(..) {
-            rule_local.extend(i.rule_local.into_iter());
-            intercept_rule.extend(i.interception.into_iter());
+        // schemas are kept for provider metadata
+        for i in self.module_schema.iter() {
+            rule_local.extend(i.rule_local.iter().cloned());
+            intercept_rule.extend(i.interception.iter().cloned());
         }
         rule_local.append(&mut self.config.rule_local);
         intercept_rule.append(&mut self.config.interception);
@@ -126,38 +133,60 @@ async fn load_remote_config<T>(
 ) -> Result<T, FileError>
 where
     T: serde::de::DeserializeOwned,
+{
+    load_remote_content(url, path, root_path, force_update, |data| {
+        serde_yaml::from_slice(data).map_err(|e| FileError::Serde(path.to_string(), e))
+    })
+    .await
+}
+
+/// Like `load_remote_config`, but the content is parsed by `parse` before being cached.
+async fn load_remote_content<T, E, F>(
+    url: &str,
+    path: &str,
+    root_path: impl AsRef<Path>,
+    force_update: bool,
+    parse: F,
+) -> Result<T, E>
+where
+    F: Fn(&[u8]) -> Result<T, E>,
+    E: From<FileError>,
 {
     let io_error = |e| FileError::Io(path.to_string(), e);
-    let serde_error = |e| FileError::Serde(path.to_string(), e);
     let http_error = |e| FileError::Http(url.to_string(), e);
     let full_path = safe_join_path(root_path.as_ref(), path).map_err(io_error)?;
     let content: T = if !force_update && full_path.as_path().exists() {
-        serde_yaml::from_str(
-            fs::read_to_string(full_path.as_path())
-                .map_err(io_error)?
-                .as_str(),
-        )
-        .map_err(serde_error)?
+        parse(fs::read(full_path.as_path()).map_err(io_error)?.as_slice())?
     } else {
         tracing::debug!("Downloading external resource from {}", url);
         let resp = reqwest::get(url).await.map_err(http_error)?;
-        let text = resp.text().await.map_err(http_error)?;
-        let content: T = serde_yaml::from_str(text.as_str()).map_err(serde_error)?;
+        let data = resp.bytes().await.map_err(http_error)?;
+        let content: T = parse(data.as_ref())?;
         // security: `full_path` should be (layers of) subdir of `root_path`,
         //           so arbitrary write should not happen
-        fs::write(full_path.as_path(), text).map_err(io_error)?;
-        set_real_ownership(&full_path).map_err(io_error)?;
+        write_atomically(full_path.as_path(), data.as_ref()).map_err(io_error)?;
         content
     };
     Ok(content)
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AuthData {
     pub username: String,
     pub password: String,
 }
 
+/// Write through a temporary file renamed into place, so that readers (and mmap users) never
+/// observe a partially written file.
+pub(crate) fn write_atomically(path: &Path, content: &[u8]) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let tmp = PathBuf::from(tmp);
+    fs::write(&tmp, content)?;
+    set_real_ownership(&tmp)?;
+    fs::rename(&tmp, path)
+}
+
 pub(super) fn set_real_ownership(path: &Path) -> io::Result<()> {
     if let Some((_, uid, gid)) = get_user_info() {
         nix::unistd::chown(path, Some(uid.into()), Some(gid.into()))?;
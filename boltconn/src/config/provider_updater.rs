@@ -0,0 +1,786 @@
+use crate::config::rule_provider::is_binary_ruleset;
+use crate::config::{
+    parse_proxy_schema, safe_join_path, write_atomically, ConfigError, FileError, LoadedConfig,
+    ModuleLocation, ModuleSchema, ProxyLocation, ProxyProviderFormat, RawRootCfg, RawRuleSchema,
+    RuleLocation,
+};
+use crate::dispatch::RuleSet;
+use reqwest::header::{HeaderName, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+
+const SUBSCRIPTION_USERINFO: &str = "subscription-userinfo";
+
+/// Reports whether a forced refresh has been applied.
+type Responder = oneshot::Sender<bool>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProviderKind {
+    Rule,
+    Proxy(ProxyProviderFormat),
+    Module,
+}
+
+impl Display for ProviderKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            ProviderKind::Rule => "rule",
+            ProviderKind::Proxy(_) => "proxy",
+            ProviderKind::Module => "module",
+        })
+    }
+}
+
+/// Validated content of a remote provider, waiting to replace the cached copy.
+#[derive(Debug)]
+pub struct ProviderUpdate {
+    pub kind: ProviderKind,
+    pub name: String,
+    full_path: PathBuf,
+    content: Vec<u8>,
+    responder: Option<Responder>,
+}
+
+impl ProviderUpdate {
+    /// Replace the cached copy, returning the previous one for rollback.
+    pub fn commit(&self) -> io::Result<Option<Vec<u8>>> {
+        let previous = fs::read(&self.full_path).ok();
+        write_atomically(&self.full_path, &self.content)?;
+        Ok(previous)
+    }
+
+    pub fn rollback(&self, previous: Option<Vec<u8>>) -> io::Result<()> {
+        match previous {
+            Some(content) => write_atomically(&self.full_path, &content),
+            None => fs::remove_file(&self.full_path),
+        }
+    }
+
+    /// Notify the requester of a forced refresh, if any.
+    pub fn respond(&mut self, applied: bool) {
+        if let Some(responder) = self.responder.take() {
+            let _ = responder.send(applied);
+        }
+    }
+}
+
+/// Traffic quota reported by the `subscription-userinfo` header, in bytes and unix seconds.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SubscriptionUserInfo {
+    pub upload: Option<u64>,
+    pub download: Option<u64>,
+    pub total: Option<u64>,
+    pub expire: Option<u64>,
+}
+
+impl SubscriptionUserInfo {
+    /// Parse `upload=1; download=2; total=3; expire=4`, ignoring unknown or malformed fields.
+    pub fn parse(value: &str) -> Option<Self> {
+        let mut info = Self::default();
+        for (key, value) in value.split(';').filter_map(|kv| kv.split_once('=')) {
+            // some providers send floating-point numbers
+            let Ok(value) = value.trim().parse::<f64>() else {
+                continue;
+            };
+            let value = Some(value as u64);
+            match key.trim() {
+                "upload" => info.upload = value,
+                "download" => info.download = value,
+                "total" => info.total = value,
+                "expire" => info.expire = value,
+                _ => {}
+            }
+        }
+        (info != Self::default()).then_some(info)
+    }
+}
+
+/// Snapshot of a provider in the running configuration.
+#[derive(Debug, Clone)]
+pub struct ProviderInfo {
+    pub kind: ProviderKind,
+    pub name: String,
+    pub url: Option<String>,
+    pub path: String,
+    pub count: usize,
+    pub updated_at: Option<SystemTime>,
+    pub userinfo: Option<SubscriptionUserInfo>,
+}
+
+struct ProviderMeta {
+    kind: ProviderKind,
+    name: String,
+    url: Option<String>,
+    path: String,
+    count: usize,
+}
+
+/// Providers of the running configuration, shared with the controller across reloads.
+#[derive(Default)]
+pub struct ProviderRegistry {
+    config_path: PathBuf,
+    providers: RwLock<Vec<ProviderMeta>>,
+    userinfo: RwLock<HashMap<(ProviderKind, String), SubscriptionUserInfo>>,
+    triggers: RwLock<HashMap<(ProviderKind, String), mpsc::Sender<Responder>>>,
+}
+
+impl ProviderRegistry {
+    pub fn new(config_path: &Path) -> Self {
+        Self {
+            config_path: config_path.to_path_buf(),
+            ..Default::default()
+        }
+    }
+
+    /// Record providers of a newly loaded configuration.
+    pub fn refresh(&self, loaded_config: &LoadedConfig) {
+        let config = &loaded_config.config;
+        let mut providers = vec![];
+        for (name, provider) in &config.rule_provider {
+            let (url, path) = match &provider.location {
+                RuleLocation::File { path } => (None, path),
+                RuleLocation::Http { url, path, .. } => (Some(url.clone()), path),
+            };
+            providers.push(ProviderMeta {
+                kind: ProviderKind::Rule,
+                name: name.clone(),
+                url,
+                path: path.clone(),
+                count: loaded_config
+                    .rule_schema
+                    .get(name)
+                    .map_or(0, |s| s.payload.len()),
+            });
+        }
+        for (name, provider) in &config.proxy_provider {
+            let (url, path) = match &provider.location {
+                ProxyLocation::File { path } => (None, path),
+                ProxyLocation::Http { url, path, .. } => (Some(url.clone()), path),
+            };
+            providers.push(ProviderMeta {
+                kind: ProviderKind::Proxy(provider.format),
+                name: name.clone(),
+                url,
+                path: path.clone(),
+                count: loaded_config
+                    .proxy_schema
+                    .get(name)
+                    .map_or(0, |s| s.proxies.len()),
+            });
+        }
+        // modules are loaded in the order of declaration
+        for (module, schema) in config.module.iter().zip(&loaded_config.module_schema) {
+            let (url, path) = match &module.content {
+                ModuleLocation::File { path } => (None, path),
+                ModuleLocation::Http { url, path, .. } => (Some(url.clone()), path),
+            };
+            providers.push(ProviderMeta {
+                kind: ProviderKind::Module,
+                name: module.name.clone(),
+                url,
+                path: path.clone(),
+                count: schema.rule_local.len() + schema.interception.len(),
+            });
+        }
+        providers.sort_by_key(|p| (p.kind.to_string(), p.name.clone()));
+        *self.providers.write().unwrap() = providers;
+    }
+
+    pub fn list(&self) -> Vec<ProviderInfo> {
+        let userinfo = self.userinfo.read().unwrap();
+        self.providers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|p| ProviderInfo {
+                kind: p.kind,
+                name: p.name.clone(),
+                url: p.url.clone(),
+                path: p.path.clone(),
+                count: p.count,
+                // the cached copy is rewritten on every refresh
+                updated_at: safe_join_path(&self.config_path, &p.path)
+                    .and_then(fs::metadata)
+                    .and_then(|m| m.modified())
+                    .ok(),
+                userinfo: userinfo.get(&(p.kind, p.name.clone())).copied(),
+            })
+            .collect()
+    }
+
+    /// Refetch a remote provider immediately, returning whether the new content is applied.
+    /// `kind` can be omitted unless providers of different kinds share the name.
+    pub async fn update(&self, kind: Option<&str>, name: &str) -> bool {
+        let trigger = {
+            let triggers = self.triggers.read().unwrap();
+            let mut found = triggers
+                .iter()
+                .filter(|((k, n), _)| n == name && kind.map_or(true, |kind| k.to_string() == kind));
+            match (found.next(), found.next()) {
+                (Some((_, trigger)), None) => trigger.clone(),
+                (Some(_), Some(_)) => {
+                    tracing::warn!("Provider {} is ambiguous, specify its kind", name);
+                    return false;
+                }
+                _ => return false,
+            }
+        };
+        let (responder, result) = oneshot::channel();
+        if trigger.send(responder).await.is_err() {
+            return false;
+        }
+        result.await.unwrap_or(false)
+    }
+
+    fn set_userinfo(&self, kind: ProviderKind, name: &str, info: SubscriptionUserInfo) {
+        self.userinfo
+            .write()
+            .unwrap()
+            .insert((kind, name.to_string()), info);
+    }
+}
+
+/// Periodically refetch HTTP providers according to their `interval` in seconds.
+pub struct ProviderUpdater {
+    config_path: PathBuf,
+    sender: mpsc::Sender<ProviderUpdate>,
+    registry: Arc<ProviderRegistry>,
+    tasks: HashMap<(ProviderKind, String), (RemoteProvider, JoinHandle<()>)>,
+}
+
+impl ProviderUpdater {
+    pub fn new(
+        config_path: &Path,
+        sender: mpsc::Sender<ProviderUpdate>,
+        registry: Arc<ProviderRegistry>,
+    ) -> Self {
+        Self {
+            config_path: config_path.to_path_buf(),
+            sender,
+            registry,
+            tasks: HashMap::new(),
+        }
+    }
+
+    /// Follow the providers of a newly loaded configuration.
+    /// Unchanged providers keep refreshing with their validators, the others are restarted.
+    pub fn sync(&mut self, config: &RawRootCfg) {
+        self.sync_providers(RemoteProvider::from_config(config));
+    }
+
+    fn sync_providers(&mut self, providers: Vec<RemoteProvider>) {
+        let mut tasks = HashMap::new();
+        for provider in providers {
+            let key = (provider.kind, provider.name.clone());
+            match self.tasks.remove(&key) {
+                Some((running, handle)) if running == provider && !handle.is_finished() => {
+                    tasks.insert(key, (running, handle));
+                }
+                previous => {
+                    if let Some((_, handle)) = previous {
+                        handle.abort();
+                    }
+                    if let Some(handle) = self.spawn(provider.clone()) {
+                        tasks.insert(key, (provider, handle));
+                    }
+                }
+            }
+        }
+        // providers removed from the configuration
+        for (_, handle) in self.tasks.values() {
+            handle.abort();
+        }
+        self.registry
+            .triggers
+            .write()
+            .unwrap()
+            .retain(|key, _| tasks.contains_key(key));
+        self.tasks = tasks;
+    }
+
+    fn spawn(&self, provider: RemoteProvider) -> Option<JoinHandle<()>> {
+        let full_path = match safe_join_path(&self.config_path, &provider.path) {
+            Ok(p) => p,
+            Err(e) => {
+                tracing::warn!(
+                    "Skip refreshing {} provider {}: {}",
+                    provider.kind,
+                    provider.name,
+                    e
+                );
+                return None;
+            }
+        };
+        let (trigger, receiver) = mpsc::channel(1);
+        self.registry
+            .triggers
+            .write()
+            .unwrap()
+            .insert((provider.kind, provider.name.clone()), trigger);
+        Some(tokio::spawn(provider.refresh_loop(
+            full_path,
+            receiver,
+            self.sender.clone(),
+            self.registry.clone(),
+        )))
+    }
+}
+
+impl Drop for ProviderUpdater {
+    fn drop(&mut self) {
+        for (_, handle) in self.tasks.values() {
+            handle.abort();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct RemoteProvider {
+    kind: ProviderKind,
+    name: String,
+    url: String,
+    path: String,
+    interval: Duration,
+}
+
+impl RemoteProvider {
+    fn new(kind: ProviderKind, name: &str, url: &str, path: &str, interval: u32) -> Self {
+        Self {
+            kind,
+            name: name.to_string(),
+            url: url.to_string(),
+            path: path.to_string(),
+            interval: Duration::from_secs(interval as u64),
+        }
+    }
+
+    fn from_config(config: &RawRootCfg) -> Vec<Self> {
+        let mut providers = vec![];
+        for (name, provider) in &config.rule_provider {
+            if let RuleLocation::Http {
+                url,
+                path,
+                interval,
+            } = &provider.location
+            {
+                providers.push(Self::new(ProviderKind::Rule, name, url, path, *interval));
+            }
+        }
+        for (name, provider) in &config.proxy_provider {
+            if let ProxyLocation::Http {
+                url,
+                path,
+                interval,
+            } = &provider.location
+            {
+                providers.push(Self::new(
+                    ProviderKind::Proxy(provider.format),
+                    name,
+                    url,
+                    path,
+                    *interval,
+                ));
+            }
+        }
+        for module in &config.module {
+            if let ModuleLocation::Http {
+                url,
+                path,
+                interval,
+            } = &module.content
+            {
+                providers.push(Self::new(
+                    ProviderKind::Module,
+                    &module.name,
+                    url,
+                    path,
+                    *interval,
+                ));
+            }
+        }
+        providers
+    }
+
+    async fn refresh_loop(
+        self,
+        full_path: PathBuf,
+        mut trigger: mpsc::Receiver<Responder>,
+        sender: mpsc::Sender<ProviderUpdate>,
+        registry: Arc<ProviderRegistry>,
+    ) {
+        let mut validators = Validators::default();
+        // the cached copy is as fresh as its modification time
+        let mut delay = self.interval;
+        if let Ok(modified) = fs::metadata(&full_path).and_then(|m| m.modified()) {
+            delay = self
+                .interval
+                .saturating_sub(modified.elapsed().unwrap_or_default());
+            validators.last_modified = Some(httpdate::fmt_http_date(modified));
+        }
+        loop {
+            // zero interval disables periodic refreshing, leaving only forced ones
+            let responder = tokio::select! {
+                _ = tokio::time::sleep(delay), if !self.interval.is_zero() => None,
+                responder = trigger.recv() => match responder {
+                    Some(r) => Some(r),
+                    // replaced by a newer updater
+                    None => return,
+                },
+            };
+            delay = self.interval;
+            let content = match validators.fetch(&self.url, responder.is_some()).await {
+                Ok((Some(content), userinfo)) => {
+                    if let Some(info) = userinfo {
+                        registry.set_userinfo(self.kind, &self.name, info);
+                    }
+                    content
+                }
+                Ok((None, _)) => {
+                    tracing::debug!("{} provider {} is not modified", self.kind, self.name);
+                    continue;
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "Failed to refresh {} provider {}: {}",
+                        self.kind,
+                        self.name,
+                        e
+                    );
+                    continue;
+                }
+            };
+            if let Err(e) = self.validate(&content) {
+                tracing::warn!(
+                    "Keep the last copy of {} provider {}: {}",
+                    self.kind,
+                    self.name,
+                    e
+                );
+                continue;
+            }
+            let update = ProviderUpdate {
+                kind: self.kind,
+                name: self.name.clone(),
+                full_path: full_path.clone(),
+                content,
+                responder,
+            };
+            if sender.send(update).await.is_err() {
+                return;
+            }
+        }
+    }
+
+    fn validate(&self, content: &[u8]) -> Result<(), ConfigError> {
+        let serde_error = |e| FileError::Serde(self.path.clone(), e);
+        match self.kind {
+            ProviderKind::Rule => {
+                if is_binary_ruleset(content) {
+                    RuleSet::validate_binary(&self.name, content)?;
+                } else {
+                    serde_yaml::from_slice::<RawRuleSchema>(content).map_err(serde_error)?;
+                }
+            }
+            ProviderKind::Proxy(format) => {
+                let content = String::from_utf8_lossy(content);
+                // an empty list is more likely an error page than an empty subscription
+                if parse_proxy_schema(&self.path, &content, format)?
+                    .proxies
+                    .is_empty()
+                {
+                    return Err(FileError::Format(self.path.clone(), "no proxy").into());
+                }
+            }
+            ProviderKind::Module => {
+                serde_yaml::from_slice::<ModuleSchema>(content).map_err(serde_error)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    /// Fetch the content unless `force`d to skip validators; `None` means not modified.
+    async fn fetch(
+        &mut self,
+        url: &str,
+        force: bool,
+    ) -> reqwest::Result<(Option<Vec<u8>>, Option<SubscriptionUserInfo>)> {
+        let mut req = reqwest::Client::new().get(url);
+        if !force {
+            if let Some(etag) = &self.etag {
+                req = req.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &self.last_modified {
+                req = req.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let resp = req.send().await?;
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Ok((None, None));
+        }
+        let resp = resp.error_for_status()?;
+        let header = |name| {
+            resp.headers()
+                .get(name)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_string)
+        };
+        self.etag = header(ETAG);
+        self.last_modified = header(LAST_MODIFIED);
+        let userinfo = header(HeaderName::from_static(SUBSCRIPTION_USERINFO))
+            .as_deref()
+            .and_then(SubscriptionUserInfo::parse);
+        Ok((Some(resp.bytes().await?.to_vec()), userinfo))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_subscription_userinfo() {
+        let info = SubscriptionUserInfo::parse(
+            "upload=455727941; download=6174315083; total=1073741824000; expire=1671815872",
+        )
+        .unwrap();
+        assert_eq!(info.upload, Some(455727941));
+        assert_eq!(info.download, Some(6174315083));
+        assert_eq!(info.total, Some(1073741824000));
+        assert_eq!(info.expire, Some(1671815872));
+        let info = SubscriptionUserInfo::parse("upload=0; download=1.5e3; expire=").unwrap();
+        assert_eq!(info.download, Some(1500));
+        assert_eq!(info.expire, None);
+        assert!(SubscriptionUserInfo::parse("garbage").is_none());
+    }
+
+    #[test]
+    fn test_commit_rollback() {
+        let dir = tempfile::tempdir().unwrap();
+        let full_path = dir.path().join("rules.yml");
+        let update = |content: &[u8]| ProviderUpdate {
+            kind: ProviderKind::Rule,
+            name: "rules".to_string(),
+            full_path: full_path.clone(),
+            content: content.to_vec(),
+            responder: None,
+        };
+        // the file is replaced as a whole, without a temporary file left behind
+        assert_eq!(update(b"first").commit().unwrap(), None);
+        let second = update(b"second");
+        let previous = second.commit().unwrap();
+        assert_eq!(previous.as_deref(), Some(&b"first"[..]));
+        assert_eq!(fs::read(&full_path).unwrap(), b"second");
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+        second.rollback(previous).unwrap();
+        assert_eq!(fs::read(&full_path).unwrap(), b"first");
+        update(b"first").rollback(None).unwrap();
+        assert!(!full_path.exists());
+    }
+
+    // Answer each connection with the next response, collecting the requests
+    async fn serve(responses: Vec<&'static str>) -> (String, JoinHandle<Vec<String>>) {
+        use tokio::io::{AsyncReadExt, AsyncWriteExt};
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/sub", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            let mut requests = vec![];
+            for resp in responses {
+                let (mut conn, _) = listener.accept().await.unwrap();
+                let mut req = vec![];
+                let mut buf = [0u8; 1024];
+                while !req.ends_with(b"\r\n\r\n") {
+                    let n = conn.read(&mut buf).await.unwrap();
+                    req.extend_from_slice(&buf[..n]);
+                }
+                requests.push(String::from_utf8(req).unwrap().to_lowercase());
+                conn.write_all(resp.as_bytes()).await.unwrap();
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_validators() {
+        let (url, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nETag: \"v1\"\r\nLast-Modified: Wed, 21 Oct 2015 07:28:00 GMT\r\n\
+             Subscription-Userinfo: upload=1; download=2\r\nContent-Length: 5\r\n\
+             Connection: close\r\n\r\nhello",
+            "HTTP/1.1 304 Not Modified\r\nConnection: close\r\n\r\n",
+            "HTTP/1.1 200 OK\r\nContent-Length: 5\r\nConnection: close\r\n\r\nworld",
+            "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        ])
+        .await;
+        let mut validators = Validators::default();
+        let (content, userinfo) = validators.fetch(&url, false).await.unwrap();
+        assert_eq!(content.as_deref(), Some(b"hello".as_slice()));
+        assert_eq!(userinfo.unwrap().download, Some(2));
+        assert_eq!(validators.etag.as_deref(), Some("\"v1\""));
+        // not modified
+        assert!(validators.fetch(&url, false).await.unwrap().0.is_none());
+        // forced refreshes skip validators
+        let (content, _) = validators.fetch(&url, true).await.unwrap();
+        assert_eq!(content.as_deref(), Some(b"world".as_slice()));
+        assert!(validators.etag.is_none());
+        assert!(validators.fetch(&url, false).await.is_err());
+
+        let requests = server.await.unwrap();
+        assert!(!requests[0].contains("if-none-match"));
+        assert!(requests[1].contains("if-none-match: \"v1\""));
+        assert!(requests[1].contains("if-modified-since: wed, 21 oct 2015 07:28:00 gmt"));
+        assert!(!requests[2].contains("if-none-match"));
+        assert!(!requests[3].contains("if-none-match"));
+    }
+
+    #[test]
+    fn test_validate() {
+        let provider = |kind| RemoteProvider::new(kind, "test", "http://127.0.0.1/", "test.yml", 0);
+        let rule = provider(ProviderKind::Rule);
+        assert!(rule.validate(b"payload:\n  - DOMAIN,example.com\n").is_ok());
+        assert!(rule.validate(b"<html></html>").is_err());
+        assert!(rule.validate(b"BCRS\x02garbage").is_err());
+        let schema = crate::config::RuleSchema {
+            behavior: crate::config::ProviderBehavior::Domain,
+            payload: vec!["example.com".to_string()],
+            binary: None,
+        };
+        let compiled = crate::dispatch::RuleSetBuilder::new("test", &schema)
+            .unwrap()
+            .compile()
+            .unwrap();
+        assert!(rule.validate(&compiled).is_ok());
+
+        let proxy = provider(ProviderKind::Proxy(ProxyProviderFormat::Clash));
+        assert!(proxy
+            .validate(b"proxies:\n  - {name: a, type: socks5, server: 1.1.1.1, port: 1080}\n")
+            .is_ok());
+        // an empty subscription is rejected
+        assert!(proxy.validate(b"proxies: []\n").is_err());
+        assert!(proxy.validate(b"<html></html>").is_err());
+
+        let module = provider(ProviderKind::Module);
+        assert!(module
+            .validate(b"rule-local:\n  - DOMAIN,example.com,DIRECT\n")
+            .is_ok());
+        assert!(module.validate(b"unknown-field: 1\n").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_registry_update() {
+        let registry = ProviderRegistry::default();
+        let mut receivers = vec![];
+        for kind in [
+            ProviderKind::Rule,
+            ProviderKind::Proxy(ProxyProviderFormat::Auto),
+        ] {
+            for name in ["shared", &format!("{}-only", kind)] {
+                let (trigger, receiver) = mpsc::channel::<Responder>(1);
+                registry
+                    .triggers
+                    .write()
+                    .unwrap()
+                    .insert((kind, name.to_string()), trigger);
+                receivers.push((kind, name.to_string(), receiver));
+            }
+        }
+        let (hits, mut hit_receiver) = mpsc::unbounded_channel();
+        for (kind, name, mut receiver) in receivers {
+            let hits = hits.clone();
+            tokio::spawn(async move {
+                while let Some(responder) = receiver.recv().await {
+                    let _ = hits.send((kind, name.clone()));
+                    let _ = responder.send(true);
+                }
+            });
+        }
+
+        assert!(registry.update(None, "rule-only").await);
+        assert_eq!(
+            hit_receiver.recv().await,
+            Some((ProviderKind::Rule, "rule-only".to_string()))
+        );
+        assert!(registry.update(Some("proxy"), "shared").await);
+        assert_eq!(
+            hit_receiver.recv().await,
+            Some((
+                ProviderKind::Proxy(ProxyProviderFormat::Auto),
+                "shared".to_string()
+            ))
+        );
+        // ambiguous, mismatched or unknown
+        assert!(!registry.update(None, "shared").await);
+        assert!(!registry.update(Some("module"), "shared").await);
+        assert!(!registry.update(Some("rule"), "proxy-only").await);
+        assert!(!registry.update(None, "missing").await);
+        assert!(hit_receiver.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_updater_schedule() {
+        let tmp = tempfile::tempdir().unwrap();
+        let dir = tmp.path();
+        let (url, server) = serve(vec![
+            "HTTP/1.1 200 OK\r\nContent-Length: 31\r\nConnection: close\r\n\r\n\
+             payload:\n  - DOMAIN,example.com",
+            "HTTP/1.1 200 OK\r\nContent-Length: 13\r\nConnection: close\r\n\r\n\
+             <html></html>",
+        ])
+        .await;
+        let (sender, mut receiver) = mpsc::channel(8);
+        let registry = Arc::new(ProviderRegistry::new(dir));
+        let mut updater = ProviderUpdater::new(dir, sender, registry.clone());
+        // zero interval: forced refreshes only
+        let rule = RemoteProvider::new(ProviderKind::Rule, "rules", &url, "rules.yml", 0);
+        let module = RemoteProvider::new(ProviderKind::Module, "mod", &url, "mod.yml", 0);
+        updater.sync_providers(vec![rule.clone(), module.clone()]);
+        let trigger =
+            |kind, name: &str| registry.triggers.read().unwrap()[&(kind, name.to_string())].clone();
+        let rule_trigger = trigger(ProviderKind::Rule, "rules");
+        let module_trigger = trigger(ProviderKind::Module, "mod");
+
+        // a forced refresh is handed over for applying
+        let update = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.update(None, "rules").await }
+        });
+        let mut applied = receiver.recv().await.unwrap();
+        assert_eq!(applied.kind, ProviderKind::Rule);
+        assert_eq!(applied.full_path, dir.join("rules.yml"));
+        assert_eq!(applied.content, b"payload:\n  - DOMAIN,example.com");
+        applied.respond(true);
+        assert!(update.await.unwrap());
+        // invalid content is dropped, so the requester is never answered
+        let (responder, result) = oneshot::channel();
+        rule_trigger.send(responder).await.unwrap();
+        assert!(result.await.is_err());
+        assert!(receiver.try_recv().is_err());
+        server.await.unwrap();
+
+        // unchanged providers keep running, changed ones restart and removed ones stop
+        let moved = RemoteProvider::new(ProviderKind::Rule, "rules", &url, "moved.yml", 0);
+        updater.sync_providers(vec![module.clone(), moved]);
+        assert!(trigger(ProviderKind::Module, "mod").same_channel(&module_trigger));
+        assert!(!trigger(ProviderKind::Rule, "rules").same_channel(&rule_trigger));
+        updater.sync_providers(vec![module]);
+        assert_eq!(registry.triggers.read().unwrap().len(), 1);
+        drop(updater);
+        tokio::task::yield_now().await;
+        assert!(module_trigger.send(oneshot::channel().0).await.is_err());
+    }
+}
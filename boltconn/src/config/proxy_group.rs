@@ -1,24 +1,133 @@
+use crate::config::ProviderError;
+use regex::Regex;
 use serde::{Deserialize, Serialize};
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields)]
 pub struct RawProxyGroupCfg {
+    #[serde(rename = "type", default = "default_group_type")]
+    pub group_type: RawProxyGroupType,
     pub proxies: Option<Vec<String>>,
     pub providers: Option<Vec<RawProxyProviderOption>>,
     pub chains: Option<Vec<String>>,
     pub interface: Option<String>,
+    // in seconds
+    pub interval: Option<u32>,
+    // in milliseconds
+    pub tolerance: Option<u32>,
+    pub strategy: Option<LoadBalanceStrategy>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RawProxyGroupType {
+    #[serde(alias = "select")]
+    Select,
+    #[serde(alias = "url-test")]
+    UrlTest,
+    #[serde(alias = "fallback")]
+    Fallback,
+    #[serde(alias = "load-balance")]
+    LoadBalance,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LoadBalanceStrategy {
+    /// Connections to the same destination use the same proxy
+    #[serde(alias = "consistent-hashing")]
+    ConsistentHashing,
+    /// Connections from the same source use the same proxy
+    #[serde(alias = "sticky-sessions")]
+    StickySessions,
+    #[serde(alias = "round-robin")]
+    RoundRobin,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(deny_unknown_fields, untagged)]
 pub enum RawProxyProviderOption {
     Name(String),
-    Filter { name: String, filter: String },
+    Filter {
+        name: String,
+        filter: Option<String>,
+        #[serde(alias = "exclude-filter")]
+        exclude_filter: Option<String>,
+    },
+}
+
+impl RawProxyProviderOption {
+    pub fn provider_name(&self) -> &str {
+        match self {
+            RawProxyProviderOption::Name(name) | RawProxyProviderOption::Filter { name, .. } => {
+                name.as_str()
+            }
+        }
+    }
+
+    /// Pick proxies of the provider by `filter` and `exclude-filter`.
+    pub fn select<'a>(
+        &self,
+        proxies: impl Iterator<Item = &'a str>,
+    ) -> Result<Vec<&'a str>, ProviderError> {
+        match self {
+            RawProxyProviderOption::Name(_) => Ok(proxies.collect()),
+            RawProxyProviderOption::Filter {
+                name,
+                filter,
+                exclude_filter,
+            } => {
+                let compile = |f: &String| {
+                    Regex::new(f).map_err(|_| ProviderError::BadFilter(name.clone(), f.clone()))
+                };
+                let filter = filter.as_ref().map(compile).transpose()?;
+                let exclude_filter = exclude_filter.as_ref().map(compile).transpose()?;
+                Ok(proxies
+                    .filter(|proxy| {
+                        filter.as_ref().map_or(true, |r| r.is_match(proxy))
+                            && !exclude_filter.as_ref().is_some_and(|r| r.is_match(proxy))
+                    })
+                    .collect())
+            }
+        }
+    }
 }
 
 impl RawProxyGroupCfg {
     pub fn roughly_validate(&self) -> bool {
         let valid_proxy_list = !(self.proxies.is_none() && self.providers.is_none());
-        valid_proxy_list ^ self.chains.is_some()
+        // chains are not selectable, so they cannot be switched automatically
+        let valid_type = self.group_type == RawProxyGroupType::Select || self.chains.is_none();
+        (valid_proxy_list ^ self.chains.is_some()) && valid_type
+    }
+}
+
+fn default_group_type() -> RawProxyGroupType {
+    RawProxyGroupType::Select
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_provider_filter() {
+        let proxies = ["HK 01", "HK 02 Premium", "US 01", "Info: expire 2025"];
+        let select = |option: &str| {
+            let option: RawProxyProviderOption = serde_yaml::from_str(option).unwrap();
+            option.select(proxies.iter().copied())
+        };
+        assert_eq!(select("sub").unwrap().len(), 4);
+        assert_eq!(
+            select("{name: sub, filter: '^HK'}").unwrap(),
+            vec!["HK 01", "HK 02 Premium"]
+        );
+        assert_eq!(
+            select("{name: sub, exclude-filter: 'Info|Premium'}").unwrap(),
+            vec!["HK 01", "US 01"]
+        );
+        assert_eq!(
+            select("{name: sub, filter: 'HK', exclude-filter: 'Premium'}").unwrap(),
+            vec!["HK 01"]
+        );
+        assert!(select("{name: sub, exclude-filter: '('}").is_err());
     }
 }
@@ -1,4 +1,7 @@
-use crate::config::{load_remote_config, safe_join_path, ConfigError, FileError, RawProxyLocalCfg};
+use crate::config::{
+    config::default_false, load_remote_content, parse_proxy_schema, safe_join_path, ConfigError,
+    FileError, ProxyProviderFormat, RawDialOptions, RawProxyLocalCfg,
+};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
@@ -8,7 +11,7 @@ use tokio::task::JoinHandle;
 #[derive(Serialize, Deserialize, Debug, Clone)]
 #[serde(tag = "type")]
 // not deny_unknown_fields, in order to achieve compatibility
-pub enum ProxyProvider {
+pub enum ProxyLocation {
     #[serde(alias = "file")]
     File { path: String },
     #[serde(alias = "http")]
@@ -19,6 +22,109 @@ pub enum ProxyProvider {
     },
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProxyProvider {
+    #[serde(default = "default_boltconn_format")]
+    pub format: ProxyProviderFormat,
+    #[serde(flatten)]
+    pub location: ProxyLocation,
+    #[serde(alias = "health-check")]
+    pub health_check: Option<RawProviderHealthCheck>,
+    #[serde(rename = "override")]
+    pub overrides: Option<RawProviderOverride>,
+}
+
+fn default_boltconn_format() -> ProxyProviderFormat {
+    ProxyProviderFormat::BoltConn
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RawProviderHealthCheck {
+    pub url: Option<String>,
+    // in seconds
+    #[serde(default = "default_health_check_interval")]
+    pub interval: u32,
+    /// Skip the test if no proxy of the provider has been used since the last one
+    #[serde(default = "default_false")]
+    pub lazy: bool,
+}
+
+fn default_health_check_interval() -> u32 {
+    300
+}
+
+/// Options applied to every proxy of a provider.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct RawProviderOverride {
+    pub udp: Option<bool>,
+    #[serde(alias = "skip-cert-verify")]
+    pub skip_cert_verify: Option<bool>,
+    pub dial: Option<RawDialOptions>,
+}
+
+impl RawProviderOverride {
+    pub fn apply(&self, cfg: &mut RawProxyLocalCfg) {
+        let (udp, skip_cert_verify, dial) = match cfg {
+            RawProxyLocalCfg::Http {
+                skip_cert_verify,
+                dial,
+                ..
+            } => (None, Some(skip_cert_verify), dial),
+            RawProxyLocalCfg::Socks5 {
+                udp,
+                skip_cert_verify,
+                dial,
+                ..
+            }
+            | RawProxyLocalCfg::Trojan {
+                udp,
+                skip_cert_verify,
+                dial,
+                ..
+            }
+            | RawProxyLocalCfg::Vmess {
+                udp,
+                skip_cert_verify,
+                dial,
+                ..
+            }
+            | RawProxyLocalCfg::Vless {
+                udp,
+                skip_cert_verify,
+                dial,
+                ..
+            }
+            | RawProxyLocalCfg::Hysteria2 {
+                udp,
+                skip_cert_verify,
+                dial,
+                ..
+            }
+            | RawProxyLocalCfg::Tuic {
+                udp,
+                skip_cert_verify,
+                dial,
+                ..
+            } => (Some(udp), Some(skip_cert_verify), dial),
+            RawProxyLocalCfg::Shadowsocks { udp, dial, .. } => (Some(udp), None, dial),
+            RawProxyLocalCfg::Wireguard { dial, .. }
+            | RawProxyLocalCfg::WireguardConf { dial, .. }
+            | RawProxyLocalCfg::Ssh { dial, .. } => (None, None, dial),
+        };
+        if let (Some(value), Some(udp)) = (self.udp, udp) {
+            *udp = value;
+        }
+        if let (Some(value), Some(skip_cert_verify)) = (self.skip_cert_verify, skip_cert_verify) {
+            *skip_cert_verify = value;
+        }
+        if self.dial.is_some() {
+            dial.clone_from(&self.dial);
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct RawProxyProviderCfg {
     pub name: String,
@@ -53,23 +159,33 @@ pub async fn read_proxy_schema(
             (
                 name,
                 tokio::spawn(async move {
-                    match item {
-                        ProxyProvider::File { path } => {
+                    let format = item.format;
+                    let mut schema = match item.location {
+                        ProxyLocation::File { path } => {
                             let io_err = |e| FileError::Io(path.clone(), e);
-                            let content: ProxySchema = serde_yaml::from_str(
+                            parse_proxy_schema(
+                                &path,
                                 fs::read_to_string(
                                     safe_join_path(&root_path, &path).map_err(io_err)?,
                                 )
                                 .map_err(io_err)?
                                 .as_str(),
-                            )
-                            .map_err(|e| FileError::Serde(path.clone(), e))?;
-                            Ok(content)
+                                format,
+                            )?
+                        }
+                        ProxyLocation::Http { url, path, .. } => {
+                            load_remote_content(&url, &path, &root_path, force_update, |data| {
+                                parse_proxy_schema(&path, &String::from_utf8_lossy(data), format)
+                            })
+                            .await?
                         }
-                        ProxyProvider::Http { url, path, .. } => {
-                            Ok(load_remote_config(&url, &path, &root_path, force_update).await?)
+                    };
+                    if let Some(overrides) = &item.overrides {
+                        for proxy in schema.proxies.iter_mut() {
+                            overrides.apply(&mut proxy.cfg);
                         }
                     }
+                    Ok(schema)
                 }),
             )
         })
@@ -83,3 +199,55 @@ pub async fn read_proxy_schema(
     }
     Ok(table)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_provider_override() {
+        let overrides: RawProviderOverride =
+            serde_yaml::from_str("{udp: false, skip-cert-verify: true, dial: {mark: 255}}")
+                .unwrap();
+        let mut socks5: RawProxyLocalCfg =
+            serde_yaml::from_str("{type: socks5, server: example.com, port: 1080}").unwrap();
+        overrides.apply(&mut socks5);
+        let RawProxyLocalCfg::Socks5 {
+            udp,
+            skip_cert_verify,
+            dial,
+            ..
+        } = socks5
+        else {
+            unreachable!()
+        };
+        assert!(!udp);
+        assert!(skip_cert_verify);
+        assert_eq!(dial.and_then(|d| d.mark), Some(255));
+
+        // options a proxy does not have are left out
+        let mut http: RawProxyLocalCfg =
+            serde_yaml::from_str("{type: http, server: 127.0.0.1, port: 8080}").unwrap();
+        overrides.apply(&mut http);
+        let RawProxyLocalCfg::Http {
+            skip_cert_verify, ..
+        } = http
+        else {
+            unreachable!()
+        };
+        assert!(skip_cert_verify);
+
+        let empty: RawProviderOverride = serde_yaml::from_str("{}").unwrap();
+        let mut ss: RawProxyLocalCfg = serde_yaml::from_str(
+            "{type: ss, server: 1.1.1.1, port: 8388, password: pwd, cipher: aes-128-gcm, \
+             dial: {interface: eth0}}",
+        )
+        .unwrap();
+        empty.apply(&mut ss);
+        let RawProxyLocalCfg::Shadowsocks { udp, dial, .. } = ss else {
+            unreachable!()
+        };
+        assert!(udp);
+        assert_eq!(dial.and_then(|d| d.interface), Some("eth0".to_string()));
+    }
+}
@@ -1,12 +1,14 @@
 use crate::config;
-use crate::config::{load_remote_config, ConfigError, FileError};
+use crate::config::{load_remote_content, ConfigError, FileError};
+use crate::dispatch::RuleSet;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::io::Read;
+use std::path::{Path, PathBuf};
 use tokio::task::JoinHandle;
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
 // not deny_unknown_fields, in order to achieve compatibility
 pub enum RuleLocation {
@@ -20,7 +22,7 @@ pub enum RuleLocation {
     },
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub struct RuleProvider {
     #[serde(default = "default_classical")]
@@ -29,7 +31,7 @@ pub struct RuleProvider {
     pub location: RuleLocation,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub enum ProviderBehavior {
     #[serde(alias = "domain")]
     Domain,
@@ -53,6 +55,29 @@ pub struct RawRuleSchema {
 pub struct RuleSchema {
     pub behavior: ProviderBehavior,
     pub payload: Vec<String>,
+    /// Binary rule-set loaded in place of `payload`
+    pub binary: Option<BinaryRuleSet>,
+}
+
+#[derive(Debug, Clone)]
+pub enum BinaryRuleSet {
+    /// File of users, which may be rewritten in place
+    File(PathBuf),
+    /// Cached copy of a remote rule-set, which is only replaced by renaming
+    Cached(PathBuf),
+}
+
+// compiled rule-sets of BoltConn and sing-box rule-sets
+const BINARY_MAGICS: [&[u8]; 2] = [b"BCRS", b"SRS"];
+
+pub(super) fn is_binary_ruleset(data: &[u8]) -> bool {
+    BINARY_MAGICS.iter().any(|m| data.starts_with(m))
+}
+
+fn is_binary_ruleset_file(path: &Path) -> std::io::Result<bool> {
+    let mut magic = Vec::with_capacity(4);
+    fs::File::open(path)?.take(4).read_to_end(&mut magic)?;
+    Ok(is_binary_ruleset(&magic))
 }
 
 pub async fn read_rule_schema(
@@ -67,6 +92,7 @@ pub async fn read_rule_schema(
         .into_iter()
         .map(|(name, item)| {
             let root_path = config_path.to_path_buf();
+            let ruleset_name = name.clone();
             (
                 name,
                 tokio::spawn(async move {
@@ -76,25 +102,55 @@ pub async fn read_rule_schema(
 
                     match item.location {
                         RuleLocation::File { path } => {
+                            let full_path =
+                                config::safe_join_path(&root_path, &path).map_err(io_error)?;
+                            if is_binary_ruleset_file(&full_path).map_err(io_error)? {
+                                return Ok(RuleSchema {
+                                    behavior: item.behavior,
+                                    payload: vec![],
+                                    binary: Some(BinaryRuleSet::File(full_path)),
+                                });
+                            }
                             let content: RawRuleSchema = serde_yaml::from_str(
-                                fs::read_to_string(
-                                    config::safe_join_path(&root_path, &path).map_err(io_error)?,
-                                )
-                                .map_err(io_error)?
-                                .as_str(),
+                                fs::read_to_string(full_path).map_err(io_error)?.as_str(),
                             )
                             .map_err(serde_error)?;
                             Ok(RuleSchema {
                                 behavior: item.behavior,
                                 payload: content.payload,
+                                binary: None,
                             })
                         }
                         RuleLocation::Http { url, path, .. } => {
-                            let content: RawRuleSchema =
-                                load_remote_config(&url, &path, &root_path, force_update).await?;
-                            Ok(RuleSchema {
-                                behavior: item.behavior,
-                                payload: content.payload,
+                            let full_path =
+                                config::safe_join_path(&root_path, &path).map_err(io_error)?;
+                            // binary rule-sets are loaded from the cached copy
+                            let content = load_remote_content(
+                                &url,
+                                &path,
+                                &root_path,
+                                force_update,
+                                |data| -> Result<Option<RawRuleSchema>, ConfigError> {
+                                    if is_binary_ruleset(data) {
+                                        RuleSet::validate_binary(&ruleset_name, data)?;
+                                        Ok(None)
+                                    } else {
+                                        Ok(Some(serde_yaml::from_slice(data).map_err(serde_error)?))
+                                    }
+                                },
+                            )
+                            .await?;
+                            Ok(match content {
+                                Some(content) => RuleSchema {
+                                    behavior: item.behavior,
+                                    payload: content.payload,
+                                    binary: None,
+                                },
+                                None => RuleSchema {
+                                    behavior: item.behavior,
+                                    payload: vec![],
+                                    binary: Some(BinaryRuleSet::Cached(full_path)),
+                                },
                             })
                         }
                     }
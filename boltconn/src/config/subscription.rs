@@ -0,0 +1,571 @@
+use crate::config::{
+    AuthData, FileError, ProxySchema, RawProxyLocalCfg, RawProxyProviderCfg, RawServerAddr,
+    RawShadowsocksPlugin, SingleOrVec,
+};
+use crate::transport::tuic::TuicUdpRelayMode;
+use crate::transport::vmess::VmessSecurity;
+use base64::Engine;
+use percent_encoding::percent_decode_str;
+use serde::{Deserialize, Serialize};
+use serde_yaml::{Mapping, Value};
+use std::collections::HashSet;
+use std::net::IpAddr;
+use url::Url;
+
+const SS_CIPHERS: [&str; 3] = ["chacha20-ietf-poly1305", "aes-256-gcm", "aes-128-gcm"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ProxyProviderFormat {
+    #[serde(alias = "boltconn")]
+    BoltConn,
+    #[serde(alias = "clash")]
+    Clash,
+    #[serde(alias = "uri-list")]
+    UriList,
+    #[serde(alias = "auto")]
+    Auto,
+}
+
+type ConvertResult<T> = Result<T, &'static str>;
+
+/// Parse the content of a proxy provider; unsupported entries of foreign formats are skipped.
+pub fn parse_proxy_schema(
+    path: &str,
+    text: &str,
+    format: ProxyProviderFormat,
+) -> Result<ProxySchema, FileError> {
+    let serde_error = |e| FileError::Serde(path.to_string(), e);
+    match format {
+        ProxyProviderFormat::BoltConn => serde_yaml::from_str(text).map_err(serde_error),
+        ProxyProviderFormat::Clash => {
+            let root: Value = serde_yaml::from_str(text).map_err(serde_error)?;
+            from_clash(path, &root)
+        }
+        ProxyProviderFormat::UriList => from_uri_list(path, text),
+        ProxyProviderFormat::Auto => {
+            if let Ok(schema) = serde_yaml::from_str::<ProxySchema>(text) {
+                return Ok(schema);
+            }
+            match serde_yaml::from_str::<Value>(text) {
+                Ok(root) if root.get("proxies").is_some() => from_clash(path, &root),
+                _ => from_uri_list(path, text),
+            }
+        }
+    }
+}
+
+fn from_clash(path: &str, root: &Value) -> Result<ProxySchema, FileError> {
+    let Some(proxies) = root.get("proxies").and_then(Value::as_sequence) else {
+        return Err(FileError::Format(path.to_string(), "missing proxies"));
+    };
+    let mut schema = SchemaBuilder::new(path);
+    for (idx, entry) in proxies.iter().enumerate() {
+        let Some(map) = entry.as_mapping() else {
+            tracing::warn!("Skipped proxy #{} in {}: not a mapping", idx, path);
+            continue;
+        };
+        let proxy = ClashProxy(map);
+        let Some(name) = proxy.str("name") else {
+            tracing::warn!("Skipped proxy #{} in {}: missing name", idx, path);
+            continue;
+        };
+        schema.push(name, proxy.convert());
+    }
+    Ok(schema.finish())
+}
+
+fn from_uri_list(path: &str, text: &str) -> Result<ProxySchema, FileError> {
+    let text = if text.contains("://") {
+        text.to_string()
+    } else {
+        let compact: String = text.split_whitespace().collect();
+        base64_decode(compact.as_str())
+            .and_then(|d| String::from_utf8(d).ok())
+            .ok_or_else(|| FileError::Format(path.to_string(), "neither URI list nor base64"))?
+    };
+    let mut schema = SchemaBuilder::new(path);
+    for line in text.lines().map(str::trim).filter(|l| !l.is_empty()) {
+        let Ok(url) = Url::parse(line) else {
+            tracing::warn!("Skipped invalid link in {}", path);
+            continue;
+        };
+        let name = match url.fragment() {
+            Some(f) if !f.is_empty() => percent_decode(f),
+            _ => format!(
+                "{}:{}",
+                url.host_str().unwrap_or_default(),
+                url.port().unwrap_or(0)
+            ),
+        };
+        let cfg = match url.scheme() {
+            "ss" => ss_uri(&url, line),
+            "trojan" => trojan_uri(&url),
+            "vless" => vless_uri(&url),
+            _ => Err("unsupported scheme"),
+        };
+        schema.push(name, cfg);
+    }
+    Ok(schema.finish())
+}
+
+struct SchemaBuilder<'a> {
+    path: &'a str,
+    names: HashSet<String>,
+    proxies: Vec<RawProxyProviderCfg>,
+}
+
+impl<'a> SchemaBuilder<'a> {
+    fn new(path: &'a str) -> Self {
+        Self {
+            path,
+            names: HashSet::new(),
+            proxies: vec![],
+        }
+    }
+
+    fn push(&mut self, name: String, cfg: ConvertResult<RawProxyLocalCfg>) {
+        match cfg {
+            Ok(_) if self.names.contains(&name) => {
+                tracing::warn!("Skipped proxy {} in {}: duplicate name", name, self.path)
+            }
+            Ok(cfg) => {
+                self.names.insert(name.clone());
+                self.proxies.push(RawProxyProviderCfg { name, cfg });
+            }
+            Err(reason) => tracing::warn!("Skipped proxy {} in {}: {}", name, self.path, reason),
+        }
+    }
+
+    fn finish(self) -> ProxySchema {
+        ProxySchema {
+            proxies: self.proxies,
+        }
+    }
+}
+
+struct ClashProxy<'a>(&'a Mapping);
+
+impl<'a> ClashProxy<'a> {
+    fn str(&self, key: &str) -> Option<String> {
+        match self.0.get(key)? {
+            Value::String(s) => Some(s.clone()),
+            Value::Number(n) => Some(n.to_string()),
+            _ => None,
+        }
+    }
+
+    fn required(&self, key: &str, err: &'static str) -> ConvertResult<String> {
+        self.str(key).ok_or(err)
+    }
+
+    fn flag(&self, key: &str) -> bool {
+        self.0.get(key).and_then(Value::as_bool).unwrap_or(false)
+    }
+
+    fn opts(&self, key: &str) -> Option<ClashProxy<'a>> {
+        self.0.get(key)?.as_mapping().map(ClashProxy)
+    }
+
+    fn port(&self) -> ConvertResult<u16> {
+        self.str("port")
+            .and_then(|p| p.parse().ok())
+            .ok_or("invalid port")
+    }
+
+    fn auth(&self) -> Option<AuthData> {
+        Some(AuthData {
+            username: self.str("username")?,
+            password: self.str("password").unwrap_or_default(),
+        })
+    }
+
+    fn websocket_path(&self) -> ConvertResult<Option<String>> {
+        match self.str("network").as_deref() {
+            None | Some("tcp") => Ok(None),
+            Some("ws") => Ok(Some(
+                self.opts("ws-opts")
+                    .and_then(|o| o.str("path"))
+                    .unwrap_or_else(|| "/".to_string()),
+            )),
+            Some(_) => Err("unsupported network"),
+        }
+    }
+
+    fn convert(&self) -> ConvertResult<RawProxyLocalCfg> {
+        let host = self.required("server", "missing server")?;
+        let server = server_addr(host.as_str());
+        let port = self.port()?;
+        let skip_cert_verify = self.flag("skip-cert-verify");
+        Ok(match self.str("type").as_deref() {
+            Some("ss") => {
+                let cipher = self.required("cipher", "missing cipher")?;
+                if !SS_CIPHERS.contains(&cipher.as_str()) {
+                    return Err("unsupported cipher");
+                }
+                let plugin = match self.str("plugin").as_deref() {
+                    None | Some("") => None,
+                    Some("obfs") => {
+                        let opts = self.opts("plugin-opts").ok_or("missing plugin-opts")?;
+                        Some(RawShadowsocksPlugin::Obfs {
+                            mode: opts.required("mode", "missing obfs mode")?,
+                            host: opts.str("host").unwrap_or_else(|| "bing.com".to_string()),
+                        })
+                    }
+                    Some("shadow-tls") => {
+                        let opts = self.opts("plugin-opts").ok_or("missing plugin-opts")?;
+                        if opts.str("version").is_some_and(|v| v != "3") {
+                            return Err("only ShadowTLS v3 is supported");
+                        }
+                        Some(RawShadowsocksPlugin::ShadowTls {
+                            host: opts.required("host", "missing shadow-tls host")?,
+                            password: opts.required("password", "missing shadow-tls password")?,
+                            version: 3,
+                        })
+                    }
+                    Some(_) => return Err("unsupported plugin"),
+                };
+                RawProxyLocalCfg::Shadowsocks {
+                    server,
+                    port,
+                    password: self.required("password", "missing password")?,
+                    cipher,
+                    udp: self.flag("udp"),
+                    plugin,
+                    udp_over_tcp: self.flag("udp-over-tcp"),
+                    dial: None,
+                }
+            }
+            Some("socks5") => RawProxyLocalCfg::Socks5 {
+                server,
+                port,
+                auth: self.auth(),
+                udp: self.flag("udp"),
+                version: "5".to_string(),
+                tls: self.flag("tls"),
+                sni: None,
+                skip_cert_verify,
+                udp_over_tcp: false,
+                dial: None,
+            },
+            Some("http") => RawProxyLocalCfg::Http {
+                server,
+                port,
+                auth: self.auth(),
+                tls: self.flag("tls"),
+                sni: self.str("sni"),
+                skip_cert_verify,
+                http2: false,
+                dial: None,
+            },
+            Some("trojan") => RawProxyLocalCfg::Trojan {
+                server,
+                port,
+                password: self.required("password", "missing password")?,
+                sni: self.str("sni").unwrap_or(host),
+                skip_cert_verify,
+                websocket_path: self.websocket_path()?,
+                udp: self.flag("udp"),
+                udp_over_tcp: false,
+                dial: None,
+            },
+            Some("vmess") => {
+                if self.str("alterId").is_some_and(|id| id != "0") {
+                    return Err("only AEAD header is supported");
+                }
+                let cipher = self.str("cipher").unwrap_or_else(|| "auto".to_string());
+                if VmessSecurity::from_name(cipher.as_str()).is_none() {
+                    return Err("unsupported cipher");
+                }
+                RawProxyLocalCfg::Vmess {
+                    server,
+                    port,
+                    uuid: self.required("uuid", "missing uuid")?,
+                    cipher,
+                    tls: self.flag("tls"),
+                    sni: self.str("servername"),
+                    skip_cert_verify,
+                    websocket_path: self.websocket_path()?,
+                    udp: self.flag("udp"),
+                    dial: None,
+                }
+            }
+            Some("vless") => {
+                if self.str("flow").is_some_and(|f| !f.is_empty()) {
+                    return Err("flow is not supported");
+                }
+                if self.opts("reality-opts").is_some() {
+                    return Err("REALITY is not supported");
+                }
+                RawProxyLocalCfg::Vless {
+                    server,
+                    port,
+                    uuid: self.required("uuid", "missing uuid")?,
+                    tls: self.flag("tls"),
+                    sni: self.str("servername"),
+                    skip_cert_verify,
+                    websocket_path: self.websocket_path()?,
+                    udp: self.flag("udp"),
+                    dial: None,
+                }
+            }
+            Some("hysteria2") => RawProxyLocalCfg::Hysteria2 {
+                server,
+                port,
+                password: self.required("password", "missing password")?,
+                sni: self.str("sni"),
+                skip_cert_verify,
+                udp: true,
+                dial: None,
+            },
+            Some("tuic") => {
+                let udp_relay_mode = self
+                    .str("udp-relay-mode")
+                    .unwrap_or_else(|| "native".to_string());
+                if TuicUdpRelayMode::from_name(udp_relay_mode.as_str()).is_none() {
+                    return Err("unsupported udp-relay-mode");
+                }
+                let alpn = self.0.get("alpn").and_then(Value::as_sequence).map(|l| {
+                    SingleOrVec::List(
+                        l.iter()
+                            .filter_map(|v| v.as_str().map(str::to_string))
+                            .collect(),
+                    )
+                });
+                RawProxyLocalCfg::Tuic {
+                    server,
+                    port,
+                    // TUIC v4 uses a token instead
+                    uuid: self.required("uuid", "only TUIC v5 is supported")?,
+                    password: self.required("password", "missing password")?,
+                    sni: self.str("sni"),
+                    alpn,
+                    skip_cert_verify,
+                    udp_relay_mode,
+                    udp: true,
+                    dial: None,
+                }
+            }
+            _ => return Err("unsupported proxy type"),
+        })
+    }
+}
+
+fn ss_uri(url: &Url, line: &str) -> ConvertResult<RawProxyLocalCfg> {
+    let (cipher, password, host, port) = match url.port() {
+        // SIP002: ss://base64(method:password)@host:port or with percent-encoded user info
+        Some(port) => {
+            let (cipher, password) = match url.password() {
+                Some(password) => (percent_decode(url.username()), percent_decode(password)),
+                None => {
+                    let user_info = base64_decode(percent_decode(url.username()).as_str())
+                        .and_then(|d| String::from_utf8(d).ok())
+                        .ok_or("invalid user info")?;
+                    let (cipher, password) =
+                        user_info.split_once(':').ok_or("invalid user info")?;
+                    (cipher.to_string(), password.to_string())
+                }
+            };
+            (cipher, password, url_host(url)?, port)
+        }
+        // legacy: ss://base64(method:password@host:port)
+        None => {
+            let encoded = line["ss://".len()..].split('#').next().unwrap_or_default();
+            let decoded = base64_decode(encoded)
+                .and_then(|d| String::from_utf8(d).ok())
+                .ok_or("invalid legacy link")?;
+            let (user_info, addr) = decoded.rsplit_once('@').ok_or("invalid legacy link")?;
+            let (cipher, password) = user_info.split_once(':').ok_or("invalid legacy link")?;
+            let (host, port) = addr.rsplit_once(':').ok_or("invalid legacy link")?;
+            (
+                cipher.to_string(),
+                password.to_string(),
+                host.trim_start_matches('[')
+                    .trim_end_matches(']')
+                    .to_string(),
+                port.parse().map_err(|_| "invalid port")?,
+            )
+        }
+    };
+    if !SS_CIPHERS.contains(&cipher.as_str()) {
+        return Err("unsupported cipher");
+    }
+    let plugin = match query(url, "plugin") {
+        None => None,
+        Some(plugin) => {
+            let mut opts = plugin.split(';');
+            match opts.next() {
+                Some("obfs-local") | Some("simple-obfs") => {
+                    let mut mode = None;
+                    let mut obfs_host = "bing.com".to_string();
+                    for opt in opts {
+                        match opt.split_once('=') {
+                            Some(("obfs", m)) => mode = Some(m.to_string()),
+                            Some(("obfs-host", h)) => obfs_host = h.to_string(),
+                            _ => {}
+                        }
+                    }
+                    Some(RawShadowsocksPlugin::Obfs {
+                        mode: mode.ok_or("missing obfs mode")?,
+                        host: obfs_host,
+                    })
+                }
+                _ => return Err("unsupported plugin"),
+            }
+        }
+    };
+    Ok(RawProxyLocalCfg::Shadowsocks {
+        server: server_addr(host.as_str()),
+        port,
+        password,
+        cipher,
+        udp: true,
+        plugin,
+        udp_over_tcp: false,
+        dial: None,
+    })
+}
+
+fn trojan_uri(url: &Url) -> ConvertResult<RawProxyLocalCfg> {
+    let host = url_host(url)?;
+    Ok(RawProxyLocalCfg::Trojan {
+        server: server_addr(host.as_str()),
+        port: url.port().ok_or("missing port")?,
+        password: percent_decode(url.username()),
+        sni: query(url, "sni")
+            .or_else(|| query(url, "peer"))
+            .unwrap_or(host),
+        skip_cert_verify: insecure(url),
+        websocket_path: uri_websocket_path(url)?,
+        udp: true,
+        udp_over_tcp: false,
+        dial: None,
+    })
+}
+
+fn vless_uri(url: &Url) -> ConvertResult<RawProxyLocalCfg> {
+    if query(url, "flow").is_some_and(|f| !f.is_empty()) {
+        return Err("flow is not supported");
+    }
+    let tls = match query(url, "security").as_deref() {
+        None | Some("none") => false,
+        Some("tls") => true,
+        Some(_) => return Err("unsupported security"),
+    };
+    let host = url_host(url)?;
+    Ok(RawProxyLocalCfg::Vless {
+        server: server_addr(host.as_str()),
+        port: url.port().ok_or("missing port")?,
+        uuid: url.username().to_string(),
+        tls,
+        sni: query(url, "sni"),
+        skip_cert_verify: insecure(url),
+        websocket_path: uri_websocket_path(url)?,
+        udp: true,
+        dial: None,
+    })
+}
+
+fn query(url: &Url, key: &str) -> Option<String> {
+    url.query_pairs()
+        .find(|(k, _)| k == key)
+        .map(|(_, v)| v.to_string())
+}
+
+fn insecure(url: &Url) -> bool {
+    matches!(
+        query(url, "allowInsecure").as_deref(),
+        Some("1") | Some("true")
+    )
+}
+
+fn uri_websocket_path(url: &Url) -> ConvertResult<Option<String>> {
+    match query(url, "type").as_deref() {
+        None | Some("tcp") => Ok(None),
+        Some("ws") => Ok(Some(query(url, "path").unwrap_or_else(|| "/".to_string()))),
+        Some(_) => Err("unsupported transport"),
+    }
+}
+
+fn url_host(url: &Url) -> ConvertResult<String> {
+    let host = url.host_str().ok_or("missing host")?;
+    Ok(host
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .to_string())
+}
+
+fn server_addr(host: &str) -> RawServerAddr {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => RawServerAddr::IpAddr(ip),
+        Err(_) => RawServerAddr::DomainName(host.to_string()),
+    }
+}
+
+fn percent_decode(s: &str) -> String {
+    percent_decode_str(s).decode_utf8_lossy().to_string()
+}
+
+fn base64_decode(s: &str) -> Option<Vec<u8>> {
+    use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD};
+    [STANDARD, STANDARD_NO_PAD, URL_SAFE, URL_SAFE_NO_PAD]
+        .iter()
+        .find_map(|engine| engine.decode(s).ok())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_clash() {
+        let text = r#"
+proxies:
+  - {name: hk, type: ss, server: 1.2.3.4, port: 8388, cipher: aes-256-gcm, password: pwd, udp: true}
+  - {name: jp, type: trojan, server: jp.example.com, port: "443", password: pwd, network: ws, ws-opts: {path: /ws}}
+  - {name: us, type: vless, server: us.example.com, port: 443, uuid: 8a7c4c5e-3d1f-4a49-9c3e-1f0b2a6d5e4c, flow: xtls-rprx-vision}
+  - {name: sg, type: snell, server: sg.example.com, port: 443, psk: key}
+"#;
+        let schema = parse_proxy_schema("sub.yml", text, ProxyProviderFormat::Auto).unwrap();
+        assert_eq!(schema.proxies.len(), 2);
+        assert!(matches!(
+            &schema.proxies[0].cfg,
+            RawProxyLocalCfg::Shadowsocks {
+                udp: true,
+                server: RawServerAddr::IpAddr(_),
+                ..
+            }
+        ));
+        assert!(matches!(
+            &schema.proxies[1].cfg,
+            RawProxyLocalCfg::Trojan { port: 443, sni, websocket_path: Some(p), .. }
+                if sni == "jp.example.com" && p == "/ws"
+        ));
+    }
+
+    #[test]
+    fn test_parse_uri_list() {
+        let links = "ss://YWVzLTI1Ni1nY206cGFzc3dvcmQ@1.2.3.4:8388#HK%2001\n\
+            trojan://pwd@jp.example.com:443?sni=cdn.example.com&allowInsecure=1#JP\n\
+            vless://8a7c4c5e-3d1f-4a49-9c3e-1f0b2a6d5e4c@[2001:db8::1]:443?security=tls&type=ws&path=%2Fv#US\n\
+            vmess://eyJhZGQiOiIxLjIuMy40In0=";
+        let encoded = base64::engine::general_purpose::STANDARD.encode(links);
+        let schema =
+            parse_proxy_schema("sub.txt", encoded.as_str(), ProxyProviderFormat::Auto).unwrap();
+        assert_eq!(schema.proxies.len(), 3);
+        assert_eq!(schema.proxies[0].name, "HK 01");
+        assert!(matches!(
+            &schema.proxies[0].cfg,
+            RawProxyLocalCfg::Shadowsocks { cipher, password, port: 8388, .. }
+                if cipher == "aes-256-gcm" && password == "password"
+        ));
+        assert!(matches!(
+            &schema.proxies[1].cfg,
+            RawProxyLocalCfg::Trojan { sni, skip_cert_verify: true, .. } if sni == "cdn.example.com"
+        ));
+        assert!(matches!(
+            &schema.proxies[2].cfg,
+            RawProxyLocalCfg::Vless { tls: true, server: RawServerAddr::IpAddr(_), websocket_path: Some(p), .. }
+                if p == "/v"
+        ));
+    }
+}
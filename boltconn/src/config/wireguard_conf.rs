@@ -0,0 +1,303 @@
+use crate::config::{
+    DnsPreference, ProxyError, RawDialOptions, RawProxyLocalCfg, RawServerSockAddr,
+    RawWireguardPeer,
+};
+use base64::Engine;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+
+const DEFAULT_MTU: usize = 1420;
+
+enum Section {
+    None,
+    Interface,
+    Peer,
+}
+
+#[derive(Default)]
+struct PeerSection {
+    public_key: Option<String>,
+    endpoint: Option<RawServerSockAddr>,
+    preshared_key: Option<String>,
+    keepalive: Option<u16>,
+    allowed_ips: Vec<String>,
+}
+
+impl PeerSection {
+    fn finish(self, name: &str) -> Result<RawWireguardPeer, ProxyError> {
+        Ok(RawWireguardPeer {
+            public_key: self.public_key.ok_or_else(|| {
+                ProxyError::ProxyFieldError(name.to_string(), "Missing PublicKey of peer")
+            })?,
+            endpoint: self.endpoint.ok_or_else(|| {
+                ProxyError::ProxyFieldError(name.to_string(), "Missing Endpoint of peer")
+            })?,
+            preshared_key: self.preshared_key,
+            keepalive: self.keepalive,
+            reserved: None,
+            allowed_ips: self.allowed_ips,
+        })
+    }
+}
+
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value.split(',').map(|s| s.trim()).filter(|s| !s.is_empty())
+}
+
+// keys are checked here so that the offending line can be reported
+fn check_key(name: &str, key: &str, value: &str, line: usize) -> Result<String, ProxyError> {
+    match base64::engine::general_purpose::STANDARD.decode(value) {
+        Ok(k) if k.len() == 32 => Ok(value.to_string()),
+        _ => Err(ProxyError::WireguardKey(
+            name.to_string(),
+            key.to_string(),
+            line,
+        )),
+    }
+}
+
+/// Parse a wg-quick configuration file into a WireGuard outbound.
+pub fn parse_wireguard_conf(
+    name: &str,
+    content: &str,
+    dns_preference: DnsPreference,
+    over_tcp: bool,
+    dial: Option<RawDialOptions>,
+) -> Result<RawProxyLocalCfg, ProxyError> {
+    let field_err = |msg: &'static str| ProxyError::ProxyFieldError(name.to_string(), msg);
+
+    let mut section = Section::None;
+    let mut private_key = None;
+    let mut local_addr: Option<Ipv4Addr> = None;
+    let mut local_addr_v6: Option<Ipv6Addr> = None;
+    let mut dns = vec![];
+    let mut mtu = DEFAULT_MTU;
+    let mut peers = vec![];
+    let mut current_peer: Option<PeerSection> = None;
+
+    for (idx, line) in content.lines().enumerate() {
+        let line = match line.split_once('#') {
+            Some((l, _)) => l,
+            None => line,
+        }
+        .trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            if let Some(peer) = current_peer.take() {
+                peers.push(peer.finish(name)?);
+            }
+            section = match line.to_ascii_lowercase().as_str() {
+                "[interface]" => Section::Interface,
+                "[peer]" => {
+                    current_peer = Some(PeerSection::default());
+                    Section::Peer
+                }
+                _ => {
+                    return Err(ProxyError::WireguardDirective(
+                        name.to_string(),
+                        line.to_string(),
+                        idx + 1,
+                    ))
+                }
+            };
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(field_err("Invalid line in WireGuard configuration file"));
+        };
+        let (key, value) = (key.trim(), value.trim());
+        match (&section, key.to_ascii_lowercase().as_str()) {
+            (Section::Interface, "privatekey") => {
+                private_key = Some(check_key(name, key, value, idx + 1)?)
+            }
+            (Section::Interface, "address") => {
+                for addr in split_list(value) {
+                    let ip = addr
+                        .split_once('/')
+                        .map_or(addr, |(ip, _)| ip)
+                        .parse::<IpAddr>()
+                        .map_err(|_| field_err("Invalid Address of WireGuard interface"))?;
+                    match ip {
+                        IpAddr::V4(v4) => {
+                            local_addr.get_or_insert(v4);
+                        }
+                        IpAddr::V6(v6) => {
+                            local_addr_v6.get_or_insert(v6);
+                        }
+                    }
+                }
+            }
+            (Section::Interface, "dns") => {
+                for server in split_list(value) {
+                    // search domains are not supported
+                    let server = server
+                        .parse::<IpAddr>()
+                        .map_err(|_| field_err("Only IP addresses are supported in DNS"))?;
+                    dns.push(server.to_string());
+                }
+            }
+            (Section::Interface, "mtu") => {
+                mtu = value.parse().map_err(|_| field_err("Invalid MTU"))?;
+            }
+            // meaningless for an outbound
+            (Section::Interface, "listenport") => {}
+            (Section::Peer, k) => {
+                let peer = current_peer.as_mut().unwrap();
+                match k {
+                    "publickey" => peer.public_key = Some(check_key(name, key, value, idx + 1)?),
+                    "presharedkey" => {
+                        peer.preshared_key = Some(check_key(name, key, value, idx + 1)?)
+                    }
+                    "endpoint" => {
+                        peer.endpoint = Some(match value.parse::<SocketAddr>() {
+                            Ok(addr) => RawServerSockAddr::Ip(addr),
+                            Err(_) => RawServerSockAddr::Domain(value.to_string()),
+                        })
+                    }
+                    "allowedips" => {
+                        peer.allowed_ips
+                            .extend(split_list(value).map(|s| s.to_string()));
+                    }
+                    "persistentkeepalive" => {
+                        peer.keepalive = if value == "off" {
+                            None
+                        } else {
+                            Some(
+                                value
+                                    .parse()
+                                    .map_err(|_| field_err("Invalid PersistentKeepalive"))?,
+                            )
+                        };
+                    }
+                    _ => {
+                        return Err(ProxyError::WireguardDirective(
+                            name.to_string(),
+                            key.to_string(),
+                            idx + 1,
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(ProxyError::WireguardDirective(
+                    name.to_string(),
+                    key.to_string(),
+                    idx + 1,
+                ))
+            }
+        }
+    }
+    if let Some(peer) = current_peer.take() {
+        peers.push(peer.finish(name)?);
+    }
+
+    let private_key =
+        private_key.ok_or_else(|| field_err("Missing PrivateKey of WireGuard interface"))?;
+    if dns.is_empty() {
+        return Err(field_err("Missing DNS of WireGuard interface"));
+    }
+    if peers.is_empty() {
+        return Err(field_err("No peer in WireGuard configuration file"));
+    }
+    let first = peers.remove(0);
+    Ok(RawProxyLocalCfg::Wireguard {
+        local_addr,
+        local_addr_v6,
+        private_key,
+        public_key: first.public_key,
+        endpoint: first.endpoint,
+        dns: dns.join(","),
+        dns_preference,
+        mtu,
+        preshared_key: first.preshared_key,
+        keepalive: first.keepalive,
+        reserved: None,
+        allowed_ips: Some(first.allowed_ips),
+        peers,
+        over_tcp,
+        dial,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_wireguard_conf() {
+        let conf = r#"
+[Interface]
+PrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=
+Address = 10.0.0.2/32, fd00::2/128
+DNS = 10.0.0.1, 1.1.1.1
+MTU = 1380
+
+[Peer]
+PublicKey = xTIBA5rboUvnH4htodjb6e697QjLERt1NAB4mZqp8Dg=
+Endpoint = vpn.example.com:51820
+AllowedIPs = 10.0.0.0/24, 192.168.1.0/24 # office
+PersistentKeepalive = 25
+
+[Peer]
+PublicKey = TrMvSoP4jYQlY6RIzBgbssQqY3vxI2Pi+y71lOWWXX0=
+Endpoint = 198.51.100.7:51820
+AllowedIPs = 0.0.0.0/0
+"#;
+        let RawProxyLocalCfg::Wireguard {
+            local_addr,
+            local_addr_v6,
+            dns,
+            mtu,
+            keepalive,
+            allowed_ips,
+            peers,
+            endpoint,
+            ..
+        } = parse_wireguard_conf("wg", conf, DnsPreference::PreferIpv4, false, None).unwrap()
+        else {
+            panic!("Unexpected proxy type");
+        };
+        assert_eq!(local_addr, Some(Ipv4Addr::new(10, 0, 0, 2)));
+        assert_eq!(local_addr_v6, Some("fd00::2".parse().unwrap()));
+        assert_eq!(dns, "10.0.0.1,1.1.1.1");
+        assert_eq!(mtu, 1380);
+        assert_eq!(keepalive, Some(25));
+        assert_eq!(
+            allowed_ips,
+            Some(vec![
+                "10.0.0.0/24".to_string(),
+                "192.168.1.0/24".to_string()
+            ])
+        );
+        assert!(matches!(endpoint, RawServerSockAddr::Domain(d) if d == "vpn.example.com:51820"));
+        assert_eq!(peers.len(), 1);
+        assert!(matches!(peers[0].endpoint, RawServerSockAddr::Ip(_)));
+
+        let unsupported = "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\nPostUp = iptables -A FORWARD\n";
+        assert!(matches!(
+            parse_wireguard_conf("wg", unsupported, DnsPreference::PreferIpv4, false, None),
+            Err(ProxyError::WireguardDirective(_, directive, 3)) if directive == "PostUp"
+        ));
+    }
+
+    #[test]
+    fn test_parse_wireguard_bad_key() {
+        let parse = |conf: &str| {
+            parse_wireguard_conf("wg", conf, DnsPreference::PreferIpv4, false, None).err()
+        };
+        // not base64
+        let conf = "[Interface]\nPrivateKey = not a key\n";
+        assert!(matches!(
+            parse(conf),
+            Some(ProxyError::WireguardKey(_, directive, 2)) if directive == "PrivateKey"
+        ));
+        // base64 of 31 bytes
+        let conf = "[Interface]\nPrivateKey = yAnz5TF+lXXJte14tji3zlMNq+hd2rYUIgJBgB3fBmk=\n\
+            [Peer]\nPublicKey = AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA==\n";
+        assert!(matches!(
+            parse(conf),
+            Some(ProxyError::WireguardKey(_, directive, 4)) if directive == "PublicKey"
+        ));
+    }
+}
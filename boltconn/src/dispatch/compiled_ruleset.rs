@@ -0,0 +1,456 @@
+use crate::common::host_matcher::HostType;
+use aho_corasick::AhoCorasick;
+use memmap2::Mmap;
+use regex::RegexSet;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Layout, all integers in little endian:
+//   magic, u32 version, SECTION_COUNT * (u32 offset, u32 length), sections...
+// Domains form a trie of reversed labels; each node is
+//   u8 flags, 3 bytes padding, u32 child count, children * (u32 label offset, u32 label length,
+//   u32 node offset) sorted by label, with offsets relative to the section.
+pub(crate) const COMPILED_MAGIC: &[u8; 4] = b"BCRS";
+const VERSION: u32 = 2;
+
+const DOMAIN: usize = 0;
+const DOMAIN_KEYWORD: usize = 1;
+const DOMAIN_REGEX: usize = 2;
+const IPV4: usize = 3;
+const IPV6: usize = 4;
+// followed by the other three kinds of ports
+const PORT: usize = 5;
+const PROCESS_NAME: usize = 9;
+const PROCESS_KEYWORD: usize = 10;
+const PROCPATH_KEYWORD: usize = 11;
+const PROCESS_PATH: usize = 12;
+const SECTION_COUNT: usize = 13;
+const HEADER_LEN: usize = 8 + SECTION_COUNT * 8;
+
+const EXACT: u8 = 1;
+const SUFFIX: u8 = 2;
+const SUBDOMAIN: u8 = 4;
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum PortKind {
+    SrcTcp = 0,
+    SrcUdp = 1,
+    DstTcp = 2,
+    DstUdp = 3,
+}
+
+/// Content of a compiled rule-set.
+#[derive(Default)]
+pub(crate) struct CompiledPayload {
+    pub domain: Vec<(String, HostType)>,
+    pub domain_keyword: Vec<String>,
+    pub domain_regex: Vec<String>,
+    pub ip_cidr: Vec<(IpAddr, u8)>,
+    /// (any port, ports), indexed by `PortKind`
+    pub ports: [(bool, Vec<u16>); 4],
+    pub process_name: Vec<String>,
+    pub process_keyword: Vec<String>,
+    pub procpath_keyword: Vec<String>,
+    pub process_path: Vec<String>,
+}
+
+impl CompiledPayload {
+    pub fn encode(self) -> Vec<u8> {
+        let mut sections: Vec<Vec<u8>> = Vec::with_capacity(SECTION_COUNT);
+        sections.push(encode_trie(&self.domain));
+        sections.push(encode_strings(self.domain_keyword));
+        sections.push(encode_strings(self.domain_regex));
+        let (v4, v6) = merge_ranges(&self.ip_cidr);
+        sections.push(
+            v4.iter()
+                .flat_map(|(s, e)| [*s as u32, *e as u32])
+                .flat_map(u32::to_le_bytes)
+                .collect(),
+        );
+        sections.push(
+            v6.iter()
+                .flat_map(|(s, e)| [*s, *e])
+                .flat_map(u128::to_le_bytes)
+                .collect(),
+        );
+        for (any, mut ports) in self.ports {
+            ports.sort_unstable();
+            ports.dedup();
+            let mut buf = vec![any as u8];
+            buf.extend(ports.into_iter().flat_map(u16::to_le_bytes));
+            sections.push(buf);
+        }
+        let mut process_name = self.process_name;
+        // binary searched when matching
+        process_name.sort_unstable();
+        sections.push(encode_strings(process_name));
+        sections.push(encode_strings(self.process_keyword));
+        sections.push(encode_strings(self.procpath_keyword));
+        let mut process_path = self.process_path;
+        process_path.sort_unstable();
+        sections.push(encode_strings(process_path));
+
+        let mut buf = Vec::from(*COMPILED_MAGIC);
+        buf.extend(VERSION.to_le_bytes());
+        let mut offset = HEADER_LEN;
+        for s in &sections {
+            buf.extend((offset as u32).to_le_bytes());
+            buf.extend((s.len() as u32).to_le_bytes());
+            offset += s.len();
+        }
+        for s in sections {
+            buf.extend(s);
+        }
+        buf
+    }
+}
+
+#[derive(Default)]
+struct TrieNode {
+    flags: u8,
+    children: BTreeMap<String, TrieNode>,
+}
+
+fn encode_trie(domains: &[(String, HostType)]) -> Vec<u8> {
+    let mut root = TrieNode::default();
+    for (host, ty) in domains {
+        let mut node = &mut root;
+        for label in host.rsplit('.') {
+            node = node.children.entry(label.to_string()).or_default();
+        }
+        node.flags |= match ty {
+            HostType::Exact => EXACT,
+            HostType::Suffix => SUFFIX,
+            HostType::Subdomain => SUBDOMAIN,
+        };
+    }
+    let mut buf = vec![];
+    write_node(&root, &mut buf);
+    buf
+}
+
+fn write_node(node: &TrieNode, buf: &mut Vec<u8>) -> u32 {
+    let start = buf.len();
+    buf.extend([node.flags, 0, 0, 0]);
+    buf.extend((node.children.len() as u32).to_le_bytes());
+    let table = buf.len();
+    buf.resize(table + node.children.len() * 12, 0);
+    for (idx, (label, child)) in node.children.iter().enumerate() {
+        let label_offset = buf.len() as u32;
+        buf.extend(label.as_bytes());
+        let child_offset = write_node(child, buf);
+        let entry = table + idx * 12;
+        buf[entry..entry + 4].copy_from_slice(&label_offset.to_le_bytes());
+        buf[entry + 4..entry + 8].copy_from_slice(&(label.len() as u32).to_le_bytes());
+        buf[entry + 8..entry + 12].copy_from_slice(&child_offset.to_le_bytes());
+    }
+    start as u32
+}
+
+fn encode_strings(list: Vec<String>) -> Vec<u8> {
+    let mut buf = Vec::from((list.len() as u32).to_le_bytes());
+    let mut offset = 4 + list.len() * 8;
+    for s in &list {
+        buf.extend((offset as u32).to_le_bytes());
+        buf.extend((s.len() as u32).to_le_bytes());
+        offset += s.len();
+    }
+    for s in list {
+        buf.extend(s.into_bytes());
+    }
+    buf
+}
+
+type Ranges = Vec<(u128, u128)>;
+
+/// Turn CIDRs into sorted and disjoint ranges of IPv4 and IPv6.
+fn merge_ranges(cidrs: &[(IpAddr, u8)]) -> (Ranges, Ranges) {
+    let mut v4 = vec![];
+    let mut v6 = vec![];
+    for (ip, prefix) in cidrs {
+        let (addr, bits, list) = match ip {
+            IpAddr::V4(ip) => (u32::from(*ip) as u128, 32, &mut v4),
+            IpAddr::V6(ip) => (u128::from(*ip), 128, &mut v6),
+        };
+        let host_bits = bits - (*prefix as u32).min(bits);
+        let host_mask = if host_bits == 0 {
+            0
+        } else {
+            u128::MAX >> (128 - host_bits)
+        };
+        list.push((addr & !host_mask, addr | host_mask));
+    }
+    let merge = |mut list: Ranges| {
+        list.sort_unstable();
+        let mut merged: Ranges = vec![];
+        for (start, end) in list {
+            match merged.last_mut() {
+                Some(last) if last.1.checked_add(1).map_or(true, |next| next >= start) => {
+                    last.1 = last.1.max(end)
+                }
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    };
+    (merge(v4), merge(v6))
+}
+
+enum RuleSetData {
+    Mapped(Mmap),
+    Owned(Vec<u8>),
+}
+
+impl std::ops::Deref for RuleSetData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            RuleSetData::Mapped(map) => map,
+            RuleSetData::Owned(data) => data,
+        }
+    }
+}
+
+/// A compiled rule-set queried in place; automata are only built when first used.
+pub struct CompiledRuleSet {
+    map: RuleSetData,
+    sections: [(usize, usize); SECTION_COUNT],
+    domain_keyword: OnceLock<Option<AhoCorasick>>,
+    domain_regex: OnceLock<Option<RegexSet>>,
+    process_keyword: OnceLock<Option<AhoCorasick>>,
+    procpath_keyword: OnceLock<Option<AhoCorasick>>,
+}
+
+impl CompiledRuleSet {
+    /// Map a cached rule-set, which must only be replaced by renaming a new file into place.
+    pub fn map(path: &Path) -> io::Result<Self> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len() as usize;
+        if len < HEADER_LEN {
+            return Err(invalid("Truncated compiled rule-set"));
+        }
+        // SAFETY: cached rule-sets are written by `write_atomically` only, so the mapped file is
+        // never modified; files of users may be rewritten in place and are read instead
+        let map = unsafe { Mmap::map(&file)? };
+        Self::new(RuleSetData::Mapped(map))
+    }
+
+    /// Read a rule-set into memory, since the file may be rewritten in place.
+    pub fn read(path: &Path) -> io::Result<Self> {
+        Self::new(RuleSetData::Owned(std::fs::read(path)?))
+    }
+
+    fn new(map: RuleSetData) -> io::Result<Self> {
+        let sections = read_sections(&map)?;
+        Ok(Self {
+            map,
+            sections,
+            domain_keyword: OnceLock::new(),
+            domain_regex: OnceLock::new(),
+            process_keyword: OnceLock::new(),
+            procpath_keyword: OnceLock::new(),
+        })
+    }
+
+    fn section(&self, idx: usize) -> &[u8] {
+        let (offset, length) = self.sections[idx];
+        &self.map[offset..offset + length]
+    }
+
+    fn automaton<'a>(
+        &'a self,
+        cell: &'a OnceLock<Option<AhoCorasick>>,
+        idx: usize,
+    ) -> Option<&'a AhoCorasick> {
+        cell.get_or_init(|| {
+            let section = self.section(idx);
+            (string_count(section) > 0)
+                .then(|| AhoCorasick::new(strings(section)).ok())
+                .flatten()
+        })
+        .as_ref()
+    }
+
+    pub fn matches_domain(&self, domain: &str) -> bool {
+        trie_contains(self.section(DOMAIN), domain)
+            || self
+                .automaton(&self.domain_keyword, DOMAIN_KEYWORD)
+                .is_some_and(|ac| ac.is_match(domain))
+            || self
+                .domain_regex
+                .get_or_init(|| {
+                    let section = self.section(DOMAIN_REGEX);
+                    (string_count(section) > 0)
+                        .then(|| RegexSet::new(strings(section)).ok())
+                        .flatten()
+                })
+                .as_ref()
+                .is_some_and(|r| r.is_match(domain))
+    }
+
+    pub fn matches_ip(&self, ip: IpAddr) -> bool {
+        match ip {
+            IpAddr::V4(ip) => range_contains(self.section(IPV4), 4, u32::from(ip) as u128),
+            IpAddr::V6(ip) => range_contains(self.section(IPV6), 16, u128::from(ip)),
+        }
+    }
+
+    pub fn matches_port(&self, kind: PortKind, port: u16) -> bool {
+        let section = self.section(PORT + kind as usize);
+        if section.first().is_some_and(|any| *any != 0) {
+            return true;
+        }
+        let count = section.len().saturating_sub(1) / 2;
+        let (mut lo, mut hi) = (0, count);
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let val = u16::from_le_bytes([section[1 + mid * 2], section[2 + mid * 2]]);
+            match val.cmp(&port) {
+                std::cmp::Ordering::Equal => return true,
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+            }
+        }
+        false
+    }
+
+    pub fn matches_process(&self, name: &str, path: &str) -> bool {
+        sorted_contains(self.section(PROCESS_NAME), name)
+            || self
+                .automaton(&self.process_keyword, PROCESS_KEYWORD)
+                .is_some_and(|ac| ac.is_match(name))
+            || sorted_contains(self.section(PROCESS_PATH), path)
+            || self
+                .automaton(&self.procpath_keyword, PROCPATH_KEYWORD)
+                .is_some_and(|ac| ac.is_match(path))
+    }
+
+    /// Check whether `data` can be opened as a compiled rule-set.
+    pub fn check(data: &[u8]) -> io::Result<()> {
+        read_sections(data).map(|_| ())
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_sections(data: &[u8]) -> io::Result<[(usize, usize); SECTION_COUNT]> {
+    if data.len() < HEADER_LEN {
+        return Err(invalid("Truncated compiled rule-set"));
+    }
+    if !data.starts_with(COMPILED_MAGIC) {
+        return Err(invalid("Not a compiled rule-set"));
+    }
+    if read_u32(data, 4) != Some(VERSION) {
+        return Err(invalid("Unsupported version of compiled rule-set"));
+    }
+    let mut sections = [(0, 0); SECTION_COUNT];
+    for (idx, section) in sections.iter_mut().enumerate() {
+        let offset = read_u32(data, 8 + idx * 8).unwrap() as usize;
+        let length = read_u32(data, 12 + idx * 8).unwrap() as usize;
+        if offset
+            .checked_add(length)
+            .map_or(true, |end| end > data.len())
+        {
+            return Err(invalid("Corrupted compiled rule-set"));
+        }
+        *section = (offset, length);
+    }
+    Ok(sections)
+}
+
+fn read_u32(buf: &[u8], pos: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(buf.get(pos..pos + 4)?.try_into().ok()?))
+}
+
+fn trie_contains(trie: &[u8], domain: &str) -> bool {
+    let mut node = 0;
+    let mut labels = domain.rsplit('.').peekable();
+    while let Some(label) = labels.next() {
+        match trie_child(trie, node, label.as_bytes()) {
+            Some(child) => node = child,
+            None => return false,
+        }
+        // a suffix also covers the exact host
+        let flags = trie.get(node).copied().unwrap_or_default();
+        if flags & SUFFIX != 0 || (flags & SUBDOMAIN != 0 && labels.peek().is_some()) {
+            return true;
+        }
+    }
+    trie.get(node).is_some_and(|flags| flags & EXACT != 0)
+}
+
+fn trie_child(trie: &[u8], node: usize, label: &[u8]) -> Option<usize> {
+    let count = read_u32(trie, node + 4)? as usize;
+    let (mut lo, mut hi) = (0, count);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let entry = node + 8 + mid * 12;
+        let offset = read_u32(trie, entry)? as usize;
+        let length = read_u32(trie, entry + 4)? as usize;
+        match trie.get(offset..offset.checked_add(length)?)?.cmp(label) {
+            std::cmp::Ordering::Equal => return Some(read_u32(trie, entry + 8)? as usize),
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+        }
+    }
+    None
+}
+
+fn string_count(section: &[u8]) -> usize {
+    read_u32(section, 0).unwrap_or(0) as usize
+}
+
+fn string_at(section: &[u8], idx: usize) -> Option<&str> {
+    let offset = read_u32(section, 4 + idx * 8)? as usize;
+    let length = read_u32(section, 8 + idx * 8)? as usize;
+    std::str::from_utf8(section.get(offset..offset.checked_add(length)?)?).ok()
+}
+
+fn strings(section: &[u8]) -> impl Iterator<Item = &str> {
+    (0..string_count(section)).filter_map(|idx| string_at(section, idx))
+}
+
+fn sorted_contains(section: &[u8], target: &str) -> bool {
+    let (mut lo, mut hi) = (0, string_count(section));
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        let Some(val) = string_at(section, mid) else {
+            return false;
+        };
+        match val.cmp(target) {
+            std::cmp::Ordering::Equal => return true,
+            std::cmp::Ordering::Less => lo = mid + 1,
+            std::cmp::Ordering::Greater => hi = mid,
+        }
+    }
+    false
+}
+
+fn range_contains(section: &[u8], width: usize, ip: u128) -> bool {
+    let read = |pos: usize| -> Option<u128> {
+        let bytes = section.get(pos..pos + width)?;
+        Some(match width {
+            4 => u32::from_le_bytes(bytes.try_into().ok()?) as u128,
+            _ => u128::from_le_bytes(bytes.try_into().ok()?),
+        })
+    };
+    let record = width * 2;
+    // find the first range starting after `ip`
+    let (mut lo, mut hi) = (0, section.len() / record);
+    while lo < hi {
+        let mid = (lo + hi) / 2;
+        match read(mid * record) {
+            Some(start) if start <= ip => lo = mid + 1,
+            Some(_) => hi = mid,
+            None => return false,
+        }
+    }
+    lo > 0 && read((lo - 1) * record + width).is_some_and(|end| ip <= end)
+}
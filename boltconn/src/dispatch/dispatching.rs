@@ -1,29 +1,40 @@
-use crate::adapter::{HttpConfig, ShadowSocksConfig, Socks5Config};
+use crate::adapter::{
+    HttpConfig, ShadowSocksConfig, ShadowsocksPlugin, Socks5Config, SocksVersion,
+};
 use crate::config::{
-    ConfigError, LoadedConfig, ProviderError, ProxyError, ProxySchema, RawProxyGroupCfg,
-    RawProxyLocalCfg, RawProxyProviderOption, RawServerAddr, RawServerSockAddr, RawState,
-    RuleAction, RuleConfigLine, RuleError, SingleOrVec,
+    parse_wireguard_conf, ConfigError, LoadBalanceStrategy, LoadedConfig, ProviderError,
+    ProxyError, ProxySchema, RawDialOptions, RawProxyGroupCfg, RawProxyGroupType, RawProxyLocalCfg,
+    RawServerAddr, RawServerSockAddr, RawShadowsocksPlugin, RawState, RawWireguardPeer, RuleAction,
+    RuleConfigLine, RuleError, SingleOrVec,
 };
 use crate::dispatch::action::{Action, SubDispatch};
-use crate::dispatch::proxy::ProxyImpl;
+use crate::dispatch::proxy::{chain_support_udp, ProxyImpl};
 use crate::dispatch::rule::{RuleBuilder, RuleOrAction};
-use crate::dispatch::ruleset::RuleSet;
 use crate::dispatch::temporary::TemporaryList;
-use crate::dispatch::{GeneralProxy, InboundInfo, Proxy, ProxyGroup, RuleSetTable};
-use crate::external::MmdbReader;
+use crate::dispatch::{
+    GeneralProxy, GroupPolicy, InboundInfo, ProviderHealthCheck, Proxy, ProxyGroup, RuleSetTable,
+};
+use crate::external::{GeositeReader, MmdbReader};
 use crate::instrument::action::InstrumentAction;
 use crate::instrument::bus::MessageBus;
 use crate::network::dns::Dns;
-use crate::platform::process::{NetworkType, ProcessInfo};
+use crate::network::egress::DialOptions;
+use crate::platform::process::{NetworkType, ProcessInfo, SocketOwner};
 use crate::proxy::NetworkAddr;
+use crate::transport::hysteria2::Hysteria2Config;
+use crate::transport::parse_uuid;
+use crate::transport::simple_obfs::ObfsMode;
 use crate::transport::ssh::{SshAuthentication, SshConfig};
 use crate::transport::trojan::TrojanConfig;
-use crate::transport::wireguard::WireguardConfig;
+use crate::transport::tuic::{TuicConfig, TuicUdpRelayMode};
+use crate::transport::vless::VlessConfig;
+use crate::transport::vmess::{VmessConfig, VmessSecurity};
+use crate::transport::wireguard::{WireguardConfig, WireguardPeerConfig};
 use arc_swap::ArcSwap;
 use base64::Engine;
 use hickory_resolver::config::{NameServerConfig, Protocol, ResolverConfig};
+use ipnet::IpNet;
 use linked_hash_map::LinkedHashMap;
-use regex::Regex;
 use russh::keys::key::PublicKey;
 use shadowsocks::crypto::CipherKind;
 use shadowsocks::ServerAddr;
@@ -31,6 +42,7 @@ use std::collections::{HashMap, HashSet};
 use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 
 pub struct ConnInfo {
     pub src: SocketAddr,
@@ -40,6 +52,7 @@ pub struct ConnInfo {
     pub resolved_dst: Option<SocketAddr>,
     pub connection_type: NetworkType,
     pub process_info: Option<ProcessInfo>,
+    pub socket_owner: Option<SocketOwner>,
 }
 
 impl ConnInfo {
@@ -57,6 +70,7 @@ pub struct Dispatching {
     templist_builder: DispatchingBuilder,
     proxies: HashMap<String, Arc<Proxy>>,
     groups: LinkedHashMap<String, Arc<ProxyGroup>>,
+    provider_checks: Vec<Arc<ProviderHealthCheck>>,
     snippet: DispatchingSnippet,
 }
 
@@ -91,6 +105,29 @@ impl Dispatching {
     pub fn get_group_list(&self) -> Vec<Arc<ProxyGroup>> {
         self.groups.values().cloned().collect()
     }
+
+    /// Switch fallback groups away from failed members without waiting for the next test.
+    pub fn update_fallback_groups(&self) {
+        for group in self.groups.values() {
+            if let GroupPolicy::Fallback { .. } = group.get_policy() {
+                if group.update_selection() {
+                    tracing::info!(
+                        "Group {} switched to {}",
+                        group.get_name(),
+                        group.get_selection()
+                    );
+                }
+            }
+        }
+    }
+
+    pub fn get_proxy(&self, name: &str) -> Option<Arc<Proxy>> {
+        self.proxies.get(name).cloned()
+    }
+
+    pub fn get_provider_checks(&self) -> Vec<Arc<ProviderHealthCheck>> {
+        self.provider_checks.clone()
+    }
 }
 
 fn stringfy_process(info: &ConnInfo) -> &str {
@@ -105,10 +142,12 @@ pub struct DispatchingBuilder {
     config_path: PathBuf,
     proxies: HashMap<String, Arc<Proxy>>,
     groups: HashMap<String, Arc<ProxyGroup>>,
-    rulesets: HashMap<String, Arc<RuleSet>>,
+    provider_checks: Vec<Arc<ProviderHealthCheck>>,
+    rulesets: RuleSetTable,
     group_order: Vec<String>,
     dns: Arc<Dns>,
     mmdb: Option<Arc<MmdbReader>>,
+    geosite: Option<Arc<GeositeReader>>,
     msg_bus: Arc<MessageBus>,
 }
 
@@ -117,16 +156,19 @@ impl DispatchingBuilder {
         config_path: &Path,
         dns: Arc<Dns>,
         mmdb: Option<Arc<MmdbReader>>,
+        geosite: Option<Arc<GeositeReader>>,
         msg_bus: Arc<MessageBus>,
     ) -> Self {
         let mut builder = Self {
             config_path: config_path.to_path_buf(),
             proxies: Default::default(),
             groups: Default::default(),
+            provider_checks: Default::default(),
             rulesets: Default::default(),
             group_order: Default::default(),
             dns,
             mmdb,
+            geosite,
             msg_bus,
         };
         builder.proxies.insert(
@@ -148,11 +190,12 @@ impl DispatchingBuilder {
         config_path: &Path,
         dns: Arc<Dns>,
         mmdb: Option<Arc<MmdbReader>>,
+        geosite: Option<Arc<GeositeReader>>,
         loaded_config: &LoadedConfig,
         ruleset: &RuleSetTable,
         msg_bus: Arc<MessageBus>,
     ) -> Result<Self, ConfigError> {
-        let mut builder = Self::empty(config_path, dns, mmdb, msg_bus);
+        let mut builder = Self::empty(config_path, dns, mmdb, geosite, msg_bus);
         // start init
         let LoadedConfig {
             config,
@@ -165,6 +208,26 @@ impl DispatchingBuilder {
         for proxies in proxy_schema.values() {
             builder.parse_proxies(proxies.proxies.iter().map(|c| (&c.name, &c.cfg)))?;
         }
+        for (name, provider) in &config.proxy_provider {
+            let (Some(check), Some(schema)) = (&provider.health_check, proxy_schema.get(name))
+            else {
+                continue;
+            };
+            let proxies = schema
+                .proxies
+                .iter()
+                .filter_map(|c| builder.proxies.get(&c.name).cloned())
+                .collect();
+            builder
+                .provider_checks
+                .push(Arc::new(ProviderHealthCheck::new(
+                    name.clone(),
+                    proxies,
+                    check.url.clone(),
+                    Duration::from_secs(check.interval.max(1) as u64),
+                    check.lazy,
+                )));
+        }
 
         // read proxy groups
         let mut wg_history = HashMap::new();
@@ -217,11 +280,13 @@ impl DispatchingBuilder {
             TemporaryList::empty()
         };
         let proxies = self.proxies.clone();
+        let provider_checks = self.provider_checks.clone();
         Ok(Dispatching {
             temporary_list: ArcSwap::new(Arc::new(temporary_list)),
             templist_builder: self,
             proxies,
             groups,
+            provider_checks,
             snippet: DispatchingSnippet { rules, fallback },
         })
     }
@@ -233,6 +298,7 @@ impl DispatchingBuilder {
         let mut rule_builder = RuleBuilder::new(
             self.dns.clone(),
             self.mmdb.clone(),
+            self.geosite.clone(),
             &self.proxies,
             &self.groups,
             &self.rulesets,
@@ -302,6 +368,7 @@ impl DispatchingBuilder {
         let mut rule_builder = RuleBuilder::new(
             self.dns.clone(),
             self.mmdb.clone(),
+            self.geosite.clone(),
             &self.proxies,
             &self.groups,
             ruleset,
@@ -321,6 +388,7 @@ impl DispatchingBuilder {
             templist_builder: self,
             proxies,
             groups,
+            provider_checks: vec![],
             snippet: DispatchingSnippet {
                 rules,
                 fallback: GeneralProxy::Single(Arc::new(Proxy::new("REJECT", ProxyImpl::Reject))),
@@ -337,33 +405,107 @@ impl DispatchingBuilder {
             if self.proxies.contains_key(name) || self.groups.contains_key(name) {
                 return Err(ProxyError::DuplicateProxy(name.to_string()).into());
             }
+            let loaded_proxy;
+            let proxy = if let RawProxyLocalCfg::WireguardConf {
+                path,
+                dns_preference,
+                over_tcp,
+                dial,
+            } = proxy
+            {
+                let content = get_file_path(self.config_path.as_path(), path)
+                    .and_then(|p| std::fs::read_to_string(p).ok())
+                    .ok_or_else(|| {
+                        ProxyError::ProxyFieldError(
+                            name.clone(),
+                            "Read WireGuard configuration file",
+                        )
+                    })?;
+                loaded_proxy =
+                    parse_wireguard_conf(name, &content, *dns_preference, *over_tcp, dial.clone())?;
+                &loaded_proxy
+            } else {
+                proxy
+            };
             let p = match proxy {
-                RawProxyLocalCfg::Http { server, port, auth } => Arc::new(Proxy::new(
-                    name.clone(),
-                    ProxyImpl::Http(HttpConfig {
-                        server_addr: NetworkAddr::from(server, *port),
-                        auth: auth.clone(),
-                    }),
-                )),
+                RawProxyLocalCfg::Http {
+                    server,
+                    port,
+                    auth,
+                    tls,
+                    sni,
+                    skip_cert_verify,
+                    http2,
+                    dial,
+                } => {
+                    let host = match server {
+                        RawServerAddr::IpAddr(ip) => ip.to_string(),
+                        RawServerAddr::DomainName(dn) => dn.clone(),
+                    };
+                    Arc::new(Proxy::new(
+                        name.clone(),
+                        ProxyImpl::Http(HttpConfig {
+                            server_addr: NetworkAddr::from(server, *port),
+                            auth: auth.clone(),
+                            tls: *tls,
+                            sni: sni.clone().unwrap_or(host),
+                            skip_cert_verify: *skip_cert_verify,
+                            http2: *http2,
+                            dial: parse_dial_options(name, dial)?,
+                        }),
+                    ))
+                }
                 RawProxyLocalCfg::Socks5 {
                     server,
                     port,
                     auth,
                     udp,
-                } => Arc::new(Proxy::new(
-                    name.clone(),
-                    ProxyImpl::Socks5(Socks5Config {
-                        server_addr: NetworkAddr::from(server, *port),
-                        auth: auth.clone(),
-                        udp: *udp,
-                    }),
-                )),
+                    version,
+                    tls,
+                    sni,
+                    skip_cert_verify,
+                    udp_over_tcp,
+                    dial,
+                } => {
+                    let version = SocksVersion::from_name(version.as_str()).ok_or_else(|| {
+                        ProxyError::ProxyFieldError(name.clone(), "Unknown SOCKS version")
+                    })?;
+                    if *udp_over_tcp && version != SocksVersion::V5 {
+                        return Err(ProxyError::ProxyFieldError(
+                            name.clone(),
+                            "UDP over TCP requires SOCKS5",
+                        )
+                        .into());
+                    }
+                    let host = match server {
+                        RawServerAddr::IpAddr(ip) => ip.to_string(),
+                        RawServerAddr::DomainName(dn) => dn.clone(),
+                    };
+                    Arc::new(Proxy::new(
+                        name.clone(),
+                        ProxyImpl::Socks5(Socks5Config {
+                            server_addr: NetworkAddr::from(server, *port),
+                            auth: auth.clone(),
+                            // UDP ASSOCIATE is only available in SOCKS5
+                            udp: *udp && version == SocksVersion::V5,
+                            version,
+                            tls: *tls,
+                            sni: sni.clone().unwrap_or(host),
+                            skip_cert_verify: *skip_cert_verify,
+                            udp_over_tcp: *udp_over_tcp,
+                            dial: parse_dial_options(name, dial)?,
+                        }),
+                    ))
+                }
                 RawProxyLocalCfg::Shadowsocks {
                     server,
                     port,
                     password,
                     cipher,
                     udp,
+                    plugin,
+                    udp_over_tcp,
+                    dial,
                 } => {
                     let cipher_kind = match cipher.as_str() {
                         "chacha20-ietf-poly1305" => CipherKind::CHACHA20_POLY1305,
@@ -383,13 +525,51 @@ impl DispatchingBuilder {
                         }
                         RawServerAddr::DomainName(dn) => ServerAddr::DomainName(dn.clone(), *port),
                     };
+                    let plugin = match plugin {
+                        None => None,
+                        Some(RawShadowsocksPlugin::Obfs { mode, host }) => {
+                            let Some(mode) = ObfsMode::from_name(mode.as_str()) else {
+                                return Err(ProxyError::ProxyFieldError(
+                                    name.clone(),
+                                    "Unknown obfs mode in Shadowsocks proxy",
+                                )
+                                .into());
+                            };
+                            Some(ShadowsocksPlugin::Obfs {
+                                mode,
+                                host: host.clone(),
+                            })
+                        }
+                        Some(RawShadowsocksPlugin::ShadowTls {
+                            host,
+                            password,
+                            version,
+                        }) => {
+                            if *version != 3 {
+                                return Err(ProxyError::ProxyFieldError(
+                                    name.clone(),
+                                    "Only ShadowTLS v3 is supported",
+                                )
+                                .into());
+                            }
+                            Some(ShadowsocksPlugin::ShadowTls {
+                                host: host.clone(),
+                                password: password.clone(),
+                            })
+                        }
+                    };
+                    // UDP packets can't pass through ShadowTLS servers
+                    let udp = *udp && !matches!(plugin, Some(ShadowsocksPlugin::ShadowTls { .. }));
                     Arc::new(Proxy::new(
                         name.clone(),
                         ProxyImpl::Shadowsocks(ShadowSocksConfig {
                             server_addr: addr,
                             password: password.clone(),
                             cipher_kind,
-                            udp: *udp,
+                            udp,
+                            plugin,
+                            udp_over_tcp: *udp_over_tcp,
+                            dial: parse_dial_options(name, dial)?,
                         }),
                     ))
                 }
@@ -401,6 +581,8 @@ impl DispatchingBuilder {
                     skip_cert_verify,
                     websocket_path,
                     udp,
+                    udp_over_tcp,
+                    dial,
                 } => {
                     let addr = match server {
                         RawServerAddr::IpAddr(ip) => NetworkAddr::Raw(SocketAddr::new(*ip, *port)),
@@ -418,6 +600,184 @@ impl DispatchingBuilder {
                             skip_cert_verify: *skip_cert_verify,
                             websocket_path: websocket_path.clone(),
                             udp: *udp,
+                            udp_over_tcp: *udp_over_tcp,
+                            dial: parse_dial_options(name, dial)?,
+                        }),
+                    ))
+                }
+                RawProxyLocalCfg::Vmess {
+                    server,
+                    port,
+                    uuid,
+                    cipher,
+                    tls,
+                    sni,
+                    skip_cert_verify,
+                    websocket_path,
+                    udp,
+                    dial,
+                } => {
+                    let uuid = parse_uuid(uuid.as_str()).ok_or_else(|| {
+                        ProxyError::ProxyFieldError(name.clone(), "Invalid UUID in VMess proxy")
+                    })?;
+                    let security = VmessSecurity::from_name(cipher.as_str()).ok_or_else(|| {
+                        ProxyError::ProxyFieldError(
+                            name.clone(),
+                            "Unknown cipher kind in VMess proxy",
+                        )
+                    })?;
+                    let (addr, host) = match server {
+                        RawServerAddr::IpAddr(ip) => (
+                            NetworkAddr::Raw(SocketAddr::new(*ip, *port)),
+                            ip.to_string(),
+                        ),
+                        RawServerAddr::DomainName(dn) => (
+                            NetworkAddr::DomainName {
+                                domain_name: dn.clone(),
+                                port: *port,
+                            },
+                            dn.clone(),
+                        ),
+                    };
+                    Arc::new(Proxy::new(
+                        name.clone(),
+                        ProxyImpl::Vmess(VmessConfig {
+                            server_addr: addr,
+                            uuid,
+                            security,
+                            tls: *tls,
+                            sni: sni.clone().unwrap_or(host),
+                            skip_cert_verify: *skip_cert_verify,
+                            websocket_path: websocket_path.clone(),
+                            udp: *udp,
+                            dial: parse_dial_options(name, dial)?,
+                        }),
+                    ))
+                }
+                RawProxyLocalCfg::Vless {
+                    server,
+                    port,
+                    uuid,
+                    tls,
+                    sni,
+                    skip_cert_verify,
+                    websocket_path,
+                    udp,
+                    dial,
+                } => {
+                    let uuid = parse_uuid(uuid.as_str()).ok_or_else(|| {
+                        ProxyError::ProxyFieldError(name.clone(), "Invalid UUID in VLESS proxy")
+                    })?;
+                    let (addr, host) = match server {
+                        RawServerAddr::IpAddr(ip) => (
+                            NetworkAddr::Raw(SocketAddr::new(*ip, *port)),
+                            ip.to_string(),
+                        ),
+                        RawServerAddr::DomainName(dn) => (
+                            NetworkAddr::DomainName {
+                                domain_name: dn.clone(),
+                                port: *port,
+                            },
+                            dn.clone(),
+                        ),
+                    };
+                    Arc::new(Proxy::new(
+                        name.clone(),
+                        ProxyImpl::Vless(VlessConfig {
+                            server_addr: addr,
+                            uuid,
+                            tls: *tls,
+                            sni: sni.clone().unwrap_or(host),
+                            skip_cert_verify: *skip_cert_verify,
+                            websocket_path: websocket_path.clone(),
+                            udp: *udp,
+                            dial: parse_dial_options(name, dial)?,
+                        }),
+                    ))
+                }
+                RawProxyLocalCfg::Hysteria2 {
+                    server,
+                    port,
+                    password,
+                    sni,
+                    skip_cert_verify,
+                    udp,
+                    dial,
+                } => {
+                    let (addr, host) = match server {
+                        RawServerAddr::IpAddr(ip) => (
+                            NetworkAddr::Raw(SocketAddr::new(*ip, *port)),
+                            ip.to_string(),
+                        ),
+                        RawServerAddr::DomainName(dn) => (
+                            NetworkAddr::DomainName {
+                                domain_name: dn.clone(),
+                                port: *port,
+                            },
+                            dn.clone(),
+                        ),
+                    };
+                    Arc::new(Proxy::new(
+                        name.clone(),
+                        ProxyImpl::Hysteria2(Hysteria2Config {
+                            server_addr: addr,
+                            password: password.clone(),
+                            sni: sni.clone().unwrap_or(host),
+                            skip_cert_verify: *skip_cert_verify,
+                            udp: *udp,
+                            dial: parse_dial_options(name, dial)?,
+                        }),
+                    ))
+                }
+                RawProxyLocalCfg::Tuic {
+                    server,
+                    port,
+                    uuid,
+                    password,
+                    sni,
+                    alpn,
+                    skip_cert_verify,
+                    udp_relay_mode,
+                    udp,
+                    dial,
+                } => {
+                    let uuid = parse_uuid(uuid.as_str()).ok_or_else(|| {
+                        ProxyError::ProxyFieldError(name.clone(), "Invalid UUID in TUIC proxy")
+                    })?;
+                    let udp_relay_mode = TuicUdpRelayMode::from_name(udp_relay_mode.as_str())
+                        .ok_or_else(|| {
+                            ProxyError::ProxyFieldError(
+                                name.clone(),
+                                "Unknown UDP relay mode in TUIC proxy",
+                            )
+                        })?;
+                    let (addr, host) = match server {
+                        RawServerAddr::IpAddr(ip) => (
+                            NetworkAddr::Raw(SocketAddr::new(*ip, *port)),
+                            ip.to_string(),
+                        ),
+                        RawServerAddr::DomainName(dn) => (
+                            NetworkAddr::DomainName {
+                                domain_name: dn.clone(),
+                                port: *port,
+                            },
+                            dn.clone(),
+                        ),
+                    };
+                    Arc::new(Proxy::new(
+                        name.clone(),
+                        ProxyImpl::Tuic(TuicConfig {
+                            server_addr: addr,
+                            uuid,
+                            password: password.clone(),
+                            sni: sni.clone().unwrap_or(host),
+                            alpn: alpn
+                                .clone()
+                                .map_or_else(|| vec!["h3".to_string()], |a| a.linearize()),
+                            skip_cert_verify: *skip_cert_verify,
+                            udp_relay_mode,
+                            udp: *udp,
+                            dial: parse_dial_options(name, dial)?,
                         }),
                     ))
                 }
@@ -433,7 +793,10 @@ impl DispatchingBuilder {
                     dns,
                     dns_preference,
                     reserved,
+                    allowed_ips,
+                    peers,
                     over_tcp,
+                    dial,
                 } => {
                     if local_addr.is_none() && local_addr_v6.is_none() {
                         return Err(ProxyError::ProxyFieldError(
@@ -442,64 +805,28 @@ impl DispatchingBuilder {
                         )
                         .into());
                     }
-                    let endpoint = match endpoint {
-                        RawServerSockAddr::Ip(addr) => NetworkAddr::Raw(*addr),
-                        RawServerSockAddr::Domain(a) => {
-                            let parts = a.split(':').collect::<Vec<&str>>();
-                            let Some(port_str) = parts.get(1) else {
-                                return Err(ProxyError::ProxyFieldError(
-                                    name.clone(),
-                                    "No port configured for the WireGuard outbound",
-                                )
-                                .into());
-                            };
-                            let port = port_str.parse::<u16>().map_err(|_| {
-                                ProxyError::ProxyFieldError(name.clone(), "Invalid port")
-                            })?;
-                            #[allow(clippy::get_first)]
-                            NetworkAddr::DomainName {
-                                domain_name: parts.get(0).unwrap().to_string(),
-                                port,
-                            }
-                        }
-                    };
-                    // parse key
-                    let b64decoder = base64::engine::general_purpose::STANDARD;
-                    let private_key = {
-                        let val = b64decoder.decode(private_key).map_err(|_| {
-                            ProxyError::ProxyFieldError(
-                                name.clone(),
-                                "Decode private key in base64 format",
-                            )
-                        })?;
-                        let val: [u8; 32] = val.try_into().map_err(|_| {
-                            ProxyError::ProxyFieldError(name.clone(), "Invalid private key")
-                        })?;
-                        x25519_dalek::StaticSecret::from(val)
-                    };
-                    let public_key = {
-                        let val = b64decoder.decode(public_key).map_err(|_| {
-                            ProxyError::ProxyFieldError(
-                                name.clone(),
-                                "Decode public key in base64 format",
-                            )
-                        })?;
-                        let val: [u8; 32] = val.try_into().map_err(|_| {
-                            ProxyError::ProxyFieldError(name.clone(), "Invalid public key")
-                        })?;
-                        x25519_dalek::PublicKey::from(val)
-                    };
-                    let preshared_key = if let Some(v) = preshared_key {
-                        let val = b64decoder.decode(v).map_err(|_| {
-                            ProxyError::ProxyFieldError(name.clone(), "Decode PSK in base64 format")
-                        })?;
-                        let val: [u8; 32] = val.try_into().map_err(|_| {
-                            ProxyError::ProxyFieldError(name.clone(), "Invalid PSK")
-                        })?;
-                        Some(val)
-                    } else {
-                        None
-                    };
+                    let private_key = x25519_dalek::StaticSecret::from(parse_wireguard_key(
+                        private_key,
+                        name,
+                        "Decode private key in base64 format",
+                        "Invalid private key",
+                    )?);
+                    // the first peer routes everything by default
+                    let default_allowed_ips = vec!["0.0.0.0/0".to_string(), "::/0".to_string()];
+                    let mut peer_list = vec![parse_wireguard_peer(
+                        name,
+                        &RawWireguardPeer {
+                            public_key: public_key.clone(),
+                            endpoint: endpoint.clone(),
+                            preshared_key: preshared_key.clone(),
+                            keepalive: *keepalive,
+                            reserved: *reserved,
+                            allowed_ips: allowed_ips.clone().unwrap_or(default_allowed_ips),
+                        },
+                    )?];
+                    for peer in peers {
+                        peer_list.push(parse_wireguard_peer(name, peer)?);
+                    }
                     let dns = {
                         let list = String::from("[") + dns.as_str() + "]";
                         let list: Vec<IpAddr> =
@@ -520,18 +847,18 @@ impl DispatchingBuilder {
                             ip_addr: *local_addr,
                             ip_addr6: *local_addr_v6,
                             private_key,
-                            public_key,
-                            endpoint,
+                            peers: peer_list,
                             mtu: *mtu,
-                            preshared_key,
-                            keepalive: *keepalive,
                             dns,
                             dns_preference: *dns_preference,
-                            reserved: *reserved,
                             over_tcp: *over_tcp,
+                            dial: parse_dial_options(name, dial)?,
                         }),
                     ))
                 }
+                RawProxyLocalCfg::WireguardConf { .. } => {
+                    unreachable!("WireGuard configuration file should have been loaded")
+                }
                 RawProxyLocalCfg::Ssh {
                     server,
                     port,
@@ -540,9 +867,23 @@ impl DispatchingBuilder {
                     private_key,
                     key_passphrase,
                     host_pubkey,
+                    agent,
+                    agent_socket,
+                    known_hosts,
+                    udpgw,
+                    dial,
                 } => {
                     // construct authentication data
-                    let auth = if let Some(key_path) = private_key {
+                    let auth = if *agent {
+                        let socket = match agent_socket {
+                            Some(path) => get_file_path(self.config_path.as_path(), path),
+                            None => std::env::var_os("SSH_AUTH_SOCK").map(PathBuf::from),
+                        }
+                        .ok_or_else(|| {
+                            ProxyError::ProxyFieldError(name.clone(), "No ssh-agent socket found")
+                        })?;
+                        SshAuthentication::Agent(socket)
+                    } else if let Some(key_path) = private_key {
                         let key_content = russh::keys::load_secret_key(
                             get_file_path(self.config_path.as_path(), key_path).ok_or_else(
                                 || {
@@ -584,6 +925,24 @@ impl DispatchingBuilder {
                     } else {
                         None
                     };
+                    // hosts are only checked against known_hosts when it is configured
+                    let known_hosts = match known_hosts {
+                        Some(path) => Some(
+                            get_file_path(self.config_path.as_path(), path)
+                                .filter(|p| p.exists())
+                                .ok_or_else(|| {
+                                    ProxyError::ProxyFieldError(
+                                        name.clone(),
+                                        "known_hosts file not found",
+                                    )
+                                })?,
+                        ),
+                        None => None,
+                    };
+                    let udpgw = match udpgw {
+                        Some(addr) => Some(parse_server_sock_addr(name, addr)?),
+                        None => None,
+                    };
                     Arc::new(Proxy::new(
                         name.clone(),
                         ProxyImpl::Ssh(SshConfig {
@@ -591,6 +950,9 @@ impl DispatchingBuilder {
                             user: user.clone(),
                             auth,
                             host_pubkey,
+                            known_hosts,
+                            udpgw,
+                            dial: parse_dial_options(name, dial)?,
                         }),
                     ))
                 }
@@ -649,6 +1011,10 @@ impl DispatchingBuilder {
                 }
                 contents.push(proxy);
             }
+            // UDP sessions routed to such chains are rejected while dispatching
+            if !chain_support_udp(&contents) {
+                tracing::warn!("Chain {} cannot relay UDP with current selections", name);
+            }
             self.proxies.insert(
                 name.to_string(),
                 Arc::new(Proxy::new(name.to_string(), ProxyImpl::Chain(contents))),
@@ -684,32 +1050,14 @@ impl DispatchingBuilder {
 
             // used providers
             for p in proxy_group.providers.as_ref().unwrap_or(&vec![]) {
-                let valid_proxies: Vec<&str> = match p {
-                    RawProxyProviderOption::Name(name) => proxy_schema
-                        .get(name)
-                        .ok_or_else(|| ProviderError::Missing(name.clone()))?
+                let valid_proxies = p.select(
+                    proxy_schema
+                        .get(p.provider_name())
+                        .ok_or_else(|| ProviderError::Missing(p.provider_name().to_string()))?
                         .proxies
                         .iter()
-                        .map(|entry| entry.name.as_str())
-                        .collect(),
-                    RawProxyProviderOption::Filter { name, filter } => {
-                        let regex = Regex::new(filter)
-                            .map_err(|_| ProviderError::BadFilter(name.clone(), filter.clone()))?;
-                        proxy_schema
-                            .get(name)
-                            .ok_or_else(|| ProviderError::Missing(name.clone()))?
-                            .proxies
-                            .iter()
-                            .filter_map(|entry| {
-                                if regex.is_match(entry.name.as_str()) {
-                                    Some(entry.name.as_str())
-                                } else {
-                                    None
-                                }
-                            })
-                            .collect()
-                    }
-                };
+                        .map(|entry| entry.name.as_str()),
+                )?;
                 for p in valid_proxies {
                     let content = if let Some(single) = self.proxies.get(p) {
                         GeneralProxy::Single(single.clone())
@@ -732,6 +1080,21 @@ impl DispatchingBuilder {
             }
 
             let first = arr.first().unwrap().clone();
+            let interval = Duration::from_secs(proxy_group.interval.unwrap_or(300).max(1) as u64);
+            let policy = match proxy_group.group_type {
+                RawProxyGroupType::Select => GroupPolicy::Select,
+                RawProxyGroupType::UrlTest => GroupPolicy::UrlTest {
+                    interval,
+                    tolerance: proxy_group.tolerance.unwrap_or(50),
+                },
+                RawProxyGroupType::Fallback => GroupPolicy::Fallback { interval },
+                RawProxyGroupType::LoadBalance => GroupPolicy::LoadBalance {
+                    interval,
+                    strategy: proxy_group
+                        .strategy
+                        .unwrap_or(LoadBalanceStrategy::ConsistentHashing),
+                },
+            };
             // If there is no selection now, select the first.
             self.groups.insert(
                 name.to_string(),
@@ -740,6 +1103,7 @@ impl DispatchingBuilder {
                     arr,
                     selection.unwrap_or(first),
                     proxy_group.interface.clone(),
+                    policy,
                 )),
             );
             Ok(())
@@ -838,8 +1202,9 @@ impl DispatchingSnippet {
         rule_str: &str,
         verbose: bool,
     ) -> (String, Arc<ProxyImpl>, Option<String>) {
-        let (proxy_impl, iface) = proxy.get_impl();
-        let name = proxy.selected_instance_name();
+        let (selected, iface) = proxy.get_proxy_and_interface(info);
+        let proxy_impl = selected.get_impl();
+        let name = selected.get_name();
         if !proxy_impl.support_udp() && info.connection_type == NetworkType::Udp {
             if verbose {
                 tracing::info!(
@@ -878,6 +1243,79 @@ fn get_file_path(config_path: &Path, path: &Path) -> Option<PathBuf> {
     })
 }
 
+fn parse_server_sock_addr(name: &str, addr: &RawServerSockAddr) -> Result<NetworkAddr, ProxyError> {
+    Ok(match addr {
+        RawServerSockAddr::Ip(addr) => NetworkAddr::Raw(*addr),
+        RawServerSockAddr::Domain(a) => {
+            let parts = a.split(':').collect::<Vec<&str>>();
+            let Some(port_str) = parts.get(1) else {
+                return Err(ProxyError::ProxyFieldError(
+                    name.to_string(),
+                    "No port configured in the server address",
+                ));
+            };
+            let port = port_str
+                .parse::<u16>()
+                .map_err(|_| ProxyError::ProxyFieldError(name.to_string(), "Invalid port"))?;
+            #[allow(clippy::get_first)]
+            NetworkAddr::DomainName {
+                domain_name: parts.get(0).unwrap().to_string(),
+                port,
+            }
+        }
+    })
+}
+
+fn parse_wireguard_key(
+    key: &str,
+    name: &str,
+    decode_err: &'static str,
+    invalid_err: &'static str,
+) -> Result<[u8; 32], ProxyError> {
+    let val = base64::engine::general_purpose::STANDARD
+        .decode(key)
+        .map_err(|_| ProxyError::ProxyFieldError(name.to_string(), decode_err))?;
+    val.try_into()
+        .map_err(|_| ProxyError::ProxyFieldError(name.to_string(), invalid_err))
+}
+
+fn parse_wireguard_peer(
+    name: &str,
+    peer: &RawWireguardPeer,
+) -> Result<WireguardPeerConfig, ProxyError> {
+    let endpoint = parse_server_sock_addr(name, &peer.endpoint)?;
+    let public_key = x25519_dalek::PublicKey::from(parse_wireguard_key(
+        peer.public_key.as_str(),
+        name,
+        "Decode public key in base64 format",
+        "Invalid public key",
+    )?);
+    let preshared_key = if let Some(v) = &peer.preshared_key {
+        Some(parse_wireguard_key(
+            v.as_str(),
+            name,
+            "Decode PSK in base64 format",
+            "Invalid PSK",
+        )?)
+    } else {
+        None
+    };
+    let allowed_ips = peer
+        .allowed_ips
+        .iter()
+        .map(|s| s.parse::<IpNet>())
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| ProxyError::ProxyFieldError(name.to_string(), "Invalid AllowedIPs"))?;
+    Ok(WireguardPeerConfig {
+        public_key,
+        endpoint,
+        preshared_key,
+        keepalive: peer.keepalive,
+        reserved: peer.reserved,
+        allowed_ips,
+    })
+}
+
 fn parse_pubkey(pubkey: &str, name: &str) -> Result<(String, PublicKey), ProxyError> {
     let (key_type, content) = pubkey.split_once(' ').ok_or_else(|| {
         ProxyError::ProxyFieldError(
@@ -895,3 +1333,73 @@ fn parse_pubkey(pubkey: &str, name: &str) -> Result<(String, PublicKey), ProxyEr
         })?,
     ))
 }
+
+fn parse_dial_options(
+    name: &str,
+    dial: &Option<RawDialOptions>,
+) -> Result<DialOptions, ProxyError> {
+    let Some(dial) = dial else {
+        return Ok(DialOptions::default());
+    };
+    if !cfg!(target_os = "linux") && (dial.mark.is_some() || dial.tcp_fast_open) {
+        return Err(ProxyError::ProxyFieldError(
+            name.to_string(),
+            "SO_MARK and TCP Fast Open are only supported on Linux",
+        ));
+    }
+    Ok(DialOptions {
+        interface: dial.interface.clone(),
+        source_addr: dial.source_addr,
+        mark: dial.mark,
+        tcp_keepalive: dial.tcp_keepalive.map(Duration::from_secs),
+        tcp_fast_open: dial.tcp_fast_open,
+        connect_timeout: dial.connect_timeout.map(Duration::from_secs),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn raw_dial() -> RawDialOptions {
+        RawDialOptions {
+            interface: Some("eth1".to_string()),
+            source_addr: Some("192.0.2.2".parse().unwrap()),
+            mark: None,
+            tcp_keepalive: Some(30),
+            tcp_fast_open: false,
+            connect_timeout: Some(5),
+        }
+    }
+
+    #[test]
+    fn test_parse_dial_options() {
+        assert_eq!(
+            parse_dial_options("proxy", &None).unwrap(),
+            DialOptions::default()
+        );
+        let dial = parse_dial_options("proxy", &Some(raw_dial())).unwrap();
+        assert_eq!(dial.interface.as_deref(), Some("eth1"));
+        assert_eq!(dial.source_addr, Some("192.0.2.2".parse().unwrap()));
+        assert_eq!(dial.tcp_keepalive, Some(Duration::from_secs(30)));
+        assert_eq!(dial.connect_timeout, Some(Duration::from_secs(5)));
+
+        // SO_MARK and TCP Fast Open are Linux only
+        let mark = RawDialOptions {
+            mark: Some(0xff),
+            ..raw_dial()
+        };
+        let tfo = RawDialOptions {
+            tcp_fast_open: true,
+            ..raw_dial()
+        };
+        for raw in [mark, tfo] {
+            let result = parse_dial_options("proxy", &Some(raw));
+            if cfg!(target_os = "linux") {
+                assert!(result.is_ok());
+            } else {
+                assert!(matches!(result, Err(ProxyError::ProxyFieldError(n, _)) if n == "proxy"));
+            }
+        }
+    }
+}
@@ -1,9 +1,11 @@
 mod action;
+mod compiled_ruleset;
 mod dispatching;
 mod inbound;
 mod proxy;
 mod rule;
 mod ruleset;
+mod srs;
 mod temporary;
 
 pub use dispatching::*;
@@ -12,3 +14,4 @@ pub use proxy::*;
 // expose this interface for performance
 pub use rule::RuleImpl;
 pub use ruleset::*;
+pub(crate) use srs::SRS_MAGIC;
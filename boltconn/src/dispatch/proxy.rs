@@ -1,13 +1,25 @@
-use crate::adapter::{HttpConfig, ShadowSocksConfig, Socks5Config};
-use crate::config::ProxyError;
+use crate::adapter::{
+    HttpConfig, OutboundType, ShadowSocksConfig, Socks5Config, SocksVersion, TcpTransferType,
+    UdpTransferType,
+};
+use crate::config::{LoadBalanceStrategy, ProxyError};
+use crate::dispatch::ConnInfo;
 use crate::proxy::NetworkAddr;
+use crate::transport::hysteria2::Hysteria2Config;
 use crate::transport::ssh::SshConfig;
 use crate::transport::trojan::TrojanConfig;
+use crate::transport::tuic::TuicConfig;
+use crate::transport::vless::VlessConfig;
+use crate::transport::vmess::VmessConfig;
 use crate::transport::wireguard::WireguardConfig;
 use arc_swap::ArcSwap;
 use shadowsocks::ServerAddr;
+use std::collections::hash_map::DefaultHasher;
 use std::fmt::{Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum Latency {
@@ -16,12 +28,17 @@ pub enum Latency {
     Failed,
 }
 
+// Consecutive connect failures before a proxy is marked as failed
+const FAILURE_THRESHOLD: u32 = 3;
+
 /// Single proxy configuation.
 #[derive(Debug)]
 pub struct Proxy {
     name: String,
     detail: Arc<ProxyImpl>,
     latency: Mutex<Latency>,
+    failures: AtomicU32,
+    used: AtomicBool,
 }
 
 impl Proxy {
@@ -30,6 +47,8 @@ impl Proxy {
             name: name.into(),
             detail: Arc::new(detail),
             latency: Mutex::new(Latency::Unknown),
+            failures: AtomicU32::new(0),
+            used: AtomicBool::new(false),
         }
     }
     pub fn get_name(&self) -> String {
@@ -45,8 +64,34 @@ impl Proxy {
     }
 
     pub fn set_latency(&self, latency: Latency) {
+        if let Latency::Value(_) = latency {
+            self.failures.store(0, Ordering::Relaxed);
+        }
         *self.latency.lock().unwrap() = latency;
     }
+
+    /// Record a failed connection; mark the proxy as failed if it keeps failing.
+    /// Return true if the proxy has just been marked as failed.
+    pub fn report_failure(&self) -> bool {
+        self.used.store(true, Ordering::Relaxed);
+        if self.failures.fetch_add(1, Ordering::Relaxed) + 1 < FAILURE_THRESHOLD {
+            return false;
+        }
+        let mut latency = self.latency.lock().unwrap();
+        let changed = *latency != Latency::Failed;
+        *latency = Latency::Failed;
+        changed
+    }
+
+    pub fn report_success(&self) {
+        self.used.store(true, Ordering::Relaxed);
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    /// Whether any connection has used the proxy since the last call.
+    pub fn take_used(&self) -> bool {
+        self.used.swap(false, Ordering::Relaxed)
+    }
 }
 
 #[derive(Debug)]
@@ -58,6 +103,10 @@ pub enum ProxyImpl {
     Socks5(Socks5Config),
     Shadowsocks(ShadowSocksConfig),
     Trojan(TrojanConfig),
+    Vmess(VmessConfig),
+    Vless(VlessConfig),
+    Hysteria2(Hysteria2Config),
+    Tuic(TuicConfig),
     Wireguard(WireguardConfig),
     Ssh(SshConfig),
     Chain(Vec<GeneralProxy>),
@@ -70,13 +119,50 @@ impl ProxyImpl {
             ProxyImpl::Reject => true,
             ProxyImpl::BlackHole => true,
             ProxyImpl::Http(_) => false,
-            ProxyImpl::Socks5(c) => c.udp,
-            ProxyImpl::Shadowsocks(c) => c.udp,
-            ProxyImpl::Trojan(c) => c.udp,
+            ProxyImpl::Socks5(c) => c.udp || c.udp_over_tcp,
+            ProxyImpl::Shadowsocks(c) => c.udp || c.udp_over_tcp,
+            ProxyImpl::Trojan(c) => c.udp || c.udp_over_tcp,
+            ProxyImpl::Vmess(c) => c.udp,
+            ProxyImpl::Vless(c) => c.udp,
+            ProxyImpl::Hysteria2(c) => c.udp,
+            ProxyImpl::Tuic(c) => c.udp,
             ProxyImpl::Wireguard(_) => true,
-            ProxyImpl::Ssh(_) => false,
-            // since it's hard to determine, we just assume it true
-            ProxyImpl::Chain(_) => true,
+            ProxyImpl::Ssh(c) => c.udpgw.is_some(),
+            ProxyImpl::Chain(hops) => chain_support_udp(hops),
+        }
+    }
+
+    pub fn outbound_type(&self) -> Option<OutboundType> {
+        Some(match self {
+            ProxyImpl::Direct => OutboundType::Direct,
+            ProxyImpl::Reject | ProxyImpl::BlackHole => return None,
+            ProxyImpl::Http(_) => OutboundType::Http,
+            ProxyImpl::Socks5(_) => OutboundType::Socks5,
+            ProxyImpl::Shadowsocks(_) => OutboundType::Shadowsocks,
+            ProxyImpl::Trojan(_) => OutboundType::Trojan,
+            ProxyImpl::Vmess(_) => OutboundType::Vmess,
+            ProxyImpl::Vless(_) => OutboundType::Vless,
+            ProxyImpl::Hysteria2(_) => OutboundType::Hysteria2,
+            ProxyImpl::Tuic(_) => OutboundType::Tuic,
+            ProxyImpl::Wireguard(_) => OutboundType::Wireguard,
+            ProxyImpl::Ssh(_) => OutboundType::Ssh,
+            ProxyImpl::Chain(_) => OutboundType::Chain,
+        })
+    }
+
+    pub fn tcp_transfer_type(&self) -> TcpTransferType {
+        self.outbound_type()
+            .map_or(TcpTransferType::NotApplicable, |t| t.tcp_transfer_type())
+    }
+
+    /// Same as the outbound's, where UDP over TCP is taken into account.
+    pub fn udp_transfer_type(&self) -> UdpTransferType {
+        match self {
+            ProxyImpl::Socks5(c) if c.udp_over_tcp => UdpTransferType::UdpOverTcp,
+            ProxyImpl::Shadowsocks(c) if c.udp_over_tcp => UdpTransferType::UdpOverTcp,
+            _ => self
+                .outbound_type()
+                .map_or(UdpTransferType::NotApplicable, |t| t.udp_transfer_type()),
         }
     }
 
@@ -86,9 +172,16 @@ impl ProxyImpl {
             ProxyImpl::Reject => "reject",
             ProxyImpl::BlackHole => "blackhole",
             ProxyImpl::Http(_) => "http",
-            ProxyImpl::Socks5(_) => "socks5",
+            ProxyImpl::Socks5(c) => match c.version {
+                SocksVersion::V4 | SocksVersion::V4a => "socks4",
+                SocksVersion::V5 => "socks5",
+            },
             ProxyImpl::Shadowsocks(_) => "shadowsocks",
             ProxyImpl::Trojan(_) => "trojan",
+            ProxyImpl::Vmess(_) => "vmess",
+            ProxyImpl::Vless(_) => "vless",
+            ProxyImpl::Hysteria2(_) => "hysteria2",
+            ProxyImpl::Tuic(_) => "tuic",
             ProxyImpl::Wireguard(_) => "wireguard",
             ProxyImpl::Ssh(_) => "ssh",
             ProxyImpl::Chain(_) => "chain",
@@ -110,12 +203,144 @@ impl ProxyImpl {
                 }
             }),
             ProxyImpl::Trojan(c) => Some(c.server_addr.clone()),
-            ProxyImpl::Wireguard(c) => Some(c.endpoint.clone()),
+            ProxyImpl::Vmess(c) => Some(c.server_addr.clone()),
+            ProxyImpl::Vless(c) => Some(c.server_addr.clone()),
+            ProxyImpl::Hysteria2(c) => Some(c.server_addr.clone()),
+            ProxyImpl::Tuic(c) => Some(c.server_addr.clone()),
+            ProxyImpl::Wireguard(c) => c.peers.first().map(|p| p.endpoint.clone()),
             ProxyImpl::Ssh(c) => Some(c.server.clone()),
         }
     }
 }
 
+/// Whether a chain can relay UDP, following the current selection of group hops.
+///
+/// Hops are ordered from the exit to the entry like `ChainOutbound`. Each hop carries the traffic
+/// of the previous one over TCP or UDP, and only the entry connects to the network by itself.
+pub(crate) fn chain_support_udp(hops: &[GeneralProxy]) -> bool {
+    let mut over_udp = true;
+    for (idx, hop) in hops.iter().enumerate() {
+        let proxy = match hop {
+            GeneralProxy::Single(p) => p.get_impl(),
+            GeneralProxy::Group(g) => g.get_proxy().get_impl(),
+        };
+        if matches!(
+            proxy.as_ref(),
+            ProxyImpl::Reject | ProxyImpl::BlackHole | ProxyImpl::Chain(_)
+        ) {
+            return false;
+        }
+        if idx == hops.len() - 1 {
+            return !over_udp || proxy.support_udp();
+        }
+        if matches!(proxy.as_ref(), ProxyImpl::Direct | ProxyImpl::Hysteria2(_)) {
+            return false;
+        }
+        over_udp = if over_udp {
+            if !proxy.support_udp() {
+                return false;
+            }
+            match proxy.udp_transfer_type() {
+                // SOCKS5 relays UDP only with its own control connection
+                UdpTransferType::Udp if matches!(proxy.as_ref(), ProxyImpl::Socks5(_)) => {
+                    return false
+                }
+                UdpTransferType::Udp => true,
+                UdpTransferType::UdpOverTcp => false,
+                UdpTransferType::NotApplicable => return false,
+            }
+        } else {
+            proxy.tcp_transfer_type() == TcpTransferType::TcpOverUdp
+        };
+    }
+    false
+}
+
+/// How a group decides its selection
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupPolicy {
+    /// Selected manually
+    Select,
+    /// Tested periodically and switched to the member with the lowest latency
+    UrlTest { interval: Duration, tolerance: u32 },
+    /// Tested periodically and switched to the first member that is not failed
+    Fallback { interval: Duration },
+    /// Tested periodically and each connection is assigned to one of the healthy members
+    LoadBalance {
+        interval: Duration,
+        strategy: LoadBalanceStrategy,
+    },
+}
+
+impl GroupPolicy {
+    /// Interval of background test, if needed
+    pub fn test_interval(&self) -> Option<Duration> {
+        match self {
+            GroupPolicy::Select => None,
+            GroupPolicy::UrlTest { interval, .. }
+            | GroupPolicy::Fallback { interval }
+            | GroupPolicy::LoadBalance { interval, .. } => Some(*interval),
+        }
+    }
+}
+
+/// Background latency test of all proxies from a provider.
+#[derive(Debug)]
+pub struct ProviderHealthCheck {
+    name: String,
+    proxies: Vec<Arc<Proxy>>,
+    url: Option<String>,
+    interval: Duration,
+    lazy: bool,
+}
+
+impl ProviderHealthCheck {
+    pub fn new(
+        name: String,
+        proxies: Vec<Arc<Proxy>>,
+        url: Option<String>,
+        interval: Duration,
+        lazy: bool,
+    ) -> Self {
+        Self {
+            name,
+            proxies,
+            url,
+            interval,
+            lazy,
+        }
+    }
+
+    pub fn get_name(&self) -> String {
+        self.name.clone()
+    }
+
+    pub fn get_proxies(&self) -> &Vec<Arc<Proxy>> {
+        &self.proxies
+    }
+
+    pub fn get_url(&self) -> Option<String> {
+        self.url.clone()
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+
+    /// Lazy checks are skipped if the provider has not been used since the last one.
+    pub fn should_test(&self) -> bool {
+        if !self.lazy {
+            return true;
+        }
+        // clear the flags of all proxies for the next turn
+        let mut used = false;
+        for proxy in self.proxies.iter() {
+            used |= proxy.take_used();
+        }
+        used
+    }
+}
+
 /// A group of proxies
 #[derive(Debug)]
 pub struct ProxyGroup {
@@ -123,6 +348,8 @@ pub struct ProxyGroup {
     proxies: Vec<GeneralProxy>,
     selection: ArcSwap<GeneralProxy>,
     interface: Option<String>,
+    policy: GroupPolicy,
+    next_index: AtomicUsize,
 }
 
 impl ProxyGroup {
@@ -131,12 +358,15 @@ impl ProxyGroup {
         proxies: Vec<GeneralProxy>,
         selection: GeneralProxy,
         interface: Option<String>,
+        policy: GroupPolicy,
     ) -> Self {
         Self {
             name: name.into(),
             proxies,
             selection: ArcSwap::new(Arc::new(selection)),
             interface,
+            policy,
+            next_index: AtomicUsize::new(0),
         }
     }
 
@@ -163,13 +393,120 @@ impl ProxyGroup {
         self.interface.clone()
     }
 
-    pub fn get_proxy_and_interface(&self) -> (Arc<Proxy>, Option<String>) {
+    pub fn get_policy(&self) -> GroupPolicy {
+        self.policy
+    }
+
+    /// Update the selection according to the latest latency.
+    /// Return true if the selection has changed.
+    pub fn update_selection(&self) -> bool {
+        match self.policy {
+            GroupPolicy::Select => false,
+            GroupPolicy::UrlTest { tolerance, .. } => self.select_fastest(tolerance),
+            // for load balance, the selection is only used when a single proxy is required
+            GroupPolicy::Fallback { .. } | GroupPolicy::LoadBalance { .. } => {
+                self.select_first_available()
+            }
+        }
+    }
+
+    // Switch to the member with the lowest latency.
+    // The current selection is kept if it is slower by no more than `tolerance` ms.
+    fn select_fastest(&self, tolerance: u32) -> bool {
+        let mut fastest: Option<(&GeneralProxy, u32)> = None;
+        for p in &self.proxies {
+            if let Latency::Value(ms) = p.get_latency() {
+                match fastest {
+                    Some((_, best)) if best <= ms => {}
+                    _ => fastest = Some((p, ms)),
+                }
+            }
+        }
+        let Some((candidate, best)) = fastest else {
+            return false;
+        };
+        let current = self.selection.load();
+        if current.as_ref() == candidate {
+            return false;
+        }
+        if let Latency::Value(ms) = current.get_latency() {
+            if ms <= best.saturating_add(tolerance) {
+                return false;
+            }
+        }
+        self.selection.store(Arc::new(candidate.clone()));
+        true
+    }
+
+    // Switch to the first member that is not failed, so the preferred one is back once recovered.
+    // If all of them failed, the first one is used.
+    fn select_first_available(&self) -> bool {
+        let Some(candidate) = self
+            .proxies
+            .iter()
+            .find(|p| p.get_latency() != Latency::Failed)
+            .or(self.proxies.first())
+        else {
+            return false;
+        };
+        if self.selection.load().as_ref() == candidate {
+            return false;
+        }
+        self.selection.store(Arc::new(candidate.clone()));
+        true
+    }
+
+    pub fn get_proxy_and_interface(&self, info: &ConnInfo) -> (Arc<Proxy>, Option<String>) {
+        if let GroupPolicy::LoadBalance { strategy, .. } = self.policy {
+            return match self.balance(strategy, info) {
+                GeneralProxy::Single(p) => (p.clone(), self.get_direct_interface()),
+                GeneralProxy::Group(g) => g.get_proxy_and_interface(info),
+            };
+        }
         match self.selection.load().as_ref() {
             GeneralProxy::Single(ref p) => (p.clone(), self.get_direct_interface()),
-            GeneralProxy::Group(ref g) => g.get_proxy_and_interface(),
+            GeneralProxy::Group(ref g) => g.get_proxy_and_interface(info),
+        }
+    }
+
+    fn balance(&self, strategy: LoadBalanceStrategy, info: &ConnInfo) -> &GeneralProxy {
+        let mut candidates: Vec<&GeneralProxy> = self
+            .proxies
+            .iter()
+            .filter(|p| p.get_latency() != Latency::Failed)
+            .collect();
+        if candidates.is_empty() {
+            candidates = self.proxies.iter().collect();
+        }
+        match strategy {
+            LoadBalanceStrategy::ConsistentHashing => match &info.dst {
+                NetworkAddr::DomainName { domain_name, .. } => {
+                    Self::rendezvous(&candidates, domain_name)
+                }
+                NetworkAddr::Raw(addr) => Self::rendezvous(&candidates, addr.ip()),
+            },
+            LoadBalanceStrategy::StickySessions => Self::rendezvous(&candidates, info.src.ip()),
+            LoadBalanceStrategy::RoundRobin => {
+                let idx = self.next_index.fetch_add(1, Ordering::Relaxed);
+                candidates[idx % candidates.len()]
+            }
         }
     }
 
+    // Rendezvous hashing: when a member fails, only connections assigned to it are moved.
+    fn rendezvous<'a, K: Hash>(candidates: &[&'a GeneralProxy], key: K) -> &'a GeneralProxy {
+        candidates
+            .iter()
+            .copied()
+            .max_by_key(|p| {
+                let mut hasher = DefaultHasher::new();
+                key.hash(&mut hasher);
+                p.get_name().hash(&mut hasher);
+                hasher.finish()
+            })
+            .unwrap()
+    }
+
     pub fn set_selection(&self, name: &str) -> Result<(), ProxyError> {
         for p in &self.proxies {
             match p {
@@ -203,13 +540,24 @@ pub enum GeneralProxy {
 }
 
 impl GeneralProxy {
-    pub fn get_impl(&self) -> (Arc<ProxyImpl>, Option<String>) {
+    pub fn get_name(&self) -> String {
+        match self {
+            GeneralProxy::Single(p) => p.get_name(),
+            GeneralProxy::Group(g) => g.get_name(),
+        }
+    }
+
+    pub fn get_proxy_and_interface(&self, info: &ConnInfo) -> (Arc<Proxy>, Option<String>) {
         match &self {
-            GeneralProxy::Single(p) => (p.get_impl(), None),
-            GeneralProxy::Group(g) => {
-                let (p, iface) = g.get_proxy_and_interface();
-                (p.get_impl(), iface)
-            }
+            GeneralProxy::Single(p) => (p.clone(), None),
+            GeneralProxy::Group(g) => g.get_proxy_and_interface(info),
+        }
+    }
+
+    pub fn get_latency(&self) -> Latency {
+        match self {
+            GeneralProxy::Single(p) => p.get_latency(),
+            GeneralProxy::Group(g) => g.get_proxy().get_latency(),
         }
     }
 
@@ -247,3 +595,212 @@ impl PartialEq for GeneralProxy {
         false
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn socks5(udp: bool, udp_over_tcp: bool) -> GeneralProxy {
+        GeneralProxy::Single(Arc::new(Proxy::new(
+            "socks5",
+            ProxyImpl::Socks5(Socks5Config {
+                server_addr: NetworkAddr::Raw("127.0.0.1:1080".parse().unwrap()),
+                auth: None,
+                udp,
+                version: SocksVersion::V5,
+                tls: false,
+                sni: String::new(),
+                skip_cert_verify: false,
+                udp_over_tcp,
+                dial: Default::default(),
+            }),
+        )))
+    }
+
+    fn http() -> GeneralProxy {
+        GeneralProxy::Single(Arc::new(Proxy::new(
+            "http",
+            ProxyImpl::Http(HttpConfig {
+                server_addr: NetworkAddr::Raw("127.0.0.1:8080".parse().unwrap()),
+                auth: None,
+                tls: false,
+                sni: String::new(),
+                skip_cert_verify: false,
+                http2: false,
+                dial: Default::default(),
+            }),
+        )))
+    }
+
+    #[test]
+    fn test_group_fallback() {
+        let new_proxy = |name: &str| Arc::new(Proxy::new(name, ProxyImpl::Direct));
+        let (a, b, c) = (new_proxy("A"), new_proxy("B"), new_proxy("C"));
+        let members = vec![
+            GeneralProxy::Single(a.clone()),
+            GeneralProxy::Single(b.clone()),
+            GeneralProxy::Single(c.clone()),
+        ];
+        let fallback = ProxyGroup::new(
+            "Fallback",
+            members.clone(),
+            members[0].clone(),
+            None,
+            GroupPolicy::Fallback {
+                interval: Duration::from_secs(60),
+            },
+        );
+        for _ in 1..FAILURE_THRESHOLD {
+            assert!(!a.report_failure());
+        }
+        assert!(a.report_failure());
+        // only reported once
+        assert!(!a.report_failure());
+        // reading the selection does not switch it
+        assert_eq!(fallback.get_proxy().get_name(), "A");
+        assert!(fallback.update_selection());
+        assert_eq!(fallback.get_proxy().get_name(), "B");
+
+        a.set_latency(Latency::Value(100));
+        assert!(fallback.update_selection());
+        assert_eq!(fallback.get_proxy().get_name(), "A");
+
+        // all failed: back to the first one
+        for p in [&a, &b, &c] {
+            p.set_latency(Latency::Failed);
+        }
+        assert!(!fallback.update_selection());
+        assert_eq!(fallback.get_proxy().get_name(), "A");
+    }
+
+    #[test]
+    fn test_group_load_balance() {
+        use crate::dispatch::InboundInfo;
+        use crate::platform::process::NetworkType;
+        let members: Vec<_> = ["A", "B", "C"]
+            .into_iter()
+            .map(|name| GeneralProxy::Single(Arc::new(Proxy::new(name, ProxyImpl::Direct))))
+            .collect();
+        let new_group = |strategy| {
+            ProxyGroup::new(
+                "LoadBalance",
+                members.clone(),
+                members[0].clone(),
+                None,
+                GroupPolicy::LoadBalance {
+                    interval: Duration::from_secs(60),
+                    strategy,
+                },
+            )
+        };
+        let info = |domain_name: &str| ConnInfo {
+            src: "192.168.1.2:12345".parse().unwrap(),
+            dst: NetworkAddr::DomainName {
+                domain_name: domain_name.to_string(),
+                port: 443,
+            },
+            local_ip: None,
+            inbound: InboundInfo::Tun,
+            resolved_dst: None,
+            connection_type: NetworkType::Tcp,
+            process_info: None,
+            socket_owner: None,
+        };
+
+        let hashing = new_group(LoadBalanceStrategy::ConsistentHashing);
+        let (first, _) = hashing.get_proxy_and_interface(&info("example.com"));
+        let (second, _) = hashing.get_proxy_and_interface(&info("example.com"));
+        assert_eq!(first.get_name(), second.get_name());
+        // a destination assigned to another member, found deterministically
+        let other_domain = (0..)
+            .map(|i| format!("host{}.example.org", i))
+            .find(|domain| {
+                let (p, _) = hashing.get_proxy_and_interface(&info(domain.as_str()));
+                p.get_name() != first.get_name()
+            })
+            .unwrap();
+        let (other, _) = hashing.get_proxy_and_interface(&info(other_domain.as_str()));
+        // other members are not affected when the chosen one fails
+        first.set_latency(Latency::Failed);
+        let (after, _) = hashing.get_proxy_and_interface(&info(other_domain.as_str()));
+        assert_eq!(after.get_name(), other.get_name());
+        // while destinations of the failed one are moved
+        let (moved, _) = hashing.get_proxy_and_interface(&info("example.com"));
+        assert_ne!(moved.get_name(), first.get_name());
+        first.set_latency(Latency::Unknown);
+
+        let round_robin = new_group(LoadBalanceStrategy::RoundRobin);
+        let (first, _) = round_robin.get_proxy_and_interface(&info("example.com"));
+        let (second, _) = round_robin.get_proxy_and_interface(&info("example.com"));
+        assert_ne!(first.get_name(), second.get_name());
+    }
+
+    #[test]
+    fn test_group_url_test() {
+        let new_proxy = |name: &str| Arc::new(Proxy::new(name, ProxyImpl::Direct));
+        let (a, b, c) = (new_proxy("A"), new_proxy("B"), new_proxy("C"));
+        let members = vec![
+            GeneralProxy::Single(a.clone()),
+            GeneralProxy::Single(b.clone()),
+            GeneralProxy::Single(c.clone()),
+        ];
+        let group = ProxyGroup::new(
+            "UrlTest",
+            members.clone(),
+            members[0].clone(),
+            None,
+            GroupPolicy::UrlTest {
+                interval: Duration::from_secs(60),
+                tolerance: 50,
+            },
+        );
+        // nothing tested yet
+        assert!(!group.update_selection());
+        assert_eq!(group.get_proxy().get_name(), "A");
+
+        // faster, but within the tolerance
+        a.set_latency(Latency::Value(100));
+        b.set_latency(Latency::Value(80));
+        c.set_latency(Latency::Failed);
+        assert!(!group.update_selection());
+        assert_eq!(group.get_proxy().get_name(), "A");
+
+        // exactly at the tolerance
+        b.set_latency(Latency::Value(50));
+        assert!(!group.update_selection());
+
+        // beyond the tolerance
+        b.set_latency(Latency::Value(20));
+        assert!(group.update_selection());
+        assert_eq!(group.get_proxy().get_name(), "B");
+
+        // the current selection failed, so any tested member is better
+        b.set_latency(Latency::Failed);
+        c.set_latency(Latency::Value(300));
+        assert!(group.update_selection());
+        assert_eq!(group.get_proxy().get_name(), "A");
+
+        // all failed: keep the last selection
+        a.set_latency(Latency::Failed);
+        c.set_latency(Latency::Failed);
+        assert!(!group.update_selection());
+        assert_eq!(group.get_proxy().get_name(), "A");
+    }
+
+    #[test]
+    fn test_chain_support_udp() {
+        // ordered from the exit to the entry
+        assert!(chain_support_udp(&[socks5(false, true), http()]));
+        assert!(chain_support_udp(&[
+            socks5(false, true),
+            socks5(true, false)
+        ]));
+        assert!(!chain_support_udp(&[socks5(true, false), http()]));
+        assert!(!chain_support_udp(&[
+            socks5(false, false),
+            socks5(true, false)
+        ]));
+        assert!(!chain_support_udp(&[http(), socks5(true, false)]));
+        assert!(!chain_support_udp(&[http()]));
+    }
+}
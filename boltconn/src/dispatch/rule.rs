@@ -1,11 +1,13 @@
 use crate::config::{ConfigError, ProxyError, RuleError};
 use crate::dispatch::action::{Action, LocalResolve};
-use crate::dispatch::ruleset::RuleSet;
+use crate::dispatch::ruleset::{RuleSet, RuleSetTable};
 use crate::dispatch::{ConnInfo, GeneralProxy, InboundInfo, Proxy, ProxyGroup};
-use crate::external::MmdbReader;
+use crate::external::{GeositeMatcher, GeositeReader, MmdbReader};
 use crate::network::dns::Dns;
 use crate::platform::process::NetworkType;
 use crate::proxy::NetworkAddr;
+use arc_swap::ArcSwap;
+use chrono::{Datelike, FixedOffset, NaiveDateTime, Timelike, Weekday};
 use ipnet::IpNet;
 use regex::Regex;
 use std::collections::HashMap;
@@ -49,6 +51,153 @@ impl FromStr for PortRule {
     }
 }
 
+/// Weekdays and a daily time window, e.g. `Mon-Fri 09:00-18:00 +08:00`.
+#[derive(Debug, Clone)]
+pub struct TimeRule {
+    // bit 0 is Monday
+    days: u8,
+    // minutes of the day, end exclusive; a window ending before it starts spans midnight
+    window: Option<(u32, u32)>,
+    // local time if not set
+    offset: Option<FixedOffset>,
+}
+
+impl TimeRule {
+    pub fn matches_now(&self) -> bool {
+        let now = match self.offset {
+            Some(offset) => chrono::Utc::now().with_timezone(&offset).naive_local(),
+            None => chrono::Local::now().naive_local(),
+        };
+        self.matches_at(now)
+    }
+
+    fn matches_at(&self, time: NaiveDateTime) -> bool {
+        let minute = time.hour() * 60 + time.minute();
+        let day = match self.window {
+            None => time.weekday(),
+            Some((start, end)) if start < end => {
+                if minute < start || minute >= end {
+                    return false;
+                }
+                time.weekday()
+            }
+            // the part after midnight belongs to the window of the previous day
+            Some((start, end)) => {
+                if minute >= start {
+                    time.weekday()
+                } else if minute < end {
+                    time.weekday().pred()
+                } else {
+                    return false;
+                }
+            }
+        };
+        self.days & (1 << day.num_days_from_monday()) != 0
+    }
+
+    // e.g. Mon-Fri, Sat/Sun, Mon/Wed-Fri
+    fn parse_days(s: &str) -> Option<u8> {
+        let mut days = 0;
+        for item in s.split('/') {
+            let (first, last) = match item.split_once('-') {
+                Some((first, last)) => (first.parse::<Weekday>().ok()?, last.parse().ok()?),
+                None => {
+                    let day = item.parse::<Weekday>().ok()?;
+                    (day, day)
+                }
+            };
+            let mut day = first;
+            loop {
+                days |= 1 << day.num_days_from_monday();
+                if day == last {
+                    break;
+                }
+                day = day.succ();
+            }
+        }
+        Some(days)
+    }
+
+    fn parse_window(s: &str) -> Option<(u32, u32)> {
+        let minutes = |t: &str| {
+            let (h, m) = t.split_once(':')?;
+            let (h, m) = (h.parse::<u32>().ok()?, m.parse::<u32>().ok()?);
+            (m < 60 && h * 60 + m <= 24 * 60).then_some(h * 60 + m)
+        };
+        let (start, end) = s.split_once('-')?;
+        let (start, end) = (minutes(start)?, minutes(end)?);
+        (start != end && start < 24 * 60).then_some((start, end))
+    }
+
+    // UTC, +8, +08, +0800, +08:00 or UTC+08:00
+    fn parse_offset(s: &str) -> Option<FixedOffset> {
+        let s = s.strip_prefix("UTC").unwrap_or(s);
+        if s.is_empty() || s == "Z" {
+            return FixedOffset::east_opt(0);
+        }
+        let (sign, s) = match (s.strip_prefix('+'), s.strip_prefix('-')) {
+            (Some(s), _) => (1, s),
+            (_, Some(s)) => (-1, s),
+            _ => return None,
+        };
+        let (h, m) = match s.split_once(':') {
+            _ if !s.is_ascii() => return None,
+            Some(hm) => hm,
+            None if s.len() == 4 => s.split_at(2),
+            None if s.len() <= 2 => (s, "0"),
+            None => return None,
+        };
+        let (h, m) = (h.parse::<i32>().ok()?, m.parse::<i32>().ok()?);
+        if h > 14 || m >= 60 {
+            return None;
+        }
+        FixedOffset::east_opt(sign * (h * 3600 + m * 60))
+    }
+}
+
+impl FromStr for TimeRule {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut days = None;
+        let mut window = None;
+        let mut offset = None;
+        for token in s.split_whitespace() {
+            if days.is_none() && window.is_none() && offset.is_none() {
+                if let Some(d) = Self::parse_days(token) {
+                    days = Some(d);
+                    continue;
+                }
+            }
+            if window.is_none() && offset.is_none() {
+                if let Some(w) = Self::parse_window(token) {
+                    window = Some(w);
+                    continue;
+                }
+            }
+            if offset.is_none() {
+                if let Some(o) = Self::parse_offset(&token.to_uppercase()) {
+                    offset = Some(o);
+                    continue;
+                }
+            }
+            // zone names like Asia/Shanghai would need the tz database
+            return Err(
+                "expected weekdays, a window and a fixed UTC offset like +08:00 in order; \
+                 time zone names are not supported",
+            );
+        }
+        if days.is_none() && window.is_none() {
+            return Err("either weekdays or a window is required");
+        }
+        Ok(Self {
+            days: days.unwrap_or(0x7f),
+            window,
+            offset,
+        })
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum RuleImpl {
     Inbound(InboundInfo),
@@ -56,17 +205,24 @@ pub enum RuleImpl {
     ProcessKeyword(String),
     ProcPathKeyword(String),
     ProcCmdRegex(Regex),
+    Uid(u32),
+    Gid(u32),
+    User(String),
     Domain(String),
     DomainSuffix(String),
     DomainKeyword(String),
+    DomainRegex(Regex),
+    DomainWildcard(Regex),
     LocalIpCidr(IpNet),
     SrcIpCidr(IpNet),
     IpCidr(IpNet),
     SrcPort(PortRule),
     DstPort(PortRule),
-    RuleSet(Arc<RuleSet>),
+    RuleSet(Arc<ArcSwap<RuleSet>>),
     GeoIP(Arc<MmdbReader>, String),
     Asn(Arc<MmdbReader>, u32),
+    GeoSite(Arc<GeositeMatcher>, String),
+    Time(TimeRule),
     And(Vec<RuleImpl>),
     Or(Vec<RuleImpl>),
     Not(Box<RuleImpl>),
@@ -104,6 +260,20 @@ impl RuleImpl {
                     false
                 }
             }
+            RuleImpl::DomainRegex(regex) | RuleImpl::DomainWildcard(regex) => {
+                if let NetworkAddr::DomainName { domain_name, .. } = &info.dst {
+                    regex.is_match(domain_name)
+                } else {
+                    false
+                }
+            }
+            RuleImpl::GeoSite(matcher, _) => {
+                if let NetworkAddr::DomainName { domain_name, .. } = &info.dst {
+                    matcher.matches(domain_name)
+                } else {
+                    false
+                }
+            }
             RuleImpl::LocalIpCidr(net) => info.local_ip.as_ref().map_or(false, |s| net.contains(s)),
             RuleImpl::SrcIpCidr(net) => net.contains(&info.src.ip()),
             RuleImpl::IpCidr(net) => info.dst_addr().is_some_and(|s| net.contains(&s.ip())),
@@ -152,7 +322,20 @@ impl RuleImpl {
                 .process_info
                 .as_ref()
                 .map_or_else(|| false, |proc_info| regex.is_match(&proc_info.cmdline)),
-            RuleImpl::RuleSet(rs) => rs.matches(info),
+            RuleImpl::Uid(uid) => info
+                .socket_owner
+                .as_ref()
+                .map_or_else(|| false, |owner| owner.uid == *uid),
+            RuleImpl::Gid(gid) => info
+                .socket_owner
+                .as_ref()
+                .map_or_else(|| false, |owner| owner.gid == Some(*gid)),
+            RuleImpl::User(user) => info
+                .socket_owner
+                .as_ref()
+                .map_or_else(|| false, |owner| owner.user.as_ref() == Some(user)),
+            RuleImpl::RuleSet(rs) => rs.load().matches(info),
+            RuleImpl::Time(time) => time.matches_now(),
             RuleImpl::And(subs) => (|| {
                 for i in subs {
                     if !i.matches(info) {
@@ -179,19 +362,21 @@ impl RuleImpl {
 pub(crate) struct RuleBuilder<'a> {
     proxies: &'a HashMap<String, Arc<Proxy>>,
     groups: &'a HashMap<String, Arc<ProxyGroup>>,
-    rulesets: &'a HashMap<String, Arc<RuleSet>>,
+    rulesets: &'a RuleSetTable,
     buffer: Vec<RuleOrAction>,
     dns: Arc<Dns>,
     mmdb: Option<Arc<MmdbReader>>,
+    geosite: Option<Arc<GeositeReader>>,
 }
 
 impl RuleBuilder<'_> {
     pub fn new<'a>(
         dns: Arc<Dns>,
         mmdb: Option<Arc<MmdbReader>>,
+        geosite: Option<Arc<GeositeReader>>,
         proxies: &'a HashMap<String, Arc<Proxy>>,
         groups: &'a HashMap<String, Arc<ProxyGroup>>,
-        rulesets: &'a HashMap<String, Arc<RuleSet>>,
+        rulesets: &'a RuleSetTable,
     ) -> RuleBuilder<'a> {
         RuleBuilder {
             proxies,
@@ -200,6 +385,7 @@ impl RuleBuilder<'_> {
             buffer: vec![],
             dns,
             mmdb,
+            geosite,
         }
     }
 
@@ -222,7 +408,13 @@ impl RuleBuilder<'_> {
     #[allow(clippy::get_first)]
     pub fn parse_literal(&mut self, s: &str) -> Result<Rule<GeneralProxy>, ConfigError> {
         let invalid_err = || RuleError::Invalid(s.to_string());
-        let processed_str = "[".to_string() + s + "]";
+        if let Some((prefix, rest)) = strip_pattern_prefix(s) {
+            let (pattern, proxy) = rest.rsplit_once(',').ok_or_else(invalid_err)?;
+            let general = self.get_general(proxy.trim())?;
+            let rule = Self::parse_pattern(prefix, pattern).ok_or_else(invalid_err)?;
+            return Ok(Rule::new(rule, general));
+        }
+        let processed_str = "[".to_string() + quote_nested_patterns(s).as_str() + "]";
         let list: serde_yaml::Sequence = serde_yaml::from_str(processed_str.as_str())
             .map_err(|_| RuleError::Invalid(s.to_string()))?;
 
@@ -239,22 +431,27 @@ impl RuleBuilder<'_> {
             may_proxy_str = retrive_string(may_proxy.get(0).unwrap()).ok_or_else(invalid_err)?;
         }
 
-        let general = {
-            if let Some(p) = self.proxies.get(&may_proxy_str) {
-                GeneralProxy::Single(p.clone())
-            } else if let Some(p) = self.groups.get(&may_proxy_str) {
-                GeneralProxy::Group(p.clone())
-            } else {
-                return Err(ProxyError::MissingProxy(may_proxy_str).into());
-            }
-        };
-
+        let general = self.get_general(&may_proxy_str)?;
         let rule = self.parse_sub_rule(first, s)?;
         Ok(Rule::new(rule, general))
     }
 
+    fn get_general(&self, name: &str) -> Result<GeneralProxy, ConfigError> {
+        if let Some(p) = self.proxies.get(name) {
+            Ok(GeneralProxy::Single(p.clone()))
+        } else if let Some(p) = self.groups.get(name) {
+            Ok(GeneralProxy::Group(p.clone()))
+        } else {
+            Err(ProxyError::MissingProxy(name.to_string()).into())
+        }
+    }
+
     pub fn parse_incomplete(&mut self, s: &str) -> Result<RuleImpl, RuleError> {
-        let processed_str = "[".to_string() + s + "]";
+        if let Some((prefix, pattern)) = strip_pattern_prefix(s) {
+            return Self::parse_pattern(prefix, pattern)
+                .ok_or_else(|| RuleError::Invalid(s.to_string()));
+        }
+        let processed_str = "[".to_string() + quote_nested_patterns(s).as_str() + "]";
         let list: serde_yaml::Sequence = serde_yaml::from_str(processed_str.as_str())
             .map_err(|_| RuleError::Invalid(s.to_string()))?;
         if list.len() < 2 {
@@ -304,8 +501,14 @@ impl RuleBuilder<'_> {
                             // all other rules
                             let content =
                                 retrive_string(list.get(1).unwrap()).ok_or_else(invalid_err)?;
-                            Self::parse(prefix, content, Some(self.rulesets), self.mmdb.as_ref())
-                                .ok_or_else(invalid_err)
+                            Self::parse(
+                                prefix,
+                                content,
+                                Some(self.rulesets),
+                                self.mmdb.as_ref(),
+                                self.geosite.as_ref(),
+                            )
+                            .ok_or_else(invalid_err)
                         }
                     },
                     3 => {
@@ -324,6 +527,7 @@ impl RuleBuilder<'_> {
                                     content,
                                     Some(self.rulesets),
                                     self.mmdb.as_ref(),
+                                    self.geosite.as_ref(),
                                 )
                                 .ok_or_else(invalid_err)
                             }
@@ -358,8 +562,12 @@ impl RuleBuilder<'_> {
     pub fn parse_rulesets(
         s: &str,
         mmdb: Option<&Arc<MmdbReader>>,
-        rulesets: Option<&HashMap<String, Arc<RuleSet>>>,
+        rulesets: Option<&RuleSetTable>,
     ) -> Option<RuleImpl> {
+        // patterns may contain commas and spaces
+        if let Some((prefix, pattern)) = strip_pattern_prefix(s) {
+            return Self::parse_pattern(prefix, pattern);
+        }
         let processed_str: String = s.chars().filter(|c| *c != ' ').collect();
         let list: Vec<&str> = processed_str.split(',').collect();
         // ignore no-resolve
@@ -367,28 +575,48 @@ impl RuleBuilder<'_> {
         if list.len() < 2 {
             return None;
         }
-        let (prefix, content) = (
-            String::from(*list.get(0).unwrap()),
-            String::from(*list.get(1).unwrap()),
-        );
-        Self::parse(prefix, content, rulesets, mmdb)
+        Self::parse(
+            list.get(0).unwrap().to_string(),
+            list.get(1).unwrap().to_string(),
+            rulesets,
+            mmdb,
+            None,
+        )
+    }
+
+    fn parse_pattern(prefix: &str, pattern: &str) -> Option<RuleImpl> {
+        Self::parse(
+            prefix.to_string(),
+            unquote(pattern).to_string(),
+            None,
+            None,
+            None,
+        )
     }
 
     fn parse(
         prefix: String,
         content: String,
-        rulesets: Option<&HashMap<String, Arc<RuleSet>>>,
+        rulesets: Option<&RuleSetTable>,
         mmdb: Option<&Arc<MmdbReader>>,
+        geosite: Option<&Arc<GeositeReader>>,
     ) -> Option<RuleImpl> {
         match prefix.as_str() {
             "INBOUND" => Some(RuleImpl::Inbound(InboundInfo::from_str(&content).ok()?)),
             "DOMAIN-SUFFIX" => Some(RuleImpl::DomainSuffix(content)),
             "DOMAIN-KEYWORD" => Some(RuleImpl::DomainKeyword(content)),
             "DOMAIN" => Some(RuleImpl::Domain(content)),
+            "DOMAIN-REGEX" => Some(RuleImpl::DomainRegex(Regex::new(&content).ok()?)),
+            "DOMAIN-WILDCARD" => Some(RuleImpl::DomainWildcard(
+                Regex::new(&wildcard_to_regex(&content)).ok()?,
+            )),
             "PROCESS-NAME" => Some(RuleImpl::ProcessName(content)),
             "PROCESS-KEYWORD" => Some(RuleImpl::ProcessKeyword(content)),
             "PROC-PATH-KEYWORD" => Some(RuleImpl::ProcPathKeyword(content)),
             "PROC-CMD-REGEX" => Some(RuleImpl::ProcCmdRegex(Regex::new(&content).ok()?)),
+            "UID" => content.parse::<u32>().ok().map(RuleImpl::Uid),
+            "GID" => content.parse::<u32>().ok().map(RuleImpl::Gid),
+            "USER" => Some(RuleImpl::User(content)),
             "LOCAL-IP-CIDR" => IpNet::from_str(content.as_str())
                 .ok()
                 .map(RuleImpl::LocalIpCidr),
@@ -400,8 +628,18 @@ impl RuleBuilder<'_> {
             "ASN" => {
                 mmdb.and_then(|x| Some(RuleImpl::Asn(x.clone(), content.parse::<u32>().ok()?)))
             }
+            "GEOSITE" => {
+                geosite.and_then(|x| Some(RuleImpl::GeoSite(x.matcher(&content)?, content)))
+            }
             "SRC-PORT" => content.parse::<PortRule>().ok().map(RuleImpl::SrcPort),
             "DST-PORT" => content.parse::<PortRule>().ok().map(RuleImpl::DstPort),
+            "TIME" => match content.parse::<TimeRule>() {
+                Ok(rule) => Some(RuleImpl::Time(rule)),
+                Err(e) => {
+                    tracing::warn!("Invalid TIME rule {}: {}", content, e);
+                    None
+                }
+            },
             "RULE-SET" => rulesets
                 .and_then(|table| table.get(&content))
                 .map(|rs| RuleImpl::RuleSet(rs.clone())),
@@ -447,6 +685,84 @@ impl<T: Clone> Display for Rule<T> {
     }
 }
 
+/// Prefix and pattern of a top-level DOMAIN-REGEX or DOMAIN-WILDCARD rule. The pattern may contain
+/// commas, brackets, braces or a leading `*` that break the flow sequence of other rules.
+fn strip_pattern_prefix(s: &str) -> Option<(&str, &str)> {
+    let (prefix, rest) = s.split_once(',')?;
+    let prefix = prefix.trim();
+    matches!(prefix, "DOMAIN-REGEX" | "DOMAIN-WILDCARD").then_some((prefix, rest))
+}
+
+/// Quote patterns of DOMAIN-REGEX and DOMAIN-WILDCARD nested in AND/OR/NOT, so that they survive
+/// as single strings in the flow sequence. An unquoted pattern ends at the unbalanced `]`.
+fn quote_nested_patterns(s: &str) -> String {
+    let mut result = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('[') {
+        result.push_str(&rest[..=pos]);
+        rest = &rest[pos + 1..];
+        let Some((prefix, pattern)) = strip_pattern_prefix(rest) else {
+            continue;
+        };
+        let pattern = pattern.trim_start();
+        let (content, len) = match pattern.chars().next() {
+            Some(q @ ('\'' | '"')) => match pattern[1..].find(q) {
+                Some(end) => (&pattern[1..end + 1], end + 2),
+                None => break,
+            },
+            _ => {
+                let mut depth = 0;
+                let mut escaped = false;
+                let end = pattern.char_indices().find(|(_, c)| {
+                    match (escaped, *c) {
+                        (true, _) => escaped = false,
+                        (false, '\\') => escaped = true,
+                        (false, '[') => depth += 1,
+                        (false, ']') if depth == 0 => return true,
+                        (false, ']') => depth -= 1,
+                        _ => {}
+                    }
+                    false
+                });
+                let Some((end, _)) = end else {
+                    break;
+                };
+                (pattern[..end].trim_end(), end)
+            }
+        };
+        result.push_str(prefix);
+        result.push_str(", '");
+        result.push_str(content.replace('\'', "''").as_str());
+        result.push('\'');
+        rest = &pattern[len..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Quotes around the pattern are optional.
+fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    ['\'', '"']
+        .iter()
+        .find_map(|q| s.strip_prefix(*q)?.strip_suffix(*q))
+        .unwrap_or(s)
+}
+
+/// `*` matches any characters and `?` matches a single one, case-insensitively.
+pub(crate) fn wildcard_to_regex(pattern: &str) -> String {
+    let mut regex = String::from("(?i)^");
+    for c in pattern.chars() {
+        match c {
+            '*' => regex.push_str(".*"),
+            '?' => regex.push('.'),
+            c => regex.push_str(&regex::escape(c.encode_utf8(&mut [0; 4]))),
+        }
+    }
+    regex.push('$');
+    regex
+}
+
 fn retrive_string(val: &serde_yaml::Value) -> Option<String> {
     match val {
         serde_yaml::Value::String(s) => Some(s.clone()),
@@ -459,3 +775,286 @@ pub enum RuleOrAction {
     Rule(Rule<GeneralProxy>),
     Action(Action),
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::platform::process::{ProcessInfo, SocketOwner};
+    use chrono::NaiveDate;
+
+    fn at(day: u32, hour: u32, minute: u32) -> NaiveDateTime {
+        // 2024-01-01 is a Monday
+        NaiveDate::from_ymd_opt(2024, 1, day)
+            .unwrap()
+            .and_hms_opt(hour, minute, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_time_rule() {
+        let office: TimeRule = "Mon-Fri 09:00-18:00".parse().unwrap();
+        assert!(office.offset.is_none());
+        assert!(office.matches_at(at(1, 9, 0)));
+        assert!(office.matches_at(at(5, 17, 59)));
+        assert!(!office.matches_at(at(5, 18, 0)));
+        assert!(!office.matches_at(at(6, 10, 0)));
+
+        let night: TimeRule = "fri/sat 22:00-06:00 UTC+08:00".parse().unwrap();
+        assert_eq!(night.offset, FixedOffset::east_opt(8 * 3600));
+        assert!(night.matches_at(at(5, 23, 0)));
+        assert!(night.matches_at(at(7, 5, 0)));
+        assert!(!night.matches_at(at(5, 5, 0)));
+        assert!(!night.matches_at(at(7, 22, 0)));
+
+        let weekend: TimeRule = "Sat-Sun -0530".parse().unwrap();
+        assert_eq!(weekend.offset, FixedOffset::west_opt(5 * 3600 + 30 * 60));
+        assert!(weekend.matches_at(at(7, 12, 0)));
+        assert!(!weekend.matches_at(at(1, 12, 0)));
+        let daily: TimeRule = "00:00-24:00".parse().unwrap();
+        assert!(daily.matches_at(at(3, 23, 59)));
+
+        assert!("UTC".parse::<TimeRule>().is_err());
+        assert!("Mon-Fri 09:00-09:00".parse::<TimeRule>().is_err());
+        assert!("Mon-Fri 09:00-18:60".parse::<TimeRule>().is_err());
+        assert!("09:00-18:00 Mon-Fri".parse::<TimeRule>().is_err());
+        assert!("Mon-Fri 09:00-18:00 Asia/Shanghai"
+            .parse::<TimeRule>()
+            .is_err());
+    }
+
+    #[test]
+    fn test_time_rule_literal() {
+        let (dns, proxies) = test_env();
+        let (groups, rulesets) = (HashMap::new(), HashMap::new());
+        let mut builder = RuleBuilder::new(dns, None, None, &proxies, &groups, &rulesets);
+        let conn = |domain: &str| ConnInfo {
+            dst: NetworkAddr::DomainName {
+                domain_name: domain.to_string(),
+                port: 443,
+            },
+            ..local_conn(None)
+        };
+        let always = builder
+            .parse_literal("TIME, Mon-Sun 00:00-24:00 UTC+08:00, DIRECT")
+            .unwrap();
+        let RuleImpl::Time(time) = always.get_impl() else {
+            panic!("not a TIME rule");
+        };
+        assert_eq!(time.offset, FixedOffset::east_opt(8 * 3600));
+        assert!(always.get_impl().matches(&conn("a.com")));
+
+        let rule = builder
+            .parse_literal("AND, [TIME, Mon-Sun 00:00-24:00], [DOMAIN, a.com], DIRECT")
+            .unwrap();
+        assert!(rule.get_impl().matches(&conn("a.com")));
+        assert!(!rule.get_impl().matches(&conn("b.com")));
+        let rule = builder
+            .parse_incomplete("OR, [NOT, [TIME, Mon-Sun 00:00-24:00 -05:00]], [DOMAIN, a.com]")
+            .unwrap();
+        assert!(rule.matches(&conn("a.com")));
+        assert!(!rule.matches(&conn("b.com")));
+
+        assert!(builder
+            .parse_literal("TIME, Mon-Fri 09:00-18:00 Asia/Shanghai, DIRECT")
+            .is_err());
+        assert!(builder
+            .parse_literal("OR, [TIME, Mon-Fri America/New_York], [DOMAIN, a.com], DIRECT")
+            .is_err());
+    }
+
+    fn test_env() -> (Arc<Dns>, HashMap<String, Arc<Proxy>>) {
+        use crate::config::DnsPreference;
+        use crate::dispatch::ProxyImpl;
+        use crate::network::dns::NameserverPolicies;
+        let dns = Arc::new(Dns::with_config(
+            "lo",
+            DnsPreference::PreferIpv4,
+            &HashMap::new(),
+            NameserverPolicies::empty(),
+            vec![],
+        ));
+        let proxies = HashMap::from([(
+            "DIRECT".to_string(),
+            Arc::new(Proxy::new("DIRECT", ProxyImpl::Direct)),
+        )]);
+        (dns, proxies)
+    }
+
+    fn local_conn(process_info: Option<ProcessInfo>) -> ConnInfo {
+        ConnInfo {
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst: NetworkAddr::Raw("1.1.1.1:443".parse().unwrap()),
+            local_ip: None,
+            inbound: InboundInfo::Tun,
+            resolved_dst: None,
+            connection_type: NetworkType::Tcp,
+            process_info,
+            socket_owner: None,
+        }
+    }
+
+    #[test]
+    fn test_domain_regex_literal() {
+        let (dns, proxies) = test_env();
+        let (groups, rulesets) = (HashMap::new(), HashMap::new());
+        let mut builder = RuleBuilder::new(dns, None, None, &proxies, &groups, &rulesets);
+        let rule = builder
+            .parse_literal(r"DOMAIN-REGEX, ^(api|cdn)\d{1,3}\.[a-z,]+\.com$, DIRECT")
+            .unwrap();
+        let RuleImpl::DomainRegex(regex) = rule.get_impl() else {
+            panic!("not a regex rule");
+        };
+        assert_eq!(regex.as_str(), r"^(api|cdn)\d{1,3}\.[a-z,]+\.com$");
+        assert!(regex.is_match("cdn12.example.com"));
+        assert!(!regex.is_match("cdn1234.example.com"));
+        let rule = builder
+            .parse_literal(r"DOMAIN-REGEX, '^api\d{1,3}\.corp\.', DIRECT")
+            .unwrap();
+        assert!(rule.get_impl().matches(&ConnInfo {
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst: NetworkAddr::DomainName {
+                domain_name: "api1.corp.internal".to_string(),
+                port: 443,
+            },
+            local_ip: None,
+            inbound: InboundInfo::Tun,
+            resolved_dst: None,
+            connection_type: NetworkType::Tcp,
+            process_info: None,
+            socket_owner: None,
+        }));
+        assert!(builder
+            .parse_literal("DOMAIN-REGEX,[a-z]{2,},MISSING")
+            .is_err());
+        assert!(builder
+            .parse_literal("DOMAIN-REGEX,(unclosed,DIRECT")
+            .is_err());
+        assert!(matches!(
+            builder.parse_incomplete(r"DOMAIN-REGEX,^[0-9]{1,3}$"),
+            Ok(RuleImpl::DomainRegex(_))
+        ));
+    }
+
+    #[test]
+    fn test_nested_domain_regex() {
+        let (dns, proxies) = test_env();
+        let (groups, rulesets) = (HashMap::new(), HashMap::new());
+        let mut builder = RuleBuilder::new(dns, None, None, &proxies, &groups, &rulesets);
+        let rule = builder
+            .parse_literal(
+                r"AND, [DOMAIN-REGEX, ^(api|cdn)\d{1,3}\.[a-z,]+\.com$], [NOT, [DOMAIN-REGEX, '^cdn\d\.']], DIRECT",
+            )
+            .unwrap();
+        let RuleImpl::And(subs) = rule.get_impl() else {
+            panic!("not an AND rule");
+        };
+        let RuleImpl::DomainRegex(regex) = &subs[0] else {
+            panic!("not a regex rule");
+        };
+        assert_eq!(regex.as_str(), r"^(api|cdn)\d{1,3}\.[a-z,]+\.com$");
+        let conn = |domain: &str| ConnInfo {
+            dst: NetworkAddr::DomainName {
+                domain_name: domain.to_string(),
+                port: 443,
+            },
+            ..local_conn(None)
+        };
+        assert!(rule.get_impl().matches(&conn("api12.example.com")));
+        assert!(!rule.get_impl().matches(&conn("cdn1.example.com")));
+        assert!(!rule.get_impl().matches(&conn("www.example.com")));
+
+        let rule = builder
+            .parse_incomplete(r"OR, [DOMAIN-REGEX, ^a\]b$], [DOMAIN, example.com]")
+            .unwrap();
+        assert!(rule.matches(&conn("a]b")));
+        assert!(rule.matches(&conn("example.com")));
+        assert!(builder
+            .parse_literal("AND, [DOMAIN-REGEX, (unclosed], [DOMAIN, a.com], DIRECT")
+            .is_err());
+    }
+
+    #[test]
+    fn test_domain_wildcard_literal() {
+        let (dns, proxies) = test_env();
+        let (groups, rulesets) = (HashMap::new(), HashMap::new());
+        let mut builder = RuleBuilder::new(dns, None, None, &proxies, &groups, &rulesets);
+        let conn = |domain: &str| ConnInfo {
+            dst: NetworkAddr::DomainName {
+                domain_name: domain.to_string(),
+                port: 443,
+            },
+            ..local_conn(None)
+        };
+        for literal in [
+            "DOMAIN-WILDCARD, *.cdn-*.example.com, DIRECT",
+            "DOMAIN-WILDCARD, '*.cdn-*.example.com', DIRECT",
+        ] {
+            let rule = builder.parse_literal(literal).unwrap();
+            assert!(matches!(rule.get_impl(), RuleImpl::DomainWildcard(_)));
+            assert!(rule.get_impl().matches(&conn("a.cdn-01.example.com")));
+            assert!(!rule.get_impl().matches(&conn("a.cdn.example.com")));
+        }
+        let rule = builder
+            .parse_literal("OR, [DOMAIN-WILDCARD, *.cdn-?.example.com], [DOMAIN, a.com], DIRECT")
+            .unwrap();
+        assert!(rule.get_impl().matches(&conn("x.cdn-1.example.com")));
+        assert!(rule.get_impl().matches(&conn("a.com")));
+        assert!(!rule.get_impl().matches(&conn("x.cdn-12.example.com")));
+    }
+
+    #[test]
+    fn test_ruleset_patterns() {
+        let conn = |domain: &str| ConnInfo {
+            dst: NetworkAddr::DomainName {
+                domain_name: domain.to_string(),
+                port: 443,
+            },
+            ..local_conn(None)
+        };
+        // a literal space in the pattern is kept
+        let rule = RuleBuilder::parse_rulesets(r"DOMAIN-REGEX,^a b\.com$", None, None).unwrap();
+        assert!(rule.matches(&conn("a b.com")));
+        assert!(!rule.matches(&conn("ab.com")));
+        let rule =
+            RuleBuilder::parse_rulesets(r"DOMAIN-REGEX, '^api\d{1,3}\.'", None, None).unwrap();
+        assert!(rule.matches(&conn("api12.corp.com")));
+        assert!(!rule.matches(&conn("www.api12.com")));
+        let rule = RuleBuilder::parse_rulesets("DOMAIN-WILDCARD, *.cdn-*.example.com", None, None)
+            .unwrap();
+        assert!(rule.matches(&conn("a.cdn-01.example.com")));
+    }
+
+    #[test]
+    fn test_process_owner_rules() {
+        let (dns, proxies) = test_env();
+        let (groups, rulesets) = (HashMap::new(), HashMap::new());
+        let mut builder = RuleBuilder::new(dns, None, None, &proxies, &groups, &rulesets);
+        let uid = builder.parse_literal("UID,1000,DIRECT").unwrap();
+        assert!(matches!(uid.get_impl(), RuleImpl::Uid(1000)));
+        let gid = builder.parse_literal("GID, 100, DIRECT").unwrap();
+        assert!(matches!(gid.get_impl(), RuleImpl::Gid(100)));
+        let user = builder.parse_literal("USER,alice,DIRECT").unwrap();
+        assert!(matches!(user.get_impl(), RuleImpl::User(u) if u == "alice"));
+        assert!(builder.parse_literal("UID,alice,DIRECT").is_err());
+        assert!(builder.parse_literal("GID,-1,DIRECT").is_err());
+
+        let owned = |gid, user: Option<&str>| ConnInfo {
+            socket_owner: Some(SocketOwner {
+                uid: 1000,
+                gid,
+                user: user.map(String::from),
+            }),
+            ..local_conn(None)
+        };
+        let known = owned(Some(100), Some("alice"));
+        assert!(uid.get_impl().matches(&known));
+        assert!(gid.get_impl().matches(&known));
+        assert!(user.get_impl().matches(&known));
+        // the process is unknown, or the user is not in the database
+        let partial = owned(None, None);
+        assert!(uid.get_impl().matches(&partial));
+        assert!(!gid.get_impl().matches(&partial));
+        assert!(!user.get_impl().matches(&partial));
+        assert!(!uid.get_impl().matches(&local_conn(None)));
+    }
+}
@@ -1,21 +1,27 @@
 use crate::common::host_matcher::{HostMatcher, HostMatcherBuilder};
-use crate::config::{ProviderBehavior, RuleError, RuleSchema};
+use crate::config::{BinaryRuleSet, ProviderBehavior, RuleError, RuleSchema};
+use crate::dispatch::compiled_ruleset::{CompiledPayload, CompiledRuleSet, PortKind};
 use crate::dispatch::rule::{PortRule, RuleBuilder, RuleImpl};
+use crate::dispatch::srs::{read_srs, SRS_MAGIC};
 use crate::dispatch::{ConnInfo, InboundIdentity, InboundInfo};
 use crate::external::MmdbReader;
 use crate::platform::process::NetworkType;
 use crate::proxy::NetworkAddr;
 use aho_corasick::AhoCorasick;
+use arc_swap::ArcSwap;
 use ip_network_table::IpNetworkTable;
 use ipnet::IpNet;
-use regex::Regex;
+use regex::{Regex, RegexSet};
 use std::collections::{HashMap, HashSet};
 use std::fmt::{Debug, Formatter};
+use std::fs::File;
+use std::io::Read;
 use std::net::IpAddr;
 use std::str::FromStr;
 use std::sync::Arc;
 
-pub type RuleSetTable = HashMap<String, Arc<RuleSet>>;
+/// Rulesets by name, swapped in place when their provider refreshes.
+pub type RuleSetTable = HashMap<String, Arc<ArcSwap<RuleSet>>>;
 
 /// Matcher for rules in the same group
 pub struct RuleSet {
@@ -30,10 +36,13 @@ pub struct RuleSet {
     socks5_inbound: InboundFilter,
     tun_inbound: bool,
     domain_keyword: AhoCorasick,
+    domain_regex: RegexSet,
     process_name: HashSet<String>,
     mmdb: Option<(Arc<MmdbReader>, HashSet<u32>, HashSet<String>)>,
     process_keyword: AhoCorasick,
     procpath_keyword: AhoCorasick,
+    process_path: HashSet<String>,
+    compiled: Option<CompiledRuleSet>,
 }
 
 impl Debug for RuleSet {
@@ -43,11 +52,51 @@ impl Debug for RuleSet {
 }
 
 impl RuleSet {
+    /// Load a compiled rule-set, mapping cached copies into memory, or convert a sing-box
+    /// rule-set.
+    pub fn load_file(name: &str, binary: &BinaryRuleSet) -> Result<Self, RuleError> {
+        let path = match binary {
+            BinaryRuleSet::File(path) | BinaryRuleSet::Cached(path) => path.as_path(),
+        };
+        let file_error =
+            |e: std::io::Error| RuleError::RulesetFile(name.to_string(), e.to_string());
+        let mut magic = [0u8; 4];
+        File::open(path)
+            .and_then(|mut f| f.read_exact(&mut magic))
+            .map_err(file_error)?;
+        if magic.starts_with(SRS_MAGIC) {
+            let data = std::fs::read(path).map_err(file_error)?;
+            RuleSetBuilder::from_srs(name, &data)?.build()
+        } else {
+            let mut ruleset = RuleSetBuilder::empty(name).build()?;
+            let compiled = match binary {
+                BinaryRuleSet::File(_) => CompiledRuleSet::read(path),
+                BinaryRuleSet::Cached(_) => CompiledRuleSet::map(path),
+            };
+            ruleset.compiled = Some(compiled.map_err(file_error)?);
+            Ok(ruleset)
+        }
+    }
+
+    /// Check a downloaded binary rule-set before replacing the cached copy.
+    pub fn validate_binary(name: &str, data: &[u8]) -> Result<(), RuleError> {
+        if data.starts_with(SRS_MAGIC) {
+            RuleSetBuilder::from_srs(name, data).map(|_| ())
+        } else {
+            CompiledRuleSet::check(data)
+                .map_err(|e| RuleError::RulesetFile(name.to_string(), e.to_string()))
+        }
+    }
+
     pub fn matches(&self, info: &ConnInfo) -> bool {
+        let compiled = self.compiled.as_ref();
+        let matches_ip = |ip: IpAddr| {
+            self.ip.longest_match(ip).is_some() || compiled.is_some_and(|c| c.matches_ip(ip))
+        };
         // do NOT perform DNS lookup
         let port = match &info.dst {
             NetworkAddr::Raw(addr) => {
-                if self.ip.longest_match(addr.ip()).is_some() {
+                if matches_ip(addr.ip()) {
                     return true;
                 }
                 addr.port()
@@ -55,24 +104,39 @@ impl RuleSet {
             NetworkAddr::DomainName { domain_name, port } => {
                 if self.domain.matches(domain_name)
                     || self.domain_keyword.is_match(domain_name.as_str())
+                    || self.domain_regex.is_match(domain_name.as_str())
+                    || compiled.is_some_and(|c| c.matches_domain(domain_name))
                     || info
                         .resolved_dst
                         .as_ref()
-                        .is_some_and(|dst| self.ip.longest_match(dst.ip()).is_some())
+                        .is_some_and(|dst| matches_ip(dst.ip()))
                 {
                     return true;
                 }
                 *port
             }
         };
+        let src_port = info.src.port();
         match info.connection_type {
             NetworkType::Tcp => {
-                if self.dst_tcp_port.contains(port) || self.src_tcp_port.contains(info.src.port()) {
+                if self.dst_tcp_port.contains(port)
+                    || self.src_tcp_port.contains(src_port)
+                    || compiled.is_some_and(|c| {
+                        c.matches_port(PortKind::DstTcp, port)
+                            || c.matches_port(PortKind::SrcTcp, src_port)
+                    })
+                {
                     return true;
                 }
             }
             NetworkType::Udp => {
-                if self.dst_udp_port.contains(port) || self.src_udp_port.contains(info.src.port()) {
+                if self.dst_udp_port.contains(port)
+                    || self.src_udp_port.contains(src_port)
+                    || compiled.is_some_and(|c| {
+                        c.matches_port(PortKind::DstUdp, port)
+                            || c.matches_port(PortKind::SrcUdp, src_port)
+                    })
+                {
                     return true;
                 }
             }
@@ -81,6 +145,8 @@ impl RuleSet {
             if self.process_name.contains(&proc.name)
                 || self.process_keyword.is_match(proc.name.as_str())
                 || self.procpath_keyword.is_match(proc.path.as_str())
+                || self.process_path.contains(&proc.path)
+                || compiled.is_some_and(|c| c.matches_process(&proc.name, &proc.path))
             {
                 return true;
             }
@@ -103,12 +169,14 @@ pub struct RuleSetBuilder {
     name: String,
     domain: HostMatcherBuilder,
     domain_keyword: Vec<String>,
+    domain_regex: Vec<String>,
     ip_cidr: IpNetworkTable<()>,
     src_ip_cidr: IpNetworkTable<()>,
     local_ip_cidr: IpNetworkTable<()>,
     process_name: HashSet<String>,
     process_keyword: Vec<String>,
     procpath_keyword: Vec<String>,
+    process_path: HashSet<String>,
     src_tcp_port: PortFilter,
     src_udp_port: PortFilter,
     dst_tcp_port: PortFilter,
@@ -122,17 +190,19 @@ pub struct RuleSetBuilder {
 }
 
 impl RuleSetBuilder {
-    pub fn new(name: &str, payload: &RuleSchema) -> Option<Self> {
-        let mut retval = Self {
+    fn empty(name: &str) -> Self {
+        Self {
             name: name.to_string(),
             domain: HostMatcher::builder(),
             domain_keyword: vec![],
+            domain_regex: vec![],
             ip_cidr: Default::default(),
             src_ip_cidr: Default::default(),
             local_ip_cidr: Default::default(),
             process_name: Default::default(),
             process_keyword: vec![],
             procpath_keyword: vec![],
+            process_path: Default::default(),
             src_tcp_port: Default::default(),
             src_udp_port: Default::default(),
             dst_tcp_port: Default::default(),
@@ -143,7 +213,11 @@ impl RuleSetBuilder {
             asn: Default::default(),
             geoip_country: Default::default(),
             mmdb: None,
-        };
+        }
+    }
+
+    pub fn new(name: &str, payload: &RuleSchema) -> Option<Self> {
+        let mut retval = Self::empty(name);
         match payload.behavior {
             ProviderBehavior::Domain => {
                 let prefix_reg = Regex::new(r"[*+]\.").unwrap();
@@ -184,6 +258,9 @@ impl RuleSetBuilder {
                         RuleImpl::Domain(dn) => retval.domain.add_exact(dn.as_str()),
                         RuleImpl::DomainSuffix(sfx) => retval.domain.add_suffix(sfx.as_str()),
                         RuleImpl::DomainKeyword(kw) => retval.domain_keyword.push(kw.clone()),
+                        RuleImpl::DomainRegex(r) | RuleImpl::DomainWildcard(r) => {
+                            retval.domain_regex.push(r.as_str().to_string())
+                        }
                         RuleImpl::LocalIpCidr(ip) => {
                             let ip =
                                 ip_network::IpNetwork::new_truncate(ip.addr(), ip.prefix_len())
@@ -244,6 +321,11 @@ impl RuleSetBuilder {
                         | RuleImpl::Or(..)
                         | RuleImpl::Not(_)
                         | RuleImpl::ProcCmdRegex(_)
+                        | RuleImpl::GeoSite(..)
+                        | RuleImpl::Time(_)
+                        | RuleImpl::Uid(_)
+                        | RuleImpl::Gid(_)
+                        | RuleImpl::User(_)
                         | RuleImpl::Always
                         | RuleImpl::Never => return None,
                     }
@@ -261,11 +343,13 @@ impl RuleSetBuilder {
         self.process_name.extend(rhs.process_name);
         self.process_keyword.extend(rhs.process_keyword);
         self.procpath_keyword.extend(rhs.procpath_keyword);
+        self.process_path.extend(rhs.process_path);
         self.src_tcp_port.extend(rhs.src_tcp_port);
         self.src_udp_port.extend(rhs.src_udp_port);
         self.dst_tcp_port.extend(rhs.dst_tcp_port);
         self.dst_udp_port.extend(rhs.dst_udp_port);
         self.domain_keyword.extend(rhs.domain_keyword);
+        self.domain_regex.extend(rhs.domain_regex);
         self
     }
 
@@ -283,12 +367,17 @@ impl RuleSetBuilder {
             tun_inbound: self.tun_inbound,
             domain_keyword: AhoCorasick::new(self.domain_keyword.into_iter())
                 .map_err(|_| RuleError::RulesetExceededLimit(self.name.clone()))?,
+            // one automaton for all patterns keeps large lists fast
+            domain_regex: RegexSet::new(self.domain_regex)
+                .map_err(|_| RuleError::RulesetExceededLimit(self.name.clone()))?,
             process_name: self.process_name,
             mmdb: self.mmdb.map(|m| (m, self.asn, self.geoip_country)),
             process_keyword: AhoCorasick::new(self.process_keyword.into_iter())
                 .map_err(|_| RuleError::RulesetExceededLimit(self.name.clone()))?,
             procpath_keyword: AhoCorasick::new(self.procpath_keyword.into_iter())
                 .map_err(|_| RuleError::RulesetExceededLimit(self.name.clone()))?,
+            process_path: self.process_path,
+            compiled: None,
         })
     }
 
@@ -298,26 +387,83 @@ impl RuleSetBuilder {
             table.insert(*ip, ());
         });
         Self {
-            name: name.to_string(),
-            domain: HostMatcher::builder(),
-            domain_keyword: vec![],
             ip_cidr: table,
-            src_ip_cidr: Default::default(),
-            local_ip_cidr: Default::default(),
-            process_name: Default::default(),
-            process_keyword: vec![],
-            procpath_keyword: vec![],
-            src_tcp_port: Default::default(),
-            src_udp_port: Default::default(),
-            dst_tcp_port: Default::default(),
-            dst_udp_port: Default::default(),
-            http_inbound: Default::default(),
-            socks5_inbound: Default::default(),
-            tun_inbound: false,
-            asn: Default::default(),
-            geoip_country: Default::default(),
-            mmdb: None,
+            ..Self::empty(name)
+        }
+    }
+
+    /// Convert a sing-box binary rule-set; each rule can only have one kind of condition.
+    pub fn from_srs(name: &str, data: &[u8]) -> Result<Self, RuleError> {
+        let file_error = |msg: String| RuleError::RulesetFile(name.to_string(), msg);
+        let mut retval = Self::empty(name);
+        for rule in read_srs(data).map_err(|e| file_error(e.to_string()))? {
+            if rule.condition_count() > 1 {
+                return Err(file_error(
+                    "rules with multiple conditions are not supported".to_string(),
+                ));
+            }
+            for (host, ty) in rule.domain {
+                retval.domain.add(&host, ty);
+            }
+            retval.domain_keyword.extend(rule.domain_keyword);
+            retval.domain_regex.extend(rule.domain_regex);
+            for (ip, prefix) in rule.ip_cidr {
+                let ip = ip_network::IpNetwork::new_truncate(ip, prefix).unwrap();
+                retval.ip_cidr.insert(ip, ());
+            }
+            // sing-box ports are for both TCP and UDP
+            for (start, end) in rule.src_port {
+                retval.src_tcp_port.insert_range(start, end);
+                retval.src_udp_port.insert_range(start, end);
+            }
+            for (start, end) in rule.dst_port {
+                retval.dst_tcp_port.insert_range(start, end);
+                retval.dst_udp_port.insert_range(start, end);
+            }
+            retval.process_name.extend(rule.process_name);
+            retval.process_path.extend(rule.process_path);
         }
+        Ok(retval)
+    }
+
+    /// Serialize into the compiled format loaded by `RuleSet::load_file`.
+    pub fn compile(self) -> Result<Vec<u8>, RuleError> {
+        let unsupported = |kind: &str| {
+            RuleError::RulesetFile(self.name.clone(), format!("{} cannot be compiled", kind))
+        };
+        if self.tun_inbound || !self.http_inbound.is_empty() || !self.socks5_inbound.is_empty() {
+            return Err(unsupported("INBOUND"));
+        }
+        if !self.asn.is_empty() || !self.geoip_country.is_empty() {
+            return Err(unsupported("GEOIP or ASN"));
+        }
+        if !self.src_ip_cidr.is_empty() || !self.local_ip_cidr.is_empty() {
+            return Err(unsupported("SRC-IP-CIDR or LOCAL-IP-CIDR"));
+        }
+        // patterns are only compiled when first matched, so check them now
+        RegexSet::new(&self.domain_regex)
+            .map_err(|e| RuleError::RulesetFile(self.name.clone(), e.to_string()))?;
+        Ok(CompiledPayload {
+            domain: self.domain.iter().collect(),
+            domain_keyword: self.domain_keyword,
+            domain_regex: self.domain_regex,
+            ip_cidr: self
+                .ip_cidr
+                .iter()
+                .map(|(ip, _)| (ip.network_address(), ip.netmask()))
+                .collect(),
+            ports: [
+                self.src_tcp_port.to_compiled(),
+                self.src_udp_port.to_compiled(),
+                self.dst_tcp_port.to_compiled(),
+                self.dst_udp_port.to_compiled(),
+            ],
+            process_name: self.process_name.into_iter().collect(),
+            process_keyword: self.process_keyword,
+            procpath_keyword: self.procpath_keyword,
+            process_path: self.process_path.into_iter().collect(),
+        }
+        .encode())
     }
 }
 
@@ -336,6 +482,14 @@ impl PortFilter {
         }
     }
 
+    pub fn insert_range(&mut self, start: u16, end: u16) {
+        if start == 0 && end == u16::MAX {
+            self.set_any();
+        } else {
+            (start..=end).for_each(|p| self.insert(p));
+        }
+    }
+
     pub fn set_any(&mut self) {
         *self = PortFilter::Any
     }
@@ -347,6 +501,13 @@ impl PortFilter {
         }
     }
 
+    fn to_compiled(&self) -> (bool, Vec<u16>) {
+        match self {
+            PortFilter::Any => (true, vec![]),
+            PortFilter::Some(s) => (false, s.iter().copied().collect()),
+        }
+    }
+
     pub fn extend(&mut self, rhs: Self) {
         match rhs {
             PortFilter::Any => self.set_any(),
@@ -387,6 +548,10 @@ impl InboundFilter {
         *self = InboundFilter::Any
     }
 
+    pub fn is_empty(&self) -> bool {
+        matches!(self, InboundFilter::Some(v) if v.is_empty())
+    }
+
     pub fn contains(&self, rhs: &InboundIdentity) -> bool {
         match self {
             InboundFilter::Any => true,
@@ -425,6 +590,7 @@ fn test_rule_provider() {
         &RuleSchema {
             behavior: ProviderBehavior::Classical,
             payload: deserialized.payload,
+            binary: None,
         },
     );
     assert!(builder.is_some());
@@ -441,6 +607,7 @@ fn test_rule_provider() {
         resolved_dst: None,
         connection_type: NetworkType::Tcp,
         process_info: None,
+        socket_owner: None,
     };
     assert!(ruleset.matches(&info1));
     let info2 = ConnInfo {
@@ -454,6 +621,7 @@ fn test_rule_provider() {
         resolved_dst: None,
         connection_type: NetworkType::Tcp,
         process_info: None,
+        socket_owner: None,
     };
     assert!(ruleset.matches(&info2));
     let info3 = ConnInfo {
@@ -467,6 +635,7 @@ fn test_rule_provider() {
         resolved_dst: None,
         connection_type: NetworkType::Tcp,
         process_info: None,
+        socket_owner: None,
     };
     assert!(ruleset.matches(&info3));
     let info4 = ConnInfo {
@@ -480,6 +649,167 @@ fn test_rule_provider() {
         resolved_dst: None,
         connection_type: NetworkType::Tcp,
         process_info: None,
+        socket_owner: None,
     };
     assert!(!ruleset.matches(&info4));
 }
+
+#[test]
+fn test_domain_pattern_ruleset() {
+    use crate::dispatch::inbound::InboundInfo;
+    use crate::platform::process::NetworkType;
+    let ruleset = RuleSetBuilder::new(
+        "Test",
+        &RuleSchema {
+            behavior: ProviderBehavior::Classical,
+            payload: vec![
+                r"DOMAIN-REGEX,^api\d{1,3}\.corp\.".to_string(),
+                "DOMAIN-WILDCARD,*.cdn-??.example.com".to_string(),
+            ],
+            binary: None,
+        },
+    )
+    .unwrap()
+    .build()
+    .unwrap();
+    let matches = |domain_name: &str| {
+        ruleset.matches(&ConnInfo {
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst: NetworkAddr::DomainName {
+                domain_name: domain_name.to_string(),
+                port: 443,
+            },
+            local_ip: None,
+            inbound: InboundInfo::Tun,
+            resolved_dst: None,
+            connection_type: NetworkType::Tcp,
+            process_info: None,
+            socket_owner: None,
+        })
+    };
+    assert!(matches("api12.corp.internal"));
+    assert!(!matches("web.api12.corp.internal"));
+    assert!(matches("img.cdn-eu.example.com"));
+    assert!(matches("a.b.CDN-us.example.com"));
+    assert!(!matches("img.cdn-eu1.example.com"));
+    assert!(!matches("cdn-eu.example.com"));
+}
+
+#[test]
+fn test_compiled_ruleset() {
+    use crate::dispatch::inbound::InboundInfo;
+    let builder = RuleSetBuilder::new(
+        "Test",
+        &RuleSchema {
+            behavior: ProviderBehavior::Classical,
+            payload: [
+                "DOMAIN-SUFFIX,google.com",
+                "DOMAIN,www.example.com",
+                "DOMAIN-KEYWORD,tracker",
+                "DOMAIN-WILDCARD,cdn-?.example.org",
+                "IP-CIDR,10.1.0.0/16",
+                "IP-CIDR,10.2.0.0/16",
+                "IP-CIDR6,2001:db8::/32",
+                "DST-PORT,8443/udp",
+            ]
+            .map(String::from)
+            .to_vec(),
+            binary: None,
+        },
+    )
+    .unwrap();
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), builder.compile().unwrap()).unwrap();
+    let ruleset =
+        RuleSet::load_file("Test", &BinaryRuleSet::Cached(file.path().to_path_buf())).unwrap();
+    let matches = |dst: NetworkAddr, connection_type: NetworkType| {
+        ruleset.matches(&ConnInfo {
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst,
+            local_ip: None,
+            inbound: InboundInfo::Tun,
+            resolved_dst: None,
+            connection_type,
+            process_info: None,
+            socket_owner: None,
+        })
+    };
+    let domain = |d: &str| NetworkAddr::DomainName {
+        domain_name: d.to_string(),
+        port: 443,
+    };
+    let ip = |s: &str| NetworkAddr::Raw(s.parse().unwrap());
+    assert!(matches(domain("google.com"), NetworkType::Tcp));
+    assert!(matches(domain("mail.google.com"), NetworkType::Tcp));
+    assert!(!matches(domain("notgoogle.com"), NetworkType::Tcp));
+    assert!(matches(domain("www.example.com"), NetworkType::Tcp));
+    assert!(!matches(domain("example.com"), NetworkType::Tcp));
+    assert!(matches(domain("a.tracker.net"), NetworkType::Tcp));
+    assert!(matches(domain("cdn-1.example.org"), NetworkType::Tcp));
+    assert!(matches(ip("10.1.2.3:80"), NetworkType::Tcp));
+    assert!(matches(ip("10.2.255.255:80"), NetworkType::Tcp));
+    assert!(!matches(ip("10.3.0.1:80"), NetworkType::Tcp));
+    assert!(matches(ip("[2001:db8::1]:80"), NetworkType::Tcp));
+    assert!(!matches(ip("[2001:db9::1]:80"), NetworkType::Tcp));
+    assert!(matches(ip("1.1.1.1:8443"), NetworkType::Udp));
+    assert!(!matches(ip("1.1.1.1:8443"), NetworkType::Tcp));
+}
+
+#[test]
+fn test_compiled_ruleset_conditions() {
+    use crate::common::host_matcher::HostType;
+    use crate::dispatch::inbound::InboundInfo;
+    use crate::platform::process::ProcessInfo;
+    let schema = |payload: &[&str]| RuleSchema {
+        behavior: ProviderBehavior::Classical,
+        payload: payload.iter().map(|s| s.to_string()).collect(),
+        binary: None,
+    };
+    for payload in ["SRC-IP-CIDR,10.0.0.0/8", "LOCAL-IP-CIDR,192.168.0.0/16"] {
+        let builder = RuleSetBuilder::new("Test", &schema(&[payload])).unwrap();
+        assert!(builder.compile().is_err());
+    }
+
+    // converted from a sing-box rule-set
+    let mut builder = RuleSetBuilder::empty("Test");
+    builder.domain.add("google.com", HostType::Subdomain);
+    builder.process_path.insert("/usr/bin/curl".to_string());
+    let data = builder.compile().unwrap();
+    assert!(RuleSet::validate_binary("Test", &data).is_ok());
+    assert!(RuleSet::validate_binary("Test", &data[..data.len() / 2]).is_err());
+    assert!(RuleSet::validate_binary("Test", b"SRS\x01garbage").is_err());
+    let file = tempfile::NamedTempFile::new().unwrap();
+    std::fs::write(file.path(), data).unwrap();
+    let ruleset =
+        RuleSet::load_file("Test", &BinaryRuleSet::File(file.path().to_path_buf())).unwrap();
+    let matches = |domain_name: &str, process_path: &str| {
+        ruleset.matches(&ConnInfo {
+            src: "127.0.0.1:12345".parse().unwrap(),
+            dst: NetworkAddr::DomainName {
+                domain_name: domain_name.to_string(),
+                port: 443,
+            },
+            local_ip: None,
+            inbound: InboundInfo::Tun,
+            resolved_dst: None,
+            connection_type: NetworkType::Tcp,
+            process_info: Some(ProcessInfo {
+                pid: 1,
+                ppid: 0,
+                path: process_path.to_string(),
+                name: "curl".to_string(),
+                cmdline: String::new(),
+                parent_name: None,
+                uid: Some(0),
+                gid: Some(0),
+                user: None,
+            }),
+            socket_owner: None,
+        })
+    };
+    assert!(matches("www.google.com", "/bin/sh"));
+    assert!(!matches("google.com", "/bin/sh"));
+    assert!(matches("example.com", "/usr/bin/curl"));
+    assert!(!matches("example.com", "/usr/bin/curl2"));
+    assert!(!matches("example.com", "/usr/bin"));
+}
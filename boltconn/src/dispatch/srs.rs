@@ -0,0 +1,432 @@
+use crate::common::host_matcher::HostType;
+use flate2::read::ZlibDecoder;
+use std::io::{self, Read};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// sing-box binary rule-set, see sing-box common/srs
+pub(crate) const SRS_MAGIC: &[u8; 3] = b"SRS";
+const MAX_VERSION: u8 = 3;
+
+const ITEM_DOMAIN: u8 = 2;
+const ITEM_DOMAIN_KEYWORD: u8 = 3;
+const ITEM_DOMAIN_REGEX: u8 = 4;
+const ITEM_IP_CIDR: u8 = 6;
+const ITEM_SOURCE_PORT: u8 = 7;
+const ITEM_SOURCE_PORT_RANGE: u8 = 8;
+const ITEM_PORT: u8 = 9;
+const ITEM_PORT_RANGE: u8 = 10;
+const ITEM_PROCESS_NAME: u8 = 11;
+const ITEM_PROCESS_PATH: u8 = 12;
+const ITEM_FINAL: u8 = 0xff;
+
+// special labels of the domain matcher
+const PREFIX_LABEL: char = '\r';
+const ROOT_LABEL: char = '\n';
+
+/// A headless rule of sing-box; conditions of different fields are ANDed.
+#[derive(Debug, Default)]
+pub(crate) struct SrsRule {
+    pub domain: Vec<(String, HostType)>,
+    pub domain_keyword: Vec<String>,
+    pub domain_regex: Vec<String>,
+    pub ip_cidr: Vec<(IpAddr, u8)>,
+    pub src_port: Vec<(u16, u16)>,
+    pub dst_port: Vec<(u16, u16)>,
+    pub process_name: Vec<String>,
+    pub process_path: Vec<String>,
+}
+
+impl SrsRule {
+    /// Number of fields with conditions; domain fields count as one.
+    pub fn condition_count(&self) -> usize {
+        [
+            !self.domain.is_empty()
+                || !self.domain_keyword.is_empty()
+                || !self.domain_regex.is_empty(),
+            !self.ip_cidr.is_empty(),
+            !self.src_port.is_empty(),
+            !self.dst_port.is_empty(),
+            !self.process_name.is_empty(),
+            !self.process_path.is_empty(),
+        ]
+        .into_iter()
+        .filter(|b| *b)
+        .count()
+    }
+}
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub(crate) fn read_srs(data: &[u8]) -> io::Result<Vec<SrsRule>> {
+    let Some(rest) = data.strip_prefix(SRS_MAGIC) else {
+        return Err(invalid("Not a sing-box rule-set".to_string()));
+    };
+    let (&version, compressed) = rest
+        .split_first()
+        .ok_or_else(|| invalid("Truncated sing-box rule-set".to_string()))?;
+    if version == 0 || version > MAX_VERSION {
+        return Err(invalid(format!(
+            "Unsupported sing-box rule-set version {}",
+            version
+        )));
+    }
+    let mut reader = io::BufReader::new(ZlibDecoder::new(compressed));
+    let count = read_uvarint(&mut reader)?;
+    let mut rules = vec![];
+    for _ in 0..count {
+        match read_u8(&mut reader)? {
+            0 => rules.push(read_default_rule(&mut reader)?),
+            1 => return Err(invalid("Logical rules are not supported".to_string())),
+            t => return Err(invalid(format!("Unknown rule type {}", t))),
+        }
+    }
+    Ok(rules)
+}
+
+fn read_default_rule(reader: &mut impl Read) -> io::Result<SrsRule> {
+    let mut rule = SrsRule::default();
+    loop {
+        match read_u8(reader)? {
+            ITEM_DOMAIN => rule.domain = read_domain_matcher(reader)?,
+            ITEM_DOMAIN_KEYWORD => rule.domain_keyword = read_strings(reader)?,
+            ITEM_DOMAIN_REGEX => rule.domain_regex = read_strings(reader)?,
+            ITEM_IP_CIDR => rule.ip_cidr = read_ip_set(reader)?,
+            ITEM_SOURCE_PORT => rule.src_port.extend(read_ports(reader)?),
+            ITEM_SOURCE_PORT_RANGE => rule.src_port.extend(read_port_ranges(reader)?),
+            ITEM_PORT => rule.dst_port.extend(read_ports(reader)?),
+            ITEM_PORT_RANGE => rule.dst_port.extend(read_port_ranges(reader)?),
+            ITEM_PROCESS_NAME => rule.process_name = read_strings(reader)?,
+            ITEM_PROCESS_PATH => rule.process_path = read_strings(reader)?,
+            ITEM_FINAL => {
+                if read_u8(reader)? != 0 {
+                    return Err(invalid("Inverted rules are not supported".to_string()));
+                }
+                return Ok(rule);
+            }
+            t => return Err(invalid(format!("Unsupported rule item {}", t))),
+        }
+    }
+}
+
+/// Restore domains from the succinct trie of reversed domains.
+fn read_domain_matcher(reader: &mut impl Read) -> io::Result<Vec<(String, HostType)>> {
+    if read_u8(reader)? != 0 {
+        return Err(invalid("Unsupported domain matcher".to_string()));
+    }
+    let leaves = read_u64s(reader)?;
+    let bitmap = read_u64s(reader)?;
+    let len = read_uvarint(reader)? as usize;
+    let labels = read_bytes(reader, len)?;
+    let get_bit = |bm: &[u64], idx: usize| bm.get(idx >> 6).map(|w| w & (1 << (idx & 63)) != 0);
+
+    // nodes are stored in BFS order: a 0 bit for each child, then a 1 bit
+    let mut prefixes: Vec<Vec<u8>> = vec![vec![]];
+    let mut keys = vec![];
+    let (mut pos, mut label_idx, mut node) = (0, 0, 0);
+    let corrupted = || invalid("Corrupted domain matcher".to_string());
+    while node < prefixes.len() {
+        if get_bit(&leaves, node).unwrap_or(false) {
+            keys.push(prefixes[node].clone());
+        }
+        while !get_bit(&bitmap, pos).ok_or_else(corrupted)? {
+            let mut child = prefixes[node].clone();
+            child.push(*labels.get(label_idx).ok_or_else(corrupted)?);
+            prefixes.push(child);
+            label_idx += 1;
+            pos += 1;
+        }
+        pos += 1;
+        node += 1;
+    }
+
+    let mut domains = vec![];
+    for key in keys {
+        let key = String::from_utf8(key).map_err(|_| corrupted())?;
+        let domain: String = key.chars().rev().collect();
+        // `.example.com` is stored with the prefix label and `example.com` with the root label
+        if let Some(d) = domain.strip_prefix(PREFIX_LABEL) {
+            domains.push((d.trim_start_matches('.').to_string(), HostType::Subdomain));
+        } else if let Some(d) = domain.strip_prefix(ROOT_LABEL) {
+            domains.push((d.to_string(), HostType::Suffix));
+        } else {
+            domains.push((domain, HostType::Exact));
+        }
+    }
+    Ok(domains)
+}
+
+fn read_ip_set(reader: &mut impl Read) -> io::Result<Vec<(IpAddr, u8)>> {
+    if read_u8(reader)? != 1 {
+        return Err(invalid("Unsupported IP set".to_string()));
+    }
+    let mut len = [0u8; 8];
+    reader.read_exact(&mut len)?;
+    let mut cidrs = vec![];
+    for _ in 0..u64::from_be_bytes(len) {
+        let from = read_ip(reader)?;
+        let to = read_ip(reader)?;
+        match (from, to) {
+            (IpAddr::V4(from), IpAddr::V4(to)) => {
+                for (ip, prefix) in
+                    range_to_cidrs(u32::from(from) as u128, u32::from(to) as u128, 32)
+                {
+                    cidrs.push((IpAddr::V4(Ipv4Addr::from(ip as u32)), prefix));
+                }
+            }
+            (IpAddr::V6(from), IpAddr::V6(to)) => {
+                for (ip, prefix) in range_to_cidrs(u128::from(from), u128::from(to), 128) {
+                    cidrs.push((IpAddr::V6(Ipv6Addr::from(ip)), prefix));
+                }
+            }
+            _ => return Err(invalid("Mismatched IP range".to_string())),
+        }
+    }
+    Ok(cidrs)
+}
+
+fn read_ip(reader: &mut impl Read) -> io::Result<IpAddr> {
+    let len = read_uvarint(reader)? as usize;
+    let bytes = read_bytes(reader, len)?;
+    if let Ok(v4) = <[u8; 4]>::try_from(bytes.as_slice()) {
+        Ok(IpAddr::from(v4))
+    } else if let Ok(v6) = <[u8; 16]>::try_from(bytes.as_slice()) {
+        Ok(IpAddr::from(v6))
+    } else {
+        Err(invalid("Invalid IP address".to_string()))
+    }
+}
+
+/// Split an inclusive range into the fewest CIDRs.
+fn range_to_cidrs(mut start: u128, end: u128, bits: u32) -> Vec<(u128, u8)> {
+    let mask = |size: u32| {
+        if size == 0 {
+            0
+        } else {
+            u128::MAX >> (128 - size)
+        }
+    };
+    let mut cidrs = vec![];
+    while start <= end {
+        let mut size = start.trailing_zeros().min(bits);
+        while start + mask(size) > end {
+            size -= 1;
+        }
+        cidrs.push((start, (bits - size) as u8));
+        match (start + mask(size)).checked_add(1) {
+            Some(next) => start = next,
+            None => break,
+        }
+    }
+    cidrs
+}
+
+fn read_ports(reader: &mut impl Read) -> io::Result<Vec<(u16, u16)>> {
+    let mut ports = vec![];
+    for _ in 0..read_uvarint(reader)? {
+        let mut buf = [0u8; 2];
+        reader.read_exact(&mut buf)?;
+        let port = u16::from_be_bytes(buf);
+        ports.push((port, port));
+    }
+    Ok(ports)
+}
+
+fn read_port_ranges(reader: &mut impl Read) -> io::Result<Vec<(u16, u16)>> {
+    // in the form of `start:end`, either of which can be omitted
+    read_strings(reader)?
+        .iter()
+        .map(|r| {
+            let (start, end) = r
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("Invalid port range {}", r)))?;
+            let parse = |s: &str, default: u16| {
+                if s.is_empty() {
+                    Ok(default)
+                } else {
+                    s.parse::<u16>()
+                        .map_err(|_| invalid(format!("Invalid port range {}", r)))
+                }
+            };
+            Ok((parse(start, 0)?, parse(end, u16::MAX)?))
+        })
+        .collect()
+}
+
+fn read_u8(reader: &mut impl Read) -> io::Result<u8> {
+    let mut buf = [0u8; 1];
+    reader.read_exact(&mut buf)?;
+    Ok(buf[0])
+}
+
+fn read_uvarint(reader: &mut impl Read) -> io::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let b = read_u8(reader)?;
+        value |= ((b & 0x7f) as u64) << shift;
+        if b & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(invalid("Varint overflow".to_string()))
+}
+
+fn read_bytes(reader: &mut impl Read, len: usize) -> io::Result<Vec<u8>> {
+    // do not trust the length before reading
+    let mut buf = vec![];
+    reader.take(len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(buf)
+}
+
+fn read_u64s(reader: &mut impl Read) -> io::Result<Vec<u64>> {
+    let len = read_uvarint(reader)? as usize;
+    let bytes = read_bytes(
+        reader,
+        len.checked_mul(8)
+            .ok_or_else(|| invalid("Too long".to_string()))?,
+    )?;
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|c| u64::from_be_bytes(c.try_into().unwrap()))
+        .collect())
+}
+
+fn read_strings(reader: &mut impl Read) -> io::Result<Vec<String>> {
+    let mut list = vec![];
+    for _ in 0..read_uvarint(reader)? {
+        let len = read_uvarint(reader)? as usize;
+        list.push(
+            String::from_utf8(read_bytes(reader, len)?)
+                .map_err(|_| invalid("Invalid string".to_string()))?,
+        );
+    }
+    Ok(list)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use std::io::Write;
+
+    fn uvarint(buf: &mut Vec<u8>, mut v: u64) {
+        while v >= 0x80 {
+            buf.push(v as u8 | 0x80);
+            v >>= 7;
+        }
+        buf.push(v as u8);
+    }
+
+    // same construction as sing-box, from sorted keys
+    fn succinct_set(keys: &[Vec<u8>]) -> Vec<u8> {
+        fn set_bit(bm: &mut Vec<u64>, i: usize) {
+            while i >> 6 >= bm.len() {
+                bm.push(0);
+            }
+            bm[i >> 6] |= 1 << (i & 63);
+        }
+        let (mut leaves, mut bitmap, mut labels) = (vec![], vec![], vec![]);
+        let mut queue = vec![(0, keys.len(), 0)];
+        let (mut i, mut l_idx) = (0, 0);
+        while i < queue.len() {
+            let (mut s, e, col) = queue[i];
+            if col == keys[s].len() {
+                s += 1;
+                set_bit(&mut leaves, i);
+            }
+            let mut j = s;
+            while j < e {
+                let frm = j;
+                while j < e && keys[j][col] == keys[frm][col] {
+                    j += 1;
+                }
+                queue.push((frm, j, col + 1));
+                labels.push(keys[frm][col]);
+                l_idx += 1;
+            }
+            set_bit(&mut bitmap, l_idx);
+            l_idx += 1;
+            i += 1;
+        }
+        let mut buf = vec![0];
+        for bm in [leaves, bitmap] {
+            uvarint(&mut buf, bm.len() as u64);
+            bm.iter().for_each(|w| buf.extend(w.to_be_bytes()));
+        }
+        uvarint(&mut buf, labels.len() as u64);
+        buf.extend(labels);
+        buf
+    }
+
+    fn strings(buf: &mut Vec<u8>, list: &[&str]) {
+        uvarint(buf, list.len() as u64);
+        for s in list {
+            uvarint(buf, s.len() as u64);
+            buf.extend(s.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_read_srs() {
+        let mut keys: Vec<Vec<u8>> = ["\r.google.com", "\nexample.org", "www.example.com"]
+            .iter()
+            .map(|d| d.chars().rev().collect::<String>().into_bytes())
+            .collect();
+        keys.sort();
+        let mut body = vec![];
+        uvarint(&mut body, 3);
+        // domains
+        body.extend([0, ITEM_DOMAIN]);
+        body.extend(succinct_set(&keys));
+        body.push(ITEM_DOMAIN_KEYWORD);
+        strings(&mut body, &["tracker"]);
+        body.extend([ITEM_FINAL, 0]);
+        // 10.0.0.1 - 10.0.0.6
+        body.extend([0, ITEM_IP_CIDR, 1]);
+        body.extend(1u64.to_be_bytes());
+        body.extend([4, 10, 0, 0, 1, 4, 10, 0, 0, 6]);
+        body.extend([ITEM_FINAL, 0]);
+        // ports
+        body.extend([0, ITEM_PORT]);
+        uvarint(&mut body, 1);
+        body.extend(53u16.to_be_bytes());
+        body.push(ITEM_PORT_RANGE);
+        strings(&mut body, &["8000:8001"]);
+        body.extend([ITEM_FINAL, 0]);
+
+        let mut encoder = ZlibEncoder::new(vec![], Default::default());
+        encoder.write_all(&body).unwrap();
+        let mut data = Vec::from(*SRS_MAGIC);
+        data.push(2);
+        data.extend(encoder.finish().unwrap());
+        let rules = read_srs(&data).unwrap();
+        assert_eq!(rules.len(), 3);
+        let mut domains = rules[0].domain.clone();
+        domains.sort();
+        assert_eq!(
+            domains,
+            vec![
+                ("example.org".to_string(), HostType::Suffix),
+                ("google.com".to_string(), HostType::Subdomain),
+                ("www.example.com".to_string(), HostType::Exact)
+            ]
+        );
+        assert_eq!(rules[0].domain_keyword, vec!["tracker".to_string()]);
+        assert_eq!(rules[0].condition_count(), 1);
+        assert_eq!(
+            rules[1].ip_cidr,
+            ["10.0.0.1/32", "10.0.0.2/31", "10.0.0.4/31", "10.0.0.6/32"]
+                .iter()
+                .map(|s| {
+                    let (ip, prefix) = s.split_once('/').unwrap();
+                    (ip.parse().unwrap(), prefix.parse().unwrap())
+                })
+                .collect::<Vec<(IpAddr, u8)>>()
+        );
+        assert_eq!(rules[2].dst_port, vec![(53, 53), (8000, 8001)]);
+    }
+}
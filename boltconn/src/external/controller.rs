@@ -1,17 +1,17 @@
-use crate::config::{LinkedState, RuleConfigLine};
+use crate::config::{LinkedState, ProviderRegistry, RuleConfigLine};
 use crate::dispatch::{GeneralProxy, Latency};
 use crate::external::{SharedDispatching, StreamLoggerRecv, StreamLoggerSend};
 use crate::network::configure::TunConfigure;
 use crate::network::dns::Dns;
 use crate::proxy::{
-    latency_test, ConnContext, ContextManager, Dispatcher, HttpCapturer, HttpInterceptData,
+    group_latency_test, ConnContext, ContextManager, Dispatcher, HttpCapturer, HttpInterceptData,
     SessionManager,
 };
 use boltapi::{
     ConnectionSchema, GetGroupRespSchema, GetInterceptDataResp, GetInterceptRangeReq,
-    HttpInterceptSchema, ProcessSchema, ProxyData, SessionSchema, TrafficResp, TunStatusSchema,
+    HttpInterceptSchema, ProcessSchema, ProviderSchema, ProxyData, SessionSchema,
+    SubscriptionInfoSchema, TrafficResp, TunStatusSchema,
 };
-use std::collections::HashSet;
 use std::io::Write;
 use std::sync::atomic::Ordering;
 use std::sync::Arc;
@@ -27,6 +27,7 @@ pub struct Controller {
     dispatching: SharedDispatching,
     tun_configure: Arc<std::sync::Mutex<TunConfigure>>,
     reload_sender: Arc<tokio::sync::mpsc::Sender<()>>,
+    providers: Arc<ProviderRegistry>,
     state: Arc<std::sync::Mutex<LinkedState>>,
     stream_logger: StreamLoggerSend,
     speedtest_url: Arc<std::sync::RwLock<String>>,
@@ -43,6 +44,7 @@ impl Controller {
         dispatching: SharedDispatching,
         global_setting: Arc<std::sync::Mutex<TunConfigure>>,
         reload_sender: tokio::sync::mpsc::Sender<()>,
+        providers: Arc<ProviderRegistry>,
         state: Arc<std::sync::Mutex<LinkedState>>,
         stream_logger: StreamLoggerSend,
         speedtest_url: Arc<std::sync::RwLock<String>>,
@@ -56,6 +58,7 @@ impl Controller {
             dispatcher,
             dispatching,
             reload_sender: Arc::new(reload_sender),
+            providers,
             state,
             stream_logger,
             speedtest_url,
@@ -122,6 +125,9 @@ impl Controller {
                 name: i.name.clone(),
                 cmdline: i.cmdline.clone(),
                 parent_name: i.parent_name.clone(),
+                uid: i.uid,
+                gid: i.gid,
+                user: i.user.clone(),
             }),
             upload: info.upload_traffic.load(Ordering::Relaxed),
             download: info.download_traffic.load(Ordering::Relaxed),
@@ -426,41 +432,48 @@ impl Controller {
         let list = self.dispatching.load().get_group_list();
         for g in list.iter() {
             if g.get_name() == group {
-                let iface = g.get_direct_interface();
                 // update all latency inside the group
-                let mut handles = vec![];
-                let mut tested_proxy = HashSet::new();
-                for p in g.get_members() {
-                    let p = match p {
-                        GeneralProxy::Single(p) => p,
-                        GeneralProxy::Group(g) => &g.get_proxy(),
-                    };
-                    if tested_proxy.contains(&p.get_name()) {
-                        continue;
-                    }
-                    tested_proxy.insert(p.get_name());
-                    if let Ok(h) = latency_test(
-                        self.dispatcher.as_ref(),
-                        p.clone(),
-                        speedtest_url.as_str(),
-                        Duration::from_secs(2),
-                        iface.clone(),
-                    )
-                    .await
-                    {
-                        handles.push(h);
-                    } else {
-                        p.set_latency(Latency::Failed)
-                    }
-                }
-                for h in handles {
-                    let _ = h.await;
-                }
+                group_latency_test(
+                    self.dispatcher.as_ref(),
+                    g.as_ref(),
+                    speedtest_url.as_str(),
+                    Duration::from_secs(2),
+                )
+                .await;
+                g.update_selection();
                 break;
             }
         }
     }
 
+    pub fn get_providers(&self) -> Vec<ProviderSchema> {
+        self.providers
+            .list()
+            .into_iter()
+            .map(|p| ProviderSchema {
+                name: p.name,
+                kind: p.kind.to_string(),
+                url: p.url,
+                path: p.path,
+                count: p.count,
+                updated_at: p
+                    .updated_at
+                    .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                    .map(|d| d.as_secs()),
+                subscription: p.userinfo.map(|info| SubscriptionInfoSchema {
+                    upload: info.upload,
+                    download: info.download,
+                    total: info.total,
+                    expire: info.expire,
+                }),
+            })
+            .collect()
+    }
+
+    pub async fn update_provider(&self, kind: Option<String>, name: String) -> bool {
+        self.providers.update(kind.as_deref(), name.as_str()).await
+    }
+
     pub async fn reload(&self) {
         let _ = self.reload_sender.send(()).await;
     }
@@ -477,11 +490,13 @@ fn pretty_proxy(g: &GeneralProxy) -> ProxyData {
             name: p.get_name(),
             proto: p.get_impl().simple_description(),
             latency: latency_to_str(p.get_latency()),
+            udp: p.get_impl().support_udp(),
         },
         GeneralProxy::Group(g) => ProxyData {
             name: g.get_name(),
             proto: "group".to_string(),
             latency: latency_to_str(g.get_proxy().get_latency()),
+            udp: g.get_proxy().get_impl().support_udp(),
         },
     }
 }
@@ -0,0 +1,387 @@
+use crate::common::host_matcher::HostMatcher;
+use aho_corasick::AhoCorasick;
+use bytes::Bytes;
+use prost::Message;
+use regex::{Regex, RegexSet};
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Debug, Formatter};
+use std::io;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+// v2ray geosite.dat, see v2fly/v2ray-core app/router/routercommon
+#[derive(Clone, PartialEq, Message)]
+struct GeoSiteList {
+    // decoded on demand
+    #[prost(bytes = "bytes", repeated, tag = "1")]
+    entry: Vec<Bytes>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GeoSiteCode {
+    #[prost(string, tag = "1")]
+    country_code: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct GeoSite {
+    #[prost(string, tag = "1")]
+    country_code: String,
+    #[prost(message, repeated, tag = "2")]
+    domain: Vec<Domain>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Domain {
+    // 0: plain, 1: regex, 2: root domain, 3: full
+    #[prost(int32, tag = "1")]
+    r#type: i32,
+    #[prost(string, tag = "2")]
+    value: String,
+    #[prost(message, repeated, tag = "3")]
+    attribute: Vec<Attribute>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct Attribute {
+    #[prost(string, tag = "1")]
+    key: String,
+    #[prost(bool, tag = "2")]
+    bool_value: bool,
+    #[prost(int64, tag = "3")]
+    int_value: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum ItemType {
+    Full,
+    Suffix,
+    Keyword,
+    Regex,
+}
+
+type Item = (ItemType, String);
+
+enum GeositeSource {
+    V2ray(HashMap<String, Bytes>),
+    // sing-box geosite.db: items of a code start at metadata end + offset
+    SingBox {
+        items: Bytes,
+        codes: HashMap<String, (usize, usize)>,
+    },
+}
+
+pub struct GeositeReader {
+    source: GeositeSource,
+    cache: Mutex<HashMap<String, Arc<GeositeMatcher>>>,
+}
+
+impl GeositeReader {
+    /// Read a v2ray `geosite.dat` or a sing-box `geosite.db`.
+    pub fn read_from_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_bytes(Bytes::from(std::fs::read(path)?))
+    }
+
+    fn from_bytes(data: Bytes) -> io::Result<Self> {
+        let source = match data.first() {
+            // field 1, length-delimited
+            Some(0x0a) => {
+                let list = GeoSiteList::decode(data)?;
+                let mut codes = HashMap::new();
+                for entry in list.entry {
+                    let code = GeoSiteCode::decode(entry.clone())?;
+                    codes.insert(code.country_code.to_lowercase(), entry);
+                }
+                GeositeSource::V2ray(codes)
+            }
+            // version 0
+            Some(0x00) => {
+                let mut cursor = SingBoxCursor {
+                    data: &data,
+                    pos: 1,
+                };
+                let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Corrupted geosite.db");
+                let count = cursor.uvarint().ok_or_else(invalid)?;
+                let mut codes = HashMap::new();
+                for _ in 0..count {
+                    let code = cursor.vstring().ok_or_else(invalid)?;
+                    let index = cursor.uvarint().ok_or_else(invalid)?;
+                    let length = cursor.uvarint().ok_or_else(invalid)?;
+                    codes.insert(code.to_lowercase(), (index as usize, length as usize));
+                }
+                GeositeSource::SingBox {
+                    items: data.slice(cursor.pos..),
+                    codes,
+                }
+            }
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Unknown geosite database format",
+                ))
+            }
+        };
+        Ok(Self {
+            source,
+            cache: Default::default(),
+        })
+    }
+
+    /// Get the matcher of `category`, `category@attr` or `category@!attr`.
+    pub fn matcher(&self, code: &str) -> Option<Arc<GeositeMatcher>> {
+        let code = code.to_lowercase();
+        if let Some(m) = self.cache.lock().unwrap().get(&code) {
+            return Some(m.clone());
+        }
+        let items = match code.split_once('@') {
+            None => self.read_items(&code, None)?,
+            Some((category, attr)) => match attr.strip_prefix('!') {
+                Some(attr) => self.read_items(category, Some((attr, false)))?,
+                None => self.read_items(category, Some((attr, true)))?,
+            },
+        };
+        let matcher = Arc::new(GeositeMatcher::new(items));
+        self.cache.lock().unwrap().insert(code, matcher.clone());
+        Some(matcher)
+    }
+
+    fn read_items(&self, category: &str, attr: Option<(&str, bool)>) -> Option<Vec<Item>> {
+        match &self.source {
+            GeositeSource::V2ray(codes) => {
+                let site = GeoSite::decode(codes.get(category)?.clone()).ok()?;
+                Some(
+                    site.domain
+                        .into_iter()
+                        .filter(|d| {
+                            attr.map_or(true, |(attr, expected)| {
+                                d.attribute.iter().any(|a| a.key == attr) == expected
+                            })
+                        })
+                        .filter_map(|d| {
+                            let t = match d.r#type {
+                                0 => ItemType::Keyword,
+                                1 => ItemType::Regex,
+                                2 => ItemType::Suffix,
+                                3 => ItemType::Full,
+                                _ => return None,
+                            };
+                            Some((t, d.value))
+                        })
+                        .collect(),
+                )
+            }
+            GeositeSource::SingBox { items, codes } => {
+                // attributes are compiled into codes like `category@attr`
+                let read = |code: &str| {
+                    let (index, length) = *codes.get(code)?;
+                    let mut cursor = SingBoxCursor {
+                        data: items,
+                        pos: index,
+                    };
+                    let mut result = vec![];
+                    for _ in 0..length {
+                        let t = match cursor.byte()? {
+                            0 => ItemType::Full,
+                            1 => ItemType::Suffix,
+                            2 => ItemType::Keyword,
+                            3 => ItemType::Regex,
+                            _ => return None,
+                        };
+                        result.push((t, cursor.vstring()?));
+                    }
+                    Some(result)
+                };
+                match attr {
+                    None => read(category),
+                    Some((attr, true)) => read(format!("{}@{}", category, attr).as_str()),
+                    Some((attr, false)) => {
+                        let excluded: HashSet<Item> =
+                            read(format!("{}@{}", category, attr).as_str())
+                                .unwrap_or_default()
+                                .into_iter()
+                                .collect();
+                        Some(
+                            read(category)?
+                                .into_iter()
+                                .filter(|i| !excluded.contains(i))
+                                .collect(),
+                        )
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Debug for GeositeReader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Geosite")
+    }
+}
+
+struct SingBoxCursor<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl SingBoxCursor<'_> {
+    fn byte(&mut self) -> Option<u8> {
+        let b = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(b)
+    }
+
+    fn uvarint(&mut self) -> Option<u64> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let b = self.byte()?;
+            value |= ((b & 0x7f) as u64) << shift;
+            if b & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+
+    fn vstring(&mut self) -> Option<String> {
+        let len = self.uvarint()? as usize;
+        let s = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        String::from_utf8(s.to_vec()).ok()
+    }
+}
+
+pub struct GeositeMatcher {
+    hosts: HostMatcher,
+    keywords: Option<AhoCorasick>,
+    regex: RegexSet,
+}
+
+impl GeositeMatcher {
+    fn new(items: Vec<Item>) -> Self {
+        let mut hosts = HostMatcher::builder();
+        let mut keywords = vec![];
+        let mut regex = vec![];
+        for (t, value) in items {
+            match t {
+                ItemType::Full => hosts.add_exact(value.as_str()),
+                // sing-box keeps the leading dot of suffixes
+                ItemType::Suffix => hosts.add_suffix(value.trim_start_matches('.')),
+                ItemType::Keyword => keywords.push(value),
+                // skip patterns the regex crate does not support
+                ItemType::Regex => match Regex::new(&value) {
+                    Ok(_) => regex.push(value),
+                    Err(e) => tracing::warn!("Skip geosite regex {}: {}", value, e),
+                },
+            }
+        }
+        Self {
+            hosts: hosts.build(),
+            keywords: (!keywords.is_empty())
+                .then(|| AhoCorasick::new(keywords).ok())
+                .flatten(),
+            // the patterns are valid one by one, but the set may still exceed the size limit
+            regex: RegexSet::new(regex).unwrap_or_else(|e| {
+                tracing::warn!("Skip geosite regex of the category: {}", e);
+                RegexSet::empty()
+            }),
+        }
+    }
+
+    pub fn matches(&self, domain: &str) -> bool {
+        self.hosts.matches(domain)
+            || self.keywords.as_ref().is_some_and(|k| k.is_match(domain))
+            || self.regex.is_match(domain)
+    }
+}
+
+impl Debug for GeositeMatcher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str("Geosite")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn domain(t: i32, value: &str, attr: Option<&str>) -> Domain {
+        Domain {
+            r#type: t,
+            value: value.to_string(),
+            attribute: attr
+                .map(|key| Attribute {
+                    key: key.to_string(),
+                    bool_value: true,
+                    int_value: 0,
+                })
+                .into_iter()
+                .collect(),
+        }
+    }
+
+    fn vstring(buf: &mut Vec<u8>, s: &str) {
+        buf.push(s.len() as u8);
+        buf.extend_from_slice(s.as_bytes());
+    }
+
+    #[test]
+    fn test_geosite() {
+        let site = GeoSite {
+            country_code: "GOOGLE".to_string(),
+            domain: vec![
+                domain(2, "google.com", None),
+                domain(3, "www.google.com", None),
+                domain(2, "doubleclick.net", Some("ads")),
+                domain(0, "googleapis", None),
+                domain(1, r"^gstatic\.[a-z]+$", None),
+            ],
+        };
+        let list = GeoSiteList {
+            entry: vec![Bytes::from(site.encode_to_vec())],
+        };
+        let reader = GeositeReader::from_bytes(Bytes::from(list.encode_to_vec())).unwrap();
+        let google = reader.matcher("google").unwrap();
+        assert!(google.matches("google.com"));
+        assert!(google.matches("a.www.google.com"));
+        assert!(google.matches("ad.doubleclick.net"));
+        assert!(google.matches("fonts.googleapis.cn"));
+        assert!(google.matches("gstatic.cn"));
+        assert!(!google.matches("notgoogle.com"));
+        let ads = reader.matcher("GOOGLE@ads").unwrap();
+        assert!(ads.matches("doubleclick.net"));
+        assert!(!ads.matches("google.com"));
+        let not_ads = reader.matcher("google@!ads").unwrap();
+        assert!(!not_ads.matches("doubleclick.net"));
+        assert!(not_ads.matches("google.com"));
+        assert!(reader.matcher("facebook").is_none());
+
+        let mut items = vec![];
+        let mut index = vec![];
+        for (code, list) in [
+            ("cn", vec![(0u8, "baidu.com"), (1, ".qq.com")]),
+            ("cn@ads", vec![(1, ".qq.com")]),
+        ] {
+            index.push((code, items.len(), list.len()));
+            for (t, value) in list {
+                items.push(t);
+                vstring(&mut items, value);
+            }
+        }
+        let mut db = vec![0, index.len() as u8];
+        for (code, offset, len) in index {
+            vstring(&mut db, code);
+            db.extend_from_slice(&[offset as u8, len as u8]);
+        }
+        db.extend(items);
+        let reader = GeositeReader::from_bytes(Bytes::from(db)).unwrap();
+        let cn = reader.matcher("CN").unwrap();
+        assert!(cn.matches("baidu.com"));
+        assert!(!cn.matches("www.baidu.com"));
+        assert!(cn.matches("qq.com"));
+        assert!(cn.matches("im.qq.com"));
+        let not_ads = reader.matcher("cn@!ads").unwrap();
+        assert!(not_ads.matches("baidu.com"));
+        assert!(!not_ads.matches("im.qq.com"));
+    }
+}
@@ -1,5 +1,6 @@
 mod controller;
 mod database;
+mod geosite;
 mod instrument_server;
 mod logger;
 mod mmdb;
@@ -9,6 +10,7 @@ mod web_controller;
 
 pub use controller::*;
 pub use database::*;
+pub use geosite::*;
 pub use instrument_server::*;
 pub use logger::*;
 pub use mmdb::*;
@@ -6,7 +6,7 @@ use boltapi::multiplex::rpc_multiplex_twoway;
 use boltapi::rpc::{ClientStreamServiceClient, ControlService};
 use boltapi::{
     ConnectionSchema, GetGroupRespSchema, GetInterceptDataResp, GetInterceptRangeReq,
-    HttpInterceptSchema, TrafficResp, TunStatusSchema,
+    HttpInterceptSchema, ProviderSchema, TrafficResp, TunStatusSchema,
 };
 use std::io;
 use std::path::{Path, PathBuf};
@@ -229,6 +229,14 @@ impl ControlService for UdsRpcServer {
         true
     }
 
+    async fn get_providers(self, _ctx: Context) -> Vec<ProviderSchema> {
+        self.controller.get_providers()
+    }
+
+    async fn update_provider(self, _ctx: Context, kind: Option<String>, name: String) -> bool {
+        self.controller.update_provider(kind, name).await
+    }
+
     async fn get_all_interceptions(self, _ctx: Context) -> Vec<HttpInterceptSchema> {
         self.controller.get_intercept()
     }
@@ -10,7 +10,9 @@ use axum::middleware::map_request;
 use axum::response::IntoResponse;
 use axum::routing::{delete, get, post};
 use axum::{Json, Router};
-use boltapi::{GetInterceptRangeReq, SetGroupReqSchema, TrafficResp, TunStatusSchema};
+use boltapi::{
+    GetInterceptRangeReq, SetGroupReqSchema, TrafficResp, TunStatusSchema, UpdateProviderReq,
+};
 use http::HeaderValue;
 use serde_json::json;
 use std::collections::HashMap;
@@ -67,6 +69,8 @@ impl WebController {
             )
             .route("/dns/mapping/:fake_ip", get(Self::fake_ip_to_real))
             .route("/dns/lookup/:domain", get(Self::real_lookup))
+            .route("/providers", get(Self::get_providers))
+            .route("/providers/:name", post(Self::update_provider))
             .route("/speedtest/:group", get(Self::update_latency))
             .route(
                 "/connections/log_limit",
@@ -312,6 +316,26 @@ impl WebController {
         Json(json!(server.controller.get_conn_log_limit()))
     }
 
+    async fn get_providers(State(server): State<Self>) -> Json<serde_json::Value> {
+        Json(json!(server.controller.get_providers()))
+    }
+
+    async fn update_provider(
+        State(server): State<Self>,
+        Path(params): Path<HashMap<String, String>>,
+        Query(query): Query<UpdateProviderReq>,
+    ) -> Json<serde_json::Value> {
+        let name = {
+            let Some(name) = params.get("name") else {
+                return Json(serde_json::Value::Bool(false));
+            };
+            name.clone()
+        };
+        Json(json!(
+            server.controller.update_provider(query.kind, name).await
+        ))
+    }
+
     async fn reload(State(server): State<Self>) {
         server.controller.reload().await
     }
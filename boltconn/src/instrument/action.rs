@@ -58,6 +58,7 @@ impl FormattingObject {
             resolved_dst: None,
             connection_type: crate::platform::process::NetworkType::Tcp,
             process_info: None,
+            socket_owner: None,
         };
         if let Err(e) = Self::format_inner(usr_template.as_str(), &mock_info) {
             return Err(ConfigError::Instrument(InstrumentConfigError::BadTemplate(
@@ -135,6 +136,16 @@ impl FormattingObject {
             },
         );
 
+        let process_uid = info
+            .process_info
+            .as_ref()
+            .and_then(|info| info.uid)
+            .map_or_else(|| na_str.to_string(), |uid| uid.to_string());
+        let process_user = info.process_info.as_ref().map_or_else(
+            || na_str.to_string(),
+            |info| info.user.clone().unwrap_or_else(|| na_str.to_string()),
+        );
+
         // Collect to hashmap; needed to be exported to end user, so consistency of key name is important here.
         let mapping = [
             ("addr.src", Formattable::display(&info.src)),
@@ -151,6 +162,8 @@ impl FormattingObject {
             ("process.pid", Formattable::display(&process_pid)),
             ("process.ppid", Formattable::display(&process_ppid)),
             ("process.parent_name", Formattable::display(&process_pname)),
+            ("process.uid", Formattable::display(&process_uid)),
+            ("process.user", Formattable::display(&process_user)),
             ("time.rfc3389", Formattable::display(&time_rfc3389)),
             ("time.hms_ms", Formattable::display(&time_hms_ms)),
             ("time.datetime", Formattable::display(&time_datetime)),
@@ -184,6 +197,7 @@ fn test_instrument_formatting() {
         resolved_dst: None,
         connection_type: crate::platform::process::NetworkType::Tcp,
         process_info: None,
+        socket_owner: None,
     };
     let fmt_obj = FormattingObject::new(template.to_string()).unwrap();
     let _ = fmt_obj.format(&info);
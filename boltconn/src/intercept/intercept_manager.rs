@@ -1,6 +1,6 @@
 use crate::config::{ActionConfig, ConfigError, InterceptConfigError, InterceptionConfig};
 use crate::dispatch::{ConnInfo, Dispatching, DispatchingBuilder, ProxyImpl, RuleSetTable};
-use crate::external::MmdbReader;
+use crate::external::{GeositeReader, MmdbReader};
 use crate::instrument::bus::MessageBus;
 use crate::intercept::{HeaderEngine, ScriptEngine, UrlEngine};
 use crate::network::dns::Dns;
@@ -109,6 +109,7 @@ impl InterceptionManager {
         entries: &[InterceptionConfig],
         dns: Arc<Dns>,
         mmdb: Option<Arc<MmdbReader>>,
+        geosite: Option<Arc<GeositeReader>>,
         rulesets: &RuleSetTable,
         msg_bus: Arc<MessageBus>,
     ) -> Result<Self, ConfigError> {
@@ -117,9 +118,14 @@ impl InterceptionManager {
             if !i.enabled {
                 continue;
             }
-            let filters =
-                DispatchingBuilder::empty(config_path, dns.clone(), mmdb.clone(), msg_bus.clone())
-                    .build_filter(i.filters.as_slice(), rulesets)?;
+            let filters = DispatchingBuilder::empty(
+                config_path,
+                dns.clone(),
+                mmdb.clone(),
+                geosite.clone(),
+                msg_bus.clone(),
+            )
+            .build_filter(i.filters.as_slice(), rulesets)?;
             let payload = InterceptionPayload::parse_actions(i.actions.as_slice())?;
             res.push(InterceptionEntry {
                 filters,
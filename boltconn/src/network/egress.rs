@@ -1,60 +1,129 @@
 use crate::common::io_err;
 use crate::platform;
 use crate::platform::get_iface_address;
-use socket2::{Domain, SockAddr, Socket, Type};
-use std::io::Result;
+use socket2::{Domain, SockAddr, SockRef, Socket, TcpKeepalive, Type};
+use std::io::{ErrorKind, Result};
 use std::net::{IpAddr, SocketAddr};
 use std::os::unix::io::AsRawFd;
+use std::time::Duration;
 use tokio::net::{TcpSocket, TcpStream, UdpSocket};
 
+/// Socket options used when dialing a proxy server.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct DialOptions {
+    pub interface: Option<String>,
+    pub source_addr: Option<IpAddr>,
+    pub mark: Option<u32>,
+    pub tcp_keepalive: Option<Duration>,
+    pub tcp_fast_open: bool,
+    pub connect_timeout: Option<Duration>,
+}
+
+async fn with_timeout<T>(
+    timeout: Option<Duration>,
+    fut: impl std::future::Future<Output = Result<T>>,
+) -> Result<T> {
+    match timeout {
+        Some(timeout) => tokio::time::timeout(timeout, fut)
+            .await
+            .unwrap_or_else(|_| Err(ErrorKind::TimedOut.into())),
+        None => fut.await,
+    }
+}
+
 pub struct Egress {
     iface_name: String,
+    dial: DialOptions,
 }
 
 impl Egress {
     pub fn new(name: &str) -> Self {
         Self {
             iface_name: name.to_string(),
+            dial: DialOptions::default(),
         }
     }
 
+    /// Apply per-proxy dial options; the bind interface in options takes precedence.
+    pub fn with_dial(mut self, dial: &DialOptions) -> Self {
+        if let Some(iface) = &dial.interface {
+            self.iface_name.clone_from(iface);
+        }
+        self.dial = dial.clone();
+        self
+    }
+
     pub async fn tcp_stream(&self, addr: SocketAddr) -> Result<TcpStream> {
-        match addr {
-            SocketAddr::V4(v4addr) => {
-                if v4addr.ip().is_loopback() {
-                    TcpStream::connect(SocketAddr::V4(v4addr)).await
-                } else {
-                    self.tcpv4_stream(SocketAddr::V4(v4addr)).await
+        let fut = async {
+            match addr {
+                SocketAddr::V4(v4addr) => {
+                    if v4addr.ip().is_loopback() {
+                        TcpStream::connect(SocketAddr::V4(v4addr)).await
+                    } else {
+                        self.tcp_stream_inner(TcpSocket::new_v4()?, SocketAddr::V4(v4addr))
+                            .await
+                    }
                 }
-            }
-            SocketAddr::V6(v6addr) => {
-                if v6addr.ip().is_loopback() {
-                    TcpStream::connect(SocketAddr::V6(v6addr)).await
-                } else {
-                    self.tcpv6_stream(SocketAddr::V6(v6addr)).await
+                SocketAddr::V6(v6addr) => {
+                    if v6addr.ip().is_loopback() {
+                        TcpStream::connect(SocketAddr::V6(v6addr)).await
+                    } else {
+                        self.tcp_stream_inner(TcpSocket::new_v6()?, SocketAddr::V6(v6addr))
+                            .await
+                    }
                 }
             }
-        }
+        };
+        with_timeout(self.dial.connect_timeout, fut).await
     }
 
-    async fn tcpv4_stream(&self, addr: SocketAddr) -> Result<TcpStream> {
-        let socket = TcpSocket::new_v4()?;
+    async fn tcp_stream_inner(&self, socket: TcpSocket, addr: SocketAddr) -> Result<TcpStream> {
         platform::bind_to_device(socket.as_raw_fd(), self.iface_name.as_str())?;
+        if let Some(src) = self.source_addr(&addr)? {
+            socket.bind(SocketAddr::new(src, 0))?;
+        }
+        if let Some(mark) = self.dial.mark {
+            platform::set_socket_mark(socket.as_raw_fd(), mark)?;
+        }
+        if let Some(interval) = self.dial.tcp_keepalive {
+            SockRef::from(&socket).set_tcp_keepalive(&TcpKeepalive::new().with_time(interval))?;
+        }
+        if self.dial.tcp_fast_open {
+            platform::set_tcp_fast_open(socket.as_raw_fd())?;
+        }
         socket.connect(addr).await
     }
 
-    async fn tcpv6_stream(&self, addr: SocketAddr) -> Result<TcpStream> {
-        let socket = TcpSocket::new_v6()?;
-        platform::bind_to_device(socket.as_raw_fd(), self.iface_name.as_str())?;
-        socket.connect(addr).await
+    fn source_addr(&self, dst: &SocketAddr) -> Result<Option<IpAddr>> {
+        match self.dial.source_addr {
+            Some(src) if src.is_ipv4() != dst.is_ipv4() => Err(io_err(
+                "Source address does not match the destination family",
+            )),
+            src => Ok(src),
+        }
+    }
+
+    fn apply_udp_options(&self, socket: &Socket) -> Result<()> {
+        if let Some(mark) = self.dial.mark {
+            platform::set_socket_mark(socket.as_raw_fd(), mark)?;
+        }
+        Ok(())
     }
 
     pub async fn udpv4_socket(&self) -> Result<UdpSocket> {
-        let IpAddr::V4(local_addr) = get_iface_address(self.iface_name.as_str())? else {
-            return Err(io_err("not ipv4"));
+        let local_addr = match self.dial.source_addr {
+            Some(IpAddr::V4(src)) => src,
+            Some(IpAddr::V6(_)) => return Err(io_err("not ipv4")),
+            None => {
+                let IpAddr::V4(local_addr) = get_iface_address(self.iface_name.as_str())? else {
+                    return Err(io_err("not ipv4"));
+                };
+                local_addr
+            }
         };
         let std_udp_sock = Socket::new(Domain::IPV4, Type::DGRAM, None)?;
         platform::bind_to_device(std_udp_sock.as_raw_fd(), self.iface_name.as_str())?;
+        self.apply_udp_options(&std_udp_sock)?;
         std_udp_sock.bind(&SockAddr::from(SocketAddr::new(local_addr.into(), 0)))?;
         std_udp_sock.set_nonblocking(true)?;
         let socket = UdpSocket::from_std(std_udp_sock.into())?;
@@ -62,14 +131,86 @@ impl Egress {
     }
 
     pub async fn udpv6_socket(&self) -> Result<UdpSocket> {
-        let IpAddr::V6(local_addr) = get_iface_address(self.iface_name.as_str())? else {
-            return Err(io_err("not ipv4"));
+        let local_addr = match self.dial.source_addr {
+            Some(IpAddr::V6(src)) => src,
+            Some(IpAddr::V4(_)) => return Err(io_err("not ipv6")),
+            None => {
+                let IpAddr::V6(local_addr) = get_iface_address(self.iface_name.as_str())? else {
+                    return Err(io_err("not ipv4"));
+                };
+                local_addr
+            }
         };
         let std_udp_sock = Socket::new(Domain::IPV6, Type::DGRAM, None)?;
         platform::bind_to_device(std_udp_sock.as_raw_fd(), self.iface_name.as_str())?;
+        self.apply_udp_options(&std_udp_sock)?;
         std_udp_sock.bind(&SockAddr::from(SocketAddr::new(local_addr.into(), 0)))?;
         std_udp_sock.set_nonblocking(true)?;
         let socket = UdpSocket::from_std(std_udp_sock.into())?;
         Ok(socket)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::net::TcpListener;
+
+    fn egress(dial: DialOptions) -> Egress {
+        Egress::new("lo").with_dial(&dial)
+    }
+
+    #[test]
+    fn test_source_addr_family() {
+        let v4_dst: SocketAddr = "192.0.2.1:80".parse().unwrap();
+        let v6_dst: SocketAddr = "[2001:db8::1]:80".parse().unwrap();
+        let v4_src: IpAddr = "192.0.2.2".parse().unwrap();
+        let v6_src: IpAddr = "2001:db8::2".parse().unwrap();
+
+        let e = egress(DialOptions::default());
+        assert_eq!(e.source_addr(&v4_dst).unwrap(), None);
+        let e = egress(DialOptions {
+            source_addr: Some(v4_src),
+            ..Default::default()
+        });
+        assert_eq!(e.source_addr(&v4_dst).unwrap(), Some(v4_src));
+        assert!(e.source_addr(&v6_dst).is_err());
+        let e = egress(DialOptions {
+            source_addr: Some(v6_src),
+            ..Default::default()
+        });
+        assert_eq!(e.source_addr(&v6_dst).unwrap(), Some(v6_src));
+        assert!(e.source_addr(&v4_dst).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_udp_source_addr_family() {
+        let e = egress(DialOptions {
+            source_addr: Some("::1".parse().unwrap()),
+            ..Default::default()
+        });
+        assert!(e.udpv4_socket().await.is_err());
+        let e = egress(DialOptions {
+            source_addr: Some("127.0.0.1".parse().unwrap()),
+            ..Default::default()
+        });
+        assert!(e.udpv6_socket().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_connect_timeout() {
+        let timeout = Some(Duration::from_millis(50));
+        let err = with_timeout(timeout, std::future::pending::<Result<()>>())
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::TimedOut);
+        assert!(with_timeout(timeout, async { Ok(()) }).await.is_ok());
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let e = egress(DialOptions {
+            connect_timeout: Some(Duration::from_secs(5)),
+            ..Default::default()
+        });
+        assert!(e.tcp_stream(listener.local_addr().unwrap()).await.is_ok());
+    }
+}
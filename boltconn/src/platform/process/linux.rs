@@ -1,4 +1,4 @@
-use crate::platform::process::{NetworkType, ProcessInfo};
+use crate::platform::process::{get_user_name, NetworkType, ProcessInfo, SocketOwner};
 use netlink_packet_core::{constants::*, NetlinkHeader, NetlinkMessage, NetlinkPayload};
 use netlink_packet_sock_diag::{
     constants::*,
@@ -85,8 +85,31 @@ fn read_proc(fd: Result<DirEntry>, name: &str) -> Result<bool> {
     Ok(false)
 }
 
-pub fn get_pid(addr: SocketAddr, net_type: NetworkType) -> Result<libc::pid_t> {
-    let (inode, uid) = get_inode_and_uid(addr, net_type)?;
+/// Find the process owning the socket bound to `addr`, along with the owner of the socket.
+pub fn get_socket_process(
+    addr: SocketAddr,
+    net_type: NetworkType,
+) -> (Option<ProcessInfo>, Option<SocketOwner>) {
+    let Ok((inode, uid)) = get_inode_and_uid(addr, net_type) else {
+        return (None, None);
+    };
+    let process_info = get_pid(inode, uid)
+        .ok()
+        .and_then(|pid| get_process_info(pid, uid));
+    // the owner is still known when the process is not, e.g. it has exited or belongs to
+    // another user without enough privileges to read its fds
+    let owner = process_info
+        .as_ref()
+        .and_then(SocketOwner::of_process)
+        .unwrap_or_else(|| SocketOwner {
+            uid,
+            gid: None,
+            user: get_user_name(uid),
+        });
+    (process_info, Some(owner))
+}
+
+fn get_pid(inode: u32, uid: u32) -> Result<libc::pid_t> {
     let target_name = format!("socket:[{}]", inode);
     for proc in std::fs::read_dir("/proc")?.flatten() {
         if !proc
@@ -122,9 +145,14 @@ pub fn get_pid(addr: SocketAddr, net_type: NetworkType) -> Result<libc::pid_t> {
     Err(io::Error::new(io::ErrorKind::NotFound, "sock_diag read"))
 }
 
-pub fn get_process_info(pid: i32) -> Option<ProcessInfo> {
+// `uid` is the owner of the socket, which sock_diag reports
+fn get_process_info(pid: i32, uid: u32) -> Option<ProcessInfo> {
     let (ppid, path, name, cmdline) = get_process_info_inner(pid)?;
     let p_name = get_process_info_inner(ppid).map(|(_, _, p_name, _)| p_name);
+    let gid = procfs::process::Process::new(pid)
+        .and_then(|p| p.status())
+        .map(|s| s.egid)
+        .ok();
     Some(ProcessInfo {
         pid,
         ppid,
@@ -132,6 +160,9 @@ pub fn get_process_info(pid: i32) -> Option<ProcessInfo> {
         name,
         cmdline,
         parent_name: p_name,
+        uid: Some(uid),
+        gid,
+        user: get_user_name(uid),
     })
 }
 
@@ -1,4 +1,4 @@
-use crate::platform::process::{NetworkType, ProcessInfo};
+use crate::platform::process::{get_user_name, NetworkType, ProcessInfo, SocketOwner};
 use libc::c_int;
 use libproc::libproc::bsd_info::BSDInfo;
 use libproc::libproc::proc_pid::pidinfo;
@@ -37,7 +37,7 @@ fn get_os_basic_len() -> Result<usize> {
     Err(io::Error::from(io::ErrorKind::InvalidData))
 }
 
-pub fn get_pid(addr: SocketAddr, net_type: NetworkType) -> Result<i32> {
+fn get_pid(addr: SocketAddr, net_type: NetworkType) -> Result<i32> {
     // http://newosxbook.com/bonus/vol1ch16.html search for 'net.inet.tcp.pcblist_n'
     /*
     from bsd/netinet/in_pcblist.c:
@@ -118,11 +118,23 @@ pub fn get_pid(addr: SocketAddr, net_type: NetworkType) -> Result<i32> {
     ))
 }
 
+/// Find the process owning the socket bound to `addr`, along with the owner of the socket.
+pub fn get_socket_process(
+    addr: SocketAddr,
+    net_type: NetworkType,
+) -> (Option<ProcessInfo>, Option<SocketOwner>) {
+    let process_info = get_pid(addr, net_type).ok().and_then(get_process_info);
+    let owner = process_info.as_ref().and_then(SocketOwner::of_process);
+    (process_info, owner)
+}
+
 // from dalance/procs
 // maybe the source is https://gist.github.com/nonowarn/770696
-pub fn get_process_info(pid: i32) -> Option<ProcessInfo> {
+fn get_process_info(pid: i32) -> Option<ProcessInfo> {
     let (ppid, path, name, cmdline) = get_process_info_inner(pid)?;
     let p_name = get_process_info_inner(ppid).map(|(_, _, p_name, _)| p_name);
+    let bsd_info = pidinfo::<BSDInfo>(pid, 0).ok();
+    let uid = bsd_info.as_ref().map(|i| i.pbi_uid);
     Some(ProcessInfo {
         pid,
         ppid,
@@ -130,6 +142,9 @@ pub fn get_process_info(pid: i32) -> Option<ProcessInfo> {
         name,
         cmdline,
         parent_name: p_name,
+        uid,
+        gid: bsd_info.as_ref().map(|i| i.pbi_gid),
+        user: uid.and_then(get_user_name),
     })
 }
 
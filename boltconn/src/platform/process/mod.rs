@@ -1,3 +1,6 @@
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+
 #[cfg(target_os = "macos")]
 mod macos;
 
@@ -33,4 +36,72 @@ pub struct ProcessInfo {
     pub name: String,
     pub cmdline: String,
     pub parent_name: Option<String>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub user: Option<String>,
+}
+
+/// Owner of a socket, which may be known even if its process is not.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketOwner {
+    pub uid: u32,
+    pub gid: Option<u32>,
+    pub user: Option<String>,
+}
+
+impl SocketOwner {
+    fn of_process(info: &ProcessInfo) -> Option<Self> {
+        Some(Self {
+            uid: info.uid?,
+            gid: info.gid,
+            user: info.user.clone(),
+        })
+    }
+}
+
+/// Look up the name of `uid` in the user database, caching found names only, since users may be
+/// added later.
+fn get_user_name(uid: u32) -> Option<String> {
+    static USER_NAMES: OnceLock<Mutex<HashMap<u32, String>>> = OnceLock::new();
+    let names = USER_NAMES.get_or_init(Default::default);
+    if let Some(name) = names.lock().unwrap().get(&uid) {
+        return Some(name.clone());
+    }
+    let name = lookup_user_name(uid)?;
+    names.lock().unwrap().insert(uid, name.clone());
+    Some(name)
+}
+
+fn lookup_user_name(uid: u32) -> Option<String> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result = std::ptr::null_mut();
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let ret =
+            unsafe { libc::getpwuid_r(uid, &mut passwd, buf.as_mut_ptr(), buf.len(), &mut result) };
+        if ret == libc::ERANGE && buf.len() < 65536 {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        if ret != 0 || result.is_null() {
+            return None;
+        }
+        let name = unsafe { std::ffi::CStr::from_ptr(passwd.pw_name) };
+        return name.to_str().ok().map(String::from);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_get_user_name() {
+        assert_eq!(get_user_name(0).as_deref(), Some("root"));
+        assert_eq!(get_user_name(0).as_deref(), Some("root"));
+        // unknown users are looked up again
+        let unknown = u32::MAX - 2;
+        assert_eq!(get_user_name(unknown), None);
+        assert_eq!(get_user_name(unknown), None);
+    }
 }
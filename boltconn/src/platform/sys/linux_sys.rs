@@ -111,6 +111,39 @@ pub fn bind_to_device(fd: c_int, dst_iface_name: &str) -> io::Result<()> {
     Ok(())
 }
 
+pub fn set_socket_mark(fd: c_int, mark: u32) -> io::Result<()> {
+    unsafe {
+        if libc::setsockopt(
+            fd,
+            libc::SOL_SOCKET,
+            libc::SO_MARK,
+            &mark as *const u32 as *const libc::c_void,
+            mem::size_of_val(&mark) as socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+pub fn set_tcp_fast_open(fd: c_int) -> io::Result<()> {
+    let enable: c_int = 1;
+    unsafe {
+        if libc::setsockopt(
+            fd,
+            libc::IPPROTO_TCP,
+            libc::TCP_FASTOPEN_CONNECT,
+            &enable as *const c_int as *const libc::c_void,
+            mem::size_of_val(&enable) as socklen_t,
+        ) < 0
+        {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
 pub struct SystemDnsHandle {}
 
 impl SystemDnsHandle {
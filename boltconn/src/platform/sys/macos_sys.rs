@@ -137,6 +137,15 @@ pub fn bind_to_device(fd: c_int, dst_iface_name: &str) -> io::Result<()> {
     Ok(())
 }
 
+pub fn set_socket_mark(_fd: c_int, _mark: u32) -> io::Result<()> {
+    Err(io_err("SO_MARK is not supported on macOS"))
+}
+
+pub fn set_tcp_fast_open(_fd: c_int) -> io::Result<()> {
+    // client-side TFO requires connectx(2), which tokio does not use
+    Err(io_err("TCP Fast Open is not supported on macOS"))
+}
+
 pub fn get_default_v4_route() -> io::Result<(IpAddr, String)> {
     let kv: HashMap<String, String> = get_command_output("route", ["-n", "get", "1.1.1.1"])?
         .split('\n')
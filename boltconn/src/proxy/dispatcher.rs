@@ -1,7 +1,8 @@
 use crate::adapter::{
-    AddrConnector, ChainOutbound, Connector, DirectOutbound, HttpOutbound, Outbound, OutboundType,
-    SSOutbound, Socks5Outbound, SshManager, SshOutboundHandle, StandardUdpAdapter, TcpAdapter,
-    TrojanOutbound, TunUdpAdapter, WireguardHandle, WireguardManager,
+    AddrConnector, ChainOutbound, Connector, DirectOutbound, HttpManager, HttpOutbound,
+    Hysteria2Manager, Hysteria2Outbound, Outbound, OutboundType, SSOutbound, Socks5Outbound,
+    SshManager, SshOutboundHandle, StandardUdpAdapter, TcpAdapter, TrojanOutbound, TuicManager,
+    TuicOutbound, TunUdpAdapter, VlessOutbound, VmessOutbound, WireguardHandle, WireguardManager,
 };
 use crate::common::duplex_chan::DuplexChan;
 use crate::dispatch::{
@@ -9,7 +10,7 @@ use crate::dispatch::{
 };
 use crate::intercept::{HttpIntercept, HttpsIntercept, InterceptionManager, ModifierClosure};
 use crate::network::dns::Dns;
-use crate::platform::process::{NetworkType, ProcessInfo};
+use crate::platform::process::{NetworkType, ProcessInfo, SocketOwner};
 use crate::platform::{get_iface_address, process};
 use crate::proxy::{ConnAbortHandle, ConnContext, ContextManager, NetworkAddr};
 use arc_swap::ArcSwap;
@@ -38,7 +39,10 @@ pub struct Dispatcher {
     modifier: ArcSwap<ModifierClosure>,
     intercept_mgr: ArcSwap<InterceptionManager>,
     wireguard_mgr: Arc<WireguardManager>,
+    http_mgr: Arc<HttpManager>,
     ssh_mgr: Arc<SshManager>,
+    hysteria2_mgr: Arc<Hysteria2Manager>,
+    tuic_mgr: Arc<TuicManager>,
 }
 
 impl Dispatcher {
@@ -53,7 +57,10 @@ impl Dispatcher {
         intercept_mgr: Arc<InterceptionManager>,
     ) -> Self {
         let wg_mgr = WireguardManager::new(iface_name, dns.clone(), Duration::from_secs(180));
+        let http_mgr = HttpManager::new(iface_name, dns.clone(), Duration::from_secs(10));
         let ssh_mgr = SshManager::new(iface_name, dns.clone(), Duration::from_secs(180));
+        let hysteria2_mgr = Hysteria2Manager::new(iface_name, dns.clone(), Duration::from_secs(10));
+        let tuic_mgr = TuicManager::new(iface_name, dns.clone(), Duration::from_secs(10));
         Self {
             iface_name: iface_name.into(),
             dns,
@@ -63,7 +70,10 @@ impl Dispatcher {
             modifier: ArcSwap::new(Arc::new(modifier)),
             intercept_mgr: ArcSwap::new(intercept_mgr),
             wireguard_mgr: Arc::new(wg_mgr),
+            http_mgr: Arc::new(http_mgr),
             ssh_mgr: Arc::new(ssh_mgr),
+            hysteria2_mgr: Arc::new(hysteria2_mgr),
+            tuic_mgr: Arc::new(tuic_mgr),
         }
     }
 
@@ -71,6 +81,10 @@ impl Dispatcher {
         self.dispatching.store(dispatching);
     }
 
+    pub fn get_dispatching(&self) -> Arc<Dispatching> {
+        self.dispatching.load_full()
+    }
+
     pub fn replace_intercept_filter(&self, intercept_mgr: Arc<InterceptionManager>) {
         self.intercept_mgr.store(intercept_mgr);
     }
@@ -107,6 +121,7 @@ impl Dispatcher {
                     dst_addr.clone(),
                     self.dns.clone(),
                     cfg.clone(),
+                    self.http_mgr.clone(),
                 )),
                 OutboundType::Http,
             ),
@@ -137,6 +152,40 @@ impl Dispatcher {
                 )),
                 OutboundType::Trojan,
             ),
+            ProxyImpl::Vmess(cfg) => (
+                Box::new(VmessOutbound::new(
+                    iface_name,
+                    dst_addr.clone(),
+                    self.dns.clone(),
+                    cfg.clone(),
+                )),
+                OutboundType::Vmess,
+            ),
+            ProxyImpl::Vless(cfg) => (
+                Box::new(VlessOutbound::new(
+                    iface_name,
+                    dst_addr.clone(),
+                    self.dns.clone(),
+                    cfg.clone(),
+                )),
+                OutboundType::Vless,
+            ),
+            ProxyImpl::Hysteria2(cfg) => (
+                Box::new(Hysteria2Outbound::new(
+                    dst_addr.clone(),
+                    cfg.clone(),
+                    self.hysteria2_mgr.clone(),
+                )),
+                OutboundType::Hysteria2,
+            ),
+            ProxyImpl::Tuic(cfg) => (
+                Box::new(TuicOutbound::new(
+                    dst_addr.clone(),
+                    cfg.clone(),
+                    self.tuic_mgr.clone(),
+                )),
+                OutboundType::Tuic,
+            ),
             ProxyImpl::Wireguard(cfg) => (
                 Box::new(WireguardHandle::new(
                     src_addr,
@@ -216,8 +265,8 @@ impl Dispatcher {
         indicator: Arc<AtomicU8>,
         stream: TcpStream,
     ) -> Result<(), DispatchError> {
-        let process_info = process::get_pid(src_addr, process::NetworkType::Tcp)
-            .map_or(None, process::get_process_info);
+        let (process_info, socket_owner) =
+            process::get_socket_process(src_addr, process::NetworkType::Tcp);
         let mut conn_info = ConnInfo {
             src: src_addr,
             dst: dst_addr.clone(),
@@ -226,10 +275,16 @@ impl Dispatcher {
             resolved_dst: None,
             connection_type: NetworkType::Tcp,
             process_info: process_info.clone(),
+            socket_owner,
         };
         // match outbound proxy
-        let (proxy_name, proxy_config, iface) =
-            self.dispatching.load().matches(&mut conn_info, true).await;
+        let dispatching = self.dispatching.load_full();
+        let (proxy_name, proxy_config, iface) = dispatching.matches(&mut conn_info, true).await;
+        // used to detect proxies that keep failing;
+        // failures of direct connections are caused by destinations rather than proxies
+        let proxy_record = dispatching
+            .get_proxy(proxy_name.as_str())
+            .filter(|p| !matches!(p.get_impl().as_ref(), ProxyImpl::Direct));
         let iface_name = iface
             .as_ref()
             .map_or(self.iface_name.as_str(), |s| s.as_str());
@@ -378,8 +433,20 @@ impl Dispatcher {
         handles.push((
             outbounding.outbound_type().to_string(),
             tokio::spawn(async move {
-                if let Err(err) = outbounding.spawn_tcp(tun_next, abort_handle2).await {
-                    tracing::error!("[Dispatcher] create failed: {}", err)
+                match outbounding.spawn_tcp(tun_next, abort_handle2).await {
+                    Ok(_) => {
+                        if let Some(p) = proxy_record {
+                            p.report_success()
+                        }
+                    }
+                    Err(err) => {
+                        tracing::error!("[Dispatcher] create failed: {}", err);
+                        if let Some(p) = proxy_record {
+                            if p.report_failure() {
+                                dispatching.update_fallback_groups();
+                            }
+                        }
+                    }
                 }
             }),
         ));
@@ -443,6 +510,7 @@ impl Dispatcher {
         src_addr: SocketAddr,
         dst_addr: NetworkAddr,
         proc_info: Option<ProcessInfo>,
+        socket_owner: Option<SocketOwner>,
     ) -> bool {
         let mut conn_info = ConnInfo {
             src: src_addr,
@@ -452,6 +520,7 @@ impl Dispatcher {
             resolved_dst: None,
             connection_type: NetworkType::Udp,
             process_info: proc_info,
+            socket_owner,
         };
         !matches!(
             self.dispatching
@@ -464,11 +533,13 @@ impl Dispatcher {
         )
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn submit_tun_udp_session(
         &self,
         src_addr: SocketAddr,
         dst_addr: NetworkAddr,
         proc_info: Option<ProcessInfo>,
+        socket_owner: Option<SocketOwner>,
         send_rx: mpsc::Receiver<(Bytes, NetworkAddr)>,
         recv_tx: mpsc::Sender<(Bytes, SocketAddr)>,
         indicator: Arc<AtomicBool>,
@@ -481,6 +552,7 @@ impl Dispatcher {
             resolved_dst: None,
             connection_type: NetworkType::Udp,
             process_info: proc_info,
+            socket_owner,
         };
         let (outbounding, info, abort_handle) =
             match self.route_udp(src_addr, dst_addr, conn_info).await {
@@ -538,8 +610,7 @@ impl Dispatcher {
         indicator: Arc<AtomicBool>,
         socket: UdpSocket,
     ) -> Result<(), DispatchError> {
-        let process_info =
-            process::get_pid(src_addr, NetworkType::Udp).map_or(None, process::get_process_info);
+        let (process_info, socket_owner) = process::get_socket_process(src_addr, NetworkType::Udp);
         let conn_info = ConnInfo {
             src: src_addr,
             dst: dst_addr.clone(),
@@ -551,6 +622,7 @@ impl Dispatcher {
             resolved_dst: None,
             connection_type: NetworkType::Udp,
             process_info: process_info.clone(),
+            socket_owner,
         };
         let (outbounding, info, abort_handle) =
             match self.route_udp(src_addr, dst_addr, conn_info).await {
@@ -32,6 +32,10 @@ pub enum TransportError {
     Dns(#[from] DnsError),
     #[error("ShadowSocks error: {0}")]
     ShadowSocks(&'static str),
+    #[error("Hysteria2 error: {0}")]
+    Hysteria2(&'static str),
+    #[error("QUIC error: {0}")]
+    Quic(#[from] quinn::ConnectionError),
     #[error("HTTP proxy error: {0}")]
     Http(&'static str),
     #[error("Socks5 error: {0}")]
@@ -40,6 +44,12 @@ pub enum TransportError {
     Socks5Extra(&'static str),
     #[error("Trojan error: {0}")]
     Trojan(&'static str),
+    #[error("TUIC error: {0}")]
+    Tuic(&'static str),
+    #[error("VLESS error: {0}")]
+    Vless(&'static str),
+    #[error("VMess error: {0}")]
+    Vmess(&'static str),
     #[error("WireGuard error: {0}")]
     WireGuard(&'static str),
     #[error("SSH error: {0}")]
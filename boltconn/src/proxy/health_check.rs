@@ -0,0 +1,179 @@
+use crate::dispatch::{Dispatching, ProviderHealthCheck};
+use crate::proxy::{group_latency_test, provider_latency_test, Dispatcher};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Test groups with automatic policies and providers with health checks in background,
+/// and update the selections of groups.
+pub struct GroupHealthChecker {
+    dispatcher: Arc<Dispatcher>,
+    speedtest_url: Arc<RwLock<String>>,
+}
+
+impl GroupHealthChecker {
+    pub fn new(dispatcher: Arc<Dispatcher>, speedtest_url: Arc<RwLock<String>>) -> Self {
+        Self {
+            dispatcher,
+            speedtest_url,
+        }
+    }
+
+    pub async fn run(self) {
+        // group name -> next time to test
+        let mut schedule: HashMap<String, Instant> = HashMap::new();
+        // provider name -> next time to test
+        let mut provider_schedule: HashMap<String, Instant> = HashMap::new();
+        let mut last_dispatching: Option<Arc<Dispatching>> = None;
+        loop {
+            let dispatching = self.dispatcher.get_dispatching();
+            if !last_dispatching
+                .as_ref()
+                .is_some_and(|d| Arc::ptr_eq(d, &dispatching))
+            {
+                // groups are rebuilt after reloading, and their latency is unknown now
+                schedule.clear();
+                provider_schedule.clear();
+                last_dispatching = Some(dispatching.clone());
+            }
+            let now = Instant::now();
+            for group in dispatching.get_group_list() {
+                let Some(interval) = group.get_policy().test_interval() else {
+                    continue;
+                };
+                if schedule
+                    .get(&group.get_name())
+                    .is_some_and(|next| *next > now)
+                {
+                    continue;
+                }
+                schedule.insert(group.get_name(), now + interval);
+                let dispatcher = self.dispatcher.clone();
+                let url = self.speedtest_url.read().unwrap().clone();
+                tokio::spawn(async move {
+                    group_latency_test(
+                        dispatcher.as_ref(),
+                        group.as_ref(),
+                        url.as_str(),
+                        PROBE_TIMEOUT,
+                    )
+                    .await;
+                    if group.update_selection() {
+                        tracing::info!(
+                            "Group {} switched to {}",
+                            group.get_name(),
+                            group.get_selection()
+                        );
+                    }
+                });
+            }
+            for check in due_provider_checks(
+                &mut provider_schedule,
+                dispatching.get_provider_checks(),
+                now,
+            ) {
+                let dispatcher = self.dispatcher.clone();
+                let url = check
+                    .get_url()
+                    .unwrap_or_else(|| self.speedtest_url.read().unwrap().clone());
+                let groups = dispatching.get_group_list();
+                tokio::spawn(async move {
+                    provider_latency_test(
+                        dispatcher.as_ref(),
+                        check.as_ref(),
+                        url.as_str(),
+                        PROBE_TIMEOUT,
+                    )
+                    .await;
+                    // automatic groups may prefer other members now
+                    for group in groups {
+                        if group.update_selection() {
+                            tracing::info!(
+                                "Group {} switched to {}",
+                                group.get_name(),
+                                group.get_selection()
+                            );
+                        }
+                    }
+                });
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        }
+    }
+}
+
+/// Providers to test at `now`; lazy ones are only tested if used since their last turn.
+fn due_provider_checks(
+    schedule: &mut HashMap<String, Instant>,
+    checks: Vec<Arc<ProviderHealthCheck>>,
+    now: Instant,
+) -> Vec<Arc<ProviderHealthCheck>> {
+    checks
+        .into_iter()
+        .filter(|check| {
+            if schedule
+                .get(&check.get_name())
+                .is_some_and(|next| *next > now)
+            {
+                return false;
+            }
+            schedule.insert(check.get_name(), now + check.interval());
+            check.should_test()
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::dispatch::{Proxy, ProxyImpl};
+
+    #[test]
+    fn test_provider_schedule() {
+        let proxy = Arc::new(Proxy::new("direct", ProxyImpl::Direct));
+        let interval = Duration::from_secs(60);
+        let eager = Arc::new(ProviderHealthCheck::new(
+            "eager".to_string(),
+            vec![proxy.clone()],
+            None,
+            interval,
+            false,
+        ));
+        let lazy = Arc::new(ProviderHealthCheck::new(
+            "lazy".to_string(),
+            vec![proxy.clone()],
+            None,
+            interval,
+            true,
+        ));
+        let names = |checks: Vec<Arc<ProviderHealthCheck>>| {
+            checks.iter().map(|c| c.get_name()).collect::<Vec<_>>()
+        };
+        let mut schedule = HashMap::new();
+        let checks = vec![eager.clone(), lazy.clone()];
+        let start = Instant::now();
+
+        // the lazy provider has not been used yet
+        assert_eq!(
+            names(due_provider_checks(&mut schedule, checks.clone(), start)),
+            ["eager"]
+        );
+        proxy.report_success();
+        // neither is due before the interval passes
+        let now = start + Duration::from_secs(30);
+        assert!(due_provider_checks(&mut schedule, checks.clone(), now).is_empty());
+        let now = start + interval;
+        assert_eq!(
+            names(due_provider_checks(&mut schedule, checks.clone(), now)),
+            ["eager", "lazy"]
+        );
+        // usage is consumed by the previous turn
+        let now = start + interval * 2;
+        assert_eq!(
+            names(due_provider_checks(&mut schedule, checks, now)),
+            ["eager"]
+        );
+    }
+}
@@ -1,6 +1,7 @@
 mod context;
 mod dispatcher;
 pub mod error;
+mod health_check;
 mod http_inbound;
 mod manager;
 mod mixed_inbound;
@@ -12,11 +13,12 @@ mod tun_udp_inbound;
 use crate::adapter::{Connector, Outbound};
 use crate::common::create_tls_connector;
 use crate::common::duplex_chan::DuplexChan;
-use crate::dispatch::{Latency, Proxy, ProxyImpl};
+use crate::dispatch::{GeneralProxy, Latency, ProviderHealthCheck, Proxy, ProxyGroup, ProxyImpl};
 use crate::proxy::error::RuntimeError;
 use bytes::Bytes;
 pub use context::*;
 pub use dispatcher::*;
+pub use health_check::*;
 use http::Request;
 pub use http_inbound::*;
 use hyper::client::conn;
@@ -25,6 +27,7 @@ pub use manager::*;
 pub use mixed_inbound::*;
 use rand::{Rng, SeedableRng};
 pub use socks5_inbound::*;
+use std::collections::HashSet;
 use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime};
@@ -169,3 +172,51 @@ pub async fn latency_test(
     });
     Ok(timeout_future)
 }
+
+/// Test latency of all members inside the group, and wait for the results.
+pub async fn group_latency_test(
+    dispatcher: &Dispatcher,
+    group: &ProxyGroup,
+    url: &str,
+    timeout: Duration,
+) {
+    let iface = group.get_direct_interface();
+    let mut handles = vec![];
+    let mut tested_proxy = HashSet::new();
+    for p in group.get_members() {
+        let p = match p {
+            GeneralProxy::Single(p) => p.clone(),
+            GeneralProxy::Group(g) => g.get_proxy(),
+        };
+        if !tested_proxy.insert(p.get_name()) {
+            continue;
+        }
+        if let Ok(h) = latency_test(dispatcher, p.clone(), url, timeout, iface.clone()).await {
+            handles.push(h);
+        } else {
+            p.set_latency(Latency::Failed)
+        }
+    }
+    for h in handles {
+        let _ = h.await;
+    }
+}
+
+pub async fn provider_latency_test(
+    dispatcher: &Dispatcher,
+    check: &ProviderHealthCheck,
+    url: &str,
+    timeout: Duration,
+) {
+    let mut handles = vec![];
+    for p in check.get_proxies() {
+        if let Ok(h) = latency_test(dispatcher, p.clone(), url, timeout, None).await {
+            handles.push(h);
+        } else {
+            p.set_latency(Latency::Failed)
+        }
+    }
+    for h in handles {
+        let _ = h.await;
+    }
+}
@@ -1,7 +1,7 @@
 use crate::network::dns::Dns;
 use crate::network::packet::transport_layer::create_raw_udp_pkt;
 use crate::platform::process;
-use crate::platform::process::{NetworkType, ProcessInfo};
+use crate::platform::process::{NetworkType, ProcessInfo, SocketOwner};
 use crate::proxy::dispatcher::DispatchError;
 use crate::proxy::error::TransportError;
 use crate::proxy::{Dispatcher, NetworkAddr, SessionManager};
@@ -17,6 +17,7 @@ use tokio::sync::mpsc;
 struct UdpSession {
     local_addr: SocketAddr,
     proc_info: Option<ProcessInfo>,
+    socket_owner: Option<SocketOwner>,
     // cache of whether we should allow the connection
     remote_permit: HashMap<NetworkAddr, bool>,
     sender: mpsc::Sender<(Bytes, NetworkAddr)>,
@@ -132,7 +133,12 @@ impl TunUdpInbound {
                         // not an encountered dest, query dispatcher
                         let permit = self
                             .dispatcher
-                            .allow_tun_udp(src, dst_addr.clone(), session.proc_info.clone())
+                            .allow_tun_udp(
+                                src,
+                                dst_addr.clone(),
+                                session.proc_info.clone(),
+                                session.socket_owner.clone(),
+                            )
                             .await;
                         session.remote_permit.insert(dst_addr.clone(), permit);
                         if permit {
@@ -145,8 +151,7 @@ impl TunUdpInbound {
             Entry::Vacant(entry) => {
                 let (send_tx, send_rx) = mpsc::channel(20);
                 let (recv_tx, recv_rx) = mpsc::channel(20);
-                let proc_info =
-                    process::get_pid(src, NetworkType::Udp).map_or(None, process::get_process_info);
+                let (proc_info, socket_owner) = process::get_socket_process(src, NetworkType::Udp);
                 let probe = self.session_mgr.get_udp_probe(src);
 
                 // push payload
@@ -156,6 +161,7 @@ impl TunUdpInbound {
                 let session = UdpSession {
                     local_addr: src,
                     proc_info: proc_info.clone(),
+                    socket_owner: socket_owner.clone(),
                     remote_permit: Default::default(),
                     sender: send_tx,
                     probe: probe.clone(),
@@ -171,6 +177,7 @@ impl TunUdpInbound {
                         src,
                         dst_addr,
                         proc_info,
+                        socket_owner,
                         send_rx,
                         recv_tx,
                         probe.clone(),
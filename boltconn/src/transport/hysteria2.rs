@@ -0,0 +1,526 @@
+use crate::common::conn_pool::IsActive;
+use crate::common::quic_stream::QuicStream;
+use crate::network::egress::DialOptions;
+use crate::proxy::error::TransportError;
+use crate::proxy::NetworkAddr;
+use crate::transport::quic_socket::make_quic_tls_config;
+use bytes::{Buf, Bytes};
+use dashmap::DashMap;
+use quinn::{Connection, Endpoint, EndpointConfig, TokioRuntime, TransportConfig};
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+const TCP_REQUEST_ID: u64 = 0x401;
+const AUTH_STATUS_OK: u16 = 233;
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct Hysteria2Config {
+    pub(crate) server_addr: NetworkAddr,
+    pub(crate) password: String,
+    pub(crate) sni: String,
+    pub(crate) skip_cert_verify: bool,
+    pub(crate) udp: bool,
+    pub(crate) dial: DialOptions,
+}
+
+fn write_varint(value: u64, buf: &mut Vec<u8>) {
+    if value < 1 << 6 {
+        buf.push(value as u8);
+    } else if value < 1 << 14 {
+        buf.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes());
+    } else if value < 1 << 30 {
+        buf.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes());
+    } else {
+        buf.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes());
+    }
+}
+
+fn parse_varint(buf: &mut Bytes) -> Option<u64> {
+    let first = *buf.first()?;
+    let len = 1usize << (first >> 6);
+    if buf.len() < len {
+        return None;
+    }
+    let mut value = (first & 0x3f) as u64;
+    for b in &buf[1..len] {
+        value = (value << 8) | *b as u64;
+    }
+    buf.advance(len);
+    Some(value)
+}
+
+async fn read_varint<R: AsyncRead + Unpin>(reader: &mut R) -> io::Result<u64> {
+    let first = reader.read_u8().await?;
+    let len = 1usize << (first >> 6);
+    let mut value = (first & 0x3f) as u64;
+    for _ in 1..len {
+        value = (value << 8) | reader.read_u8().await? as u64;
+    }
+    Ok(value)
+}
+
+fn random_padding() -> Vec<u8> {
+    let len = rand::random::<usize>() % 256 + 64;
+    (0..len).map(|_| rand::random::<u8>()).collect()
+}
+
+fn parse_addr(addr: &str) -> Option<NetworkAddr> {
+    match SocketAddr::from_str(addr) {
+        Ok(s) => Some(NetworkAddr::Raw(s)),
+        Err(_) => NetworkAddr::from_str(addr).ok(),
+    }
+}
+
+/// Datagram of UDP relay, which may be a fragment of a packet
+struct UdpMessage {
+    session_id: u32,
+    packet_id: u16,
+    fragment_id: u8,
+    fragment_count: u8,
+    addr: String,
+    payload: Bytes,
+}
+
+impl UdpMessage {
+    fn parse(mut data: Bytes) -> Option<Self> {
+        if data.len() < 8 {
+            return None;
+        }
+        let session_id = data.get_u32();
+        let packet_id = data.get_u16();
+        let fragment_id = data.get_u8();
+        let fragment_count = data.get_u8();
+        let addr_len = parse_varint(&mut data)? as usize;
+        if data.len() < addr_len {
+            return None;
+        }
+        let addr = String::from_utf8(data.split_to(addr_len).to_vec()).ok()?;
+        Some(Self {
+            session_id,
+            packet_id,
+            fragment_id,
+            fragment_count,
+            addr,
+            payload: data,
+        })
+    }
+
+    fn header_len(addr: &str) -> usize {
+        let mut len = Vec::with_capacity(8);
+        write_varint(addr.len() as u64, &mut len);
+        4 + 2 + 1 + 1 + len.len() + addr.len()
+    }
+
+    fn serialize(&self) -> Bytes {
+        let mut buf = Vec::with_capacity(Self::header_len(&self.addr) + self.payload.len());
+        buf.extend_from_slice(&self.session_id.to_be_bytes());
+        buf.extend_from_slice(&self.packet_id.to_be_bytes());
+        buf.push(self.fragment_id);
+        buf.push(self.fragment_count);
+        write_varint(self.addr.len() as u64, &mut buf);
+        buf.extend_from_slice(self.addr.as_bytes());
+        buf.extend_from_slice(self.payload.as_ref());
+        Bytes::from(buf)
+    }
+}
+
+/// Reassemble fragments of the latest packet in a session.
+#[derive(Default)]
+struct Defragger {
+    packet_id: u16,
+    fragments: Vec<Option<Bytes>>,
+    received: usize,
+}
+
+impl Defragger {
+    fn feed(&mut self, msg: UdpMessage) -> Option<(Bytes, String)> {
+        if msg.fragment_count <= 1 {
+            return Some((msg.payload, msg.addr));
+        }
+        if msg.fragment_id >= msg.fragment_count {
+            return None;
+        }
+        if msg.packet_id != self.packet_id || self.fragments.len() != msg.fragment_count as usize {
+            // drop the incomplete packet
+            self.packet_id = msg.packet_id;
+            self.fragments = vec![None; msg.fragment_count as usize];
+            self.received = 0;
+        }
+        let slot = &mut self.fragments[msg.fragment_id as usize];
+        if slot.is_none() {
+            *slot = Some(msg.payload);
+            self.received += 1;
+        }
+        if self.received < self.fragments.len() {
+            return None;
+        }
+        let data: Vec<u8> = self.fragments.drain(..).flatten().flatten().collect();
+        self.received = 0;
+        Some((Bytes::from(data), msg.addr))
+    }
+}
+
+type SessionTable = DashMap<u32, mpsc::Sender<(Bytes, NetworkAddr)>>;
+
+/// An authenticated Hysteria2 connection, shared by multiple proxied connections.
+pub struct Hysteria2Connection {
+    conn: Connection,
+    udp_enabled: bool,
+    sessions: Arc<SessionTable>,
+    next_session_id: AtomicU32,
+    // dropping it may close the HTTP/3 connection
+    _send_request: std::sync::Mutex<h3::client::SendRequest<h3_quinn::OpenStreams, Bytes>>,
+    _endpoint: Endpoint,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Hysteria2Connection {
+    pub async fn connect(
+        config: &Hysteria2Config,
+        server_addr: SocketAddr,
+        socket: std::net::UdpSocket,
+    ) -> Result<Self, TransportError> {
+        let tls_config = make_quic_tls_config(config.skip_cert_verify, vec![b"h3".to_vec()]);
+        let mut client_config = quinn::ClientConfig::new(Arc::new(tls_config));
+        let mut transport_config = TransportConfig::default();
+        transport_config.keep_alive_interval(Some(Duration::from_secs(10)));
+        client_config.transport_config(Arc::new(transport_config));
+
+        let mut endpoint = Endpoint::new(
+            EndpointConfig::default(),
+            None,
+            socket,
+            Arc::new(TokioRuntime),
+        )?;
+        endpoint.set_default_client_config(client_config);
+        let conn = endpoint
+            .connect(server_addr, config.sni.as_str())
+            .map_err(|_| TransportError::Hysteria2("Invalid server name"))?
+            .await?;
+
+        // authenticate with HTTP/3
+        let (mut driver, mut send_request) =
+            h3::client::new(h3_quinn::Connection::new(conn.clone()))
+                .await
+                .map_err(|_| TransportError::Hysteria2("HTTP/3 handshake failed"))?;
+        let driver_handle = tokio::spawn(async move {
+            let _ = driver.wait_idle().await;
+        });
+        let req = http::Request::post("https://hysteria/auth")
+            .header("Hysteria-Auth", config.password.as_str())
+            .header("Hysteria-CC-RX", "0")
+            .header("Hysteria-Padding", hex_padding())
+            .body(())
+            .map_err(|_| TransportError::Hysteria2("Invalid auth request"))?;
+        let mut stream = send_request
+            .send_request(req)
+            .await
+            .map_err(|_| TransportError::Hysteria2("Send auth request failed"))?;
+        stream
+            .finish()
+            .await
+            .map_err(|_| TransportError::Hysteria2("Send auth request failed"))?;
+        let resp = stream
+            .recv_response()
+            .await
+            .map_err(|_| TransportError::Hysteria2("Receive auth response failed"))?;
+        if resp.status().as_u16() != AUTH_STATUS_OK {
+            driver_handle.abort();
+            return Err(TransportError::Hysteria2("Authentication failed"));
+        }
+        let udp_enabled = resp
+            .headers()
+            .get("Hysteria-UDP")
+            .is_some_and(|v| v.as_bytes().eq_ignore_ascii_case(b"true"));
+
+        let sessions: Arc<SessionTable> = Arc::new(DashMap::new());
+        let datagram_handle =
+            tokio::spawn(Self::dispatch_datagrams(conn.clone(), sessions.clone()));
+        Ok(Self {
+            conn,
+            udp_enabled,
+            sessions,
+            next_session_id: AtomicU32::new(1),
+            _send_request: std::sync::Mutex::new(send_request),
+            _endpoint: endpoint,
+            tasks: vec![driver_handle, datagram_handle],
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.conn.close_reason().is_none()
+    }
+
+    pub fn udp_enabled(&self) -> bool {
+        self.udp_enabled
+    }
+
+    pub async fn open_tcp(&self, dst: &NetworkAddr) -> Result<QuicStream, TransportError> {
+        let (send, recv) = self.conn.open_bi().await?;
+        let mut stream = QuicStream::new(send, recv);
+        let addr = dst.to_string();
+        let padding = random_padding();
+        let mut req = Vec::with_capacity(16 + addr.len() + padding.len());
+        write_varint(TCP_REQUEST_ID, &mut req);
+        write_varint(addr.len() as u64, &mut req);
+        req.extend_from_slice(addr.as_bytes());
+        write_varint(padding.len() as u64, &mut req);
+        req.extend_from_slice(padding.as_slice());
+        stream.write_all(req.as_slice()).await?;
+
+        let status = stream.read_u8().await?;
+        let msg_len = read_varint(&mut stream).await? as usize;
+        let mut msg = vec![0u8; msg_len];
+        stream.read_exact(msg.as_mut_slice()).await?;
+        let padding_len = read_varint(&mut stream).await? as usize;
+        let mut padding = vec![0u8; padding_len];
+        stream.read_exact(padding.as_mut_slice()).await?;
+        if status != 0x00 {
+            tracing::debug!(
+                "Hysteria2 TCP request to {} rejected: {}",
+                dst,
+                String::from_utf8_lossy(msg.as_slice())
+            );
+            return Err(TransportError::Hysteria2("TCP request rejected"));
+        }
+        Ok(stream)
+    }
+
+    pub fn open_udp(self: &Arc<Self>) -> Result<Hysteria2UdpSession, TransportError> {
+        if !self.udp_enabled {
+            return Err(TransportError::Hysteria2("UDP is disabled by server"));
+        }
+        let session_id = self.next_session_id.fetch_add(1, Ordering::Relaxed);
+        let (tx, rx) = mpsc::channel(64);
+        self.sessions.insert(session_id, tx);
+        Ok(Hysteria2UdpSession {
+            conn: self.clone(),
+            session_id,
+            next_packet_id: AtomicU16::new(0),
+            rx: Mutex::new(rx),
+        })
+    }
+
+    async fn dispatch_datagrams(conn: Connection, sessions: Arc<SessionTable>) {
+        let mut defraggers: HashMap<u32, Defragger> = HashMap::new();
+        while let Ok(data) = conn.read_datagram().await {
+            let Some(msg) = UdpMessage::parse(data) else {
+                continue;
+            };
+            let session_id = msg.session_id;
+            let Some(tx) = sessions.get(&session_id).map(|s| s.value().clone()) else {
+                defraggers.remove(&session_id);
+                continue;
+            };
+            let Some((payload, addr)) = defraggers.entry(session_id).or_default().feed(msg) else {
+                continue;
+            };
+            let Some(addr) = parse_addr(addr.as_str()) else {
+                continue;
+            };
+            // drop the packet if the session is busy
+            let _ = tx.try_send((payload, addr));
+        }
+    }
+}
+
+impl IsActive for Hysteria2Connection {
+    fn is_active(&self) -> bool {
+        Hysteria2Connection::is_active(self)
+    }
+}
+
+impl Drop for Hysteria2Connection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.conn.close(0u32.into(), b"");
+    }
+}
+
+fn hex_padding() -> String {
+    use std::fmt::Write;
+    random_padding()
+        .iter()
+        .fold(String::new(), |mut output, x| {
+            let _ = write!(output, "{:02x}", x);
+            output
+        })
+}
+
+/// UDP session relayed by QUIC datagrams.
+pub struct Hysteria2UdpSession {
+    conn: Arc<Hysteria2Connection>,
+    session_id: u32,
+    next_packet_id: AtomicU16,
+    rx: Mutex<mpsc::Receiver<(Bytes, NetworkAddr)>>,
+}
+
+impl Hysteria2UdpSession {
+    pub fn send_to(&self, data: &[u8], addr: &NetworkAddr) -> Result<(), TransportError> {
+        let addr = addr.to_string();
+        let max_size = self
+            .conn
+            .conn
+            .max_datagram_size()
+            .ok_or(TransportError::Hysteria2("Datagram unsupported"))?;
+        let header_len = UdpMessage::header_len(addr.as_str());
+        if max_size <= header_len {
+            return Err(TransportError::Hysteria2("Address too long"));
+        }
+        let fragment_size = max_size - header_len;
+        let fragment_count = data.len().div_ceil(fragment_size).max(1);
+        if fragment_count > u8::MAX as usize {
+            return Err(TransportError::Hysteria2("Packet too large"));
+        }
+        let packet_id = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+        for fragment_id in 0..fragment_count {
+            let start = fragment_id * fragment_size;
+            let end = (start + fragment_size).min(data.len());
+            let msg = UdpMessage {
+                session_id: self.session_id,
+                packet_id,
+                fragment_id: fragment_id as u8,
+                fragment_count: fragment_count as u8,
+                addr: addr.clone(),
+                payload: Bytes::copy_from_slice(&data[start..end]),
+            };
+            self.conn
+                .conn
+                .send_datagram(msg.serialize())
+                .map_err(|_| TransportError::Hysteria2("Send datagram failed"))?;
+        }
+        Ok(())
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        let (data, addr) = self
+            .rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::Hysteria2("Connection closed"))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, addr))
+    }
+}
+
+impl Drop for Hysteria2UdpSession {
+    fn drop(&mut self) {
+        self.conn.sessions.remove(&self.session_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::self_signed_cert;
+
+    async fn mock_server(endpoint: Endpoint) {
+        let conn = endpoint.accept().await.unwrap().await.unwrap();
+        let mut h3_conn: h3::server::Connection<_, Bytes> =
+            h3::server::Connection::new(h3_quinn::Connection::new(conn.clone()))
+                .await
+                .unwrap();
+        let (req, mut auth_stream) = h3_conn.accept().await.unwrap().unwrap();
+        assert_eq!(req.uri().path(), "/auth");
+        assert_eq!(req.headers().get("Hysteria-Auth").unwrap(), "password");
+        let resp = http::Response::builder()
+            .status(AUTH_STATUS_OK)
+            .header("Hysteria-UDP", "true")
+            .body(())
+            .unwrap();
+        auth_stream.send_response(resp).await.unwrap();
+        auth_stream.finish().await.unwrap();
+
+        // TCP request
+        let (send, recv) = conn.accept_bi().await.unwrap();
+        let mut stream = QuicStream::new(send, recv);
+        assert_eq!(read_varint(&mut stream).await.unwrap(), TCP_REQUEST_ID);
+        let mut addr = vec![0u8; read_varint(&mut stream).await.unwrap() as usize];
+        stream.read_exact(addr.as_mut_slice()).await.unwrap();
+        assert_eq!(addr.as_slice(), b"example.com:443");
+        let mut padding = vec![0u8; read_varint(&mut stream).await.unwrap() as usize];
+        stream.read_exact(padding.as_mut_slice()).await.unwrap();
+        stream.write_all(&[0x00, 0x00, 0x00]).await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+
+        // UDP echo
+        let msg = UdpMessage::parse(conn.read_datagram().await.unwrap()).unwrap();
+        assert_eq!(msg.addr, "1.1.1.1:53");
+        conn.send_datagram(msg.serialize()).unwrap();
+        conn.closed().await;
+    }
+
+    #[tokio::test]
+    async fn test_hysteria2_loopback() {
+        let (cert, key) = self_signed_cert();
+        let mut tls_config = quic_rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![quic_rustls::Certificate(cert)],
+                quic_rustls::PrivateKey(key),
+            )
+            .unwrap();
+        tls_config.alpn_protocols = vec![b"h3".to_vec()];
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(tls_config));
+        let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let server_handle = tokio::spawn(mock_server(server));
+
+        let config = Hysteria2Config {
+            server_addr: NetworkAddr::Raw(server_addr),
+            password: "password".to_string(),
+            sni: "localhost".to_string(),
+            skip_cert_verify: true,
+            udp: true,
+            dial: DialOptions::default(),
+        };
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let conn = Arc::new(
+            Hysteria2Connection::connect(&config, server_addr, socket)
+                .await
+                .unwrap(),
+        );
+        assert!(conn.udp_enabled());
+
+        let mut stream = conn
+            .open_tcp(&NetworkAddr::DomainName {
+                domain_name: "example.com".to_string(),
+                port: 443,
+            })
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let session = conn.open_udp().unwrap();
+        let dst = NetworkAddr::Raw("1.1.1.1:53".parse().unwrap());
+        session.send_to(b"ping", &dst).unwrap();
+        let mut buf = [0u8; 64];
+        let (len, addr) = session.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(addr, dst);
+
+        drop(session);
+        drop(stream);
+        drop(conn);
+        server_handle.await.unwrap();
+    }
+}
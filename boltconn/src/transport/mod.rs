@@ -1,12 +1,30 @@
+use crate::common::async_ws_stream::AsyncWsStream;
+use crate::common::{as_io_err, io_err};
 use crate::proxy::error::TransportError;
 use crate::proxy::NetworkAddr;
+use crate::transport::trojan::make_tls_config;
 use async_trait::async_trait;
+use http::{StatusCode, Uri};
+use std::io;
 use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
 use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio_rustls::client::TlsStream;
+use tokio_rustls::rustls::pki_types::ServerName;
+use tokio_rustls::TlsConnector;
+use tokio_tungstenite::client_async;
 
+pub mod hysteria2;
+pub mod quic_socket;
+pub mod shadow_tls;
+pub mod simple_obfs;
 pub mod smol;
+pub mod socks4;
 pub mod ssh;
 pub mod trojan;
+pub mod tuic;
+pub mod vless;
+pub mod vmess;
 pub mod wireguard;
 
 #[async_trait]
@@ -22,6 +40,66 @@ pub enum AdapterOrSocket {
     Socket(tokio::net::UdpSocket),
 }
 
+/// Parse UUID in the form of xxxxxxxx-xxxx-xxxx-xxxx-xxxxxxxxxxxx
+pub fn parse_uuid(uuid: &str) -> Option<[u8; 16]> {
+    let hex: Vec<u8> = uuid.bytes().filter(|c| *c != b'-').collect();
+    if hex.len() != 32 {
+        return None;
+    }
+    let mut res = [0u8; 16];
+    for (idx, pair) in hex.chunks(2).enumerate() {
+        res[idx] = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(res)
+}
+
+/// TLS handshake of outbounds over an established stream, advertising `alpn` if not empty.
+pub(crate) async fn connect_tls<S>(
+    outbound: S,
+    sni: &str,
+    skip_cert_verify: bool,
+    alpn: &[&[u8]],
+) -> io::Result<TlsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let server_name = ServerName::try_from(sni).map_err(as_io_err)?.to_owned();
+    let mut tls_config = make_tls_config(skip_cert_verify);
+    if !alpn.is_empty() {
+        let mut config = (*tls_config).clone();
+        config.alpn_protocols = alpn.iter().map(|p| p.to_vec()).collect();
+        tls_config = Arc::new(config);
+    }
+    TlsConnector::from(tls_config)
+        .connect(server_name, outbound)
+        .await
+}
+
+/// Websocket upgrade of outbounds over an established stream, with `host` as the authority.
+pub(crate) async fn connect_websocket<S>(
+    stream: S,
+    host: &str,
+    path: &str,
+    tls: bool,
+) -> io::Result<AsyncWsStream<S>>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + Sync,
+{
+    let uri = Uri::builder()
+        .scheme(if tls { "wss" } else { "ws" })
+        .authority(host)
+        .path_and_query(path)
+        .build()
+        .map_err(|_| io_err("Invalid websocket uri"))?;
+    let (stream, resp) = client_async(uri, stream)
+        .await
+        .map_err(|_| io_err("Websocket client_sync failed"))?;
+    if resp.status() != StatusCode::SWITCHING_PROTOCOLS {
+        return Err(io_err("Websocket upgrade failed"));
+    }
+    Ok(AsyncWsStream::new(stream))
+}
+
 #[derive(Copy, Clone, Debug)]
 pub enum InterfaceAddress {
     Ipv4(Ipv4Addr),
@@ -0,0 +1,166 @@
+use crate::proxy::NetworkAddr;
+use crate::transport::UdpSocketAdapter;
+use bytes::Bytes;
+use quic_rustls::client::{ServerCertVerified, ServerCertVerifier};
+use quic_rustls::{Certificate, ClientConfig, OwnedTrustAnchor, RootCertStore, ServerName};
+use quinn::udp::{RecvMeta, Transmit, UdpState};
+use quinn::AsyncUdpSocket;
+use std::io;
+use std::io::IoSliceMut;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::SystemTime;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+/// UDP socket for QUIC endpoints, backed by a `UdpSocketAdapter` from the previous hop of a chain.
+/// All packets are sent to and received from the given peer.
+#[derive(Debug)]
+pub struct QuicAdapterSocket {
+    peer: SocketAddr,
+    send_tx: mpsc::UnboundedSender<Bytes>,
+    recv_rx: Mutex<mpsc::Receiver<Bytes>>,
+    tasks: [JoinHandle<()>; 2],
+}
+
+impl QuicAdapterSocket {
+    pub fn new(adapter: Box<dyn UdpSocketAdapter>, peer: SocketAddr) -> Self {
+        let adapter: Arc<dyn UdpSocketAdapter> = Arc::from(adapter);
+        let (send_tx, mut send_rx) = mpsc::unbounded_channel::<Bytes>();
+        let (recv_tx, recv_rx) = mpsc::channel(128);
+        let adapter_clone = adapter.clone();
+        let send_task = tokio::spawn(async move {
+            while let Some(data) = send_rx.recv().await {
+                if adapter_clone
+                    .send_to(data.as_ref(), NetworkAddr::Raw(peer))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        let recv_task = tokio::spawn(async move {
+            let mut buf = vec![0u8; 65535];
+            while let Ok((len, _)) = adapter.recv_from(buf.as_mut_slice()).await {
+                if recv_tx
+                    .send(Bytes::copy_from_slice(&buf[..len]))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+        Self {
+            peer,
+            send_tx,
+            recv_rx: Mutex::new(recv_rx),
+            tasks: [send_task, recv_task],
+        }
+    }
+}
+
+impl AsyncUdpSocket for QuicAdapterSocket {
+    // Sending never blocks since packets are queued to the adapter
+    fn poll_send(
+        &self,
+        _state: &UdpState,
+        _cx: &mut Context,
+        transmits: &[Transmit],
+    ) -> Poll<io::Result<usize>> {
+        for transmit in transmits {
+            let segment_size = transmit.segment_size.unwrap_or(transmit.contents.len());
+            for segment in transmit.contents.chunks(segment_size.max(1)) {
+                self.send_tx
+                    .send(Bytes::copy_from_slice(segment))
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            }
+        }
+        Poll::Ready(Ok(transmits.len()))
+    }
+
+    fn poll_recv(
+        &self,
+        cx: &mut Context,
+        bufs: &mut [IoSliceMut<'_>],
+        meta: &mut [RecvMeta],
+    ) -> Poll<io::Result<usize>> {
+        let mut rx = self.recv_rx.lock().unwrap();
+        match rx.poll_recv(cx) {
+            Poll::Ready(Some(data)) => {
+                let len = data.len().min(bufs[0].len());
+                bufs[0][..len].copy_from_slice(&data[..len]);
+                meta[0] = RecvMeta {
+                    addr: self.peer,
+                    len,
+                    stride: len,
+                    ecn: None,
+                    dst_ip: None,
+                };
+                Poll::Ready(Ok(1))
+            }
+            Poll::Ready(None) => Poll::Ready(Err(io::ErrorKind::ConnectionAborted.into())),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(match self.peer {
+            SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+            SocketAddr::V6(_) => SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0),
+        })
+    }
+}
+
+impl Drop for QuicAdapterSocket {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Counterpart of the verifier in `trojan` for the rustls release used by quinn; the two
+/// releases have incompatible verifier traits.
+struct NoCertVerification;
+
+impl ServerCertVerifier for NoCertVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, quic_rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+/// TLS config for QUIC endpoints. Quinn is built on its own rustls release, so the config
+/// of TCP transports cannot be shared.
+pub(crate) fn make_quic_tls_config(skip_cert_verify: bool, alpn: Vec<Vec<u8>>) -> ClientConfig {
+    let builder = ClientConfig::builder().with_safe_defaults();
+    let mut config = if skip_cert_verify {
+        builder
+            .with_custom_certificate_verifier(Arc::new(NoCertVerification))
+            .with_no_client_auth()
+    } else {
+        let mut root_cert_store = RootCertStore::empty();
+        root_cert_store.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|ta| {
+            OwnedTrustAnchor::from_subject_spki_name_constraints(
+                ta.subject.as_ref(),
+                ta.subject_public_key_info.as_ref(),
+                ta.name_constraints.as_deref(),
+            )
+        }));
+        builder
+            .with_root_certificates(root_cert_store)
+            .with_no_client_auth()
+    };
+    config.alpn_protocols = alpn;
+    config
+}
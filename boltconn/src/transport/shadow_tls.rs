@@ -0,0 +1,769 @@
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+use bytes::{Buf, BytesMut};
+use chacha20poly1305::ChaCha20Poly1305;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha384};
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+type HmacSha1 = Hmac<Sha1>;
+
+const HMAC_LEN: usize = 4;
+const TLS_HEADER_LEN: usize = 5;
+const MAX_FRAME_PAYLOAD: usize = 16384;
+const AEAD_TAG_LEN: usize = 16;
+// handshake type, length, version, random, session id length
+const SESSION_ID_OFFSET: usize = 1 + 3 + 2 + 32 + 1;
+// random of ServerHello that is actually a HelloRetryRequest
+const HELLO_RETRY_RANDOM: [u8; 32] = [
+    0xcf, 0x21, 0xad, 0x74, 0xe5, 0x9a, 0x61, 0x11, 0xbe, 0x1d, 0x8c, 0x02, 0x1e, 0x65, 0xb8, 0x91,
+    0xc2, 0xa2, 0x11, 0x16, 0x7a, 0xbb, 0x8c, 0x5e, 0x07, 0x9e, 0x09, 0xe2, 0xc8, 0xa8, 0x33, 0x9c,
+];
+
+const CONTENT_CHANGE_CIPHER_SPEC: u8 = 0x14;
+const CONTENT_ALERT: u8 = 0x15;
+const CONTENT_HANDSHAKE: u8 = 0x16;
+const CONTENT_APPLICATION_DATA: u8 = 0x17;
+
+const HANDSHAKE_SERVER_HELLO: u8 = 0x02;
+const HANDSHAKE_CERTIFICATE_REQUEST: u8 = 0x0d;
+const HANDSHAKE_FINISHED: u8 = 0x14;
+
+fn new_hmac(password: &str, server_random: &[u8], suffix: &[u8]) -> HmacSha1 {
+    let mut hmac = HmacSha1::new_from_slice(password.as_bytes()).expect("HMAC accepts any key");
+    hmac.update(server_random);
+    hmac.update(suffix);
+    hmac
+}
+
+fn hmac_tag(hmac: &HmacSha1) -> [u8; HMAC_LEN] {
+    let mut tag = [0u8; HMAC_LEN];
+    tag.copy_from_slice(&hmac.clone().finalize().into_bytes()[..HMAC_LEN]);
+    tag
+}
+
+fn push_ext(buf: &mut Vec<u8>, ext_type: u16, data: &[u8]) {
+    buf.extend_from_slice(&ext_type.to_be_bytes());
+    buf.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+/// Build a TLS 1.3 ClientHello handshake message, with the session id signed by the password.
+fn client_hello(sni: &str, password: &str, public_key: &[u8; 32]) -> Vec<u8> {
+    let mut exts = Vec::with_capacity(256);
+    let mut server_name = Vec::with_capacity(sni.len() + 5);
+    server_name.extend_from_slice(&((sni.len() + 3) as u16).to_be_bytes());
+    server_name.push(0x00);
+    server_name.extend_from_slice(&(sni.len() as u16).to_be_bytes());
+    server_name.extend_from_slice(sni.as_bytes());
+    push_ext(&mut exts, 0x0000, server_name.as_slice());
+    push_ext(&mut exts, 0x0017, &[]);
+    push_ext(&mut exts, 0xff01, &[0x00]);
+    push_ext(
+        &mut exts,
+        0x000a,
+        &[0x00, 0x06, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x18],
+    );
+    push_ext(&mut exts, 0x000b, &[0x01, 0x00]);
+    push_ext(&mut exts, 0x0023, &[]);
+    push_ext(&mut exts, 0x0010, b"\x00\x0c\x02h2\x08http/1.1");
+    push_ext(&mut exts, 0x0005, &[0x01, 0x00, 0x00, 0x00, 0x00]);
+    push_ext(
+        &mut exts,
+        0x000d,
+        &[
+            0x00, 0x10, 0x04, 0x03, 0x08, 0x04, 0x04, 0x01, 0x05, 0x03, 0x08, 0x05, 0x05, 0x01,
+            0x08, 0x06, 0x06, 0x01,
+        ],
+    );
+    let mut key_share = vec![0x00, 0x24, 0x00, 0x1d, 0x00, 0x20];
+    key_share.extend_from_slice(public_key);
+    push_ext(&mut exts, 0x0033, key_share.as_slice());
+    push_ext(&mut exts, 0x002d, &[0x01, 0x01]);
+    push_ext(&mut exts, 0x002b, &[0x04, 0x03, 0x04, 0x03, 0x03]);
+
+    let mut body = Vec::with_capacity(128 + exts.len());
+    body.extend_from_slice(&[0x03, 0x03]);
+    body.extend_from_slice(&rand::random::<[u8; 32]>());
+    body.push(32);
+    // placeholder of session id
+    body.extend_from_slice(&[0u8; 32]);
+    body.extend_from_slice(&[
+        0x00, 0x1e, 0x13, 0x01, 0x13, 0x02, 0x13, 0x03, 0xc0, 0x2b, 0xc0, 0x2f, 0xc0, 0x2c, 0xc0,
+        0x30, 0xcc, 0xa9, 0xcc, 0xa8, 0xc0, 0x13, 0xc0, 0x14, 0x00, 0x9c, 0x00, 0x9d, 0x00, 0x2f,
+        0x00, 0x35,
+    ]);
+    body.extend_from_slice(&[0x01, 0x00]);
+    body.extend_from_slice(&(exts.len() as u16).to_be_bytes());
+    body.extend_from_slice(exts.as_slice());
+
+    let mut hello = Vec::with_capacity(4 + body.len());
+    hello.push(0x01);
+    hello.extend_from_slice(&(body.len() as u32).to_be_bytes()[1..]);
+    hello.extend_from_slice(body.as_slice());
+    // session id: 28 random bytes, followed by HMAC of the whole message with these 4 bytes zeroed
+    let session_id = &mut hello[SESSION_ID_OFFSET..SESSION_ID_OFFSET + 32];
+    session_id[..32 - HMAC_LEN].copy_from_slice(&rand::random::<[u8; 32 - HMAC_LEN]>());
+    let mut hmac = HmacSha1::new_from_slice(password.as_bytes()).expect("HMAC accepts any key");
+    hmac.update(hello.as_slice());
+    let tag = hmac_tag(&hmac);
+    hello[SESSION_ID_OFFSET + 32 - HMAC_LEN..SESSION_ID_OFFSET + 32].copy_from_slice(&tag);
+    hello
+}
+
+async fn read_frame<S: AsyncRead + Unpin>(stream: &mut S) -> io::Result<(u8, Vec<u8>)> {
+    let mut header = [0u8; TLS_HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    let mut body = vec![0u8; u16::from_be_bytes([header[3], header[4]]) as usize];
+    stream.read_exact(body.as_mut_slice()).await?;
+    Ok((header[0], body))
+}
+
+fn invalid_data(msg: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn xor_with_key(data: &mut [u8], key: &[u8; 32]) {
+    for (b, k) in data.iter_mut().zip(key.iter().cycle()) {
+        *b ^= k;
+    }
+}
+
+#[derive(Copy, Clone)]
+enum HashAlg {
+    Sha256,
+    Sha384,
+}
+
+impl HashAlg {
+    fn output_len(self) -> usize {
+        match self {
+            HashAlg::Sha256 => 32,
+            HashAlg::Sha384 => 48,
+        }
+    }
+
+    fn hash(self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlg::Sha256 => Sha256::digest(data).to_vec(),
+            HashAlg::Sha384 => Sha384::digest(data).to_vec(),
+        }
+    }
+
+    fn hmac(self, key: &[u8], data: &[u8]) -> Vec<u8> {
+        match self {
+            HashAlg::Sha256 => {
+                let mut hmac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts any key");
+                hmac.update(data);
+                hmac.finalize().into_bytes().to_vec()
+            }
+            HashAlg::Sha384 => {
+                let mut hmac = Hmac::<Sha384>::new_from_slice(key).expect("HMAC accepts any key");
+                hmac.update(data);
+                hmac.finalize().into_bytes().to_vec()
+            }
+        }
+    }
+
+    // HKDF-Expand-Label of RFC 8446; outputs are never longer than a hash, so one block is enough
+    fn expand_label(self, secret: &[u8], label: &[u8], context: &[u8], len: usize) -> Vec<u8> {
+        let mut info = Vec::with_capacity(4 + 6 + label.len() + context.len() + 1);
+        info.extend_from_slice(&(len as u16).to_be_bytes());
+        info.push((6 + label.len()) as u8);
+        info.extend_from_slice(b"tls13 ");
+        info.extend_from_slice(label);
+        info.push(context.len() as u8);
+        info.extend_from_slice(context);
+        info.push(0x01);
+        let mut okm = self.hmac(secret, info.as_slice());
+        okm.truncate(len);
+        okm
+    }
+}
+
+#[derive(Copy, Clone)]
+enum CipherSuite {
+    Aes128GcmSha256,
+    Aes256GcmSha384,
+    Chacha20Poly1305Sha256,
+}
+
+impl CipherSuite {
+    fn from_id(id: u16) -> Option<Self> {
+        Some(match id {
+            0x1301 => Self::Aes128GcmSha256,
+            0x1302 => Self::Aes256GcmSha384,
+            0x1303 => Self::Chacha20Poly1305Sha256,
+            _ => None?,
+        })
+    }
+
+    fn hash(self) -> HashAlg {
+        match self {
+            CipherSuite::Aes256GcmSha384 => HashAlg::Sha384,
+            CipherSuite::Aes128GcmSha256 | CipherSuite::Chacha20Poly1305Sha256 => HashAlg::Sha256,
+        }
+    }
+}
+
+enum RecordCipher {
+    Aes128Gcm(Box<Aes128Gcm>),
+    Aes256Gcm(Box<Aes256Gcm>),
+    Chacha20Poly1305(Box<ChaCha20Poly1305>),
+}
+
+/// Protection of TLS 1.3 records in one direction, only used for the handshake.
+struct RecordProtection {
+    cipher: RecordCipher,
+    iv: [u8; 12],
+    seq: u64,
+}
+
+impl RecordProtection {
+    fn new(suite: CipherSuite, secret: &[u8]) -> Self {
+        use aes_gcm::KeyInit;
+        let hash = suite.hash();
+        let cipher = match suite {
+            CipherSuite::Aes128GcmSha256 => RecordCipher::Aes128Gcm(Box::new(
+                Aes128Gcm::new_from_slice(&hash.expand_label(secret, b"key", &[], 16)).unwrap(),
+            )),
+            CipherSuite::Aes256GcmSha384 => RecordCipher::Aes256Gcm(Box::new(
+                Aes256Gcm::new_from_slice(&hash.expand_label(secret, b"key", &[], 32)).unwrap(),
+            )),
+            CipherSuite::Chacha20Poly1305Sha256 => RecordCipher::Chacha20Poly1305(Box::new(
+                ChaCha20Poly1305::new_from_slice(&hash.expand_label(secret, b"key", &[], 32))
+                    .unwrap(),
+            )),
+        };
+        let mut iv = [0u8; 12];
+        iv.copy_from_slice(&hash.expand_label(secret, b"iv", &[], 12));
+        Self { cipher, iv, seq: 0 }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+        self.seq += 1;
+        nonce
+    }
+
+    /// Encrypt a handshake message into a complete record.
+    fn seal(&mut self, message: &[u8]) -> io::Result<Vec<u8>> {
+        let mut inner = Vec::with_capacity(message.len() + 1);
+        inner.extend_from_slice(message);
+        inner.push(CONTENT_HANDSHAKE);
+        let mut record = vec![CONTENT_APPLICATION_DATA, 0x03, 0x03];
+        record.extend_from_slice(&((inner.len() + AEAD_TAG_LEN) as u16).to_be_bytes());
+        let nonce = self.next_nonce();
+        let payload = Payload {
+            msg: inner.as_slice(),
+            aad: record.as_slice(),
+        };
+        let encrypted = match &self.cipher {
+            RecordCipher::Aes128Gcm(c) => c.encrypt(&nonce.into(), payload),
+            RecordCipher::Aes256Gcm(c) => c.encrypt(&nonce.into(), payload),
+            RecordCipher::Chacha20Poly1305(c) => c.encrypt(&nonce.into(), payload),
+        }
+        .map_err(|_| invalid_data("ShadowTLS encryption failed"))?;
+        record.extend_from_slice(encrypted.as_slice());
+        Ok(record)
+    }
+
+    /// Decrypt the body of a record, returning the content type and the content.
+    fn open(&mut self, body: &[u8]) -> io::Result<(u8, Vec<u8>)> {
+        let mut header = [CONTENT_APPLICATION_DATA, 0x03, 0x03, 0, 0];
+        header[3..].copy_from_slice(&(body.len() as u16).to_be_bytes());
+        let nonce = self.next_nonce();
+        let payload = Payload {
+            msg: body,
+            aad: header.as_slice(),
+        };
+        let mut inner = match &self.cipher {
+            RecordCipher::Aes128Gcm(c) => c.decrypt(&nonce.into(), payload),
+            RecordCipher::Aes256Gcm(c) => c.decrypt(&nonce.into(), payload),
+            RecordCipher::Chacha20Poly1305(c) => c.decrypt(&nonce.into(), payload),
+        }
+        .map_err(|_| invalid_data("ShadowTLS decryption failed"))?;
+        // the content type is followed by zero padding
+        while let Some(content_type) = inner.pop() {
+            if content_type != 0 {
+                return Ok((content_type, inner));
+            }
+        }
+        Err(invalid_data("ShadowTLS record without content type"))
+    }
+}
+
+struct ServerHello {
+    random: [u8; 32],
+    suite: CipherSuite,
+    public_key: [u8; 32],
+}
+
+impl ServerHello {
+    fn parse(msg: &[u8]) -> io::Result<Self> {
+        let malformed = || invalid_data("Malformed ServerHello");
+        if msg.len() < SESSION_ID_OFFSET || msg[0] != HANDSHAKE_SERVER_HELLO {
+            return Err(malformed());
+        }
+        let mut random = [0u8; 32];
+        random.copy_from_slice(&msg[6..38]);
+        if random == HELLO_RETRY_RANDOM {
+            return Err(invalid_data("ShadowTLS does not support HelloRetryRequest"));
+        }
+        let session_id_len = msg[SESSION_ID_OFFSET - 1] as usize;
+        let mut rest = &msg[SESSION_ID_OFFSET..];
+        if rest.len() < session_id_len + 2 + 1 + 2 {
+            return Err(malformed());
+        }
+        rest = &rest[session_id_len..];
+        let suite_id = u16::from_be_bytes([rest[0], rest[1]]);
+        let exts_len = u16::from_be_bytes([rest[3], rest[4]]) as usize;
+        let mut exts = rest.get(5..5 + exts_len).ok_or_else(malformed)?;
+        let mut version = 0x0303;
+        let mut public_key = None;
+        while exts.len() >= 4 {
+            let ext_type = u16::from_be_bytes([exts[0], exts[1]]);
+            let len = u16::from_be_bytes([exts[2], exts[3]]) as usize;
+            let data = exts.get(4..4 + len).ok_or_else(malformed)?;
+            match ext_type {
+                0x002b if len == 2 => version = u16::from_be_bytes([data[0], data[1]]),
+                // x25519 is the only group offered
+                0x0033 if len == 4 + 32 && data[..4] == [0x00, 0x1d, 0x00, 0x20] => {
+                    let mut key = [0u8; 32];
+                    key.copy_from_slice(&data[4..]);
+                    public_key = Some(key);
+                }
+                _ => {}
+            }
+            exts = &exts[4 + len..];
+        }
+        if version != 0x0304 {
+            return Err(invalid_data("ShadowTLS v3 requires TLS 1.3"));
+        }
+        Ok(Self {
+            random,
+            suite: CipherSuite::from_id(suite_id).ok_or_else(malformed)?,
+            public_key: public_key.ok_or_else(malformed)?,
+        })
+    }
+}
+
+/// Client side of ShadowTLS v3, which borrows the TLS handshake of a trusted server.
+/// The TLS 1.3 handshake is completed with the trusted server through the ShadowTLS server,
+/// then data frames are signed by HMAC instead of being encrypted by TLS.
+pub(crate) struct ShadowTlsStream<S> {
+    inner: S,
+    write_hmac: HmacSha1,
+    read_hmac: HmacSha1,
+    // frames of the remaining handshake are signed by this one
+    handshake_hmac: Option<HmacSha1>,
+    pending: Vec<u8>,
+    written: usize,
+    read_buf: BytesMut,
+    plaintext: BytesMut,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ShadowTlsStream<S> {
+    pub async fn connect(mut inner: S, sni: &str, password: &str) -> io::Result<Self> {
+        let secret = EphemeralSecret::random_from_rng(rand::rngs::OsRng);
+        let hello = client_hello(sni, password, PublicKey::from(&secret).as_bytes());
+        let mut frame = Vec::with_capacity(TLS_HEADER_LEN + hello.len());
+        frame.extend_from_slice(&[CONTENT_HANDSHAKE, 0x03, 0x01]);
+        frame.extend_from_slice(&(hello.len() as u16).to_be_bytes());
+        frame.extend_from_slice(hello.as_slice());
+        inner.write_all(frame.as_slice()).await?;
+        inner.flush().await?;
+
+        let server_hello = match read_frame(&mut inner).await? {
+            (CONTENT_HANDSHAKE, body) => body,
+            (CONTENT_ALERT, _) => return Err(invalid_data("ShadowTLS handshake alerted")),
+            _ => return Err(invalid_data("Unexpected ShadowTLS frame")),
+        };
+        let parsed = ServerHello::parse(server_hello.as_slice())?;
+        let mut transcript = hello;
+        transcript.extend_from_slice(server_hello.as_slice());
+
+        // handshake secrets of RFC 8446
+        let hash = parsed.suite.hash();
+        let zeros = vec![0u8; hash.output_len()];
+        let early_secret = hash.hmac(&zeros, &zeros);
+        let derived = hash.expand_label(
+            &early_secret,
+            b"derived",
+            &hash.hash(&[]),
+            hash.output_len(),
+        );
+        let shared = secret.diffie_hellman(&PublicKey::from(parsed.public_key));
+        let handshake_secret = hash.hmac(&derived, shared.as_bytes());
+        let hello_hash = hash.hash(transcript.as_slice());
+        let client_secret = hash.expand_label(
+            &handshake_secret,
+            b"c hs traffic",
+            &hello_hash,
+            hash.output_len(),
+        );
+        let server_secret = hash.expand_label(
+            &handshake_secret,
+            b"s hs traffic",
+            &hello_hash,
+            hash.output_len(),
+        );
+        let mut server_protection = RecordProtection::new(parsed.suite, &server_secret);
+        let mut client_protection = RecordProtection::new(parsed.suite, &client_secret);
+
+        // Encrypted handshake from the trusted server is XORed and signed by the ShadowTLS server.
+        // Without any signed frame, the handshake is not relayed by a ShadowTLS server.
+        let xor_key: [u8; 32] = Sha256::new()
+            .chain_update(password.as_bytes())
+            .chain_update(parsed.random)
+            .finalize()
+            .into();
+        let mut handshake_hmac = new_hmac(password, &parsed.random, b"");
+        let mut authorized = false;
+        let mut messages = Vec::new();
+        'handshake: loop {
+            let (content_type, mut body) = read_frame(&mut inner).await?;
+            match content_type {
+                CONTENT_APPLICATION_DATA => {}
+                CONTENT_CHANGE_CIPHER_SPEC => continue,
+                CONTENT_ALERT => return Err(invalid_data("ShadowTLS handshake alerted")),
+                _ => return Err(invalid_data("Unexpected ShadowTLS frame")),
+            }
+            if body.len() > HMAC_LEN {
+                let mut hmac = handshake_hmac.clone();
+                hmac.update(&body[HMAC_LEN..]);
+                if hmac_tag(&hmac) == body[..HMAC_LEN] {
+                    handshake_hmac = hmac;
+                    authorized = true;
+                    body.drain(..HMAC_LEN);
+                    xor_with_key(body.as_mut_slice(), &xor_key);
+                }
+            }
+            let (content_type, content) = server_protection.open(body.as_slice())?;
+            if content_type != CONTENT_HANDSHAKE {
+                return Err(invalid_data("Unexpected ShadowTLS handshake record"));
+            }
+            messages.extend_from_slice(content.as_slice());
+            while messages.len() >= 4 {
+                let len = u32::from_be_bytes([0, messages[1], messages[2], messages[3]]) as usize;
+                if messages.len() < 4 + len {
+                    break;
+                }
+                let message: Vec<u8> = messages.drain(..4 + len).collect();
+                match message[0] {
+                    HANDSHAKE_FINISHED => {
+                        let finished_key =
+                            hash.expand_label(&server_secret, b"finished", &[], hash.output_len());
+                        let expected = hash.hmac(&finished_key, &hash.hash(transcript.as_slice()));
+                        if expected != message[4..] {
+                            return Err(invalid_data("ShadowTLS handshake verification failed"));
+                        }
+                        transcript.extend_from_slice(message.as_slice());
+                        break 'handshake;
+                    }
+                    HANDSHAKE_CERTIFICATE_REQUEST => {
+                        return Err(invalid_data("ShadowTLS client certificate is unsupported"));
+                    }
+                    _ => transcript.extend_from_slice(message.as_slice()),
+                }
+            }
+        }
+        if !authorized {
+            return Err(invalid_data("ShadowTLS server not authenticated"));
+        }
+
+        // ChangeCipherSpec for middlebox compatibility, then Finished
+        let finished_key = hash.expand_label(&client_secret, b"finished", &[], hash.output_len());
+        let verify_data = hash.hmac(&finished_key, &hash.hash(transcript.as_slice()));
+        let mut finished = vec![HANDSHAKE_FINISHED];
+        finished.extend_from_slice(&(verify_data.len() as u32).to_be_bytes()[1..]);
+        finished.extend_from_slice(verify_data.as_slice());
+        let mut frames = vec![CONTENT_CHANGE_CIPHER_SPEC, 0x03, 0x03, 0x00, 0x01, 0x01];
+        frames.extend_from_slice(client_protection.seal(finished.as_slice())?.as_slice());
+        inner.write_all(frames.as_slice()).await?;
+        inner.flush().await?;
+
+        Ok(Self {
+            inner,
+            write_hmac: new_hmac(password, &parsed.random, b"C"),
+            read_hmac: new_hmac(password, &parsed.random, b"S"),
+            handshake_hmac: Some(handshake_hmac),
+            pending: Vec::new(),
+            written: 0,
+            read_buf: BytesMut::new(),
+            plaintext: BytesMut::new(),
+        })
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    // Process a complete frame in read_buf, if any.
+    fn process_frame(&mut self) -> io::Result<bool> {
+        if self.read_buf.len() < TLS_HEADER_LEN {
+            return Ok(false);
+        }
+        let len = u16::from_be_bytes([self.read_buf[3], self.read_buf[4]]) as usize;
+        if self.read_buf.len() < TLS_HEADER_LEN + len {
+            return Ok(false);
+        }
+        let content_type = self.read_buf[0];
+        self.read_buf.advance(TLS_HEADER_LEN);
+        let mut body = self.read_buf.split_to(len);
+        match content_type {
+            CONTENT_APPLICATION_DATA => {
+                if body.len() < HMAC_LEN {
+                    return Err(invalid_data("ShadowTLS frame too short"));
+                }
+                if let Some(mut hmac) = self.handshake_hmac.take() {
+                    hmac.update(&body[HMAC_LEN..]);
+                    if hmac_tag(&hmac) == body[..HMAC_LEN] {
+                        // remaining handshake from the trusted server
+                        self.handshake_hmac = Some(hmac);
+                        return Ok(true);
+                    }
+                }
+                self.read_hmac.update(&body[HMAC_LEN..]);
+                let tag = hmac_tag(&self.read_hmac);
+                self.read_hmac.update(&tag);
+                if tag != body[..HMAC_LEN] {
+                    return Err(invalid_data("ShadowTLS HMAC mismatch"));
+                }
+                body.advance(HMAC_LEN);
+                self.plaintext = body;
+                Ok(true)
+            }
+            CONTENT_CHANGE_CIPHER_SPEC | CONTENT_HANDSHAKE if self.handshake_hmac.is_some() => {
+                Ok(true)
+            }
+            CONTENT_ALERT => Err(io::ErrorKind::ConnectionAborted.into()),
+            _ => Err(invalid_data("Unexpected ShadowTLS frame")),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for ShadowTlsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        loop {
+            if !this.plaintext.is_empty() {
+                let len = this.plaintext.len().min(buf.remaining());
+                buf.put_slice(&this.plaintext.split_to(len));
+                return Poll::Ready(Ok(()));
+            }
+            if this.process_frame()? {
+                continue;
+            }
+            let mut chunk = [0u8; 4096];
+            let mut read_buf = ReadBuf::new(&mut chunk);
+            ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+            if read_buf.filled().is_empty() {
+                return Poll::Ready(Ok(()));
+            }
+            this.read_buf.extend_from_slice(read_buf.filled());
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for ShadowTlsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        let len = buf.len().min(MAX_FRAME_PAYLOAD);
+        this.write_hmac.update(&buf[..len]);
+        let tag = hmac_tag(&this.write_hmac);
+        this.write_hmac.update(&tag);
+        this.pending
+            .extend_from_slice(&[CONTENT_APPLICATION_DATA, 0x03, 0x03]);
+        this.pending
+            .extend_from_slice(&((len + HMAC_LEN) as u16).to_be_bytes());
+        this.pending.extend_from_slice(&tag);
+        this.pending.extend_from_slice(&buf[..len]);
+        // the remaining part will be sent in the following calls
+        if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::self_signed_cert;
+    use std::sync::Arc;
+    use tokio::io::DuplexStream;
+    use tokio::sync::Mutex;
+    use tokio_rustls::rustls::pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer};
+    use tokio_rustls::rustls::ServerConfig;
+    use tokio_rustls::TlsAcceptor;
+
+    const PASSWORD: &str = "password";
+
+    async fn write_frame<S: AsyncWrite + Unpin>(stream: &mut S, content_type: u8, body: &[u8]) {
+        let mut frame = vec![content_type, 0x03, 0x03];
+        frame.extend_from_slice(&(body.len() as u16).to_be_bytes());
+        frame.extend_from_slice(body);
+        stream.write_all(frame.as_slice()).await.unwrap();
+    }
+
+    // A real TLS 1.3 server, whose handshake is borrowed
+    fn spawn_tls_server(stream: DuplexStream) -> tokio::task::JoinHandle<io::Result<()>> {
+        let (cert, key) = self_signed_cert();
+        let config = ServerConfig::builder()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![CertificateDer::from(cert)],
+                PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)),
+            )
+            .unwrap();
+        let acceptor = TlsAcceptor::from(Arc::new(config));
+        tokio::spawn(async move {
+            // succeeds only if the Finished of the client is valid
+            let mut stream = acceptor.accept(stream).await?;
+            let mut buf = vec![];
+            let _ = stream.read_to_end(&mut buf).await;
+            Ok(())
+        })
+    }
+
+    // A ShadowTLS v3 server relaying the handshake and echoing the first data frame
+    async fn mock_server(client: DuplexStream, handshake: DuplexStream) {
+        let (mut client_read, client_write) = tokio::io::split(client);
+        let (mut handshake_read, mut handshake_write) = tokio::io::split(handshake);
+        let (content_type, hello) = read_frame(&mut client_read).await.unwrap();
+        assert_eq!(content_type, CONTENT_HANDSHAKE);
+        let mut unsigned = hello.clone();
+        unsigned[SESSION_ID_OFFSET + 32 - HMAC_LEN..SESSION_ID_OFFSET + 32].fill(0);
+        let mut hmac = HmacSha1::new_from_slice(PASSWORD.as_bytes()).unwrap();
+        hmac.update(unsigned.as_slice());
+        assert_eq!(
+            hmac_tag(&hmac),
+            hello[SESSION_ID_OFFSET + 32 - HMAC_LEN..SESSION_ID_OFFSET + 32]
+        );
+        write_frame(&mut handshake_write, CONTENT_HANDSHAKE, hello.as_slice()).await;
+
+        let (content_type, server_hello) = read_frame(&mut handshake_read).await.unwrap();
+        assert_eq!(content_type, CONTENT_HANDSHAKE);
+        let server_random = server_hello[6..38].to_vec();
+        let client_write = Arc::new(Mutex::new(Some(client_write)));
+        write_frame(
+            client_write.lock().await.as_mut().unwrap(),
+            CONTENT_HANDSHAKE,
+            server_hello.as_slice(),
+        )
+        .await;
+        let relay_write = client_write.clone();
+        let relay_random = server_random.clone();
+        let relay = tokio::spawn(async move {
+            let xor_key: [u8; 32] = Sha256::new()
+                .chain_update(PASSWORD.as_bytes())
+                .chain_update(relay_random.as_slice())
+                .finalize()
+                .into();
+            let mut hmac = new_hmac(PASSWORD, relay_random.as_slice(), b"");
+            while let Ok((content_type, mut body)) = read_frame(&mut handshake_read).await {
+                if content_type == CONTENT_APPLICATION_DATA {
+                    xor_with_key(body.as_mut_slice(), &xor_key);
+                    hmac.update(body.as_slice());
+                    body.splice(0..0, hmac_tag(&hmac));
+                }
+                // stop relaying once data frames arrive
+                let mut guard = relay_write.lock().await;
+                let Some(writer) = guard.as_mut() else {
+                    break;
+                };
+                write_frame(writer, content_type, body.as_slice()).await;
+            }
+        });
+
+        let mut client_hmac = new_hmac(PASSWORD, server_random.as_slice(), b"C");
+        let data = loop {
+            let (content_type, body) = read_frame(&mut client_read).await.unwrap();
+            if content_type == CONTENT_APPLICATION_DATA {
+                let mut hmac = client_hmac.clone();
+                hmac.update(&body[HMAC_LEN..]);
+                if hmac_tag(&hmac) == body[..HMAC_LEN] {
+                    client_hmac = hmac;
+                    break body[HMAC_LEN..].to_vec();
+                }
+            }
+            write_frame(&mut handshake_write, content_type, body.as_slice()).await;
+        };
+        let mut client_write = client_write.lock().await.take().unwrap();
+        let mut server_hmac = new_hmac(PASSWORD, server_random.as_slice(), b"S");
+        server_hmac.update(data.as_slice());
+        let mut body = hmac_tag(&server_hmac).to_vec();
+        body.extend_from_slice(data.as_slice());
+        write_frame(&mut client_write, CONTENT_APPLICATION_DATA, body.as_slice()).await;
+        relay.abort();
+    }
+
+    #[tokio::test]
+    async fn test_shadow_tls_stream() {
+        let (handshake_client, handshake_server) = tokio::io::duplex(65536);
+        let tls_server = spawn_tls_server(handshake_server);
+        let (client, server) = tokio::io::duplex(65536);
+        let server = tokio::spawn(mock_server(server, handshake_client));
+        let mut stream = ShadowTlsStream::connect(client, "localhost", PASSWORD)
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+        server.await.unwrap();
+        drop(stream);
+        tls_server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_shadow_tls_unauthenticated() {
+        // the handshake is not signed without a ShadowTLS server
+        let (client, server) = tokio::io::duplex(65536);
+        let _tls_server = spawn_tls_server(server);
+        assert!(ShadowTlsStream::connect(client, "localhost", PASSWORD)
+            .await
+            .is_err());
+    }
+}
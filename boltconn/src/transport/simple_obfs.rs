@@ -0,0 +1,395 @@
+use base64::Engine;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+const MAX_TLS_PAYLOAD: usize = 16384;
+const MAX_HTTP_HEADER: usize = 8192;
+// server hello(96) + change cipher spec(6) + type and version of the first record(3) + length(2)
+const FIRST_RESPONSE_HEADER: usize = 96 + 6 + 3 + 2;
+const RESPONSE_HEADER: usize = 5;
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum ObfsMode {
+    Http,
+    Tls,
+}
+
+impl ObfsMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "http" => Some(Self::Http),
+            "tls" => Some(Self::Tls),
+            _ => None,
+        }
+    }
+}
+
+fn http_request(host: &str, port: u16, payload: &[u8]) -> Vec<u8> {
+    let key = base64::engine::general_purpose::STANDARD.encode(rand::random::<[u8; 16]>());
+    let host = if port == 80 {
+        host.to_string()
+    } else {
+        format!("{}:{}", host, port)
+    };
+    let mut req = format!(
+        "GET / HTTP/1.1\r\nHost: {}\r\nUser-Agent: curl/7.{}.{}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nContent-Length: {}\r\n\r\n",
+        host,
+        rand::random::<u8>() % 54,
+        rand::random::<u8>() % 2,
+        key,
+        payload.len()
+    )
+    .into_bytes();
+    req.extend_from_slice(payload);
+    req
+}
+
+fn tls_client_hello(host: &str, payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(216 + payload.len() + host.len());
+    // record header
+    buf.extend_from_slice(&[0x16, 0x03, 0x01]);
+    buf.extend_from_slice(&((212 + payload.len() + host.len()) as u16).to_be_bytes());
+    // handshake header
+    buf.extend_from_slice(&[0x01, 0x00]);
+    buf.extend_from_slice(&((208 + payload.len() + host.len()) as u16).to_be_bytes());
+    buf.extend_from_slice(&[0x03, 0x03]);
+    // random with timestamp, session id
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as u32);
+    buf.extend_from_slice(&now.to_be_bytes());
+    buf.extend_from_slice(&rand::random::<[u8; 28]>());
+    buf.push(32);
+    buf.extend_from_slice(&rand::random::<[u8; 32]>());
+    // cipher suites and compression methods
+    buf.extend_from_slice(&[0x00, 0x38]);
+    buf.extend_from_slice(&[
+        0xc0, 0x2c, 0xc0, 0x30, 0x00, 0x9f, 0xcc, 0xa9, 0xcc, 0xa8, 0xcc, 0xaa, 0xc0, 0x2b, 0xc0,
+        0x2f, 0x00, 0x9e, 0xc0, 0x24, 0xc0, 0x28, 0x00, 0x6b, 0xc0, 0x23, 0xc0, 0x27, 0x00, 0x67,
+        0xc0, 0x0a, 0xc0, 0x14, 0x00, 0x39, 0xc0, 0x09, 0xc0, 0x13, 0x00, 0x33, 0x00, 0x9d, 0x00,
+        0x9c, 0x00, 0x3d, 0x00, 0x3c, 0x00, 0x35, 0x00, 0x2f, 0x00, 0xff,
+    ]);
+    buf.extend_from_slice(&[0x01, 0x00]);
+    // extensions
+    buf.extend_from_slice(&((79 + payload.len() + host.len()) as u16).to_be_bytes());
+    // session ticket, carrying the payload
+    buf.extend_from_slice(&[0x00, 0x23]);
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+    // server name
+    buf.extend_from_slice(&[0x00, 0x00]);
+    buf.extend_from_slice(&((host.len() + 5) as u16).to_be_bytes());
+    buf.extend_from_slice(&((host.len() + 3) as u16).to_be_bytes());
+    buf.push(0x00);
+    buf.extend_from_slice(&(host.len() as u16).to_be_bytes());
+    buf.extend_from_slice(host.as_bytes());
+    // ec point formats, supported groups, signature algorithms
+    buf.extend_from_slice(&[0x00, 0x0b, 0x00, 0x04, 0x03, 0x01, 0x00, 0x02]);
+    buf.extend_from_slice(&[
+        0x00, 0x0a, 0x00, 0x0a, 0x00, 0x08, 0x00, 0x1d, 0x00, 0x17, 0x00, 0x19, 0x00, 0x18,
+    ]);
+    buf.extend_from_slice(&[
+        0x00, 0x0d, 0x00, 0x20, 0x00, 0x1e, 0x06, 0x01, 0x06, 0x02, 0x06, 0x03, 0x05, 0x01, 0x05,
+        0x02, 0x05, 0x03, 0x04, 0x01, 0x04, 0x02, 0x04, 0x03, 0x03, 0x01, 0x03, 0x02, 0x03, 0x03,
+        0x02, 0x01, 0x02, 0x02, 0x02, 0x03,
+    ]);
+    // encrypt then mac, extended master secret
+    buf.extend_from_slice(&[0x00, 0x16, 0x00, 0x00, 0x00, 0x17, 0x00, 0x00]);
+    buf
+}
+
+fn tls_app_data(payload: &[u8]) -> Vec<u8> {
+    let mut buf = Vec::with_capacity(5 + payload.len());
+    buf.extend_from_slice(&[0x17, 0x03, 0x03]);
+    buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+enum ReadState {
+    // http: response header; tls: record header
+    Header { buf: Vec<u8>, expected: usize },
+    // http: leftover data after header
+    Leftover { buf: Vec<u8>, offset: usize },
+    // tls: remaining payload of current record
+    Payload(usize),
+    Raw,
+}
+
+/// Client side of simple-obfs, which disguises the stream as HTTP or TLS traffic.
+pub(crate) struct ObfsStream<S> {
+    inner: S,
+    mode: ObfsMode,
+    host: String,
+    port: u16,
+    pending: Vec<u8>,
+    written: usize,
+    request_sent: bool,
+    read_state: ReadState,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> ObfsStream<S> {
+    pub fn new(inner: S, mode: ObfsMode, host: &str, port: u16) -> Self {
+        let read_state = match mode {
+            ObfsMode::Http => ReadState::Header {
+                buf: Vec::new(),
+                expected: MAX_HTTP_HEADER,
+            },
+            ObfsMode::Tls => ReadState::Header {
+                buf: Vec::new(),
+                expected: FIRST_RESPONSE_HEADER,
+            },
+        };
+        Self {
+            inner,
+            mode,
+            host: host.to_string(),
+            port,
+            pending: Vec::new(),
+            written: 0,
+            request_sent: false,
+            read_state,
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_read_http(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            match self.read_state {
+                ReadState::Header {
+                    buf: ref mut header,
+                    ..
+                } => {
+                    let mut chunk = [0u8; 4096];
+                    let mut read_buf = ReadBuf::new(&mut chunk);
+                    ready!(Pin::new(&mut self.inner).poll_read(cx, &mut read_buf))?;
+                    if read_buf.filled().is_empty() {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    header.extend_from_slice(read_buf.filled());
+                    if let Some(idx) = header.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let leftover = header.split_off(idx + 4);
+                        self.read_state = ReadState::Leftover {
+                            buf: leftover,
+                            offset: 0,
+                        };
+                    } else if header.len() > MAX_HTTP_HEADER {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "obfs response header too long",
+                        )));
+                    }
+                }
+                ReadState::Leftover {
+                    buf: ref leftover,
+                    ref mut offset,
+                } => {
+                    if *offset == leftover.len() {
+                        self.read_state = ReadState::Raw;
+                        continue;
+                    }
+                    let len = (leftover.len() - *offset).min(buf.remaining());
+                    buf.put_slice(&leftover[*offset..*offset + len]);
+                    *offset += len;
+                    return Poll::Ready(Ok(()));
+                }
+                _ => return Pin::new(&mut self.inner).poll_read(cx, buf),
+            }
+        }
+    }
+
+    fn poll_read_tls(
+        &mut self,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        loop {
+            match self.read_state {
+                ReadState::Header {
+                    buf: ref mut header,
+                    expected,
+                } => {
+                    let mut chunk = [0u8; FIRST_RESPONSE_HEADER];
+                    let mut read_buf = ReadBuf::new(&mut chunk[..expected - header.len()]);
+                    ready!(Pin::new(&mut self.inner).poll_read(cx, &mut read_buf))?;
+                    if read_buf.filled().is_empty() {
+                        // EOF at record boundary
+                        return Poll::Ready(Ok(()));
+                    }
+                    header.extend_from_slice(read_buf.filled());
+                    if header.len() == expected {
+                        let len = u16::from_be_bytes([header[expected - 2], header[expected - 1]]);
+                        self.read_state = ReadState::Payload(len as usize);
+                    }
+                }
+                ReadState::Payload(0) => {
+                    self.read_state = ReadState::Header {
+                        buf: Vec::with_capacity(RESPONSE_HEADER),
+                        expected: RESPONSE_HEADER,
+                    }
+                }
+                ReadState::Payload(ref mut remaining) => {
+                    let unfilled = buf.initialize_unfilled();
+                    let limit = (*remaining).min(unfilled.len());
+                    let mut read_buf = ReadBuf::new(&mut unfilled[..limit]);
+                    ready!(Pin::new(&mut self.inner).poll_read(cx, &mut read_buf))?;
+                    let n = read_buf.filled().len();
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    *remaining -= n;
+                    buf.advance(n);
+                    return Poll::Ready(Ok(()));
+                }
+                _ => unreachable!(),
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for ObfsStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        match this.mode {
+            ObfsMode::Http => this.poll_read_http(cx, buf),
+            ObfsMode::Tls => this.poll_read_tls(cx, buf),
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for ObfsStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        let len = match this.mode {
+            ObfsMode::Http if !this.request_sent => {
+                this.pending = http_request(this.host.as_str(), this.port, buf);
+                buf.len()
+            }
+            ObfsMode::Http => return Pin::new(&mut this.inner).poll_write(cx, buf),
+            ObfsMode::Tls => {
+                let len = buf.len().min(MAX_TLS_PAYLOAD);
+                this.pending = if this.request_sent {
+                    tls_app_data(&buf[..len])
+                } else {
+                    tls_client_hello(this.host.as_str(), &buf[..len])
+                };
+                len
+            }
+        };
+        this.request_sent = true;
+        // the remaining part will be sent in the following calls
+        if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[tokio::test]
+    async fn test_obfs_http() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut req = vec![0u8; 1024];
+            let n = server.read(&mut req).await.unwrap();
+            let req = String::from_utf8_lossy(&req[..n]).to_string();
+            assert!(req.starts_with("GET / HTTP/1.1\r\nHost: example.com:8388\r\n"));
+            assert!(req.ends_with("\r\n\r\nhello"));
+            server
+                .write_all(b"HTTP/1.1 101 Switching Protocols\r\nConnection: Upgrade\r\n\r\nworld")
+                .await
+                .unwrap();
+            server.write_all(b"!").await.unwrap();
+        });
+        let mut stream = ObfsStream::new(client, ObfsMode::Http, "example.com", 8388);
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buf = [0u8; 6];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world!");
+        server.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_obfs_tls() {
+        let (client, mut server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            // payload is right after session ticket extension header
+            let mut hello = [0u8; 142];
+            server.read_exact(&mut hello).await.unwrap();
+            assert_eq!(&hello[..3], &[0x16, 0x03, 0x01]);
+            assert_eq!(&hello[138..142], &[0x00, 0x23, 0x00, 0x05]);
+            let mut payload = [0u8; 5];
+            server.read_exact(&mut payload).await.unwrap();
+            assert_eq!(&payload, b"hello");
+            let mut rest = vec![0u8; 79 - 4 + "example.com".len()];
+            server.read_exact(rest.as_mut_slice()).await.unwrap();
+
+            let mut resp = vec![0u8; FIRST_RESPONSE_HEADER - 2];
+            resp.extend_from_slice(&5u16.to_be_bytes());
+            resp.extend_from_slice(b"world");
+            resp.extend_from_slice(&tls_app_data(b"!"));
+            server.write_all(resp.as_slice()).await.unwrap();
+            let mut frame = [0u8; 5 + 3];
+            server.read_exact(&mut frame).await.unwrap();
+            assert_eq!(&frame, &[0x17, 0x03, 0x03, 0x00, 0x03, b'f', b'o', b'o']);
+        });
+        let mut stream = ObfsStream::new(client, ObfsMode::Tls, "example.com", 443);
+        stream.write_all(b"hello").await.unwrap();
+        stream.flush().await.unwrap();
+        let mut buf = [0u8; 6];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"world!");
+        stream.write_all(b"foo").await.unwrap();
+        stream.flush().await.unwrap();
+        server.await.unwrap();
+    }
+}
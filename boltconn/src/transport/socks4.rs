@@ -0,0 +1,104 @@
+use crate::common::io_err;
+use crate::proxy::NetworkAddr;
+use std::io;
+use std::net::SocketAddr;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+const SOCKS4_VERSION: u8 = 0x04;
+const SOCKS4_CMD_CONNECT: u8 = 0x01;
+const SOCKS4_REPLY_VERSION: u8 = 0x00;
+const SOCKS4_REQUEST_GRANTED: u8 = 0x5a;
+
+/// Send a SOCKS4 CONNECT request. Domain names are only allowed with SOCKS4a.
+pub(crate) async fn socks4_connect<S>(
+    stream: &mut S,
+    dst: &NetworkAddr,
+    user_id: &str,
+    socks4a: bool,
+) -> io::Result<()>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    let mut req = vec![SOCKS4_VERSION, SOCKS4_CMD_CONNECT];
+    match dst {
+        NetworkAddr::Raw(SocketAddr::V4(addr)) => {
+            req.extend_from_slice(&addr.port().to_be_bytes());
+            req.extend_from_slice(&addr.ip().octets());
+            req.extend_from_slice(user_id.as_bytes());
+            req.push(0);
+        }
+        NetworkAddr::Raw(SocketAddr::V6(_)) => {
+            return Err(io_err("IPv6 is not supported by SOCKS4"));
+        }
+        NetworkAddr::DomainName { domain_name, port } => {
+            if !socks4a {
+                return Err(io_err("Domain name is not supported by SOCKS4"));
+            }
+            req.extend_from_slice(&port.to_be_bytes());
+            // 0.0.0.x indicates that a domain name follows the user id
+            req.extend_from_slice(&[0, 0, 0, 1]);
+            req.extend_from_slice(user_id.as_bytes());
+            req.push(0);
+            req.extend_from_slice(domain_name.as_bytes());
+            req.push(0);
+        }
+    }
+    stream.write_all(req.as_slice()).await?;
+    stream.flush().await?;
+
+    let mut resp = [0u8; 8];
+    stream.read_exact(&mut resp).await?;
+    if resp[0] != SOCKS4_REPLY_VERSION {
+        return Err(io_err("Invalid SOCKS4 reply"));
+    }
+    if resp[1] != SOCKS4_REQUEST_GRANTED {
+        return Err(io_err(
+            format!("SOCKS4 request rejected: {:#x}", resp[1]).as_str(),
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_socks4_connect() {
+        let (mut client, mut server) = tokio::io::duplex(4096);
+        let server = tokio::spawn(async move {
+            let mut buf = [0u8; 9];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(buf, [4, 1, 0, 80, 1, 2, 3, 4, 0]);
+            server
+                .write_all(&[0, 0x5a, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+
+            let mut buf = [0u8; 25];
+            server.read_exact(&mut buf).await.unwrap();
+            assert_eq!(
+                &buf[..13],
+                &[4, 1, 1, 187, 0, 0, 0, 1, b'b', b'o', b'l', b't', 0]
+            );
+            assert_eq!(&buf[13..], b"example.com\0");
+            server
+                .write_all(&[0, 0x5b, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+        });
+        let ip = NetworkAddr::Raw("1.2.3.4:80".parse().unwrap());
+        socks4_connect(&mut client, &ip, "", false).await.unwrap();
+        let domain = NetworkAddr::DomainName {
+            domain_name: "example.com".to_string(),
+            port: 443,
+        };
+        assert!(socks4_connect(&mut client, &domain, "bolt", false)
+            .await
+            .is_err());
+        assert!(socks4_connect(&mut client, &domain, "bolt", true)
+            .await
+            .is_err());
+        server.await.unwrap();
+    }
+}
@@ -1,18 +1,30 @@
+use crate::network::dns::Dns;
+use crate::network::egress::DialOptions;
 use crate::proxy::error::TransportError;
 use crate::proxy::NetworkAddr;
+use crate::transport::UdpSocketAdapter;
 use async_trait::async_trait;
 use russh::client::{connect_stream, Handle, Msg};
+use russh::keys::agent::client::AgentClient;
 use russh::keys::key::{KeyPair, PublicKey};
+use russh::keys::PublicKeyBase64;
 use russh::{ChannelStream, SshId};
+use std::collections::HashMap;
 use std::hash::Hash;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, AtomicU16};
 use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadHalf, WriteHalf};
 
 #[derive(Debug, Clone)]
 pub enum SshAuthentication {
     Password(String),
     PrivateKey(Arc<KeyPair>),
+    // path of the agent socket
+    Agent(PathBuf),
 }
 
 #[derive(Debug, Clone)]
@@ -20,14 +32,50 @@ pub struct SshConfig {
     pub server: NetworkAddr,
     pub user: String,
     pub auth: SshAuthentication,
-    // todo: check host pubkey
     // (algo, pubkey)
     pub host_pubkey: Option<Vec<(String, PublicKey)>>,
+    pub known_hosts: Option<PathBuf>,
+    // badvpn-udpgw server, as seen from the SSH server
+    pub udpgw: Option<NetworkAddr>,
+    pub dial: DialOptions,
 }
 
+impl PartialEq for SshAuthentication {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Self::Password(a), Self::Password(b)) => a == b,
+            (Self::PrivateKey(a), Self::PrivateKey(b)) => {
+                a.public_key_bytes() == b.public_key_bytes()
+            }
+            (Self::Agent(a), Self::Agent(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for SshAuthentication {}
+impl Hash for SshAuthentication {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        std::mem::discriminant(self).hash(state);
+        match self {
+            Self::Password(p) => p.hash(state),
+            Self::PrivateKey(k) => k.public_key_bytes().hash(state),
+            Self::Agent(path) => path.hash(state),
+        }
+    }
+}
+
+// Sessions are shared by equal configurations, so everything deciding how a session is
+// authenticated and used takes part in the comparison.
 impl PartialEq for SshConfig {
     fn eq(&self, other: &Self) -> bool {
-        self.server == other.server && self.user == other.user
+        self.server == other.server
+            && self.user == other.user
+            && self.auth == other.auth
+            && self.host_pubkey == other.host_pubkey
+            && self.known_hosts == other.known_hosts
+            && self.udpgw == other.udpgw
+            && self.dial == other.dial
     }
 }
 
@@ -36,11 +84,23 @@ impl Hash for SshConfig {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         self.server.hash(state);
         self.user.hash(state);
+        self.auth.hash(state);
+        if let Some(keys) = &self.host_pubkey {
+            for (algo, key) in keys {
+                algo.hash(state);
+                key.public_key_bytes().hash(state);
+            }
+        }
+        self.known_hosts.hash(state);
+        self.udpgw.hash(state);
+        self.dial.hash(state);
     }
 }
 
 struct Client {
+    server: NetworkAddr,
     expected_server_key: Option<Vec<PublicKey>>,
+    known_hosts: Option<PathBuf>,
 }
 
 #[async_trait]
@@ -51,16 +111,52 @@ impl russh::client::Handler for Client {
         &mut self,
         server_public_key: &PublicKey,
     ) -> Result<bool, Self::Error> {
+        Ok(self.verify_host_key(server_public_key))
+    }
+}
+
+impl Client {
+    fn verify_host_key(&self, server_public_key: &PublicKey) -> bool {
+        // pinned keys take precedence over known_hosts
         if let Some(ref expected) = self.expected_server_key {
-            for k in expected {
-                if k == server_public_key {
-                    return Ok(true);
-                }
+            if !expected.contains(server_public_key) {
+                tracing::warn!("[SSH] Host key of {} mismatches host-pubkey", self.server);
+                return false;
             }
-            Ok(false)
-        } else {
-            Ok(true)
+            return true;
+        }
+        if let Some(ref path) = self.known_hosts {
+            let host = match &self.server {
+                NetworkAddr::Raw(addr) => addr.ip().to_string(),
+                NetworkAddr::DomainName { domain_name, .. } => domain_name.clone(),
+            };
+            return match russh::keys::check_known_hosts_path(
+                host.as_str(),
+                self.server.port(),
+                server_public_key,
+                path,
+            ) {
+                Ok(true) => true,
+                Ok(false) => {
+                    tracing::warn!(
+                        "[SSH] Rejected {}: host is not listed in {}",
+                        self.server,
+                        path.display()
+                    );
+                    false
+                }
+                Err(e) => {
+                    tracing::warn!(
+                        "[SSH] Host key of {} mismatches known_hosts: {}",
+                        self.server,
+                        e
+                    );
+                    false
+                }
+            };
         }
+        // neither pinned nor known_hosts: any host key is accepted
+        true
     }
 }
 
@@ -117,7 +213,7 @@ impl SshTunnel {
     }
 
     pub fn is_active(&self) -> bool {
-        self.is_active.load(std::sync::atomic::Ordering::Relaxed)
+        self.is_active.load(std::sync::atomic::Ordering::Relaxed) && !self.client.is_closed()
     }
 }
 
@@ -130,21 +226,310 @@ where
     S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
 {
     let ssh_handler = Client {
+        server: config.server.clone(),
         expected_server_key: config
             .host_pubkey
             .as_ref()
             .map(|v| v.iter().map(|(_, k)| k.clone()).collect::<Vec<PublicKey>>()),
+        known_hosts: config.known_hosts.clone(),
     };
     let mut handle = connect_stream(ru_config, outbound, ssh_handler).await?;
     if !(match config.auth {
-        SshAuthentication::Password(ref p) => handle.authenticate_password(&config.user, p).await,
-        SshAuthentication::PrivateKey(ref k) => {
-            handle.authenticate_publickey(&config.user, k.clone()).await
+        SshAuthentication::Password(ref p) => handle
+            .authenticate_password(&config.user, p)
+            .await
+            .map_err(TransportError::Ssh)?,
+        SshAuthentication::PrivateKey(ref k) => handle
+            .authenticate_publickey(&config.user, k.clone())
+            .await
+            .map_err(TransportError::Ssh)?,
+        SshAuthentication::Agent(ref path) => {
+            authenticate_agent(&mut handle, config.user.as_str(), path).await?
         }
-    }
-    .map_err(TransportError::Ssh)?)
-    {
+    }) {
         return Err(TransportError::Ssh(russh::Error::NotAuthenticated));
     }
     Ok(handle)
 }
+
+// Try identities in the agent one by one
+async fn authenticate_agent(
+    handle: &mut Handle<Client>,
+    user: &str,
+    path: &Path,
+) -> Result<bool, TransportError> {
+    let mut agent = AgentClient::connect_uds(path)
+        .await
+        .map_err(|_| TransportError::Ssh(russh::Error::NotAuthenticated))?;
+    let identities = agent
+        .request_identities()
+        .await
+        .map_err(|_| TransportError::Ssh(russh::Error::NotAuthenticated))?;
+    for key in identities {
+        let (returned_agent, result) = handle.authenticate_future(user, key, agent).await;
+        agent = returned_agent;
+        if result.unwrap_or(false) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+const UDPGW_FLAG_REBIND: u8 = 0x02;
+const UDPGW_FLAG_IPV6: u8 = 0x08;
+const UDPGW_IDLE_TIMEOUT: Duration = Duration::from_secs(120);
+
+// udpgw binds one remote address to each connection id
+#[derive(Default)]
+struct ConnIds {
+    by_addr: HashMap<SocketAddr, (u16, Instant)>,
+    by_id: HashMap<u16, SocketAddr>,
+    next: u16,
+}
+
+impl ConnIds {
+    /// Returns the id bound to `addr`, and whether the binding is new.
+    fn get_or_alloc(&mut self, addr: SocketAddr, now: Instant) -> (u16, bool) {
+        if let Some((id, last)) = self.by_addr.get_mut(&addr) {
+            *last = now;
+            return (*id, false);
+        }
+        // take the next id that is free or idle, remembering the least recently used one
+        let mut lru: Option<(u16, Instant)> = None;
+        for _ in 0..=u16::MAX {
+            let id = self.next;
+            self.next = self.next.wrapping_add(1);
+            let Some(old) = self.by_id.get(&id) else {
+                return self.bind(id, addr, now);
+            };
+            let last = self.by_addr[old].1;
+            if now.duration_since(last) >= UDPGW_IDLE_TIMEOUT {
+                return self.bind(id, addr, now);
+            }
+            if lru.map_or(true, |(_, t)| last < t) {
+                lru = Some((id, last));
+            }
+        }
+        let (id, _) = lru.expect("no udpgw connection id");
+        self.bind(id, addr, now)
+    }
+
+    fn bind(&mut self, id: u16, addr: SocketAddr, now: Instant) -> (u16, bool) {
+        if let Some(old) = self.by_id.insert(id, addr) {
+            self.by_addr.remove(&old);
+        }
+        self.by_addr.insert(addr, (id, now));
+        (id, true)
+    }
+
+    fn touch(&mut self, id: u16, now: Instant) {
+        if let Some(addr) = self.by_id.get(&id) {
+            if let Some((_, last)) = self.by_addr.get_mut(addr) {
+                *last = now;
+            }
+        }
+    }
+}
+
+// Encode a client packet, including the length prefix
+fn encode_udpgw_packet(
+    flags: u8,
+    conn_id: u16,
+    addr: SocketAddr,
+    data: &[u8],
+) -> io::Result<Vec<u8>> {
+    let mut buf = Vec::with_capacity(data.len() + 23);
+    // placeholder of length
+    buf.extend_from_slice(&[0, 0]);
+    buf.push(if addr.is_ipv6() {
+        flags | UDPGW_FLAG_IPV6
+    } else {
+        flags
+    });
+    buf.extend_from_slice(&conn_id.to_le_bytes());
+    match addr.ip() {
+        IpAddr::V4(ip) => buf.extend_from_slice(&ip.octets()),
+        IpAddr::V6(ip) => buf.extend_from_slice(&ip.octets()),
+    }
+    buf.extend_from_slice(&addr.port().to_be_bytes());
+    buf.extend_from_slice(data);
+    let len =
+        u16::try_from(buf.len() - 2).map_err(|_| io::Error::from(io::ErrorKind::InvalidInput))?;
+    buf[..2].copy_from_slice(&len.to_le_bytes());
+    Ok(buf)
+}
+
+// Decode a packet without the length prefix
+fn decode_udpgw_packet(buf: &[u8]) -> io::Result<(u16, SocketAddr, &[u8])> {
+    let invalid = || io::Error::from(io::ErrorKind::InvalidData);
+    let flags = *buf.first().ok_or_else(invalid)?;
+    let conn_id = buf.get(1..3).ok_or_else(invalid)?;
+    let conn_id = u16::from_le_bytes([conn_id[0], conn_id[1]]);
+    let (addr, offset) = if flags & UDPGW_FLAG_IPV6 != 0 {
+        let ip: [u8; 16] = buf.get(3..19).ok_or_else(invalid)?.try_into().unwrap();
+        (IpAddr::from(ip), 19)
+    } else {
+        let ip: [u8; 4] = buf.get(3..7).ok_or_else(invalid)?.try_into().unwrap();
+        (IpAddr::from(ip), 7)
+    };
+    let port = buf.get(offset..offset + 2).ok_or_else(invalid)?;
+    let port = u16::from_be_bytes([port[0], port[1]]);
+    Ok((conn_id, SocketAddr::new(addr, port), &buf[offset + 2..]))
+}
+
+/// UDP relay over a direct-tcpip channel, speaking the badvpn-udpgw protocol.
+pub struct UdpgwAdapter {
+    reader: tokio::sync::Mutex<ReadHalf<ChannelStream<Msg>>>,
+    writer: tokio::sync::Mutex<WriteHalf<ChannelStream<Msg>>>,
+    conn_ids: std::sync::Mutex<ConnIds>,
+    dns: Arc<Dns>,
+}
+
+impl UdpgwAdapter {
+    pub fn new(channel: ChannelStream<Msg>, dns: Arc<Dns>) -> Self {
+        let (reader, writer) = tokio::io::split(channel);
+        Self {
+            reader: tokio::sync::Mutex::new(reader),
+            writer: tokio::sync::Mutex::new(writer),
+            conn_ids: Default::default(),
+            dns,
+        }
+    }
+}
+
+#[async_trait]
+impl UdpSocketAdapter for UdpgwAdapter {
+    async fn send_to(&self, data: &[u8], addr: NetworkAddr) -> Result<(), TransportError> {
+        let addr = match addr {
+            NetworkAddr::Raw(addr) => addr,
+            NetworkAddr::DomainName { domain_name, port } => SocketAddr::new(
+                self.dns
+                    .genuine_lookup(domain_name.as_str())
+                    .await
+                    .ok_or_else(|| io::Error::from(io::ErrorKind::AddrNotAvailable))?,
+                port,
+            ),
+        };
+        let (conn_id, is_new) = self
+            .conn_ids
+            .lock()
+            .unwrap()
+            .get_or_alloc(addr, Instant::now());
+        // a recycled id may still be bound to another address on the server
+        let flags = if is_new { UDPGW_FLAG_REBIND } else { 0 };
+        let buf = encode_udpgw_packet(flags, conn_id, addr, data)?;
+        let mut writer = self.writer.lock().await;
+        writer.write_all(buf.as_slice()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    async fn recv_from(&self, data: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        let mut reader = self.reader.lock().await;
+        let len = reader.read_u16_le().await? as usize;
+        let mut buf = vec![0u8; len];
+        reader.read_exact(buf.as_mut_slice()).await?;
+        drop(reader);
+        let (conn_id, addr, payload) = decode_udpgw_packet(buf.as_slice())?;
+        self.conn_ids.lock().unwrap().touch(conn_id, Instant::now());
+        let n = payload.len().min(data.len());
+        data[..n].copy_from_slice(&payload[..n]);
+        Ok((n, NetworkAddr::Raw(addr)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_udpgw_framing() {
+        for addr in ["1.2.3.4:53", "[2001:db8::1]:443"] {
+            let addr: SocketAddr = addr.parse().unwrap();
+            let buf = encode_udpgw_packet(UDPGW_FLAG_REBIND, 258, addr, b"hello").unwrap();
+            let len = u16::from_le_bytes([buf[0], buf[1]]) as usize;
+            assert_eq!(len, buf.len() - 2);
+            let (conn_id, decoded, payload) = decode_udpgw_packet(&buf[2..]).unwrap();
+            assert_eq!(conn_id, 258);
+            assert_eq!(decoded, addr);
+            assert_eq!(payload, b"hello");
+        }
+        assert!(decode_udpgw_packet(&[0, 1, 0, 1, 2]).is_err());
+        assert!(decode_udpgw_packet(&[UDPGW_FLAG_IPV6, 1, 0, 1, 2, 3, 4, 0, 53]).is_err());
+    }
+
+    #[test]
+    fn test_udpgw_conn_ids() {
+        let addr = |i: u32| SocketAddr::new(IpAddr::from(i.to_be_bytes()), 53);
+        let now = Instant::now();
+        let mut ids = ConnIds::default();
+        assert_eq!(ids.get_or_alloc(addr(0), now), (0, true));
+        assert_eq!(ids.get_or_alloc(addr(1), now), (1, true));
+        assert_eq!(ids.get_or_alloc(addr(0), now), (0, false));
+        for i in 2..=u16::MAX as u32 {
+            assert_eq!(ids.get_or_alloc(addr(i), now), (i as u16, true));
+        }
+        // idle bindings give their ids back
+        let later = now + UDPGW_IDLE_TIMEOUT;
+        ids.touch(0, later - Duration::from_secs(1));
+        assert_eq!(ids.get_or_alloc(addr(70000), later), (1, true));
+        assert_eq!(ids.get_or_alloc(addr(0), later), (0, false));
+        assert!(!ids.by_addr.contains_key(&addr(1)));
+
+        // with every id busy, the least recently used binding is recycled
+        let mut ids = ConnIds::default();
+        for i in 0..=u16::MAX as u32 {
+            let t = now + Duration::from_millis(1 + (i as u64 + 500) % 1000);
+            ids.get_or_alloc(addr(i), t);
+        }
+        assert_eq!(
+            ids.get_or_alloc(addr(70000), now + Duration::from_secs(2)),
+            (500, true)
+        );
+        assert!(!ids.by_addr.contains_key(&addr(500)));
+        assert_eq!(ids.by_addr.len(), u16::MAX as usize + 1);
+    }
+
+    fn client(
+        server: &str,
+        pinned: Option<Vec<PublicKey>>,
+        known_hosts: Option<PathBuf>,
+    ) -> Client {
+        Client {
+            server: NetworkAddr::Raw(server.parse().unwrap()),
+            expected_server_key: pinned,
+            known_hosts,
+        }
+    }
+
+    #[test]
+    fn test_host_key_policy() {
+        let key = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+        let other = KeyPair::generate_ed25519()
+            .unwrap()
+            .clone_public_key()
+            .unwrap();
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("known_hosts");
+        russh::keys::learn_known_hosts_path("10.0.0.1", 22, &key, &path).unwrap();
+
+        // no policy
+        assert!(client("10.0.0.1:22", None, None).verify_host_key(&other));
+        // pinned keys
+        assert!(client("10.0.0.1:22", Some(vec![key.clone()]), None).verify_host_key(&key));
+        assert!(!client("10.0.0.1:22", Some(vec![key.clone()]), None).verify_host_key(&other));
+        // known_hosts
+        let kh = Some(path.clone());
+        assert!(client("10.0.0.1:22", None, kh.clone()).verify_host_key(&key));
+        assert!(!client("10.0.0.1:22", None, kh.clone()).verify_host_key(&other));
+        assert!(!client("10.0.0.2:22", None, kh.clone()).verify_host_key(&key));
+        // a pinned mismatch wins over a known_hosts match
+        assert!(
+            !client("10.0.0.1:22", Some(vec![other.clone()]), kh.clone()).verify_host_key(&key)
+        );
+        assert!(client("10.0.0.2:22", Some(vec![key.clone()]), kh).verify_host_key(&key));
+    }
+}
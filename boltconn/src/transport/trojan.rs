@@ -1,3 +1,4 @@
+use crate::network::egress::DialOptions;
 use crate::proxy::error::TransportError;
 use crate::proxy::NetworkAddr;
 use bytes::Bytes;
@@ -22,6 +23,8 @@ pub struct TrojanConfig {
     pub(crate) skip_cert_verify: bool,
     pub(crate) websocket_path: Option<String>,
     pub(crate) udp: bool,
+    pub(crate) udp_over_tcp: bool,
+    pub(crate) dial: DialOptions,
 }
 
 #[derive(Copy, Clone, Debug)]
@@ -0,0 +1,621 @@
+use crate::common::conn_pool::IsActive;
+use crate::common::quic_stream::QuicStream;
+use crate::network::egress::DialOptions;
+use crate::proxy::error::TransportError;
+use crate::proxy::NetworkAddr;
+use crate::transport::quic_socket::make_quic_tls_config;
+use bytes::{Buf, Bytes};
+use dashmap::DashMap;
+use quinn::{Connection, Endpoint, RecvStream, TransportConfig};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::sync::atomic::{AtomicU16, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+
+const VERSION: u8 = 0x05;
+const CMD_AUTHENTICATE: u8 = 0x00;
+const CMD_CONNECT: u8 = 0x01;
+const CMD_PACKET: u8 = 0x02;
+const CMD_DISSOCIATE: u8 = 0x03;
+const CMD_HEARTBEAT: u8 = 0x04;
+// version, type, assoc id, packet id, fragment total, fragment id, size
+const PACKET_HEADER_LEN: usize = 2 + 2 + 2 + 1 + 1 + 2;
+
+#[derive(Copy, Clone, Debug, Hash, Eq, PartialEq)]
+pub enum TuicUdpRelayMode {
+    /// Relay packets with QUIC datagrams
+    Native,
+    /// Relay each packet with a QUIC unidirectional stream
+    Quic,
+}
+
+impl TuicUdpRelayMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "native" => Some(Self::Native),
+            "quic" => Some(Self::Quic),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Hash, Eq, PartialEq)]
+pub struct TuicConfig {
+    pub(crate) server_addr: NetworkAddr,
+    pub(crate) uuid: [u8; 16],
+    pub(crate) password: String,
+    pub(crate) sni: String,
+    pub(crate) alpn: Vec<String>,
+    pub(crate) skip_cert_verify: bool,
+    pub(crate) udp_relay_mode: TuicUdpRelayMode,
+    pub(crate) udp: bool,
+    pub(crate) dial: DialOptions,
+}
+
+fn encode_addr(addr: Option<&NetworkAddr>, buf: &mut Vec<u8>) {
+    match addr {
+        None => buf.push(0xff),
+        Some(NetworkAddr::DomainName { domain_name, port }) => {
+            buf.push(0x00);
+            buf.push(domain_name.len() as u8);
+            buf.extend_from_slice(domain_name.as_bytes());
+            buf.extend_from_slice(&port.to_be_bytes());
+        }
+        Some(NetworkAddr::Raw(SocketAddr::V4(addr))) => {
+            buf.push(0x01);
+            buf.extend_from_slice(&addr.ip().octets());
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+        }
+        Some(NetworkAddr::Raw(SocketAddr::V6(addr))) => {
+            buf.push(0x02);
+            buf.extend_from_slice(&addr.ip().octets());
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+        }
+    }
+}
+
+fn parse_addr(buf: &mut Bytes) -> Option<Option<NetworkAddr>> {
+    if buf.is_empty() {
+        return None;
+    }
+    let addr = match buf.get_u8() {
+        0xff => return Some(None),
+        0x00 => {
+            let len = *buf.first()? as usize;
+            if buf.len() < 1 + len + 2 {
+                return None;
+            }
+            buf.advance(1);
+            let domain_name = String::from_utf8(buf.split_to(len).to_vec()).ok()?;
+            NetworkAddr::DomainName {
+                domain_name,
+                port: buf.get_u16(),
+            }
+        }
+        0x01 => {
+            if buf.len() < 4 + 2 {
+                return None;
+            }
+            let ip = Ipv4Addr::from(buf.get_u32());
+            NetworkAddr::Raw(SocketAddr::new(IpAddr::V4(ip), buf.get_u16()))
+        }
+        0x02 => {
+            if buf.len() < 16 + 2 {
+                return None;
+            }
+            let ip = Ipv6Addr::from(buf.get_u128());
+            NetworkAddr::Raw(SocketAddr::new(IpAddr::V6(ip), buf.get_u16()))
+        }
+        _ => return None,
+    };
+    Some(Some(addr))
+}
+
+/// Packet command, which may be a fragment of a UDP packet
+struct TuicPacket {
+    assoc_id: u16,
+    packet_id: u16,
+    fragment_total: u8,
+    fragment_id: u8,
+    addr: Option<NetworkAddr>,
+    payload: Bytes,
+}
+
+impl TuicPacket {
+    fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(PACKET_HEADER_LEN + 1 + 256 + 2 + self.payload.len());
+        buf.push(VERSION);
+        buf.push(CMD_PACKET);
+        buf.extend_from_slice(&self.assoc_id.to_be_bytes());
+        buf.extend_from_slice(&self.packet_id.to_be_bytes());
+        buf.push(self.fragment_total);
+        buf.push(self.fragment_id);
+        buf.extend_from_slice(&(self.payload.len() as u16).to_be_bytes());
+        encode_addr(self.addr.as_ref(), &mut buf);
+        buf.extend_from_slice(self.payload.as_ref());
+        buf
+    }
+
+    // the command header should have been removed
+    fn parse(mut data: Bytes) -> Option<Self> {
+        if data.len() < PACKET_HEADER_LEN - 2 {
+            return None;
+        }
+        let assoc_id = data.get_u16();
+        let packet_id = data.get_u16();
+        let fragment_total = data.get_u8();
+        let fragment_id = data.get_u8();
+        let size = data.get_u16() as usize;
+        let addr = parse_addr(&mut data)?;
+        if data.len() < size {
+            return None;
+        }
+        Some(Self {
+            assoc_id,
+            packet_id,
+            fragment_total,
+            fragment_id,
+            addr,
+            payload: data.split_to(size),
+        })
+    }
+}
+
+/// Reassemble fragments of the latest packet in an association.
+#[derive(Default)]
+struct Defragger {
+    packet_id: u16,
+    addr: Option<NetworkAddr>,
+    fragments: Vec<Option<Bytes>>,
+    received: usize,
+}
+
+impl Defragger {
+    fn feed(&mut self, packet: TuicPacket) -> Option<(Bytes, NetworkAddr)> {
+        if packet.fragment_total <= 1 {
+            return Some((packet.payload, packet.addr?));
+        }
+        if packet.fragment_id >= packet.fragment_total {
+            return None;
+        }
+        if packet.packet_id != self.packet_id
+            || self.fragments.len() != packet.fragment_total as usize
+        {
+            // drop the incomplete packet
+            self.packet_id = packet.packet_id;
+            self.addr = None;
+            self.fragments = vec![None; packet.fragment_total as usize];
+            self.received = 0;
+        }
+        if packet.addr.is_some() {
+            self.addr = packet.addr;
+        }
+        let slot = &mut self.fragments[packet.fragment_id as usize];
+        if slot.is_none() {
+            *slot = Some(packet.payload);
+            self.received += 1;
+        }
+        if self.received < self.fragments.len() {
+            return None;
+        }
+        let data: Vec<u8> = self.fragments.drain(..).flatten().flatten().collect();
+        self.received = 0;
+        Some((Bytes::from(data), self.addr.take()?))
+    }
+}
+
+type AssocTable = DashMap<u16, mpsc::Sender<(Bytes, NetworkAddr)>>;
+
+/// An authenticated TUIC connection, shared by multiple proxied connections.
+pub struct TuicConnection {
+    conn: Connection,
+    relay_mode: TuicUdpRelayMode,
+    sessions: Arc<AssocTable>,
+    next_assoc_id: AtomicU16,
+    _endpoint: Endpoint,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl TuicConnection {
+    pub async fn connect(
+        config: &TuicConfig,
+        mut endpoint: Endpoint,
+        server_addr: SocketAddr,
+    ) -> Result<Self, TransportError> {
+        let alpn = config.alpn.iter().map(|p| p.as_bytes().to_vec()).collect();
+        let tls_config = make_quic_tls_config(config.skip_cert_verify, alpn);
+        let mut client_config = quinn::ClientConfig::new(Arc::new(tls_config));
+        let mut transport_config = TransportConfig::default();
+        transport_config.keep_alive_interval(Some(Duration::from_secs(10)));
+        client_config.transport_config(Arc::new(transport_config));
+        endpoint.set_default_client_config(client_config);
+        let conn = endpoint
+            .connect(server_addr, config.sni.as_str())
+            .map_err(|_| TransportError::Tuic("Invalid server name"))?
+            .await?;
+
+        // authenticate with the keying material exported from TLS session
+        let mut token = [0u8; 32];
+        conn.export_keying_material(&mut token, &config.uuid, config.password.as_bytes())
+            .map_err(|_| TransportError::Tuic("Export keying material failed"))?;
+        let mut auth = Vec::with_capacity(2 + 16 + 32);
+        auth.extend_from_slice(&[VERSION, CMD_AUTHENTICATE]);
+        auth.extend_from_slice(&config.uuid);
+        auth.extend_from_slice(&token);
+        let mut stream = conn.open_uni().await?;
+        stream
+            .write_all(auth.as_slice())
+            .await
+            .map_err(|_| TransportError::Tuic("Send authentication failed"))?;
+        let _ = stream.finish().await;
+
+        let sessions: Arc<AssocTable> = Arc::new(DashMap::new());
+        let tasks = vec![
+            tokio::spawn(Self::heartbeat(conn.clone())),
+            tokio::spawn(Self::dispatch_packets(conn.clone(), sessions.clone())),
+        ];
+        Ok(Self {
+            conn,
+            relay_mode: config.udp_relay_mode,
+            sessions,
+            next_assoc_id: AtomicU16::new(0),
+            _endpoint: endpoint,
+            tasks,
+        })
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.conn.close_reason().is_none()
+    }
+
+    pub async fn open_tcp(&self, dst: &NetworkAddr) -> Result<QuicStream, TransportError> {
+        let (send, recv) = self.conn.open_bi().await?;
+        let mut stream = QuicStream::new(send, recv);
+        let mut header = Vec::with_capacity(2 + 1 + 256 + 2);
+        header.extend_from_slice(&[VERSION, CMD_CONNECT]);
+        encode_addr(Some(dst), &mut header);
+        stream.write_all(header.as_slice()).await?;
+        Ok(stream)
+    }
+
+    pub fn open_udp(self: &Arc<Self>) -> Result<TuicUdpSession, TransportError> {
+        let (tx, rx) = mpsc::channel(64);
+        // every id is tried at most once
+        let mut assoc_id = None;
+        for _ in 0..=u16::MAX {
+            let id = self.next_assoc_id.fetch_add(1, Ordering::Relaxed);
+            if let dashmap::mapref::entry::Entry::Vacant(e) = self.sessions.entry(id) {
+                e.insert(tx);
+                assoc_id = Some(id);
+                break;
+            }
+        }
+        let assoc_id = assoc_id.ok_or(TransportError::Tuic("Too many UDP sessions"))?;
+        Ok(TuicUdpSession {
+            conn: self.clone(),
+            assoc_id,
+            next_packet_id: AtomicU16::new(0),
+            rx: tokio::sync::Mutex::new(rx),
+        })
+    }
+
+    async fn heartbeat(conn: Connection) {
+        let mut interval = tokio::time::interval(Duration::from_secs(10));
+        loop {
+            interval.tick().await;
+            if conn
+                .send_datagram(Bytes::from_static(&[VERSION, CMD_HEARTBEAT]))
+                .is_err()
+            {
+                break;
+            }
+        }
+    }
+
+    async fn dispatch_packets(conn: Connection, sessions: Arc<AssocTable>) {
+        let defraggers: Arc<Mutex<HashMap<u16, Defragger>>> = Default::default();
+        // packets relayed by unidirectional streams
+        let uni_conn = conn.clone();
+        let uni_sessions = sessions.clone();
+        let uni_defraggers = defraggers.clone();
+        let uni_task = tokio::spawn(async move {
+            while let Ok(mut stream) = uni_conn.accept_uni().await {
+                let sessions = uni_sessions.clone();
+                let defraggers = uni_defraggers.clone();
+                tokio::spawn(async move {
+                    if let Some(packet) = Self::read_packet(&mut stream).await {
+                        Self::deliver(packet, &sessions, &defraggers);
+                    }
+                });
+            }
+        });
+        // packets relayed by datagrams
+        while let Ok(mut data) = conn.read_datagram().await {
+            if data.len() < 2 || data[0] != VERSION || data[1] != CMD_PACKET {
+                continue;
+            }
+            data.advance(2);
+            if let Some(packet) = TuicPacket::parse(data) {
+                Self::deliver(packet, &sessions, &defraggers);
+            }
+        }
+        uni_task.abort();
+    }
+
+    async fn read_packet(stream: &mut RecvStream) -> Option<TuicPacket> {
+        let mut header = [0u8; 2];
+        stream.read_exact(&mut header).await.ok()?;
+        if header != [VERSION, CMD_PACKET] {
+            return None;
+        }
+        let data = stream.read_to_end(u16::MAX as usize + 512).await.ok()?;
+        TuicPacket::parse(Bytes::from(data))
+    }
+
+    fn deliver(
+        packet: TuicPacket,
+        sessions: &AssocTable,
+        defraggers: &Mutex<HashMap<u16, Defragger>>,
+    ) {
+        let assoc_id = packet.assoc_id;
+        let Some(tx) = sessions.get(&assoc_id).map(|s| s.value().clone()) else {
+            defraggers.lock().unwrap().remove(&assoc_id);
+            return;
+        };
+        let Some(result) = defraggers
+            .lock()
+            .unwrap()
+            .entry(assoc_id)
+            .or_default()
+            .feed(packet)
+        else {
+            return;
+        };
+        // drop the packet if the session is busy
+        let _ = tx.try_send(result);
+    }
+}
+
+impl IsActive for TuicConnection {
+    fn is_active(&self) -> bool {
+        TuicConnection::is_active(self)
+    }
+}
+
+impl Drop for TuicConnection {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        self.conn.close(0u32.into(), b"");
+    }
+}
+
+/// UDP association relayed by QUIC datagrams or unidirectional streams.
+pub struct TuicUdpSession {
+    conn: Arc<TuicConnection>,
+    assoc_id: u16,
+    next_packet_id: AtomicU16,
+    rx: tokio::sync::Mutex<mpsc::Receiver<(Bytes, NetworkAddr)>>,
+}
+
+impl TuicUdpSession {
+    pub async fn send_to(&self, data: &[u8], addr: &NetworkAddr) -> Result<(), TransportError> {
+        let packet_id = self.next_packet_id.fetch_add(1, Ordering::Relaxed);
+        match self.conn.relay_mode {
+            TuicUdpRelayMode::Native => {
+                let max_size = self
+                    .conn
+                    .conn
+                    .max_datagram_size()
+                    .ok_or(TransportError::Tuic("Datagram unsupported"))?;
+                // the address is only carried by the first fragment
+                let mut encoded_addr = Vec::with_capacity(1 + 256 + 2);
+                encode_addr(Some(addr), &mut encoded_addr);
+                if max_size <= PACKET_HEADER_LEN + encoded_addr.len() {
+                    return Err(TransportError::Tuic("Address too long"));
+                }
+                let fragment_size = max_size - PACKET_HEADER_LEN - encoded_addr.len();
+                let fragment_total = data.len().div_ceil(fragment_size).max(1);
+                if fragment_total > u8::MAX as usize {
+                    return Err(TransportError::Tuic("Packet too large"));
+                }
+                for fragment_id in 0..fragment_total {
+                    let start = fragment_id * fragment_size;
+                    let end = (start + fragment_size).min(data.len());
+                    let packet = TuicPacket {
+                        assoc_id: self.assoc_id,
+                        packet_id,
+                        fragment_total: fragment_total as u8,
+                        fragment_id: fragment_id as u8,
+                        addr: (fragment_id == 0).then(|| addr.clone()),
+                        payload: Bytes::copy_from_slice(&data[start..end]),
+                    };
+                    self.conn
+                        .conn
+                        .send_datagram(Bytes::from(packet.serialize()))
+                        .map_err(|_| TransportError::Tuic("Send datagram failed"))?;
+                }
+            }
+            TuicUdpRelayMode::Quic => {
+                let packet = TuicPacket {
+                    assoc_id: self.assoc_id,
+                    packet_id,
+                    fragment_total: 1,
+                    fragment_id: 0,
+                    addr: Some(addr.clone()),
+                    payload: Bytes::copy_from_slice(data),
+                };
+                let mut stream = self.conn.conn.open_uni().await?;
+                stream
+                    .write_all(packet.serialize().as_slice())
+                    .await
+                    .map_err(|_| TransportError::Tuic("Send packet failed"))?;
+                let _ = stream.finish().await;
+            }
+        }
+        Ok(())
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> Result<(usize, NetworkAddr), TransportError> {
+        let (data, addr) = self
+            .rx
+            .lock()
+            .await
+            .recv()
+            .await
+            .ok_or(TransportError::Tuic("Connection closed"))?;
+        let len = data.len().min(buf.len());
+        buf[..len].copy_from_slice(&data[..len]);
+        Ok((len, addr))
+    }
+}
+
+impl Drop for TuicUdpSession {
+    fn drop(&mut self) {
+        self.conn.sessions.remove(&self.assoc_id);
+        let conn = self.conn.conn.clone();
+        let assoc_id = self.assoc_id;
+        tokio::spawn(async move {
+            if let Ok(mut stream) = conn.open_uni().await {
+                let mut cmd = vec![VERSION, CMD_DISSOCIATE];
+                cmd.extend_from_slice(&assoc_id.to_be_bytes());
+                let _ = stream.write_all(cmd.as_slice()).await;
+                let _ = stream.finish().await;
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::common::self_signed_cert;
+    use tokio::io::AsyncReadExt;
+
+    async fn mock_server(endpoint: Endpoint, uuid: [u8; 16]) {
+        let conn = endpoint.accept().await.unwrap().await.unwrap();
+        let auth = conn
+            .accept_uni()
+            .await
+            .unwrap()
+            .read_to_end(128)
+            .await
+            .unwrap();
+        let mut token = [0u8; 32];
+        conn.export_keying_material(&mut token, &uuid, b"password")
+            .unwrap();
+        assert_eq!(&auth[..2], &[VERSION, CMD_AUTHENTICATE]);
+        assert_eq!(&auth[2..18], &uuid);
+        assert_eq!(&auth[18..], &token);
+
+        // TCP relay
+        let (send, recv) = conn.accept_bi().await.unwrap();
+        let mut stream = QuicStream::new(send, recv);
+        let mut header = [0u8; 2 + 1 + 1 + 11 + 2];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(&header[..2], &[VERSION, CMD_CONNECT]);
+        assert_eq!(
+            parse_addr(&mut Bytes::copy_from_slice(&header[2..])),
+            Some(Some(NetworkAddr::DomainName {
+                domain_name: "example.com".to_string(),
+                port: 443,
+            }))
+        );
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        stream.write_all(&buf).await.unwrap();
+
+        // UDP echo, skipping heartbeats
+        loop {
+            let mut data = conn.read_datagram().await.unwrap();
+            if data[1] != CMD_PACKET {
+                continue;
+            }
+            data.advance(2);
+            let packet = TuicPacket::parse(data).unwrap();
+            conn.send_datagram(Bytes::from(packet.serialize())).unwrap();
+            break;
+        }
+        let dissociate = conn
+            .accept_uni()
+            .await
+            .unwrap()
+            .read_to_end(8)
+            .await
+            .unwrap();
+        assert_eq!(dissociate, [VERSION, CMD_DISSOCIATE, 0, 0]);
+    }
+
+    #[tokio::test]
+    async fn test_tuic_loopback() {
+        let uuid = crate::transport::parse_uuid("b831381d-6324-4d53-ad4f-8cda48b30811").unwrap();
+        let (cert, key) = self_signed_cert();
+        let mut tls_config = quic_rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(
+                vec![quic_rustls::Certificate(cert)],
+                quic_rustls::PrivateKey(key),
+            )
+            .unwrap();
+        tls_config.alpn_protocols = vec![b"h3".to_vec()];
+        let server_config = quinn::ServerConfig::with_crypto(Arc::new(tls_config));
+        let server = Endpoint::server(server_config, "127.0.0.1:0".parse().unwrap()).unwrap();
+        let server_addr = server.local_addr().unwrap();
+        let server_handle = tokio::spawn(mock_server(server, uuid));
+
+        let config = TuicConfig {
+            server_addr: NetworkAddr::Raw(server_addr),
+            uuid,
+            password: "password".to_string(),
+            sni: "localhost".to_string(),
+            alpn: vec!["h3".to_string()],
+            skip_cert_verify: true,
+            udp_relay_mode: TuicUdpRelayMode::Native,
+            udp: true,
+            dial: DialOptions::default(),
+        };
+        let endpoint = Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+        let conn = Arc::new(
+            TuicConnection::connect(&config, endpoint, server_addr)
+                .await
+                .unwrap(),
+        );
+
+        let mut stream = conn
+            .open_tcp(&NetworkAddr::DomainName {
+                domain_name: "example.com".to_string(),
+                port: 443,
+            })
+            .await
+            .unwrap();
+        stream.write_all(b"hello").await.unwrap();
+        let mut buf = [0u8; 5];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"hello");
+
+        let session = conn.open_udp().unwrap();
+        let dst = NetworkAddr::Raw("1.1.1.1:53".parse().unwrap());
+        session.send_to(b"ping", &dst).await.unwrap();
+        let mut buf = [0u8; 64];
+        let (len, addr) = session.recv_from(&mut buf).await.unwrap();
+        assert_eq!(&buf[..len], b"ping");
+        assert_eq!(addr, dst);
+
+        // all association ids are in use
+        let (tx, _rx) = mpsc::channel(1);
+        for id in 0..=u16::MAX {
+            conn.sessions.entry(id).or_insert_with(|| tx.clone());
+        }
+        assert!(conn.open_udp().is_err());
+        conn.sessions.retain(|id, _| *id == session.assoc_id);
+
+        drop(session);
+        server_handle.await.unwrap();
+    }
+}
@@ -0,0 +1,276 @@
+use crate::network::egress::DialOptions;
+use crate::proxy::error::TransportError;
+use crate::proxy::NetworkAddr;
+use crate::transport::vmess::encode_addr;
+use std::io;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+
+#[derive(Clone, Debug)]
+pub struct VlessConfig {
+    pub(crate) server_addr: NetworkAddr,
+    pub(crate) uuid: [u8; 16],
+    pub(crate) tls: bool,
+    pub(crate) sni: String,
+    pub(crate) skip_cert_verify: bool,
+    pub(crate) websocket_path: Option<String>,
+    pub(crate) udp: bool,
+    pub(crate) dial: DialOptions,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum VlessCmd {
+    Tcp,
+    Udp,
+}
+
+pub(crate) fn encode_request(uuid: &[u8; 16], cmd: VlessCmd, addr: &NetworkAddr) -> Vec<u8> {
+    let mut data = Vec::with_capacity(1 + 16 + 1 + 1 + 2 + 1 + 256);
+    data.push(0x00);
+    data.extend_from_slice(uuid);
+    // no addons
+    data.push(0x00);
+    data.push(match cmd {
+        VlessCmd::Tcp => 0x01,
+        VlessCmd::Udp => 0x02,
+    });
+    encode_addr(addr, &mut data);
+    data
+}
+
+enum ResponseState {
+    Header { buf: [u8; 2], len: usize },
+    Addons(usize),
+    Done,
+}
+
+/// Client side of a VLESS connection, with the request header sent along with the first write.
+pub(crate) struct VlessStream<S> {
+    inner: S,
+    pending: Vec<u8>,
+    written: usize,
+    request_sent: bool,
+    response: ResponseState,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> VlessStream<S> {
+    pub fn new(inner: S, uuid: &[u8; 16], cmd: VlessCmd, addr: &NetworkAddr) -> Self {
+        Self {
+            inner,
+            pending: encode_request(uuid, cmd, addr),
+            written: 0,
+            request_sent: false,
+            response: ResponseState::Header {
+                buf: [0; 2],
+                len: 0,
+            },
+        }
+    }
+
+    fn poll_pending(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.pending.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.pending[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.pending.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for VlessStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // the request header is not sent yet if nothing has been written
+        if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+            return Poll::Ready(Err(e));
+        }
+        loop {
+            match this.response {
+                ResponseState::Done => return Pin::new(&mut this.inner).poll_read(cx, buf),
+                ResponseState::Header {
+                    buf: ref mut header,
+                    ref mut len,
+                } => {
+                    let mut read_buf = ReadBuf::new(&mut header[*len..]);
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                    let n = read_buf.filled().len();
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    *len += n;
+                    if *len == header.len() {
+                        if header[0] != 0x00 {
+                            return Poll::Ready(Err(io::Error::new(
+                                io::ErrorKind::InvalidData,
+                                TransportError::Vless("Unsupported response version"),
+                            )));
+                        }
+                        this.response = ResponseState::Addons(header[1] as usize);
+                    }
+                }
+                ResponseState::Addons(0) => this.response = ResponseState::Done,
+                ResponseState::Addons(left) => {
+                    // addons are not used, just skip them
+                    let mut skipped = [0u8; 256];
+                    let mut read_buf = ReadBuf::new(&mut skipped[..left]);
+                    ready!(Pin::new(&mut this.inner).poll_read(cx, &mut read_buf))?;
+                    let n = read_buf.filled().len();
+                    if n == 0 {
+                        return Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()));
+                    }
+                    this.response = ResponseState::Addons(left - n);
+                }
+            }
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for VlessStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        if !this.request_sent {
+            this.request_sent = true;
+            this.pending.extend_from_slice(buf);
+            // the remaining part will be sent in the following calls
+            if let Poll::Ready(Err(e)) = this.poll_pending(cx) {
+                return Poll::Ready(Err(e));
+            }
+            return Poll::Ready(Ok(buf.len()));
+        }
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_pending(cx))?;
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// VLESS UDP session, which is bound to a single destination.
+/// Each packet is prefixed with its length.
+pub(crate) struct VlessUdpSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    read_half: Mutex<ReadHalf<VlessStream<S>>>,
+    write_half: Mutex<WriteHalf<VlessStream<S>>>,
+    dst: NetworkAddr,
+}
+
+impl<S> VlessUdpSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn bind(stream: VlessStream<S>, dst: NetworkAddr) -> Self {
+        let (read_half, write_half) = tokio::io::split(stream);
+        Self {
+            read_half: Mutex::new(read_half),
+            write_half: Mutex::new(write_half),
+            dst,
+        }
+    }
+
+    pub async fn send(&self, data: &[u8]) -> Result<(), TransportError> {
+        let len = u16::try_from(data.len())
+            .map_err(|_| TransportError::Internal("VLESS packet exceeded u16::size"))?;
+        let mut buf = Vec::with_capacity(2 + data.len());
+        buf.extend_from_slice(&len.to_be_bytes());
+        buf.extend_from_slice(data);
+        let mut writer = self.write_half.lock().await;
+        writer.write_all(buf.as_slice()).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    pub async fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, NetworkAddr), TransportError> {
+        let mut reader = self.read_half.lock().await;
+        let len = reader.read_u16().await? as usize;
+        if len > buffer.len() {
+            // drop the packet, so that the next one is read from its length
+            tokio::io::copy(&mut (&mut *reader).take(len as u64), &mut tokio::io::sink()).await?;
+            return Err(TransportError::Internal("VLESS buffer too small"));
+        }
+        reader.read_exact(&mut buffer[..len]).await?;
+        Ok((len, self.dst.clone()))
+    }
+}
+
+#[tokio::test]
+async fn test_vless_stream() {
+    let uuid = crate::transport::parse_uuid("b831381d-6324-4d53-ad4f-8cda48b30811").unwrap();
+    let dst = NetworkAddr::DomainName {
+        domain_name: "example.com".to_string(),
+        port: 443,
+    };
+    let (client, mut server) = tokio::io::duplex(4096);
+    let expected = encode_request(&uuid, VlessCmd::Tcp, &dst);
+    let server = tokio::spawn(async move {
+        let mut request = vec![0u8; expected.len()];
+        server.read_exact(request.as_mut_slice()).await.unwrap();
+        assert_eq!(request, expected);
+        // version and one byte of addons
+        server.write_all(&[0x00, 0x01, 0xff]).await.unwrap();
+        let mut buf = [0u8; 5];
+        server.read_exact(&mut buf).await.unwrap();
+        server.write_all(&buf).await.unwrap();
+    });
+    let mut stream = VlessStream::new(client, &uuid, VlessCmd::Tcp, &dst);
+    stream.write_all(b"hello").await.unwrap();
+    stream.flush().await.unwrap();
+    let mut buf = [0u8; 5];
+    stream.read_exact(&mut buf).await.unwrap();
+    assert_eq!(&buf, b"hello");
+    server.await.unwrap();
+}
+
+#[tokio::test]
+async fn test_vless_udp_oversized() {
+    let uuid = crate::transport::parse_uuid("b831381d-6324-4d53-ad4f-8cda48b30811").unwrap();
+    let dst = NetworkAddr::Raw("127.0.0.1:53".parse().unwrap());
+    let (client, mut server) = tokio::io::duplex(4096);
+    let expected = encode_request(&uuid, VlessCmd::Udp, &dst);
+    let server = tokio::spawn(async move {
+        let mut request = vec![0u8; expected.len()];
+        server.read_exact(request.as_mut_slice()).await.unwrap();
+        server.write_all(&[0x00, 0x00]).await.unwrap();
+        server.write_all(&[0x00, 0x08]).await.unwrap();
+        server.write_all(&[0x42; 8]).await.unwrap();
+        server.write_all(&[0x00, 0x02]).await.unwrap();
+        server.write_all(b"ok").await.unwrap();
+    });
+    let stream = VlessStream::new(client, &uuid, VlessCmd::Udp, &dst);
+    let socket = VlessUdpSocket::bind(stream, dst);
+    assert!(socket.send(&[0u8; 65536]).await.is_err());
+    socket.send(b"query").await.unwrap();
+    let mut buf = [0u8; 4];
+    assert!(socket.recv_from(&mut buf).await.is_err());
+    let (len, _) = socket.recv_from(&mut buf).await.unwrap();
+    assert_eq!(&buf[..len], b"ok");
+    server.await.unwrap();
+}
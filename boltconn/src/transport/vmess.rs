@@ -0,0 +1,761 @@
+use crate::common::as_io_err;
+use crate::network::egress::DialOptions;
+use crate::proxy::error::TransportError;
+use crate::proxy::NetworkAddr;
+use aes::cipher::{BlockEncrypt, KeyInit};
+use aes::Aes128;
+use aes_gcm::aead::{Aead, Payload};
+use aes_gcm::Aes128Gcm;
+use bytes::{Bytes, BytesMut};
+use chacha20poly1305::ChaCha20Poly1305;
+use md5::Md5;
+use sha2::{Digest, Sha256};
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, ReadBuf, ReadHalf, WriteHalf};
+use tokio::sync::Mutex;
+
+const KDF_SALT: &[u8] = b"VMess AEAD KDF";
+const AUTH_ID_KEY: &[u8] = b"AES Auth ID Encryption";
+const HEADER_LEN_KEY: &[u8] = b"VMess Header AEAD Key_Length";
+const HEADER_LEN_IV: &[u8] = b"VMess Header AEAD Nonce_Length";
+const HEADER_KEY: &[u8] = b"VMess Header AEAD Key";
+const HEADER_IV: &[u8] = b"VMess Header AEAD Nonce";
+const RESP_HEADER_LEN_KEY: &[u8] = b"AEAD Resp Header Len Key";
+const RESP_HEADER_LEN_IV: &[u8] = b"AEAD Resp Header Len IV";
+const RESP_HEADER_KEY: &[u8] = b"AEAD Resp Header Key";
+const RESP_HEADER_IV: &[u8] = b"AEAD Resp Header IV";
+const CMD_KEY_SALT: &[u8] = b"c48619fe-8f02-49e0-b9e9-edf763e17e21";
+
+const OPTION_CHUNK_STREAM: u8 = 0x01;
+const TAG_LEN: usize = 16;
+// same as v2ray, so that a chunk fits in its 8K buffer
+const MAX_PAYLOAD_LEN: usize = 8192 - TAG_LEN - 2;
+
+#[derive(Clone, Debug)]
+pub struct VmessConfig {
+    pub(crate) server_addr: NetworkAddr,
+    pub(crate) uuid: [u8; 16],
+    pub(crate) security: VmessSecurity,
+    pub(crate) tls: bool,
+    pub(crate) sni: String,
+    pub(crate) skip_cert_verify: bool,
+    pub(crate) websocket_path: Option<String>,
+    pub(crate) udp: bool,
+    pub(crate) dial: DialOptions,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VmessSecurity {
+    Aes128Gcm,
+    Chacha20Poly1305,
+    None,
+}
+
+impl VmessSecurity {
+    pub fn from_name(name: &str) -> Option<Self> {
+        Some(match name {
+            "auto" | "aes-128-gcm" => Self::Aes128Gcm,
+            "chacha20-poly1305" | "chacha20-ietf-poly1305" => Self::Chacha20Poly1305,
+            "none" => Self::None,
+            _ => None?,
+        })
+    }
+
+    fn id(&self) -> u8 {
+        match self {
+            VmessSecurity::Aes128Gcm => 0x03,
+            VmessSecurity::Chacha20Poly1305 => 0x04,
+            VmessSecurity::None => 0x05,
+        }
+    }
+}
+
+pub(crate) fn cmd_key(uuid: &[u8; 16]) -> [u8; 16] {
+    Md5::new()
+        .chain_update(uuid)
+        .chain_update(CMD_KEY_SALT)
+        .finalize()
+        .into()
+}
+
+/// HMAC whose hash function is the HMAC keyed by previous keys, with SHA256 at the bottom.
+fn nested_hmac(keys: &[&[u8]], msg: &[u8]) -> [u8; 32] {
+    const BLOCK_SIZE: usize = 64;
+    let Some((key, rest)) = keys.split_last() else {
+        return Sha256::digest(msg).into();
+    };
+    // all keys used by VMess are shorter than a block
+    let mut padded = [0u8; BLOCK_SIZE];
+    padded[..key.len()].copy_from_slice(key);
+    let mut inner = Vec::with_capacity(BLOCK_SIZE + msg.len());
+    inner.extend(padded.iter().map(|b| b ^ 0x36));
+    inner.extend_from_slice(msg);
+    let inner = nested_hmac(rest, inner.as_slice());
+    let mut outer = Vec::with_capacity(BLOCK_SIZE + inner.len());
+    outer.extend(padded.iter().map(|b| b ^ 0x5c));
+    outer.extend_from_slice(&inner);
+    nested_hmac(rest, outer.as_slice())
+}
+
+fn kdf<const N: usize>(key: &[u8], path: &[&[u8]]) -> [u8; N] {
+    let mut keys: Vec<&[u8]> = vec![KDF_SALT];
+    keys.extend_from_slice(path);
+    let mut res = [0u8; N];
+    res.copy_from_slice(&nested_hmac(keys.as_slice(), key)[..N]);
+    res
+}
+
+fn create_auth_id(cmd_key: &[u8; 16], timestamp: u64, rand: [u8; 4]) -> [u8; 16] {
+    let mut buf = [0u8; 16];
+    buf[..8].copy_from_slice(&timestamp.to_be_bytes());
+    buf[8..12].copy_from_slice(&rand);
+    let checksum = crc32fast::hash(&buf[..12]);
+    buf[12..].copy_from_slice(&checksum.to_be_bytes());
+    let cipher = Aes128::new(&kdf::<16>(cmd_key, &[AUTH_ID_KEY]).into());
+    let mut block = aes::Block::from(buf);
+    cipher.encrypt_block(&mut block);
+    block.into()
+}
+
+fn fnv1a(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c9dc5u32, |hash, b| {
+        (hash ^ *b as u32).wrapping_mul(0x01000193)
+    })
+}
+
+fn aes_gcm_seal(key: [u8; 16], iv: [u8; 12], msg: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    Aes128Gcm::new(&key.into())
+        .encrypt(&iv.into(), Payload { msg, aad })
+        .ok()
+}
+
+fn aes_gcm_open(key: [u8; 16], iv: [u8; 12], msg: &[u8], aad: &[u8]) -> Option<Vec<u8>> {
+    Aes128Gcm::new(&key.into())
+        .decrypt(&iv.into(), Payload { msg, aad })
+        .ok()
+}
+
+/// Port, address type and address, also used by VLESS.
+pub(crate) fn encode_addr(addr: &NetworkAddr, data: &mut Vec<u8>) {
+    match addr {
+        NetworkAddr::Raw(SocketAddr::V4(v4)) => {
+            data.extend_from_slice(&v4.port().to_be_bytes());
+            data.push(0x01);
+            data.extend_from_slice(&v4.ip().octets());
+        }
+        NetworkAddr::DomainName { domain_name, port } => {
+            data.extend_from_slice(&port.to_be_bytes());
+            data.push(0x02);
+            data.push(domain_name.len() as u8);
+            data.extend_from_slice(domain_name.as_bytes());
+        }
+        NetworkAddr::Raw(SocketAddr::V6(v6)) => {
+            data.extend_from_slice(&v6.port().to_be_bytes());
+            data.push(0x03);
+            data.extend_from_slice(&v6.ip().octets());
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub(crate) enum VmessCmd {
+    Tcp,
+    Udp,
+}
+
+pub(crate) struct VmessRequest {
+    cmd: VmessCmd,
+    addr: NetworkAddr,
+    security: VmessSecurity,
+    body_key: [u8; 16],
+    body_iv: [u8; 16],
+    response_auth: u8,
+}
+
+impl VmessRequest {
+    pub fn new(cmd: VmessCmd, addr: NetworkAddr, security: VmessSecurity) -> Self {
+        Self {
+            cmd,
+            addr,
+            security,
+            body_key: rand::random(),
+            body_iv: rand::random(),
+            response_auth: rand::random(),
+        }
+    }
+
+    fn encode_header(&self, padding: &[u8]) -> Vec<u8> {
+        let padding_len = padding.len() as u8;
+        let mut data = Vec::with_capacity(64 + padding.len());
+        data.push(0x01);
+        data.extend_from_slice(&self.body_iv);
+        data.extend_from_slice(&self.body_key);
+        data.push(self.response_auth);
+        data.push(OPTION_CHUNK_STREAM);
+        data.push((padding_len << 4) | self.security.id());
+        data.push(0x00);
+        data.push(match self.cmd {
+            VmessCmd::Tcp => 0x01,
+            VmessCmd::Udp => 0x02,
+        });
+        encode_addr(&self.addr, &mut data);
+        data.extend_from_slice(padding);
+        data.extend_from_slice(&fnv1a(data.as_slice()).to_be_bytes());
+        data
+    }
+
+    /// Serialize the request header in AEAD format.
+    pub fn seal(&self, cmd_key: &[u8; 16]) -> Result<Vec<u8>, TransportError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|_| TransportError::Vmess("Invalid system time"))?
+            .as_secs();
+        let padding: Vec<u8> = (0..rand::random::<u8>() % 16)
+            .map(|_| rand::random())
+            .collect();
+        self.seal_with(
+            cmd_key,
+            create_auth_id(cmd_key, timestamp, rand::random()),
+            rand::random(),
+            padding.as_slice(),
+        )
+    }
+
+    fn seal_with(
+        &self,
+        cmd_key: &[u8; 16],
+        auth_id: [u8; 16],
+        nonce: [u8; 8],
+        padding: &[u8],
+    ) -> Result<Vec<u8>, TransportError> {
+        let header = self.encode_header(padding);
+        let path = |salt: &'static [u8]| [salt, auth_id.as_slice(), nonce.as_slice()];
+        let encrypted_len = aes_gcm_seal(
+            kdf(cmd_key, &path(HEADER_LEN_KEY)),
+            kdf(cmd_key, &path(HEADER_LEN_IV)),
+            &(header.len() as u16).to_be_bytes(),
+            &auth_id,
+        )
+        .ok_or(TransportError::Vmess("Encrypt header length failed"))?;
+        let encrypted_header = aes_gcm_seal(
+            kdf(cmd_key, &path(HEADER_KEY)),
+            kdf(cmd_key, &path(HEADER_IV)),
+            header.as_slice(),
+            &auth_id,
+        )
+        .ok_or(TransportError::Vmess("Encrypt header failed"))?;
+        let mut data = Vec::with_capacity(16 + encrypted_len.len() + 8 + encrypted_header.len());
+        data.extend_from_slice(&auth_id);
+        data.extend_from_slice(encrypted_len.as_slice());
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(encrypted_header.as_slice());
+        Ok(data)
+    }
+
+    fn response_key_iv(&self) -> ([u8; 16], [u8; 16]) {
+        let mut key = [0u8; 16];
+        key.copy_from_slice(&Sha256::digest(self.body_key)[..16]);
+        let mut iv = [0u8; 16];
+        iv.copy_from_slice(&Sha256::digest(self.body_iv)[..16]);
+        (key, iv)
+    }
+}
+
+enum ChunkCipher {
+    Aes128Gcm(Box<Aes128Gcm>),
+    Chacha20Poly1305(Box<ChaCha20Poly1305>),
+    None,
+}
+
+/// Encode and decode chunks of the body stream.
+pub(crate) struct ChunkCodec {
+    cipher: ChunkCipher,
+    iv: [u8; 16],
+    count: u16,
+}
+
+impl ChunkCodec {
+    pub fn new(security: VmessSecurity, key: &[u8; 16], iv: &[u8; 16]) -> Self {
+        let cipher = match security {
+            VmessSecurity::Aes128Gcm => {
+                ChunkCipher::Aes128Gcm(Box::new(Aes128Gcm::new(&(*key).into())))
+            }
+            VmessSecurity::Chacha20Poly1305 => {
+                let mut full_key = [0u8; 32];
+                let first: [u8; 16] = Md5::digest(key).into();
+                full_key[..16].copy_from_slice(&first);
+                full_key[16..].copy_from_slice(&Md5::digest(first));
+                ChunkCipher::Chacha20Poly1305(Box::new(ChaCha20Poly1305::new(&full_key.into())))
+            }
+            VmessSecurity::None => ChunkCipher::None,
+        };
+        Self {
+            cipher,
+            iv: *iv,
+            count: 0,
+        }
+    }
+
+    fn next_nonce(&mut self) -> [u8; 12] {
+        let mut nonce = [0u8; 12];
+        nonce[..2].copy_from_slice(&self.count.to_be_bytes());
+        nonce[2..].copy_from_slice(&self.iv[2..12]);
+        self.count = self.count.wrapping_add(1);
+        nonce
+    }
+
+    /// Append a chunk consisting of length and payload to the buffer.
+    pub fn encode_chunk(&mut self, data: &[u8], buf: &mut Vec<u8>) -> Result<(), TransportError> {
+        let nonce = self.next_nonce();
+        let payload = match &self.cipher {
+            ChunkCipher::Aes128Gcm(c) => c.encrypt(&nonce.into(), data).ok(),
+            ChunkCipher::Chacha20Poly1305(c) => c.encrypt(&nonce.into(), data).ok(),
+            ChunkCipher::None => Some(data.to_vec()),
+        }
+        .ok_or(TransportError::Vmess("Encrypt chunk failed"))?;
+        buf.extend_from_slice(&(payload.len() as u16).to_be_bytes());
+        buf.extend_from_slice(payload.as_slice());
+        Ok(())
+    }
+
+    /// Decode the payload of a chunk, without the length.
+    pub fn decode_chunk(&mut self, data: &[u8]) -> Result<Vec<u8>, TransportError> {
+        let nonce = self.next_nonce();
+        match &self.cipher {
+            ChunkCipher::Aes128Gcm(c) => c.decrypt(&nonce.into(), data).ok(),
+            ChunkCipher::Chacha20Poly1305(c) => c.decrypt(&nonce.into(), data).ok(),
+            ChunkCipher::None => Some(data.to_vec()),
+        }
+        .ok_or(TransportError::Vmess("Decrypt chunk failed"))
+    }
+}
+
+enum ReadState {
+    ResponseLen,
+    Response(usize),
+    ChunkLen,
+    Chunk(usize),
+    Eof,
+}
+
+/// Client side of a VMess connection, with the request header sent along with the first write.
+pub(crate) struct VmessStream<S> {
+    inner: S,
+    encoder: ChunkCodec,
+    write_buf: Vec<u8>,
+    written: usize,
+    eof_sent: bool,
+    decoder: ChunkCodec,
+    read_state: ReadState,
+    read_buf: BytesMut,
+    plaintext: Bytes,
+    response_key: [u8; 16],
+    response_iv: [u8; 16],
+    response_auth: u8,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> VmessStream<S> {
+    pub fn new(
+        inner: S,
+        cmd_key: &[u8; 16],
+        request: &VmessRequest,
+    ) -> Result<Self, TransportError> {
+        let (response_key, response_iv) = request.response_key_iv();
+        Ok(Self {
+            inner,
+            encoder: ChunkCodec::new(request.security, &request.body_key, &request.body_iv),
+            write_buf: request.seal(cmd_key)?,
+            written: 0,
+            eof_sent: false,
+            decoder: ChunkCodec::new(request.security, &response_key, &response_iv),
+            read_state: ReadState::ResponseLen,
+            read_buf: BytesMut::new(),
+            plaintext: Bytes::new(),
+            response_key,
+            response_iv,
+            response_auth: request.response_auth,
+        })
+    }
+
+    fn poll_write_buf(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        while self.written < self.write_buf.len() {
+            let n =
+                ready!(Pin::new(&mut self.inner).poll_write(cx, &self.write_buf[self.written..]))?;
+            if n == 0 {
+                return Poll::Ready(Err(io::ErrorKind::WriteZero.into()));
+            }
+            self.written += n;
+        }
+        self.write_buf.clear();
+        self.written = 0;
+        Poll::Ready(Ok(()))
+    }
+
+    fn advance_state(&mut self, data: &[u8]) -> Result<ReadState, TransportError> {
+        Ok(match self.read_state {
+            ReadState::ResponseLen => {
+                let len = aes_gcm_open(
+                    kdf(&self.response_key, &[RESP_HEADER_LEN_KEY]),
+                    kdf(&self.response_iv, &[RESP_HEADER_LEN_IV]),
+                    data,
+                    &[],
+                )
+                .ok_or(TransportError::Vmess("Decrypt response length failed"))?;
+                ReadState::Response(u16::from_be_bytes([len[0], len[1]]) as usize)
+            }
+            ReadState::Response(_) => {
+                let header = aes_gcm_open(
+                    kdf(&self.response_key, &[RESP_HEADER_KEY]),
+                    kdf(&self.response_iv, &[RESP_HEADER_IV]),
+                    data,
+                    &[],
+                )
+                .ok_or(TransportError::Vmess("Decrypt response header failed"))?;
+                if header.first() != Some(&self.response_auth) {
+                    return Err(TransportError::Vmess("Mismatched response header"));
+                }
+                ReadState::ChunkLen
+            }
+            ReadState::ChunkLen => {
+                ReadState::Chunk(u16::from_be_bytes([data[0], data[1]]) as usize)
+            }
+            ReadState::Chunk(_) => {
+                let payload = self.decoder.decode_chunk(data)?;
+                if payload.is_empty() {
+                    ReadState::Eof
+                } else {
+                    self.plaintext = Bytes::from(payload);
+                    ReadState::ChunkLen
+                }
+            }
+            ReadState::Eof => ReadState::Eof,
+        })
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncRead for VmessStream<S> {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        // the request header is not sent yet if nothing has been written
+        if let Poll::Ready(Err(e)) = this.poll_write_buf(cx) {
+            return Poll::Ready(Err(e));
+        }
+        loop {
+            if !this.plaintext.is_empty() {
+                let len = this.plaintext.len().min(buf.remaining());
+                buf.put_slice(&this.plaintext.split_to(len));
+                return Poll::Ready(Ok(()));
+            }
+            let need = match this.read_state {
+                ReadState::ResponseLen => 2 + TAG_LEN,
+                ReadState::Response(len) => len + TAG_LEN,
+                ReadState::ChunkLen => 2,
+                ReadState::Chunk(len) => len,
+                ReadState::Eof => return Poll::Ready(Ok(())),
+            };
+            while this.read_buf.len() < need {
+                let mut tmp = [0u8; 4096];
+                let mut tmp_buf = ReadBuf::new(&mut tmp);
+                ready!(Pin::new(&mut this.inner).poll_read(cx, &mut tmp_buf))?;
+                if tmp_buf.filled().is_empty() {
+                    return if this.read_buf.is_empty()
+                        && matches!(this.read_state, ReadState::ChunkLen)
+                    {
+                        this.read_state = ReadState::Eof;
+                        Poll::Ready(Ok(()))
+                    } else {
+                        Poll::Ready(Err(io::ErrorKind::UnexpectedEof.into()))
+                    };
+                }
+                this.read_buf.extend_from_slice(tmp_buf.filled());
+            }
+            let data = this.read_buf.split_to(need);
+            this.read_state = this.advance_state(data.as_ref()).map_err(as_io_err)?;
+        }
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> AsyncWrite for VmessStream<S> {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        // an empty chunk indicates the end of stream
+        let len = buf.len().min(MAX_PAYLOAD_LEN);
+        if len == 0 {
+            return Poll::Ready(Ok(0));
+        }
+        this.encoder
+            .encode_chunk(&buf[..len], &mut this.write_buf)
+            .map_err(as_io_err)?;
+        // the remaining part will be sent in the following calls
+        if let Poll::Ready(Err(e)) = this.poll_write_buf(cx) {
+            return Poll::Ready(Err(e));
+        }
+        Poll::Ready(Ok(len))
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        Pin::new(&mut this.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = self.get_mut();
+        ready!(this.poll_write_buf(cx))?;
+        if !this.eof_sent {
+            this.eof_sent = true;
+            this.encoder
+                .encode_chunk(&[], &mut this.write_buf)
+                .map_err(as_io_err)?;
+            ready!(this.poll_write_buf(cx))?;
+        }
+        Pin::new(&mut this.inner).poll_shutdown(cx)
+    }
+}
+
+/// VMess UDP session, which is bound to a single destination.
+pub(crate) struct VmessUdpSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    read_half: Mutex<ReadHalf<VmessStream<S>>>,
+    write_half: Mutex<WriteHalf<VmessStream<S>>>,
+    dst: NetworkAddr,
+}
+
+impl<S> VmessUdpSocket<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    pub fn bind(stream: VmessStream<S>, dst: NetworkAddr) -> Self {
+        let (read_half, write_half) = tokio::io::split(stream);
+        Self {
+            read_half: Mutex::new(read_half),
+            write_half: Mutex::new(write_half),
+            dst,
+        }
+    }
+
+    /// Each packet is sent in its own chunk.
+    pub async fn send(&self, data: &[u8]) -> Result<(), TransportError> {
+        if data.len() > MAX_PAYLOAD_LEN {
+            tracing::debug!("VMess UDP packet of {} bytes dropped", data.len());
+            return Ok(());
+        }
+        let mut writer = self.write_half.lock().await;
+        writer.write_all(data).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    pub async fn recv_from(
+        &self,
+        buffer: &mut [u8],
+    ) -> Result<(usize, NetworkAddr), TransportError> {
+        // a read never goes across chunks, so it returns exactly one packet
+        let len = self.read_half.lock().await.read(buffer).await?;
+        Ok((len, self.dst.clone()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::transport::parse_uuid;
+    use tokio::io::DuplexStream;
+
+    // A minimal VMess server that echoes everything back
+    async fn echo_server(mut stream: DuplexStream, uuid: [u8; 16]) {
+        let cmd_key = cmd_key(&uuid);
+        let mut prefix = [0u8; 16 + 18 + 8];
+        stream.read_exact(&mut prefix).await.unwrap();
+        let auth_id = &prefix[..16];
+        let nonce = &prefix[34..];
+        let path = |salt: &'static [u8]| [salt, auth_id, nonce];
+        let len = aes_gcm_open(
+            kdf(&cmd_key, &path(HEADER_LEN_KEY)),
+            kdf(&cmd_key, &path(HEADER_LEN_IV)),
+            &prefix[16..34],
+            auth_id,
+        )
+        .unwrap();
+        let mut header = vec![0u8; u16::from_be_bytes([len[0], len[1]]) as usize + TAG_LEN];
+        stream.read_exact(header.as_mut_slice()).await.unwrap();
+        let header = aes_gcm_open(
+            kdf(&cmd_key, &path(HEADER_KEY)),
+            kdf(&cmd_key, &path(HEADER_IV)),
+            header.as_slice(),
+            auth_id,
+        )
+        .unwrap();
+        let (content, checksum) = header.split_at(header.len() - 4);
+        assert_eq!(fnv1a(content).to_be_bytes(), checksum);
+        let mut body_iv = [0u8; 16];
+        body_iv.copy_from_slice(&header[1..17]);
+        let mut body_key = [0u8; 16];
+        body_key.copy_from_slice(&header[17..33]);
+        let security = match header[35] & 0x0f {
+            0x03 => VmessSecurity::Aes128Gcm,
+            0x04 => VmessSecurity::Chacha20Poly1305,
+            _ => VmessSecurity::None,
+        };
+        let request = VmessRequest {
+            cmd: VmessCmd::Tcp,
+            addr: NetworkAddr::Raw("127.0.0.1:80".parse().unwrap()),
+            security,
+            body_key,
+            body_iv,
+            response_auth: header[33],
+        };
+        let (response_key, response_iv) = request.response_key_iv();
+        let response = [request.response_auth, 0, 0, 0];
+        let mut data = aes_gcm_seal(
+            kdf(&response_key, &[RESP_HEADER_LEN_KEY]),
+            kdf(&response_iv, &[RESP_HEADER_LEN_IV]),
+            &(response.len() as u16).to_be_bytes(),
+            &[],
+        )
+        .unwrap();
+        data.extend(
+            aes_gcm_seal(
+                kdf(&response_key, &[RESP_HEADER_KEY]),
+                kdf(&response_iv, &[RESP_HEADER_IV]),
+                &response,
+                &[],
+            )
+            .unwrap(),
+        );
+        stream.write_all(data.as_slice()).await.unwrap();
+
+        let mut decoder = ChunkCodec::new(security, &body_key, &body_iv);
+        let mut encoder = ChunkCodec::new(security, &response_key, &response_iv);
+        loop {
+            let mut len = [0u8; 2];
+            stream.read_exact(&mut len).await.unwrap();
+            let mut chunk = vec![0u8; u16::from_be_bytes(len) as usize];
+            stream.read_exact(chunk.as_mut_slice()).await.unwrap();
+            let payload = decoder.decode_chunk(chunk.as_slice()).unwrap();
+            let mut buf = vec![];
+            encoder.encode_chunk(payload.as_slice(), &mut buf).unwrap();
+            stream.write_all(buf.as_slice()).await.unwrap();
+            if payload.is_empty() {
+                break;
+            }
+        }
+    }
+
+    fn hex(s: &str) -> Vec<u8> {
+        (0..s.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&s[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_kdf() {
+        // TestKDFValue from v2fly proxy/vmess/aead/kdf_test.go
+        let key: [u8; 32] = kdf(
+            b"Demo Key for KDF Value Test",
+            &[
+                b"Demo Path for KDF Value Test",
+                b"Demo Path for KDF Value Test2",
+                b"Demo Path for KDF Value Test3",
+            ],
+        );
+        assert_eq!(
+            key.to_vec(),
+            hex("53e9d7e1bd7bd25022b71ead07d8a596efc8a845c7888652fd684b4903dc8892")
+        );
+    }
+
+    #[test]
+    fn test_request_header() {
+        // generated by a port of the v2fly client in Python with the same inputs
+        let uuid = parse_uuid("b831381d-6324-4d53-ad4f-8cda48b30811").unwrap();
+        let cmd_key = cmd_key(&uuid);
+        assert_eq!(cmd_key.to_vec(), hex("b50d916ac0cec067981af8e5f38a758f"));
+        let auth_id = create_auth_id(&cmd_key, 1700000000, [1, 2, 3, 4]);
+        assert_eq!(auth_id.to_vec(), hex("4774fe5cc901ea4f81f2159909767a36"));
+        let request = VmessRequest {
+            cmd: VmessCmd::Tcp,
+            addr: NetworkAddr::DomainName {
+                domain_name: "example.com".to_string(),
+                port: 443,
+            },
+            security: VmessSecurity::Aes128Gcm,
+            body_key: std::array::from_fn(|i| i as u8),
+            body_iv: std::array::from_fn(|i| 0x10 + i as u8),
+            response_auth: 0x2a,
+        };
+        let sealed = request
+            .seal_with(
+                &cmd_key,
+                auth_id,
+                std::array::from_fn(|i| 0x30 + i as u8),
+                &[0xaa; 3],
+            )
+            .unwrap();
+        assert_eq!(
+            sealed,
+            hex(concat!(
+                "4774fe5cc901ea4f81f2159909767a36",
+                "668637eaf416210be366881731c3bebfe1d1",
+                "3031323334353637",
+                "1ec3166a072641aa1b1f611200964929dc700a1c1d86d2839a2fcd0479e41cb8",
+                "61c11da1f20e56b444a8e5ced55d0f316b7ba47feed86aa7b846be187cd0eb10",
+                "dc7ef9afc8237a9e101dce27"
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_vmess_echo() {
+        let uuid = parse_uuid("b831381d-6324-4d53-ad4f-8cda48b30811").unwrap();
+        for security in [
+            VmessSecurity::Aes128Gcm,
+            VmessSecurity::Chacha20Poly1305,
+            VmessSecurity::None,
+        ] {
+            let (client, server) = tokio::io::duplex(65536);
+            let server = tokio::spawn(echo_server(server, uuid));
+            let request = VmessRequest::new(
+                VmessCmd::Tcp,
+                NetworkAddr::DomainName {
+                    domain_name: "example.com".to_string(),
+                    port: 443,
+                },
+                security,
+            );
+            let mut stream = VmessStream::new(client, &cmd_key(&uuid), &request).unwrap();
+            stream.write_all(b"hello, vmess").await.unwrap();
+            stream.flush().await.unwrap();
+            let mut buf = [0u8; 12];
+            stream.read_exact(&mut buf).await.unwrap();
+            assert_eq!(&buf, b"hello, vmess");
+            let large = vec![0x42u8; 20000];
+            stream.write_all(large.as_slice()).await.unwrap();
+            stream.flush().await.unwrap();
+            let mut buf = vec![0u8; large.len()];
+            stream.read_exact(buf.as_mut_slice()).await.unwrap();
+            assert_eq!(buf, large);
+            stream.shutdown().await.unwrap();
+            let mut rest = vec![];
+            stream.read_to_end(&mut rest).await.unwrap();
+            assert!(rest.is_empty());
+            server.await.unwrap();
+        }
+    }
+}
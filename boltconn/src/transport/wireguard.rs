@@ -2,6 +2,7 @@ use crate::common::io_err;
 use crate::common::MAX_PKT_SIZE;
 use crate::config::DnsPreference;
 use crate::network::dns::Dns;
+use crate::network::egress::DialOptions;
 use crate::proxy::error::TransportError;
 use crate::proxy::NetworkAddr;
 use crate::transport::AdapterOrSocket;
@@ -9,15 +10,17 @@ use boringtun::noise::errors::WireGuardError;
 use boringtun::noise::{Tunn, TunnResult};
 use bytes::BytesMut;
 use hickory_resolver::config::ResolverConfig;
+use ip_network::IpNetwork;
+use ip_network_table::IpNetworkTable;
+use ipnet::IpNet;
 use std::fmt::{Debug, Formatter};
 use std::hash::{Hash, Hasher};
 use std::io;
 use std::io::ErrorKind;
-use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
 use tokio::sync::Notify;
 
-// We left AllowedIPs since it's boltconn that manages routing.
 #[derive(Clone)]
 pub struct WireguardConfig {
     pub name: String,
@@ -25,17 +28,23 @@ pub struct WireguardConfig {
     pub ip_addr: Option<Ipv4Addr>,
     pub ip_addr6: Option<Ipv6Addr>,
     pub private_key: x25519_dalek::StaticSecret,
-    // peer
+    pub peers: Vec<WireguardPeerConfig>,
+    pub mtu: usize,
+    pub dns: ResolverConfig,
+    pub dns_preference: DnsPreference,
+    pub over_tcp: bool,
+    pub dial: DialOptions,
+}
+
+#[derive(Clone)]
+pub struct WireguardPeerConfig {
     pub public_key: x25519_dalek::PublicKey,
     pub endpoint: NetworkAddr,
-    pub mtu: usize,
     pub preshared_key: Option<[u8; 32]>,
     pub keepalive: Option<u16>,
-    pub dns: ResolverConfig,
-    pub dns_preference: DnsPreference,
     // reserved fields
     pub reserved: Option<[u8; 3]>,
-    pub over_tcp: bool,
+    pub allowed_ips: Vec<IpNet>,
 }
 
 impl Debug for WireguardConfig {
@@ -43,18 +52,32 @@ impl Debug for WireguardConfig {
         f.debug_tuple("")
             .field(&self.ip_addr)
             .field(&self.ip_addr6)
+            .field(&self.peers)
+            .finish()
+    }
+}
+
+impl Debug for WireguardPeerConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("")
             .field(&self.endpoint)
             .field(&self.preshared_key)
+            .field(&self.allowed_ips)
             .finish()
     }
 }
 
 impl PartialEq for WireguardConfig {
     fn eq(&self, other: &Self) -> bool {
-        self.public_key == other.public_key
-            && self.ip_addr == other.ip_addr
+        self.ip_addr == other.ip_addr
             && self.ip_addr6 == other.ip_addr6
-            && self.endpoint == other.endpoint
+            && self.dial == other.dial
+            && self.peers.len() == other.peers.len()
+            && self.peers.iter().zip(other.peers.iter()).all(|(a, b)| {
+                a.public_key == b.public_key
+                    && a.endpoint == b.endpoint
+                    && a.allowed_ips == b.allowed_ips
+            })
     }
 }
 
@@ -64,14 +87,87 @@ impl Hash for WireguardConfig {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.ip_addr.hash(state);
         self.ip_addr6.hash(state);
-        self.public_key.hash(state);
-        self.endpoint.hash(state);
+        self.dial.hash(state);
+        for peer in &self.peers {
+            peer.public_key.hash(state);
+            peer.endpoint.hash(state);
+            peer.allowed_ips.hash(state);
+        }
     }
 }
 
-/// Wireguard Tunnel, with only one peer.
+/// Peers sharing one WireGuard interface; outgoing packets are routed by AllowedIPs.
+pub struct WireguardPeerTable {
+    peers: Vec<Arc<WireguardTunnel>>,
+    table: IpNetworkTable<usize>,
+}
+
+impl WireguardPeerTable {
+    pub fn new(peers: Vec<Arc<WireguardTunnel>>) -> Self {
+        let mut table = IpNetworkTable::new();
+        for (idx, peer) in peers.iter().enumerate() {
+            for net in &peer.allowed_ips {
+                let net = IpNetwork::new_truncate(net.addr(), net.prefix_len()).unwrap();
+                // the first peer wins on duplicated AllowedIPs
+                if table.exact_match(net).is_none() {
+                    table.insert(net, idx);
+                }
+            }
+        }
+        Self { peers, table }
+    }
+
+    pub fn peers(&self) -> &[Arc<WireguardTunnel>] {
+        self.peers.as_slice()
+    }
+
+    pub fn route(&self, dst: IpAddr) -> Option<&Arc<WireguardTunnel>> {
+        self.table
+            .longest_match(dst)
+            .map(|(_, idx)| &self.peers[*idx])
+    }
+
+    pub async fn send_outgoing_packet(
+        &self,
+        smol_rx: &mut flume::Receiver<BytesMut>,
+        wg_buf: &mut [u8; MAX_PKT_SIZE],
+    ) -> Result<(), TransportError> {
+        let data = smol_rx
+            .recv_async()
+            .await
+            .map_err(|_| io::Error::from(ErrorKind::ConnectionAborted))?;
+        let Some(dst) = packet_destination(data.as_ref()) else {
+            tracing::debug!("[WireGuard] Drop malformed packet");
+            return Ok(());
+        };
+        match self.route(dst) {
+            Some(peer) => peer.send_outgoing_packet(data.as_ref(), wg_buf).await,
+            None => {
+                tracing::debug!("[WireGuard] No peer allows {}, drop packet", dst);
+                Ok(())
+            }
+        }
+    }
+}
+
+fn packet_destination(packet: &[u8]) -> Option<IpAddr> {
+    match packet.first()? >> 4 {
+        4 if packet.len() >= 20 => {
+            let addr: [u8; 4] = packet[16..20].try_into().ok()?;
+            Some(IpAddr::from(addr))
+        }
+        6 if packet.len() >= 40 => {
+            let addr: [u8; 16] = packet[24..40].try_into().ok()?;
+            Some(IpAddr::from(addr))
+        }
+        _ => None,
+    }
+}
+
+/// Wireguard Tunnel to a single peer.
 pub struct WireguardTunnel {
     tunnel: tokio::sync::Mutex<Tunn>,
+    allowed_ips: Vec<IpNet>,
     inner: WireguardTunnelInner,
 }
 
@@ -86,7 +182,9 @@ struct WireguardTunnelInner {
 impl WireguardTunnel {
     pub async fn new(
         outbound: AdapterOrSocket,
-        config: &WireguardConfig,
+        private_key: &x25519_dalek::StaticSecret,
+        config: &WireguardPeerConfig,
+        index: u32,
         dns: Arc<Dns>,
         smol_notify: Arc<Notify>,
     ) -> Result<Self, TransportError> {
@@ -104,15 +202,16 @@ impl WireguardTunnel {
             }
         };
         let tunnel = Tunn::new(
-            config.private_key.clone(),
+            private_key.clone(),
             config.public_key,
             config.preshared_key,
             config.keepalive,
-            13,
+            index,
             None,
         );
         Ok(Self {
             tunnel: tokio::sync::Mutex::new(tunnel),
+            allowed_ips: config.allowed_ips.clone(),
             inner: WireguardTunnelInner {
                 outbound,
                 endpoint,
@@ -138,7 +237,11 @@ impl WireguardTunnel {
             .await
             .decapsulate(None, &buf[..len], wg_buf);
         Ok(match result {
-            TunnResult::WriteToTunnelV4(data, _addr) => {
+            TunnResult::WriteToTunnelV4(data, addr) => {
+                if !self.is_allowed(addr.into()) {
+                    tracing::debug!("[WireGuard] Drop packet from disallowed {}", addr);
+                    return Ok(false);
+                }
                 let data = BytesMut::from_iter(data.iter());
                 smol_tx
                     .send_async(data)
@@ -147,7 +250,11 @@ impl WireguardTunnel {
                 self.inner.smol_notify.notify_one();
                 true
             }
-            TunnResult::WriteToTunnelV6(data, _addr) => {
+            TunnResult::WriteToTunnelV6(data, addr) => {
+                if !self.is_allowed(addr.into()) {
+                    tracing::debug!("[WireGuard] Drop packet from disallowed {}", addr);
+                    return Ok(false);
+                }
                 let data = BytesMut::from_iter(data.iter());
                 smol_tx
                     .send_async(data)
@@ -170,16 +277,16 @@ impl WireguardTunnel {
         })
     }
 
+    fn is_allowed(&self, src: IpAddr) -> bool {
+        self.allowed_ips.iter().any(|net| net.contains(&src))
+    }
+
     pub async fn send_outgoing_packet(
         &self,
-        smol_rx: &mut flume::Receiver<BytesMut>,
+        data: &[u8],
         wg_buf: &mut [u8; MAX_PKT_SIZE],
     ) -> Result<(), TransportError> {
-        let data = smol_rx
-            .recv_async()
-            .await
-            .map_err(|_| io::Error::from(ErrorKind::ConnectionAborted))?;
-        match self.tunnel.lock().await.encapsulate(data.as_ref(), wg_buf) {
+        match self.tunnel.lock().await.encapsulate(data, wg_buf) {
             TunnResult::WriteToNetwork(packet) => {
                 if self.inner.outbound_send(packet).await? != packet.len() {
                     // size exceeded
@@ -267,3 +374,101 @@ impl WireguardTunnelInner {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    async fn tunnel(allowed_ips: &[&str]) -> Arc<WireguardTunnel> {
+        let secret = x25519_dalek::StaticSecret::from([1u8; 32]);
+        let peer = x25519_dalek::PublicKey::from(&x25519_dalek::StaticSecret::from([2u8; 32]));
+        let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = socket.local_addr().unwrap();
+        Arc::new(WireguardTunnel {
+            tunnel: tokio::sync::Mutex::new(Tunn::new(secret, peer, None, None, 0, None)),
+            allowed_ips: allowed_ips.iter().map(|s| s.parse().unwrap()).collect(),
+            inner: WireguardTunnelInner {
+                outbound: AdapterOrSocket::Socket(socket),
+                endpoint,
+                smol_notify: Arc::new(Notify::new()),
+                reserved: None,
+            },
+        })
+    }
+
+    fn ipv4_packet(dst: Ipv4Addr) -> Vec<u8> {
+        let mut packet = vec![0u8; 20];
+        packet[0] = 0x45;
+        packet[16..20].copy_from_slice(&dst.octets());
+        packet
+    }
+
+    fn ipv6_packet(dst: Ipv6Addr) -> Vec<u8> {
+        let mut packet = vec![0u8; 40];
+        packet[0] = 0x60;
+        packet[24..40].copy_from_slice(&dst.octets());
+        packet
+    }
+
+    #[test]
+    fn test_packet_destination() {
+        let v4 = Ipv4Addr::new(10, 0, 0, 1);
+        let v6 = "fd00::1".parse::<Ipv6Addr>().unwrap();
+        assert_eq!(
+            packet_destination(ipv4_packet(v4).as_slice()),
+            Some(IpAddr::V4(v4))
+        );
+        assert_eq!(
+            packet_destination(ipv6_packet(v6).as_slice()),
+            Some(IpAddr::V6(v6))
+        );
+        // truncated headers
+        assert_eq!(packet_destination(&ipv4_packet(v4)[..19]), None);
+        assert_eq!(packet_destination(&ipv6_packet(v6)[..39]), None);
+        // unknown version and empty packet
+        assert_eq!(packet_destination(&[0x50; 40]), None);
+        assert_eq!(packet_destination(&[]), None);
+    }
+
+    #[tokio::test]
+    async fn test_peer_table_route() {
+        let a = tunnel(&["10.0.0.0/8", "fd00::/16"]).await;
+        let b = tunnel(&["10.1.0.0/16", "0.0.0.0/0"]).await;
+        let c = tunnel(&["10.1.0.0/16", "fd00:1::/32"]).await;
+        let table = WireguardPeerTable::new(vec![a.clone(), b.clone(), c.clone()]);
+        let route = |dst: &str| table.route(dst.parse().unwrap()).cloned();
+
+        // longest prefix wins across peers
+        assert!(Arc::ptr_eq(&route("10.2.0.1").unwrap(), &a));
+        assert!(Arc::ptr_eq(&route("10.1.0.1").unwrap(), &b));
+        assert!(Arc::ptr_eq(&route("192.168.1.1").unwrap(), &b));
+        assert!(Arc::ptr_eq(&route("fd00::1").unwrap(), &a));
+        assert!(Arc::ptr_eq(&route("fd00:1::1").unwrap(), &c));
+        // no peer allows it
+        assert!(route("fe80::1").is_none());
+    }
+
+    #[tokio::test]
+    async fn test_peer_table_drop() {
+        let table = WireguardPeerTable::new(vec![tunnel(&["10.0.0.0/8"]).await]);
+        let (tx, mut rx) = flume::unbounded();
+        let mut wg_buf = [0u8; MAX_PKT_SIZE];
+        // neither a malformed packet nor one to no peer fails the interface
+        tx.send(BytesMut::from(&[0x45u8; 10][..])).unwrap();
+        tx.send(BytesMut::from(
+            ipv6_packet("fd00::1".parse().unwrap()).as_slice(),
+        ))
+        .unwrap();
+        for _ in 0..2 {
+            assert!(table
+                .send_outgoing_packet(&mut rx, &mut wg_buf)
+                .await
+                .is_ok());
+        }
+        drop(tx);
+        assert!(table
+            .send_outgoing_packet(&mut rx, &mut wg_buf)
+            .await
+            .is_err());
+    }
+}